@@ -0,0 +1,80 @@
+//! Measures the overhead cdbgen adds to each compile, i.e., the time running `true` through a
+//! shim takes compared to running it directly.  Run by `cargo bench`.  If
+//! `CDBGEN_BENCH_BUDGET_US` is set, the benchmark fails once the median overhead exceeds it,
+//! which catches regressions.  criterion is not used since its dependencies require a newer
+//! Rust than cdbgen (1.58), and since it reports regressions rather than failing on them.
+
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+fn median(mut samples: Vec<Duration>) -> Duration {
+    samples.sort();
+    samples[samples.len() / 2]
+}
+
+fn run(
+    program: &str,
+    dir: &std::path::Path,
+    path: &str,
+    n: usize,
+    env: &[(&str, &str)],
+) -> Duration {
+    let samples = (0..n)
+        .map(|i| {
+            let start = Instant::now();
+            let status = Command::new(program)
+                .args(["-O2", "-c", &format!("foo{}.c", i % 100)])
+                .env("PATH", path)
+                .env_remove("CDBGEN")
+                .envs(env.iter().copied())
+                .current_dir(dir)
+                .status()
+                .unwrap();
+            assert!(status.success());
+            start.elapsed()
+        })
+        .collect();
+    median(samples)
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("the overhead benchmark runs on Unix only");
+}
+
+#[cfg(unix)]
+fn main() {
+    let n: usize = env::var("CDBGEN_BENCH_N").map_or(500, |n| n.parse().unwrap());
+    let temp = env::temp_dir().join(format!("cdbgen-bench-{}", std::process::id()));
+    fs::create_dir_all(&temp).unwrap();
+    std::os::unix::fs::symlink(env!("CARGO_BIN_EXE_cdbgen"), temp.join("cdbgen-true")).unwrap();
+    let path = format!("{}:/bin:/usr/bin", temp.display());
+
+    let baseline = run("true", &temp, &path, n, &[]);
+    let mut failed = false;
+    for (name, env) in [
+        ("file", &[][..]),
+        ("fragments", &[("CDBGEN_STORAGE", "fragments")][..]),
+        ("sharded", &[("CDBGEN_STORAGE", "sharded")][..]),
+    ] {
+        let _ = fs::remove_file(temp.join("compile_commands.json"));
+        let shim = run("cdbgen-true", &temp, &path, n, env);
+        let overhead = shim.saturating_sub(baseline);
+        println!(
+            "{:<10} median {:>8.1}us per compile, overhead {:>8.1}us",
+            name,
+            shim.as_secs_f64() * 1e6,
+            overhead.as_secs_f64() * 1e6
+        );
+        if let Ok(budget) = env::var("CDBGEN_BENCH_BUDGET_US") {
+            failed |= overhead > Duration::from_micros(budget.parse().unwrap());
+        }
+    }
+    fs::remove_dir_all(&temp).unwrap();
+    if failed {
+        eprintln!("overhead exceeds the budget");
+        std::process::exit(1);
+    }
+}
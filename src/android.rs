@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+
+/// The ABIs of Android by the architecture of their targets.
+const ABIS: &[(&str, &str)] = &[
+    ("aarch64", "arm64-v8a"),
+    ("armv7a", "armeabi-v7a"),
+    ("armv7", "armeabi-v7a"),
+    ("arm", "armeabi-v7a"),
+    ("i686", "x86"),
+    ("x86_64", "x86_64"),
+    ("riscv64", "riscv64"),
+];
+
+/// Parses the value of `CDBGEN_ANDROID_ABI`, i.e., an ABI or `split`.
+pub fn parse_selection(selection: &str) -> Result<String, String> {
+    if selection == "split" || ABIS.iter().any(|(_, abi)| *abi == selection) {
+        Ok(selection.to_string())
+    } else {
+        let mut abis: Vec<_> = ABIS.iter().map(|(_, abi)| *abi).collect();
+        abis.dedup();
+        Err(format!(
+            "unknown ABI '{}', expected one of {} or split",
+            selection,
+            abis.join(", ")
+        ))
+    }
+}
+
+/// Returns the target of the compiler invocation `arguments` (the compiler first), given by
+/// `--target=` or `-target`, or else by the name of the compiler like the wrappers of the NDK,
+/// e.g. `aarch64-linux-android21` for `aarch64-linux-android21-clang++`.
+fn target(arguments: &[String]) -> Option<String> {
+    let mut iter = arguments.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if let Some(target) = arg.strip_prefix("--target=") {
+            return Some(target.to_string());
+        }
+        if arg == "-target" || arg == "--target" {
+            return iter.next().cloned();
+        }
+    }
+    let name = Path::new(arguments.first()?).file_name()?.to_string_lossy();
+    name.rsplit_once("-clang")
+        .map(|(target, _)| target.to_string())
+}
+
+/// Returns the ABI of Android the invocation `arguments` compiles for, if any.
+pub fn abi(arguments: &[String]) -> Option<&'static str> {
+    let target = target(arguments)?;
+    if !target.contains("-android") {
+        return None;
+    }
+    let arch = target.split('-').next()?;
+    ABIS.iter().find(|(a, _)| *a == arch).map(|(_, abi)| *abi)
+}
+
+/// Returns the database of the ABI `abi` next to `database`, e.g. `compile_commands.x86.json`.
+pub fn database(database: &Path, abi: &str) -> PathBuf {
+    let stem = database.file_stem().unwrap_or_default().to_string_lossy();
+    match database.extension() {
+        Some(ext) => database.with_file_name(format!("{}.{}.{}", stem, abi, ext.to_string_lossy())),
+        None => database.with_file_name(format!("{}.{}", stem, abi)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn abi() {
+        let clang = "/ndk/toolchains/llvm/prebuilt/linux-x86_64/bin/clang";
+        assert_eq!(
+            super::abi(&strings(&[
+                clang,
+                "--target=aarch64-none-linux-android21",
+                "a.c"
+            ])),
+            Some("arm64-v8a")
+        );
+        assert_eq!(
+            super::abi(&strings(&[
+                clang,
+                "-target",
+                "armv7-none-linux-androideabi19"
+            ])),
+            Some("armeabi-v7a")
+        );
+        assert_eq!(
+            super::abi(&strings(&[clang, "-target", "armv7a-linux-androideabi19"])),
+            Some("armeabi-v7a")
+        );
+        assert_eq!(parse_selection("x86_64").unwrap(), "x86_64");
+        assert_eq!(parse_selection("split").unwrap(), "split");
+        assert!(parse_selection("arm64").is_err());
+        let wrapper = "/ndk/bin/i686-linux-android24-clang++";
+        assert_eq!(super::abi(&strings(&[wrapper, "-c", "a.cpp"])), Some("x86"));
+        assert_eq!(super::abi(&strings(&[clang, "-c", "a.c"])), None);
+        assert_eq!(
+            super::abi(&strings(&[clang, "--target=aarch64-none-elf", "a.c"])),
+            None
+        );
+        assert_eq!(
+            database(Path::new("/p/compile_commands.json"), "x86"),
+            Path::new("/p/compile_commands.x86.json")
+        );
+
+        let arguments = strings(&[wrapper, "--sysroot=/ndk/sysroot", "-fno-addrsig", "a.cpp"]);
+        assert_eq!(
+            presets::detect(wrapper).unwrap().apply(&arguments)[1..],
+            [
+                "--target=i686-linux-android24",
+                "--sysroot=/ndk/sysroot",
+                "-fno-addrsig",
+                "a.cpp"
+            ]
+        );
+    }
+}
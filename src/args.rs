@@ -0,0 +1,509 @@
+use std::fs;
+use std::path::Path;
+
+use crate::paths;
+use crate::shell;
+
+/// Options which take a value given as separate argument, e.g. `-o foo`.
+const SEPARATE: &[&str] = &[
+    "-o",
+    "-D",
+    "-U",
+    "-I",
+    "-L",
+    "-l",
+    "-x",
+    "-include",
+    "-imacros",
+    "-isystem",
+    "-iquote",
+    "-idirafter",
+    "-iprefix",
+    "-iwithprefix",
+    "-iwithprefixbefore",
+    "-isysroot",
+    "--sysroot",
+    "-MF",
+    "-MT",
+    "-MQ",
+    "-MJ",
+    "-Xclang",
+    "-Xpreprocessor",
+    "-Xassembler",
+    "-Xlinker",
+    "-T",
+    "-target",
+    "-arch",
+    "-aux-info",
+    "--param",
+    // qcc and the drivers of Green Hills.
+    "-V",
+    "-bsp",
+    "-os_dir",
+];
+
+/// Returns true if `arg` is an option taking the next argument as its value.  Besides the options
+/// of `SEPARATE` these are `-mllvm` and the options passing an argument on to some other tool,
+/// e.g. `-Xcuda-ptxas` or `-Xarch_x86_64`, which are not listed each.
+fn is_separate(arg: &str) -> bool {
+    SEPARATE.contains(&arg) || arg == "-mllvm" || (arg.starts_with("-X") && arg.len() > 2)
+}
+
+/// Options which may have their value joined, e.g. `-DFOO`.  Longer prefixes come first.
+const JOINED: &[&str] = &[
+    "--sysroot=",
+    "-iwithprefixbefore",
+    "-iwithprefix",
+    "-idirafter",
+    "-isysroot",
+    "-isystem",
+    "-imacros",
+    "-include",
+    "-iprefix",
+    "-iquote",
+    "-MF",
+    "-MT",
+    "-MQ",
+    "-MJ",
+    "-D",
+    "-U",
+    "-I",
+    "-L",
+    "-l",
+    "-o",
+    "-x",
+];
+
+/// Options whose value is a path.
+pub const PATH_VALUED: &[&str] = &[
+    "-o",
+    "-I",
+    "-L",
+    "-include",
+    "-imacros",
+    "-isystem",
+    "-iquote",
+    "-idirafter",
+    "-isysroot",
+    "--sysroot",
+    "--sysroot=",
+    "-MF",
+    "-MJ",
+];
+
+/// Dependency generation options, whose outputs are of no use to tools reading the database.
+pub const DEPENDENCY_FLAGS: &[&str] = &["-M", "-MM", "-MD", "-MMD", "-MP", "-MF", "-MT", "-MQ"];
+
+/// Returns whether `flag` is a dependency generation option, including the ones passed to the
+/// preprocessor, e.g. `-Wp,-MD,a.d` as spelled by Kbuild.
+pub fn is_dependency(flag: &Flag) -> bool {
+    let name = match flag.name.strip_prefix("-Wp,") {
+        Some(args) => args.split(',').next().unwrap_or_default(),
+        None => &flag.name,
+    };
+    DEPENDENCY_FLAGS.contains(&name)
+}
+
+/// A single option together with its value, regardless of whether the value was joined or
+/// passed as separate argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flag {
+    pub name: String,
+    pub value: Option<String>,
+    /// The argument(s) the flag was spelled with.
+    pub args: Vec<String>,
+}
+
+impl Flag {
+    /// Renders the flag the way it would be spelled on a command line with a joined value.
+    pub fn joined(&self) -> String {
+        match &self.value {
+            Some(value) => format!("{}{}", self.name, value),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Renders the flag the way it would be spelled on a command line with a separate value.
+    pub fn separate(&self) -> String {
+        match &self.value {
+            Some(value) => format!("{} {}", self.name, value),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Returns true for arguments which are not options, i.e., input files.
+    pub fn is_input(&self) -> bool {
+        self.value.is_none() && !self.name.starts_with('-')
+    }
+
+    /// Returns the path this flag refers to, i.e., either the value of a path-valued option or
+    /// the input file itself.
+    pub fn path(&self) -> Option<&str> {
+        if self.is_input() {
+            Some(&self.name)
+        } else if PATH_VALUED.contains(&self.name.as_str()) {
+            self.value.as_deref()
+        } else {
+            None
+        }
+    }
+
+    /// Returns the argument(s) of this flag with the path replaced by `path` while keeping the
+    /// original spelling, i.e., a joined value stays joined.
+    pub fn with_path(&self, path: &str) -> Vec<String> {
+        if self.is_input() {
+            vec![path.to_string()]
+        } else if self.args.len() == 2 {
+            vec![self.name.clone(), path.to_string()]
+        } else {
+            vec![format!("{}{}", self.name, path)]
+        }
+    }
+}
+
+/// Groups the arguments of a compiler invocation (without the compiler itself) into flags.
+/// Arguments which are not options are returned as flags without value, too.
+pub fn flags(args: &[String]) -> Vec<Flag> {
+    let mut result = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if is_separate(arg) {
+            let value = iter.next().cloned();
+            result.push(Flag {
+                name: arg.clone(),
+                args: std::iter::once(arg.clone()).chain(value.clone()).collect(),
+                value,
+            });
+        } else if let Some(prefix) = JOINED.iter().find(|p| arg.starts_with(*p)) {
+            result.push(Flag {
+                name: prefix.to_string(),
+                value: Some(arg[prefix.len()..].to_string()),
+                args: vec![arg.clone()],
+            });
+        } else {
+            result.push(Flag {
+                name: arg.clone(),
+                value: None,
+                args: vec![arg.clone()],
+            });
+        }
+    }
+    result
+}
+
+/// Options of cl taking a value, either joined or, for the ones marked, as separate argument.
+/// Longer names come first.
+const CL_VALUED: &[(&str, bool)] = &[
+    ("FI", true),
+    ("Fo", false),
+    ("Fe", false),
+    ("Fd", false),
+    ("Fp", false),
+    ("Fa", false),
+    ("Fi", false),
+    ("Tc", true),
+    ("Tp", true),
+    ("Yc", false),
+    ("Yu", false),
+    ("I", true),
+    ("D", true),
+    ("U", true),
+];
+
+/// Returns true if the invocation `arguments` (the compiler first) takes the options of cl, i.e.,
+/// runs cl or clang-cl.
+pub fn is_cl(arguments: &[String]) -> bool {
+    let stem = arguments
+        .first()
+        .and_then(|c| Path::new(c).file_stem())
+        .map(|s| s.to_string_lossy().to_lowercase());
+    matches!(stem.as_deref(), Some("cl" | "clang-cl"))
+        || arguments.iter().any(|a| a == "--driver-mode=cl")
+}
+
+/// Groups the arguments of an invocation of cl (without the compiler) like `args::flags`.  The
+/// names of valued options are spelled with `/` regardless of their spelling in `args` and a
+/// value of an `/F` option may be separated by a colon.  As with clang-cl, an argument starting
+/// with `/` is an option if it starts like a valued one, i.e., `/Dir/a.c` defines `ir/a.c`.
+pub fn cl_flags(args: &[String]) -> Vec<Flag> {
+    let mut result = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "/link" || arg == "-link" {
+            // The remaining arguments are passed to the linker.
+            let args: Vec<String> = std::iter::once(arg).chain(iter).cloned().collect();
+            result.push(Flag {
+                name: "/link".to_string(),
+                value: None,
+                args,
+            });
+            break;
+        }
+        let option = arg
+            .strip_prefix('/')
+            .or_else(|| arg.strip_prefix('-'))
+            .and_then(|body| {
+                CL_VALUED
+                    .iter()
+                    .find(|(name, _)| body.starts_with(name))
+                    .map(|&(name, separate)| (name, &body[name.len()..], separate))
+            });
+        match option {
+            Some((name, "", true)) => {
+                let value = iter.next().cloned();
+                result.push(Flag {
+                    name: format!("/{}", name),
+                    args: std::iter::once(arg.clone()).chain(value.clone()).collect(),
+                    value,
+                });
+            }
+            Some((name, value, _)) => {
+                let value = if name.starts_with('F') {
+                    value.strip_prefix(':').unwrap_or(value)
+                } else {
+                    value
+                };
+                result.push(Flag {
+                    name: format!("/{}", name),
+                    value: Some(value.to_string()),
+                    args: vec![arg.clone()],
+                });
+            }
+            // Options without value starting with `/` are taken for inputs, too.
+            None => result.push(Flag {
+                name: arg.clone(),
+                value: None,
+                args: vec![arg.clone()],
+            }),
+        }
+    }
+    result
+}
+
+/// Returns the flags of `old` missing in `new` and the flags of `new` missing in `old`, each in
+/// the order given.  A flag given twice has to be given twice on the other side to match.
+pub fn drift(old: &[String], new: &[String]) -> (Vec<String>, Vec<String>) {
+    let missing = |from: &[String], to: &[String]| {
+        let mut rest: Vec<Flag> = flags(to);
+        let mut missing = Vec::new();
+        for flag in flags(from) {
+            match rest.iter().position(|f| *f == flag) {
+                Some(i) => {
+                    rest.remove(i);
+                }
+                None => missing.push(flag.args.join(" ")),
+            }
+        }
+        missing
+    };
+    (missing(old, new), missing(new, old))
+}
+
+/// Returns the mappings `(OLD, NEW)` of `-ffile-prefix-map=OLD=NEW`, `-fdebug-prefix-map`, and
+/// `-fmacro-prefix-map` in the order given.
+pub fn prefix_maps(args: &[String]) -> Vec<(String, String)> {
+    args.iter()
+        .filter_map(|arg| {
+            let map = arg
+                .strip_prefix("-ffile-prefix-map=")
+                .or_else(|| arg.strip_prefix("-fdebug-prefix-map="))
+                .or_else(|| arg.strip_prefix("-fmacro-prefix-map="))?;
+            let (old, new) = map.split_once('=')?;
+            Some((old.to_string(), new.to_string()))
+        })
+        .collect()
+}
+
+/// Returns the command line `arguments` (the compiler first) with `-x assembler-with-cpp` made
+/// explicit for the assembler sources whose language the driver infers from the extension, e.g.
+/// `foo.S`, such that tools parse them the same way.  The language is reset by `-x none` if
+/// further inputs follow.
+pub fn tag_assembler(arguments: &[String]) -> Vec<String> {
+    let flags = flags(arguments.get(1..).unwrap_or_default());
+    let mut result: Vec<String> = arguments.iter().take(1).cloned().collect();
+    let mut language = None;
+    for (i, flag) in flags.iter().enumerate() {
+        if flag.name == "-x" {
+            language = flag.value.clone().filter(|x| x != "none");
+        }
+        let inferred = flag.is_input()
+            && language.is_none()
+            && language_of_extension(&flag.name) == Some("assembler-with-cpp");
+        if !inferred {
+            result.extend(flag.args.iter().cloned());
+            continue;
+        }
+        result.extend(["-x".to_string(), "assembler-with-cpp".to_string()]);
+        result.push(flag.name.clone());
+        if flags[i + 1..].iter().any(Flag::is_input) {
+            result.extend(["-x".to_string(), "none".to_string()]);
+        }
+    }
+    result
+}
+
+/// Returns the command line `arguments` (the compiler first) with the response files `@FILE`
+/// replaced by the arguments they contain, nested ones included.  Response files which cannot be
+/// read are kept like the compiler does.
+pub fn expand_response_files(arguments: &[String], directory: &str) -> Vec<String> {
+    fn expand(arguments: &[String], directory: &str, depth: usize, result: &mut Vec<String>) {
+        for arg in arguments {
+            let expanded = arg
+                .strip_prefix('@')
+                .filter(|_| depth < 16)
+                .and_then(|path| fs::read_to_string(paths::resolve(directory, path)).ok())
+                .and_then(|data| shell::split(&data).ok());
+            match expanded {
+                Some(args) => expand(&args, directory, depth + 1, result),
+                None => result.push(arg.clone()),
+            }
+        }
+    }
+    let mut result: Vec<String> = arguments.iter().take(1).cloned().collect();
+    expand(
+        arguments.get(1..).unwrap_or_default(),
+        directory,
+        0,
+        &mut result,
+    );
+    result
+}
+
+/// Returns the language the driver infers from the extension of `file`.
+pub fn language_of_extension(file: &str) -> Option<&'static str> {
+    let ext = std::path::Path::new(file).extension()?.to_str()?;
+    match ext {
+        "c" => Some("c"),
+        "i" => Some("cpp-output"),
+        "cc" | "cp" | "cxx" | "cpp" | "CPP" | "c++" | "C" => Some("c++"),
+        "ii" => Some("c++-cpp-output"),
+        "m" => Some("objective-c"),
+        "mm" | "M" => Some("objective-c++"),
+        "s" => Some("assembler"),
+        "S" | "sx" => Some("assembler-with-cpp"),
+        _ => None,
+    }
+}
+
+/// Returns the inputs of an invocation with arguments `args` (without the compiler), i.e., also
+/// all arguments after `--`, each with the language selected for it by `-x`, if any.  A `-x`
+/// applies to all inputs following it until the next one, and `-x none` restores the language
+/// implied by the extension.
+pub fn inputs(args: &[String]) -> Vec<(String, Option<String>)> {
+    let (options, rest) = match args.iter().position(|a| a == "--") {
+        Some(i) => (&args[..i], &args[i + 1..]),
+        None => (args, &[][..]),
+    };
+    let mut current: Option<String> = None;
+    let mut result = Vec::new();
+    for flag in flags(options) {
+        if flag.name == "-x" {
+            current = flag.value.filter(|v| v != "none");
+        } else if flag.is_input() {
+            result.push((flag.name, current.clone()));
+        }
+    }
+    result.extend(rest.iter().map(|arg| (arg.clone(), current.clone())));
+    result
+}
+
+/// Returns the language `file` is compiled as by the invocation with arguments `args` (without
+/// the compiler), i.e., the one selected by a preceding `-x` or else the one implied by the
+/// extension.
+pub fn language(args: &[String], file: &str) -> Option<String> {
+    let mut current: Option<String> = None;
+    for flag in flags(args) {
+        if flag.name == "-x" {
+            current = flag.value.filter(|v| v != "none");
+        } else if flag.is_input() && flag.name == file {
+            break;
+        }
+    }
+    current.or_else(|| language_of_extension(file).map(str::to_string))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_response_files() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let dir = temp.path().to_str().unwrap();
+        fs::write(
+            temp.path().join("includes_C.rsp"),
+            "-Iinc \"-I dir\" @more.rsp\n",
+        )
+        .unwrap();
+        fs::write(temp.path().join("more.rsp"), "-DX=1").unwrap();
+        let args: Vec<String> = ["cc", "@includes_C.rsp", "@gone.rsp", "-c", "a.c"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            super::expand_response_files(&args, dir),
+            ["cc", "-Iinc", "-I dir", "-DX=1", "@gone.rsp", "-c", "a.c"]
+        );
+    }
+
+    #[test]
+    fn tag_assembler() {
+        let tag = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+            super::tag_assembler(&args).join(" ")
+        };
+        assert_eq!(
+            tag(&["cc", "-c", "start.S", "-o", "start.o"]),
+            "cc -c -x assembler-with-cpp start.S -o start.o"
+        );
+        assert_eq!(
+            tag(&["cc", "-c", "a.sx", "b.c"]),
+            "cc -c -x assembler-with-cpp a.sx -x none b.c"
+        );
+        assert_eq!(
+            tag(&["cc", "-xassembler-with-cpp", "-c", "a.S"]),
+            "cc -xassembler-with-cpp -c a.S"
+        );
+        assert_eq!(tag(&["cc", "-c", "a.c"]), "cc -c a.c");
+    }
+
+    #[test]
+    fn flags() {
+        let args: Vec<_> = ["-D", "FOO=1", "-DBAR", "-O2", "-include", "x.h", "a.c"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let flags = super::flags(&args);
+        let joined: Vec<_> = flags.iter().map(Flag::joined).collect();
+        assert_eq!(joined, ["-DFOO=1", "-DBAR", "-O2", "-includex.h", "a.c"]);
+        assert_eq!(flags[0].separate(), "-D FOO=1");
+        assert_eq!(flags[0].args, ["-D", "FOO=1"]);
+
+        // The values of options unknown by name are no inputs either.
+        let args: Vec<_> = ["-mllvm", "-x", "-Xcuda-ptxas", "v.c", "a.c"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let inputs: Vec<_> = super::flags(&args)
+            .into_iter()
+            .filter(Flag::is_input)
+            .map(|f| f.name)
+            .collect();
+        assert_eq!(inputs, ["a.c"]);
+    }
+
+    #[test]
+    fn drift() {
+        let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            super::drift(
+                &args(&["cc", "-O2", "-D", "X", "-DX", "-c", "a.c"]),
+                &args(&["cc", "-DX", "-O3", "-c", "a.c", "-DY"])
+            ),
+            (
+                vec!["-O2".to_string(), "-D X".to_string()],
+                vec!["-O3".to_string(), "-DY".to_string()]
+            )
+        );
+    }
+}
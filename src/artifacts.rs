@@ -0,0 +1,122 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::error::Error;
+use std::path::Path;
+
+use crate::cli::Options;
+use crate::graph::{self, Graph};
+use crate::paths;
+
+/// Returns true if the library node `id` is the library linked via `-l<name>`.
+fn provides(id: &str, name: &str) -> bool {
+    let file = Path::new(id)
+        .file_name()
+        .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+    [".a", ".so", ".dylib"]
+        .iter()
+        .any(|ext| file == format!("lib{}{}", name, ext))
+        || file == format!("{}.lib", name)
+}
+
+/// Maps every source of `graph` to the artifacts containing code from it, i.e., the objects it
+/// is compiled into and, transitively, the archives and binaries these are linked into.
+fn map(graph: &Graph) -> BTreeMap<String, BTreeSet<String>> {
+    let mut successors: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for e in &graph.edges {
+        match e.from.strip_prefix("-l") {
+            // Libraries linked by name are taken to be any library of that name.
+            Some(name) => {
+                for n in &graph.nodes {
+                    if n.kind == "library" && provides(&n.id, name) {
+                        successors.entry(&n.id).or_default().push(&e.to);
+                    }
+                }
+            }
+            None => successors.entry(&e.from).or_default().push(&e.to),
+        }
+    }
+    let mut map = BTreeMap::new();
+    for n in graph.nodes.iter().filter(|n| n.kind == "source") {
+        let mut reached = BTreeSet::new();
+        let mut todo = vec![n.id.as_str()];
+        while let Some(id) = todo.pop() {
+            for &next in successors.get(id).into_iter().flatten() {
+                if reached.insert(next.to_string()) {
+                    todo.push(next);
+                }
+            }
+        }
+        map.insert(n.id.clone(), reached);
+    }
+    map
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &["--json"], &["--db"])?;
+    let mut map = map(&graph::read(&opts.database())?);
+    if !opts.positional.is_empty() {
+        let cwd = env::current_dir()?.to_string_lossy().into_owned();
+        let files: BTreeSet<_> = opts
+            .positional
+            .iter()
+            .map(|f| paths::resolve(&cwd, f).to_string_lossy().into_owned())
+            .collect();
+        for f in &files {
+            if !map.contains_key(f) {
+                return Err(format!("'{}' is not recorded in the database", f).into());
+            }
+        }
+        map.retain(|source, _| files.contains(source));
+    }
+    if opts.has("--json") {
+        println!("{}", serde_json::to_string_pretty(&map)?);
+    } else {
+        for (source, artifacts) in &map {
+            println!("{}", source);
+            for artifact in artifacts {
+                println!("  {}", artifact);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graph::{Edge, Graph, Node};
+
+    #[test]
+    fn map() {
+        let node = |id: &str, kind| Node {
+            id: id.to_string(),
+            kind,
+        };
+        let edge = |from: &str, to: &str| Edge {
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        let graph = Graph {
+            nodes: vec![
+                node("/b/foo.c", "source"),
+                node("/b/bar.c", "source"),
+                node("/b/foo.o", "object"),
+                node("/b/lib/libfoo.a", "library"),
+                node("-lfoo", "library"),
+                node("/b/app", "binary"),
+                node("/b/tool", "binary"),
+            ],
+            edges: vec![
+                edge("/b/foo.c", "/b/foo.o"),
+                edge("/b/foo.o", "/b/lib/libfoo.a"),
+                edge("-lfoo", "/b/app"),
+                edge("/b/bar.c", "/b/tool"),
+            ],
+        };
+        let map = super::map(&graph);
+        assert_eq!(
+            map["/b/foo.c"].iter().collect::<Vec<_>>(),
+            ["/b/app", "/b/foo.o", "/b/lib/libfoo.a"]
+        );
+        assert_eq!(map["/b/bar.c"].iter().collect::<Vec<_>>(), ["/b/tool"]);
+    }
+}
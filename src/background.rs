@@ -0,0 +1,85 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Returns the marker of a helper spawned by this process in the directory named by
+/// `CDBGEN_PENDING`, if set by `cdbgen wrap` to wait for the helpers before summing up.
+#[cfg(unix)]
+fn marker() -> Option<std::path::PathBuf> {
+    let dir = std::env::var_os("CDBGEN_PENDING")?;
+    Some(Path::new(&dir).join(std::process::id().to_string()))
+}
+
+/// Waits up to `timeout` until the helpers which left a marker in `dir` are gone, and returns
+/// whether they are.
+pub fn wait(dir: &Path, timeout: Duration) -> Result<bool, Box<dyn Error>> {
+    let start = Instant::now();
+    while fs::read_dir(dir)?.next().is_some() {
+        if start.elapsed() >= timeout {
+            return Ok(false);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    Ok(true)
+}
+
+/// Runs `f`, which records a compile, in a process detached from the build such that the
+/// compiler is run without waiting for the database.  The process is forked twice, i.e., it is
+/// not a child of the compiler the caller execs but adopted by init.  It gets `/dev/null` as
+/// standard streams since build tools like Ninja wait for their pipes to be closed, i.e., its
+/// warnings are not shown, and is to log its event itself.  Its marker, if any, is removed once
+/// `f` returns.  Where processes cannot be forked, `f` is run right away.
+#[cfg(unix)]
+pub fn spawn<F>(f: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnOnce() -> Result<(), Box<dyn Error>>,
+{
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+
+    // Created before forking such that the wrapper cannot miss the helper.
+    let marker = marker();
+    if let Some(marker) = &marker {
+        File::create(marker)?;
+    }
+    match unsafe { libc::fork() } {
+        -1 => {
+            if let Some(marker) = &marker {
+                let _ = fs::remove_file(marker);
+            }
+            Err(std::io::Error::last_os_error().into())
+        }
+        0 => {
+            unsafe { libc::setsid() };
+            // Neither flush nor run the exit handlers of the parent a second time.
+            if unsafe { libc::fork() } != 0 {
+                unsafe { libc::_exit(0) }
+            }
+            if let Ok(null) = File::options().read(true).write(true).open("/dev/null") {
+                for fd in 0..3 {
+                    unsafe { libc::dup2(null.as_raw_fd(), fd) };
+                }
+            }
+            let code = if f().is_ok() { 0 } else { 1 };
+            if let Some(marker) = &marker {
+                let _ = fs::remove_file(marker);
+            }
+            unsafe { libc::_exit(code) }
+        }
+        child => {
+            // Reaps the intermediate process which exits right away.
+            unsafe { libc::waitpid(child, std::ptr::null_mut(), 0) };
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn spawn<F>(f: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnOnce() -> Result<(), Box<dyn Error>>,
+{
+    f()
+}
@@ -0,0 +1,246 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::fragment;
+use crate::output;
+use crate::paths;
+use crate::relocate;
+use crate::shims;
+use crate::wrap;
+
+/// Arguments the toolchain wrapper is probed with for the flags it injects.
+const PROBE: &[&str] = &["-E", "-x", "c", "-", "-o", "/dev/null"];
+
+/// Returns the value of `TARGET_CROSS` from the output of `make printvars`, i.e., the path of the
+/// toolchain wrappers without the name of the compiler, e.g. `.../host/bin/arm-buildroot-linux-`.
+fn target_cross(printvars: &str) -> Option<&str> {
+    printvars
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("TARGET_CROSS="))
+        .map(|value| value.trim_matches('\''))
+}
+
+/// Returns the command the toolchain wrapper executes as printed with `BR2_DEBUG_WRAPPER=2`, i.e.,
+/// a quoted argument per line after `Toolchain wrapper executing:`.
+fn parse_debug(stderr: &str) -> Vec<String> {
+    stderr
+        .lines()
+        .skip_while(|line| !line.starts_with("Toolchain wrapper executing:"))
+        .skip(1)
+        .map_while(|line| {
+            let line = line.trim();
+            Some(line.strip_prefix('\'')?.strip_suffix('\'')?.to_string())
+        })
+        .collect()
+}
+
+/// Returns the real compiler behind the toolchain `wrapper` and the flags the wrapper injects,
+/// e.g. `--sysroot` and the `-mcpu` Buildroot was configured for.
+fn unwrap(wrapper: &str) -> Option<Vec<String>> {
+    let output = Command::new(wrapper)
+        .args(PROBE)
+        .env("BR2_DEBUG_WRAPPER", "2")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .output()
+        .ok()?;
+    let mut command = parse_debug(&String::from_utf8_lossy(&output.stderr));
+    if command.is_empty() {
+        return None;
+    }
+    if let Some(i) = command.windows(PROBE.len()).position(|w| w == PROBE) {
+        command.drain(i..i + PROBE.len());
+    }
+    Some(command)
+}
+
+/// Returns the prefix maps of the per-package directories of `output` to the final ones, e.g.
+/// `output/per-package/zlib/host` to `output/host`.
+fn per_package_maps(output: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let packages = match fs::read_dir(output.join("per-package")) {
+        Ok(packages) => packages,
+        Err(_) => return Vec::new(),
+    };
+    packages
+        .flatten()
+        .flat_map(|package| {
+            ["host", "target"].map(|dir| (package.path().join(dir), output.join(dir)))
+        })
+        .collect()
+}
+
+/// Returns `entry` with the paths below the per-package directories, including the compiler,
+/// remapped by `maps` and, if `unwrapped` is given, the toolchain wrapper replaced by the real
+/// compiler and the flags it injects.  `unwrapped` caches the wrappers probed.
+fn fix_up(
+    entry: &Entry,
+    maps: &[(PathBuf, PathBuf)],
+    unwrapped: Option<&mut BTreeMap<String, Option<Vec<String>>>>,
+) -> Entry {
+    let mut entry = entry.clone();
+    if let (Some(unwrapped), Some((compiler, rest))) = (unwrapped, entry.arguments.split_first()) {
+        let wrapper = format!("{}.br_real", compiler);
+        if Path::new(&wrapper).is_file() {
+            let command = unwrapped
+                .entry(compiler.clone())
+                .or_insert_with(|| unwrap(compiler));
+            if let Some(command) = command {
+                entry.arguments = command.iter().chain(rest).cloned().collect();
+            }
+        }
+    }
+    let mut entry = relocate::remap(&entry, maps);
+    // The compiler is not taken for a path by the remapping.
+    if let Some(compiler) = entry.arguments.first_mut() {
+        let path = Path::new(compiler.as_str());
+        if let Some(mapped) = maps
+            .iter()
+            .find_map(|(old, new)| Some(new.join(path.strip_prefix(old).ok()?)))
+        {
+            *compiler = mapped.to_string_lossy().into_owned();
+        }
+    }
+    entry
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse_command(args, &["--unwrap"], &["--db", "--make"])?;
+    let top = env::current_dir()?;
+    if !top.join("package/Makefile.in").is_file() {
+        return Err(format!("{} is no top directory of Buildroot", top.display()).into());
+    }
+    let database = match opts.value("--db") {
+        Some(db) => paths::normalize(&top.join(db)),
+        None => top.join("compile_commands.json"),
+    };
+    let make = opts.value("--make").unwrap_or("make");
+    let output = opts
+        .rest
+        .iter()
+        .find_map(|arg| arg.strip_prefix("O="))
+        .map_or_else(|| top.join("output"), |o| paths::normalize(&top.join(o)));
+    let printvars = Command::new(make)
+        .args([
+            "-s",
+            "--no-print-directory",
+            "printvars",
+            "VARS=TARGET_CROSS",
+        ])
+        .arg(format!("O={}", output.display()))
+        .output()
+        .map_err(|error| format!("cannot run '{}': {}", make, error))?;
+    let cross = target_cross(&String::from_utf8_lossy(&printvars.stdout))
+        .map(|cross| Path::new(cross).to_path_buf())
+        .ok_or("cannot determine TARGET_CROSS; is Buildroot configured?")?;
+    let prefix = cross.file_name().unwrap_or_default().to_string_lossy();
+
+    // The shims look the wrappers up on PATH, i.e., in the host directory of each package.
+    let shim_dir = fragment::dir(&database).join("buildroot");
+    fs::create_dir_all(&shim_dir)?;
+    let mut overrides = Vec::new();
+    for (var, compiler) in [("TARGET_CC", "gcc"), ("TARGET_CXX", "g++")] {
+        let (shim, _) = shims::create_shim(&shim_dir, &format!("{}{}", prefix, compiler))?;
+        overrides.push(format!("{}={}", var, shim.display()));
+    }
+    let mut path = env::split_paths(&env::var_os("PATH").unwrap_or_default()).collect::<Vec<_>>();
+    path.push(output.join("host/bin"));
+    let cdbgen = env::current_exe()?;
+    let status = Command::new(make)
+        .args(&overrides)
+        .args(&opts.rest)
+        .env("PATH", env::join_paths(path)?)
+        .env("CDBGEN", &database)
+        .env("CDBGEN_CAPTURE", "buildroot")
+        // CMake packages take the compiler from the toolchain file of Buildroot.
+        .env("CMAKE_C_COMPILER_LAUNCHER", &cdbgen)
+        .env("CMAKE_CXX_COMPILER_LAUNCHER", &cdbgen)
+        .status()
+        .map_err(|error| format!("cannot run '{}': {}", make, error))?;
+
+    let maps = per_package_maps(&output);
+    let mut unwrapped = BTreeMap::new();
+    let unwrap = opts.has("--unwrap");
+    if database.exists() {
+        db::update_any(&database, |entries| {
+            let mut fixed = BTreeSet::new();
+            for entry in entries {
+                let unwrapped = if unwrap { Some(&mut unwrapped) } else { None };
+                fixed.insert(fix_up(entry, &maps, unwrapped));
+            }
+            Ok(fixed)
+        })?;
+        output::info(&format!("recorded into {}", database.display()));
+    }
+    if !status.success() {
+        wrap::exit_like(status);
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fix_up() {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(
+            target_cross("TARGET_CROSS=/o/host/bin/arm-buildroot-linux-gnueabihf-\n"),
+            Some("/o/host/bin/arm-buildroot-linux-gnueabihf-")
+        );
+
+        let temp = assert_fs::TempDir::new().unwrap();
+        let output = temp.path().join("output");
+        let bin = output.join("per-package/zlib/host/bin");
+        fs::create_dir_all(&bin).unwrap();
+        let wrapper = bin.join("arm-linux-gcc");
+        fs::write(
+            &wrapper,
+            format!(
+                "#!/bin/sh\necho 'Toolchain wrapper executing:' >&2\nprintf \"    '%s'\\n\" \
+                 {}.br_real --sysroot {}/per-package/zlib/host/sysroot -mcpu=cortex-a7 \"$@\" >&2\n",
+                wrapper.display(),
+                output.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&wrapper, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(bin.join("arm-linux-gcc.br_real"), "").unwrap();
+
+        let entry = Entry {
+            directory: output.join("build/zlib-1.3").to_string_lossy().into_owned(),
+            file: "adler32.c".to_string(),
+            arguments: vec![
+                wrapper.to_string_lossy().into_owned(),
+                "-c".to_string(),
+                "adler32.c".to_string(),
+            ],
+            output: None,
+        };
+        let maps = per_package_maps(&output);
+        let mut unwrapped = BTreeMap::new();
+        let fixed = super::fix_up(&entry, &maps, Some(&mut unwrapped));
+        assert_eq!(
+            fixed.arguments,
+            [
+                format!("{}/host/bin/arm-linux-gcc.br_real", output.display()),
+                "--sysroot".to_string(),
+                format!("{}/host/sysroot", output.display()),
+                "-mcpu=cortex-a7".to_string(),
+                "-c".to_string(),
+                "adler32.c".to_string(),
+            ]
+        );
+        assert_eq!(unwrapped.len(), 1);
+        assert_eq!(
+            super::fix_up(&entry, &maps, None).arguments[0],
+            format!("{}/host/bin/arm-linux-gcc", output.display())
+        );
+    }
+}
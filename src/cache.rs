@@ -0,0 +1,129 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs::{self, File, Metadata};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::{Entry, Invocation};
+use crate::fragment;
+use crate::hash;
+
+/// What identifies a version of a database, i.e., its modification time in nanoseconds and its
+/// size.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct State {
+    modified: u128,
+    len: u64,
+}
+
+impl State {
+    fn of(metadata: &Metadata) -> Option<Self> {
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(State {
+            modified: modified.as_nanos(),
+            len: metadata.len(),
+        })
+    }
+}
+
+/// The entries of a single directory as of a version of the database, each file mapped to the
+/// hash of its arguments and output.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Cache {
+    state: Option<State>,
+    files: BTreeMap<String, u64>,
+}
+
+/// Returns the cache of `directory` for the database at `json_path`.
+fn path(json_path: &Path, directory: &str) -> PathBuf {
+    fragment::dir(json_path)
+        .join("cache")
+        .join(format!("{:016x}", hash::fnv1a(directory.as_bytes())))
+}
+
+fn hash(entry: &Entry) -> u64 {
+    let mut data = entry.arguments.join("\0");
+    if let Some(output) = &entry.output {
+        data.push('\x01');
+        data.push_str(output);
+    }
+    hash::fnv1a(data.as_bytes())
+}
+
+/// Returns true if every entry of `invocation` is known to be in the database at `json_path`,
+/// i.e., if the database did not change since the cache of the directory was stored and the
+/// cache holds the same entries.  The database is neither opened nor parsed.
+pub fn is_recorded(json_path: &Path, invocation: &Invocation) -> bool {
+    let state = match fs::metadata(json_path).ok().and_then(|m| State::of(&m)) {
+        Some(state) => state,
+        None => return false,
+    };
+    let cache: Cache = match fs::read(path(json_path, &invocation.directory))
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+    {
+        Some(cache) => cache,
+        None => return false,
+    };
+    cache.state == Some(state)
+        && invocation
+            .entries()
+            .all(|e| cache.files.get(&e.file) == Some(&hash(&e)))
+}
+
+/// Stores the cache of `directory` from the `entries` of the database written to `json_file`.
+/// Must be called while the database is locked such that the entries match its state.
+pub fn store(
+    json_path: &Path,
+    json_file: &File,
+    directory: &str,
+    entries: &BTreeSet<Entry>,
+) -> Result<(), Box<dyn Error>> {
+    let cache = Cache {
+        state: State::of(&json_file.metadata()?),
+        files: entries
+            .iter()
+            .filter(|e| e.directory == directory)
+            .map(|e| (e.file.clone(), hash(e)))
+            .collect(),
+    };
+    let path = path(json_path, directory);
+    fs::create_dir_all(path.parent().unwrap())?;
+    // Written atomically since the cache is read without the lock.
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec(&cache)?)?;
+    fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_recorded() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let json_path = temp.path().join("compile_commands.json");
+        fs::write(&json_path, "[]\n").unwrap();
+        let invocation = |flag: &str| Invocation {
+            argv: Vec::new(),
+            arguments: vec!["cc".to_string(), flag.to_string(), "a.c".to_string()],
+            directory: "/src".to_string(),
+            files: [("a.c".to_string(), None)].into_iter().collect(),
+            duration: None,
+            session: None,
+        };
+        assert!(!super::is_recorded(&json_path, &invocation("-O2")));
+
+        let entries = invocation("-O2").entries().collect();
+        let json_file = File::open(&json_path).unwrap();
+        store(&json_path, &json_file, "/src", &entries).unwrap();
+        assert!(super::is_recorded(&json_path, &invocation("-O2")));
+        assert!(!super::is_recorded(&json_path, &invocation("-O3")));
+
+        fs::write(&json_path, "[ ]\n").unwrap();
+        assert!(!super::is_recorded(&json_path, &invocation("-O2")));
+    }
+}
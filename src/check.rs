@@ -0,0 +1,132 @@
+use std::env;
+use std::error::Error;
+use std::path::Path;
+use std::process::Command;
+
+use serde::Serialize;
+
+use crate::args;
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::output;
+use crate::parallel::{self, Outcome, Runner};
+use crate::paths;
+use crate::relocate;
+
+/// Options which name outputs or select a phase other than compiling and are replaced by
+/// `-fsyntax-only`.
+const DROPPED: &[&str] = &[
+    "-c", "-S", "-E", "-o", "-M", "-MM", "-MD", "-MMD", "-MP", "-MF", "-MT", "-MQ", "-MJ",
+];
+
+/// Returns the command line checking the syntax of `entry` without writing any output.
+fn check_args(entry: &Entry) -> Option<Vec<String>> {
+    let (compiler, arguments) = entry.arguments.split_first()?;
+    let mut command = vec![compiler.clone(), "-fsyntax-only".to_string()];
+    for flag in args::flags(arguments) {
+        if !DROPPED.contains(&flag.name.as_str()) {
+            command.extend(flag.args);
+        }
+    }
+    Some(command)
+}
+
+/// Result of checking a single entry.
+#[derive(Debug, Serialize)]
+struct Check {
+    file: String,
+    status: &'static str,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    output: String,
+}
+
+/// Compiles `entry` with `-fsyntax-only` and returns the diagnostics if it fails.
+fn check(entry: &Entry) -> Result<String, String> {
+    let command = check_args(entry).ok_or("entry without compiler")?;
+    let output = Command::new(&command[0])
+        .args(&command[1..])
+        .current_dir(Path::new(&entry.directory))
+        .output()
+        .map_err(|error| format!("cannot run '{}': {}", command[0], error))?;
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    if output.status.success() {
+        Ok(stderr)
+    } else {
+        Err(stderr)
+    }
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(
+        args,
+        &[&["--json"], parallel::RUNNER_FLAGS].concat(),
+        &[&["--db"], parallel::RUNNER_VALUED].concat(),
+    )?;
+    let runner = Runner::from_options(&opts)?;
+    let cwd = env::current_dir()?;
+    let json_path = paths::normalize(&cwd.join(opts.database()));
+    let db_dir = json_path.parent().unwrap_or(&cwd).to_path_buf();
+    let entries: Vec<Entry> = db::read_entries(&json_path)?
+        .iter()
+        .map(|e| relocate::absolutize(e, &db_dir))
+        .filter(|e| {
+            opts.positional.is_empty()
+                || opts
+                    .positional
+                    .iter()
+                    .any(|f| paths::resolve(&cwd.to_string_lossy(), f) == Path::new(&e.file))
+        })
+        .collect();
+
+    let files: Vec<String> = entries.iter().map(|e| e.file.clone()).collect();
+    let outcomes = parallel::run(entries, &runner, |e| e.file.clone(), check);
+    let checks: Vec<Check> = files
+        .into_iter()
+        .zip(&outcomes)
+        .map(|(file, outcome)| match outcome {
+            Outcome::Done(output) => Check {
+                file,
+                status: "ok",
+                output: output.clone(),
+            },
+            Outcome::Failed(output) => Check {
+                file,
+                status: "failed",
+                output: output.clone(),
+            },
+            Outcome::Cancelled => Check {
+                file,
+                status: "cancelled",
+                output: String::new(),
+            },
+        })
+        .collect();
+    if opts.has("--json") {
+        println!("{}", serde_json::to_string_pretty(&checks)?);
+    } else {
+        for c in checks.iter().filter(|c| c.status == "failed") {
+            println!("{}: failed", c.file);
+            print!("{}", c.output);
+        }
+        output::info(&parallel::summary(&outcomes));
+    }
+    if outcomes.iter().any(Outcome::is_failed) {
+        return Err("some entries do not compile".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_args() {
+        let arguments = ["cc", "-MD", "-MF", "a.d", "-DX", "-c", "a.c", "-o", "a.o"];
+        let entry = db::entry("/src", "a.c", &arguments);
+        assert_eq!(
+            super::check_args(&entry).unwrap(),
+            ["cc", "-fsyntax-only", "-DX", "a.c"]
+        );
+    }
+}
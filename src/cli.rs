@@ -0,0 +1,245 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use crate::config::default_database;
+use crate::{
+    artifacts, buildroot, check, codeql, cppcheck, ctags, diff, doctor, enrich, esp_idf, explain,
+    fragment, fresh, githooks, graph, grep, import, index, infer, install, ninja, parse,
+    platformio, protoc, prune, relocate, remote, rewrite, serve, session, setup, shard, shell,
+    sourcetrail, stats, top, vscode, watch, wrap, yocto, zephyr,
+};
+
+/// Command line of a subcommand split into options, positional arguments, and everything
+/// following a `--`.
+#[derive(Debug, Default)]
+pub struct Options {
+    options: Vec<(String, Option<String>)>,
+    pub positional: Vec<String>,
+    pub rest: Vec<String>,
+}
+
+impl Options {
+    /// Parses `args` where `flags` lists the options without and `valued` the options with a
+    /// value.  Values may either be passed as `--opt value` or `--opt=value`.
+    pub fn parse(args: &[String], flags: &[&str], valued: &[&str]) -> Result<Self, Box<dyn Error>> {
+        Self::parse_impl(args, flags, valued, false)
+    }
+
+    /// Like `parse` but for subcommands running another command.  The first positional argument
+    /// starts the command, i.e., it and all following arguments end up in `rest`.
+    pub fn parse_command(
+        args: &[String],
+        flags: &[&str],
+        valued: &[&str],
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::parse_impl(args, flags, valued, true)
+    }
+
+    fn parse_impl(
+        args: &[String],
+        flags: &[&str],
+        valued: &[&str],
+        command: bool,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut result = Options::default();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg == "--" {
+                result.rest = iter.cloned().collect();
+                break;
+            }
+            if !arg.starts_with('-') || arg == "-" {
+                if command {
+                    result.rest = std::iter::once(arg).chain(iter).cloned().collect();
+                    break;
+                }
+                result.positional.push(arg.clone());
+                continue;
+            }
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) if arg.starts_with("--") => (name, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            if flags.contains(&name) && inline_value.is_none() {
+                result.options.push((name.to_string(), None));
+            } else if valued.contains(&name) {
+                let value = match inline_value {
+                    Some(value) => value,
+                    None => iter
+                        .next()
+                        .ok_or_else(|| format!("option '{}' requires a value", name))?
+                        .clone(),
+                };
+                result.options.push((name.to_string(), Some(value)));
+            } else {
+                return Err(format!("unknown option '{}'", arg).into());
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.options.iter().any(|(n, _)| n == name)
+    }
+
+    /// Returns the value of the last occurrence of option `name`.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.values(name).pop()
+    }
+
+    pub fn values(&self, name: &str) -> Vec<&str> {
+        self.options
+            .iter()
+            .filter(|(n, _)| n == name)
+            .filter_map(|(_, v)| v.as_deref())
+            .collect()
+    }
+
+    /// Returns the values of all options in `names` in the order they were given.
+    pub fn ordered(&self, names: &[&str]) -> Vec<(&str, &str)> {
+        self.options
+            .iter()
+            .filter(|(n, _)| names.contains(&n.as_str()))
+            .filter_map(|(n, v)| Some((n.as_str(), v.as_deref()?)))
+            .collect()
+    }
+
+    /// Returns the database given via `--db` or else the one the shims would write to.
+    pub fn database(&self) -> PathBuf {
+        match self.value("--db") {
+            Some(path) => PathBuf::from(path),
+            None => default_database(),
+        }
+    }
+}
+
+/// Printed for `cdbgen help` and when cdbgen is run without arguments.
+const HELP: &str = "\
+usage: cdbgen SUBCOMMAND [ARGS...]
+       cdbgen [--] COMPILER ARGS...
+       cdbgen-COMPILER ARGS...
+
+Records compiler invocations into compile_commands.json.  Invoked as
+cdbgen-COMPILER, e.g. via a symlink cdbgen-gcc, or as COMPILER in a directory in
+front of PATH, it records the invocation and runs the compiler.  Invoked as
+cdbgen followed by a compiler on PATH, it does the same as a launcher.
+
+Setup:
+  setup, install, uninstall, shims, shell-init, wrap, end-build, codeql,
+  doctor, git-hooks, zephyr, esp-idf, platformio, yocto, buildroot
+Database:
+  finalize, watch, prune, relativize, absolutize, rewrite-flags, enrich, export,
+  query, import-intercept, import-bear, cache, serve
+Inspection:
+  stats, top, diff, explain, artifacts, generated, graph, grep-flags,
+  verify-fresh, parse
+Tools:
+  check-compile, cppcheck, infer, ctags, export-ninja, export-vscode,
+  export-sourcetrail, export-artifact, index
+
+See the README for the options of each subcommand.
+";
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match args.first().map(String::as_str) {
+        Some("artifacts") => artifacts::main(&args[1..]),
+        Some("buildroot") => buildroot::main(&args[1..]),
+        Some("cache") => remote::main(&args[1..]),
+        Some("check-compile") => check::main(&args[1..]),
+        Some("codeql") => codeql::main(&args[1..]),
+        Some("cppcheck") => cppcheck::main(&args[1..]),
+        Some("ctags") => ctags::main(&args[1..]),
+        Some("diff") => diff::main(&args[1..]),
+        Some("doctor") => doctor::main(&args[1..]),
+        Some("end-build") => session::main_end_build(&args[1..]),
+        Some("esp-idf") => esp_idf::main(&args[1..]),
+        Some("explain") => explain::main(&args[1..]),
+        Some("enrich") => enrich::main(&args[1..]),
+        Some("export") => shard::main_export(&args[1..]),
+        Some("export-artifact") => relocate::main_export_artifact(&args[1..]),
+        Some("export-ninja") => ninja::main(&args[1..]),
+        Some("export-sourcetrail") => sourcetrail::main(&args[1..]),
+        Some("export-vscode") => vscode::main(&args[1..]),
+        Some("finalize") => fragment::main_finalize(&args[1..]),
+        Some("generated") => protoc::main(&args[1..]),
+        Some("git-hooks") => githooks::main(&args[1..]),
+        Some("graph") => graph::main(&args[1..]),
+        Some("grep-flags") => grep::main(&args[1..]),
+        Some("rewrite-flags") => rewrite::main(&args[1..]),
+        Some("import-bear") => import::main_bear(&args[1..]),
+        Some("import-intercept") => import::main_intercept(&args[1..]),
+        Some("index") => index::main(&args[1..]),
+        Some("infer") => infer::main(&args[1..]),
+        Some("install") => install::main_install(&args[1..]),
+        Some("parse") => parse::main(&args[1..]),
+        Some("platformio") => platformio::main(&args[1..]),
+        Some("prune") => prune::main(&args[1..]),
+        Some("query") => shard::main_query(&args[1..]),
+        Some("relativize") => relocate::main_relativize(&args[1..]),
+        Some("absolutize") => relocate::main_absolutize(&args[1..]),
+        Some("serve") => serve::main(&args[1..]),
+        Some("setup") => setup::main(&args[1..]),
+        Some("shell-init") => shell::main_init(&args[1..]),
+        Some("shims") => install::main_shims(&args[1..]),
+        Some("stats") => stats::main(&args[1..]),
+        Some("top") => top::main(&args[1..]),
+        Some("uninstall") => install::main_uninstall(&args[1..]),
+        Some("verify-fresh") => fresh::main(&args[1..]),
+        Some("watch") => watch::main(&args[1..]),
+        Some("wrap") => wrap::main(&args[1..]),
+        Some("yocto") => yocto::main(&args[1..]),
+        Some("zephyr") => zephyr::main(&args[1..]),
+        Some("--") => crate::launch(&args[1..]),
+        // Used as launcher, e.g. by `CMAKE_CXX_COMPILER_LAUNCHER=cdbgen`.
+        Some(cmd) if which::which(cmd).is_ok() => crate::launch(args),
+        Some("help") | Some("-h") | Some("--help") | None => {
+            print!("{}", HELP);
+            Ok(())
+        }
+        Some(cmd) => Err(format!(
+            "unknown subcommand '{}' and no such compiler on PATH, see 'cdbgen help'",
+            cmd
+        )
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse() {
+        let opts = Options::parse(
+            &strings(&[
+                "-v",
+                "--format=dot",
+                "a",
+                "--db",
+                "x.json",
+                "--",
+                "--format",
+            ]),
+            &["-v"],
+            &["--format", "--db"],
+        )
+        .unwrap();
+        assert!(opts.has("-v"));
+        assert_eq!(opts.value("--format"), Some("dot"));
+        assert_eq!(opts.value("--db"), Some("x.json"));
+        assert_eq!(opts.positional, ["a"]);
+        assert_eq!(opts.rest, ["--format"]);
+
+        assert!(Options::parse(&strings(&["--bogus"]), &[], &[]).is_err());
+
+        let opts = Options::parse_command(&strings(&["--db", "x", "make", "-j8"]), &[], &["--db"])
+            .unwrap();
+        assert_eq!(opts.value("--db"), Some("x"));
+        assert_eq!(opts.rest, ["make", "-j8"]);
+        assert!(Options::parse(&strings(&["--db"]), &[], &["--db"]).is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cli::Options;
+use crate::shell;
+use crate::wrap::{self, Wrapper};
+
+const USAGE: &str =
+    "usage: cdbgen codeql [--db DB] [--language LANG] [--codeql PATH] CODEQL_DB [--] BUILD...";
+
+/// Returns a shell script running `build`.  CodeQL splits the value of `--command` on its own,
+/// so passing a script does not depend on how it treats quotes.
+fn build_script(build: &[String]) -> String {
+    format!("#!/bin/sh\nexec {}\n", shell::join(build))
+}
+
+/// Writes the script running `build` into `dir` and returns the value to pass as `--command`.
+fn command(dir: &Path, build: &[String]) -> Result<String, Box<dyn Error>> {
+    if cfg!(unix) {
+        let script = dir.join("build.sh");
+        fs::write(&script, build_script(build))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+        }
+        Ok(script.to_string_lossy().into_owned())
+    } else {
+        Ok(shell::join(build))
+    }
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse_command(args, &[], &["--db", "--language", "--codeql"])?;
+    let (codeql_db, build) = opts.rest.split_first().ok_or(USAGE)?;
+    let build = match build.split_first() {
+        Some((sep, build)) if sep == "--" => build,
+        _ => build,
+    };
+    if build.is_empty() {
+        return Err(USAGE.into());
+    }
+
+    let mut wrapper = Wrapper::new(&opts.database())?;
+    wrapper.set("CDBGEN_CAPTURE", "codeql");
+    let command = command(wrapper.shim_dir(), build)?;
+    let codeql = opts
+        .value("--codeql")
+        .map_or_else(|| PathBuf::from("codeql"), PathBuf::from);
+    let status = wrapper
+        .command(&codeql)
+        .args(["database", "create", codeql_db.as_str()])
+        .arg(format!(
+            "--language={}",
+            opts.value("--language").unwrap_or("cpp")
+        ))
+        .arg(format!("--command={}", command))
+        .status()
+        .map_err(|error| format!("cannot run '{}': {}", codeql.display(), error))?;
+    drop(wrapper);
+    if !status.success() {
+        wrap::exit_like(status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn build_script() {
+        let build = ["make", "-j8", "CFLAGS=-O2 -g"].map(String::from);
+        assert_eq!(
+            super::build_script(&build),
+            "#!/bin/sh\nexec make -j8 'CFLAGS=-O2 -g'\n"
+        );
+    }
+}
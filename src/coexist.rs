@@ -0,0 +1,141 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::db::{self, Entry};
+use crate::fragment;
+use crate::paths;
+
+/// Database the entries owned by cdbgen are recorded into if the one at `json_path` is maintained
+/// by CMake, e.g. `compile_commands.cdbgen.json` for `compile_commands.json`.
+pub fn owned(json_path: &Path) -> PathBuf {
+    json_path.with_extension("cdbgen.json")
+}
+
+/// File remembering the entries cdbgen added to the database at `json_path` by the last `sync`,
+/// i.e., the ones any other entry is told from as one of CMake.
+fn added_path(json_path: &Path) -> PathBuf {
+    let name = json_path.with_extension("coexist.json");
+    fragment::dir(json_path).join(name.file_name().unwrap_or_default())
+}
+
+/// Returns the source an entry of a database refers to, if any.  CMake spells entries with a
+/// `command` string which cdbgen does not parse, i.e., such entries are kept as they are.
+fn source(entry: &Value) -> Option<PathBuf> {
+    let directory = entry.get("directory")?.as_str()?;
+    let file = entry.get("file")?.as_str()?;
+    Some(paths::resolve(directory, file))
+}
+
+/// Returns the entries of the database maintained by CMake with the entries of `owned` for the
+/// sources not covered by CMake, together with the entries added.  Entries of `entries` which
+/// are not the ones cdbgen added before (`added`) are taken to be written by CMake, i.e., are
+/// kept as they are, even if cdbgen owns an entry for the same source, and survive a
+/// regeneration by CMake which drops the entries of cdbgen.
+fn reconcile(
+    entries: Vec<Value>,
+    owned: &BTreeSet<Entry>,
+    added: &[Value],
+) -> Result<(Vec<Value>, Vec<Value>), Box<dyn Error>> {
+    let mut result: Vec<Value> = entries.into_iter().filter(|e| !added.contains(e)).collect();
+    let covered: BTreeSet<PathBuf> = result.iter().filter_map(source).collect();
+    let mut added = Vec::new();
+    for entry in owned {
+        if !covered.contains(&paths::resolve(&entry.directory, &entry.file)) {
+            added.push(serde_json::to_value(entry)?);
+        }
+    }
+    result.extend(added.iter().cloned());
+    Ok((result, added))
+}
+
+/// Adds the entries owned by cdbgen to the database at `json_path` maintained by CMake for the
+/// sources CMake does not cover, e.g. of custom commands, and drops the ones added before which
+/// are outdated.  The entries of CMake are left alone.
+pub fn sync(json_path: &Path) -> Result<(), Box<dyn Error>> {
+    let owned = db::read_entries(&owned(json_path))?;
+    let added_path = added_path(json_path);
+    db::update_json(json_path, |entries: &mut Vec<Value>| {
+        let added: Vec<Value> = match fs::read_to_string(&added_path) {
+            Ok(data) => serde_json::from_str(&data)?,
+            Err(error) if error.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error.into()),
+        };
+        let (new_entries, added) = reconcile(std::mem::take(entries), &owned, &added)?;
+        *entries = new_entries;
+        if let Some(dir) = added_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&added_path, serde_json::to_string(&added)? + "\n")?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sync() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let json_path = temp.path().join("compile_commands.json");
+        let dir = temp.path().to_str().unwrap();
+        let cmake = |files: &[&str]| {
+            let entries: Vec<Value> = files
+                .iter()
+                .map(|file| {
+                    serde_json::json!({
+                        "directory": dir,
+                        "command": format!("/usr/bin/cc -c {}/{}", dir, file),
+                        "file": format!("{}/{}", dir, file),
+                    })
+                })
+                .collect();
+            fs::write(&json_path, serde_json::to_string_pretty(&entries).unwrap()).unwrap();
+        };
+        let record = |files: &[&str], flag: &str| {
+            let entries: BTreeSet<Entry> = files
+                .iter()
+                .map(|file| db::entry(dir, file, &["cc", flag, file]))
+                .collect();
+            fs::write(owned(&json_path), serde_json::to_string(&entries).unwrap()).unwrap();
+        };
+        let files = || {
+            let data = fs::read_to_string(&json_path).unwrap();
+            let entries: Vec<Value> = serde_json::from_str(&data).unwrap();
+            entries
+                .iter()
+                .map(|e| {
+                    let spelling = e.get("command").or_else(|| e["arguments"].get(1));
+                    let file = Path::new(e["file"].as_str().unwrap()).file_name().unwrap();
+                    format!("{} {}", file.to_string_lossy(), spelling.unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        cmake(&["a.c"]);
+        record(&["a.c", "gen.c"], "-O1");
+        super::sync(&json_path).unwrap();
+        let a = format!("a.c \"/usr/bin/cc -c {}/a.c\"", dir);
+        assert_eq!(files(), [a.clone(), "gen.c \"-O1\"".to_string()]);
+
+        // Updates replace the entries of cdbgen only.
+        record(&["a.c", "gen.c"], "-O2");
+        super::sync(&json_path).unwrap();
+        assert_eq!(files(), [a.clone(), "gen.c \"-O2\"".to_string()]);
+
+        // CMake regenerates the database and now covers gen.c itself.
+        cmake(&["a.c", "gen.c"]);
+        super::sync(&json_path).unwrap();
+        let gen = format!("gen.c \"/usr/bin/cc -c {}/gen.c\"", dir);
+        assert_eq!(files(), [a.clone(), gen.clone()]);
+
+        cmake(&["a.c"]);
+        super::sync(&json_path).unwrap();
+        assert_eq!(files(), [a, "gen.c \"-O2\"".to_string()]);
+    }
+}
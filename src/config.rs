@@ -0,0 +1,524 @@
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::android;
+use crate::output;
+use crate::paths;
+use crate::presets;
+use crate::prune;
+
+/// Returns true if the environment variable `name` is set to a truthy value.
+pub fn env_flag(name: &str) -> bool {
+    matches!(
+        env::var(name).as_deref(),
+        Ok("1") | Ok("yes") | Ok("true") | Ok("on")
+    )
+}
+
+thread_local! {
+    static INVALID: RefCell<BTreeSet<String>> = RefCell::new(BTreeSet::new());
+}
+
+/// Returns the environment variable `name` parsed by `parse`, if set.  An invalid value is
+/// warned about once and ignored, i.e., the default applies.
+fn env_parsed<T, E, F>(name: &str, parse: F) -> Option<T>
+where
+    E: Display,
+    F: FnOnce(&str) -> Result<T, E>,
+{
+    let value = env::var(name).ok()?;
+    match parse(&value) {
+        Ok(parsed) => Some(parsed),
+        Err(error) => {
+            let message = format!("ignoring {}={}: {}", name, value, error);
+            if INVALID.with(|invalid| invalid.borrow_mut().insert(message.clone())) {
+                output::warn(&message);
+            }
+            None
+        }
+    }
+}
+
+/// Returns the warnings about the invalid settings read so far, e.g. to fail with
+/// `CDBGEN_STRICT`.
+pub fn invalid() -> Vec<String> {
+    INVALID.with(|invalid| invalid.borrow().iter().cloned().collect())
+}
+
+/// Files marking the root of a project if roots are discovered automatically.
+const DEFAULT_MARKERS: &[&str] = &[".cdbgen-root", ".git"];
+
+/// A build session, i.e., one run of a build of a target.  Entries recorded by a session
+/// supersede all entries recorded by previous sessions of the same build.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    /// Names the build, e.g. a target, across sessions (`CDBGEN_BUILD_ID`).
+    pub build_id: String,
+    /// Identifies this session (`CDBGEN_SESSION`).
+    pub id: String,
+}
+
+/// What to do if a file is recorded again with different arguments (`CDBGEN_ON_CONFLICT`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conflict {
+    /// Replace the old entry by the new one.
+    Replace,
+    /// Keep the old entry next to the new one.
+    Keep,
+    /// Replace the old entry and print a warning.
+    Warn,
+    /// Fail the compiler invocation and leave the database untouched.
+    Fail,
+}
+
+impl FromStr for Conflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "replace" => Ok(Conflict::Replace),
+            "keep" => Ok(Conflict::Keep),
+            "warn" => Ok(Conflict::Warn),
+            "fail" => Ok(Conflict::Fail),
+            _ => Err(format!("unknown conflict policy '{}'", s)),
+        }
+    }
+}
+
+/// How the paths of source files are recorded (`CDBGEN_PATHS`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spelling {
+    /// As given on the command line.
+    AsSpelled,
+    /// Absolute but otherwise as given, e.g. for out-of-tree builds referring to sources as
+    /// `../../src/foo.c`.  The directory stays the build directory.
+    Absolute,
+    /// Absolute with symbolic links resolved such that every file has a single entry.
+    Canonical,
+    /// As given on the command line with the canonical path noted in the metadata sidecar.
+    Linked,
+}
+
+impl FromStr for Spelling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "as-spelled" => Ok(Spelling::AsSpelled),
+            "absolute" => Ok(Spelling::Absolute),
+            "canonical" => Ok(Spelling::Canonical),
+            "linked" => Ok(Spelling::Linked),
+            _ => Err(format!("unknown path spelling '{}'", s)),
+        }
+    }
+}
+
+/// Triggers of the automatic compaction of fragments.  Any of them starts a compaction in the
+/// background.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Compaction {
+    /// Number of pending fragments (`CDBGEN_COMPACT_FRAGMENTS`).
+    pub fragments: Option<usize>,
+    /// Total size of pending fragments in bytes (`CDBGEN_COMPACT_SIZE`, e.g. `10M`).
+    pub bytes: Option<u64>,
+    /// Seconds without a new fragment (`CDBGEN_COMPACT_IDLE`, e.g. `30s`).
+    pub idle: Option<u64>,
+    /// Seconds a compaction waiting for the build to become idle waits at most
+    /// (`CDBGEN_COMPACT_MAX_STALENESS`).
+    pub max_staleness: Option<u64>,
+}
+
+/// Parses a size like `512`, `64k`, `10M`, or `1G` into bytes.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let split = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", size))?;
+    let factor = match unit {
+        "" => 1,
+        "k" | "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(format!("invalid unit of size '{}'", size)),
+    };
+    number
+        .checked_mul(factor)
+        .ok_or_else(|| format!("size '{}' is too large", size))
+}
+
+/// How the mappings of `-ffile-prefix-map` and friends are applied to recorded paths
+/// (`CDBGEN_PREFIX_MAP`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefixMap {
+    /// Paths are recorded as seen by the compiler.
+    Off,
+    /// Paths are mapped the way the compiler maps them in its output, i.e., from `OLD` to `NEW`.
+    Apply,
+    /// Paths which were mapped before are mapped back, i.e., from `NEW` to `OLD`.
+    Invert,
+}
+
+impl FromStr for PrefixMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(PrefixMap::Off),
+            "apply" => Ok(PrefixMap::Apply),
+            "invert" => Ok(PrefixMap::Invert),
+            _ => Err(format!("unknown prefix map mode '{}'", s)),
+        }
+    }
+}
+
+/// How compilers whose environment adds flags, e.g. the cc-wrapper of Nix, are recorded
+/// (`CDBGEN_CC_WRAPPER`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CcWrapper {
+    /// The compiler is recorded as invoked.
+    Keep,
+    /// The flags added from the environment are recorded, too.
+    Flags,
+    /// Like `Flags` but the compiler wrapped by the cc-wrapper is recorded instead of it.
+    Unwrap,
+}
+
+impl FromStr for CcWrapper {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(CcWrapper::Keep),
+            "flags" => Ok(CcWrapper::Flags),
+            "unwrap" => Ok(CcWrapper::Unwrap),
+            _ => Err(format!("unknown cc-wrapper mode '{}'", s)),
+        }
+    }
+}
+
+/// How the paths of include options are recorded (`CDBGEN_INCLUDE_PATHS`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncludePaths {
+    /// As given on the command line.
+    AsSpelled,
+    /// Relative paths resolved against the directory of the compile.
+    Absolute,
+}
+
+impl FromStr for IncludePaths {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "as-spelled" => Ok(IncludePaths::AsSpelled),
+            "absolute" => Ok(IncludePaths::Absolute),
+            _ => Err(format!("unknown include path spelling '{}'", s)),
+        }
+    }
+}
+
+/// How response files of compiles are recorded (`CDBGEN_RESPONSE_FILES`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFiles {
+    /// As `@file` arguments.
+    Keep,
+    /// Replaced by the arguments they contain.
+    Expand,
+}
+
+impl FromStr for ResponseFiles {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(ResponseFiles::Keep),
+            "expand" => Ok(ResponseFiles::Expand),
+            _ => Err(format!("unknown response file mode '{}'", s)),
+        }
+    }
+}
+
+/// How the entries of compile-and-link invocations are recorded (`CDBGEN_LINK_OUTPUT`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkOutput {
+    /// With the link-only arguments as invoked.
+    Keep,
+    /// Reduced to compiling each source alone.
+    Strip,
+}
+
+impl FromStr for LinkOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(LinkOutput::Keep),
+            "strip" => Ok(LinkOutput::Strip),
+            _ => Err(format!("unknown link output mode '{}'", s)),
+        }
+    }
+}
+
+/// Which build system maintains the database, if any, whose entries are left alone
+/// (`CDBGEN_COEXIST`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coexist {
+    /// cdbgen maintains the database itself.
+    Off,
+    /// CMake maintains the database, i.e., entries are recorded next to it and only added for
+    /// the sources CMake does not cover.
+    Cmake,
+}
+
+impl FromStr for Coexist {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Coexist::Off),
+            "cmake" => Ok(Coexist::Cmake),
+            _ => Err(format!("unknown build system to coexist with '{}'", s)),
+        }
+    }
+}
+
+/// How the entries of compiles are stored (`CDBGEN_STORAGE`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Storage {
+    /// In the database itself.
+    File,
+    /// In per-directory shards instead of a single file.
+    Sharded,
+    /// In a fragment per compile in `.cdbgen/` which is merged into the database when it is
+    /// read.
+    Fragments,
+    /// In a staging buffer in shared memory which is drained into the database when it is read.
+    Shm,
+}
+
+impl Storage {
+    /// Returns whether the entries are merged into the database only when it is read.
+    pub fn is_deferred(self) -> bool {
+        matches!(self, Storage::Fragments | Storage::Shm)
+    }
+}
+
+impl FromStr for Storage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(Storage::File),
+            "sharded" => Ok(Storage::Sharded),
+            "fragments" => Ok(Storage::Fragments),
+            "shm" => Ok(Storage::Shm),
+            _ => Err(format!("unknown storage '{}'", s)),
+        }
+    }
+}
+
+/// Settings of the recorder which are taken from the environment.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Database entries are recorded into (`CDBGEN`).
+    pub database: PathBuf,
+    /// Record the provenance of entries in the metadata sidecar (`CDBGEN_META`).
+    pub meta: bool,
+    /// Globs naming the variables of the environment noted per entry in the metadata sidecar
+    /// (`CDBGEN_ENVIRONMENT`).
+    pub environment: Vec<String>,
+    /// Run the compiler as child process and record how long it took in the metadata sidecar
+    /// (`CDBGEN_TIMING`).
+    pub timing: bool,
+    /// Further databases every entry is recorded into as well, e.g. a machine-wide aggregate
+    /// (`CDBGEN_ALSO`).
+    pub also: Vec<PathBuf>,
+    /// Project roots each having a database of their own (`CDBGEN_ROOTS`).
+    pub roots: Vec<PathBuf>,
+    /// Files marking a project root, if roots are discovered automatically (`CDBGEN_ROUTE`).  The
+    /// markers may be overridden by `CDBGEN_ROOT_MARKERS`.
+    pub markers: Vec<String>,
+    /// How the entries of compiles are stored.
+    pub storage: Storage,
+    /// Directory entries of compiles run below it are recorded with instead of the one they run
+    /// in, relative to the directory of the database (`CDBGEN_REBASE`).
+    pub rebase: Option<PathBuf>,
+    /// Which build system maintains the database, if any.
+    pub coexist: Coexist,
+    /// Record in a child process detached from the build while the compiler already runs
+    /// (`CDBGEN_BACKGROUND`).
+    pub background: bool,
+    /// When fragments are compacted in the background, if ever.
+    pub compaction: Compaction,
+    /// Record the build session of entries in the metadata sidecar.
+    pub session: Option<Session>,
+    /// Remove entries not seen for this many seconds (`CDBGEN_PRUNE_AFTER`, e.g. `30d`).
+    pub prune_after: Option<u64>,
+    /// File in which every recorded entry is marked for a later sweep (`CDBGEN_MARK`).
+    pub mark: Option<PathBuf>,
+    /// How entries are captured as noted in the header of the sidecar (`CDBGEN_CAPTURE`).
+    pub capture: String,
+    /// What to do if the arguments of an entry change.
+    pub on_conflict: Conflict,
+    /// How the paths of source files are recorded.
+    pub spelling: Spelling,
+    /// How prefix maps of the command line apply to the recorded file and directory.
+    pub prefix_map: PrefixMap,
+    /// How the paths of include options are recorded.
+    pub include_paths: IncludePaths,
+    /// How response files are recorded, e.g. expanded since build tools like Ninja remove them
+    /// after the compile.
+    pub response_files: ResponseFiles,
+    /// Record only the compiles for this ABI of Android, or each ABI into a database of its own
+    /// if `split` (`CDBGEN_ANDROID_ABI`).
+    pub android_abi: Option<String>,
+    /// How compilers whose environment adds flags are recorded (`CDBGEN_CC_WRAPPER`).
+    pub cc_wrapper: CcWrapper,
+    /// The base directory of `ccache` below which it makes paths relative (`CCACHE_BASEDIR`),
+    /// if the paths are to be made absolute again (`CDBGEN_RESTORE_BASEDIR`).
+    pub ccache_basedir: Option<PathBuf>,
+    /// Embed the implicit include directories and the target of the compiler into the entries
+    /// (`CDBGEN_IMPLICIT_INCLUDES`).
+    pub implicit_includes: bool,
+    /// Translate the flags of toolchains clang does not understand by the preset of this name,
+    /// or of the toolchain of the compiler for `auto` (`CDBGEN_PRESET`).
+    pub preset: Option<String>,
+    /// Presets of the compiles in directories, relative to the database, overriding `preset`
+    /// (`CDBGEN_PRESET_DIRS`, e.g. `boot=avr,fw=iar`).
+    pub preset_dirs: Vec<(PathBuf, String)>,
+    /// Record link commands into `link_commands.json` (`CDBGEN_LINKS`).
+    pub links: bool,
+    /// Whether the link-only arguments are stripped from the entries of compile-and-link
+    /// invocations.
+    pub link_output: LinkOutput,
+    /// Record the headers of each translation unit from its dependency file into
+    /// `header_deps.json` after the compile (`CDBGEN_HEADER_DEPS`).
+    pub header_deps: bool,
+    /// Record the C++20 modules each translation unit provides and imports into `modules.json`
+    /// (`CDBGEN_MODULES`).
+    pub modules: bool,
+    /// Record the `.proto` files compiled by `protoc` and the sources generated from them into
+    /// `proto_sources.json` (`CDBGEN_PROTOC`).
+    pub protoc: bool,
+    /// Record invocations of resource compilers like `windres` into `resource_commands.json`
+    /// (`CDBGEN_RESOURCES`).
+    pub resources: bool,
+    /// Globs naming tools like code generators whose commands are recorded into
+    /// `tool_commands.json` (`CDBGEN_TOOLS`).
+    pub tools: Vec<String>,
+    /// Skip recording entries which are known to be in the database already by a cache per
+    /// directory (`CDBGEN_RECORD_CACHE`).
+    pub record_cache: bool,
+    /// Merge into the database while streaming it with about this many bytes of entries in
+    /// memory at most (`CDBGEN_MEMORY_LIMIT`, e.g. `256M`).
+    pub memory_limit: Option<u64>,
+    /// Fail the compile if recording fails instead of warning and compiling anyway
+    /// (`CDBGEN_STRICT`).
+    pub strict: bool,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Config {
+            database: default_database(),
+            meta: env_flag("CDBGEN_META"),
+            environment: env::var("CDBGEN_ENVIRONMENT")
+                .map(|names| {
+                    names
+                        .split(',')
+                        .filter(|n| !n.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            timing: env_flag("CDBGEN_TIMING"),
+            also: env::var_os("CDBGEN_ALSO")
+                .map(|also| env::split_paths(&also).collect())
+                .unwrap_or_default(),
+            roots: env::var_os("CDBGEN_ROOTS")
+                .map(|roots| env::split_paths(&roots).collect())
+                .unwrap_or_default(),
+            markers: if env_flag("CDBGEN_ROUTE") {
+                match env::var("CDBGEN_ROOT_MARKERS") {
+                    Ok(markers) => markers.split(',').map(String::from).collect(),
+                    Err(_) => DEFAULT_MARKERS.iter().map(|m| m.to_string()).collect(),
+                }
+            } else {
+                Vec::new()
+            },
+            storage: env_parsed("CDBGEN_STORAGE", str::parse).unwrap_or(Storage::File),
+            background: env_flag("CDBGEN_BACKGROUND"),
+            rebase: env::var_os("CDBGEN_REBASE")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from),
+            coexist: env_parsed("CDBGEN_COEXIST", str::parse).unwrap_or(Coexist::Off),
+            compaction: Compaction {
+                fragments: env_parsed("CDBGEN_COMPACT_FRAGMENTS", str::parse),
+                bytes: env_parsed("CDBGEN_COMPACT_SIZE", parse_size),
+                idle: env_parsed("CDBGEN_COMPACT_IDLE", prune::parse_age),
+                max_staleness: env_parsed("CDBGEN_COMPACT_MAX_STALENESS", prune::parse_age),
+            },
+            session: env::var("CDBGEN_BUILD_ID").ok().map(|build_id| Session {
+                build_id,
+                id: env::var("CDBGEN_SESSION").unwrap_or_default(),
+            }),
+            prune_after: env_parsed("CDBGEN_PRUNE_AFTER", prune::parse_age),
+            mark: env::var_os("CDBGEN_MARK").map(PathBuf::from),
+            capture: env::var("CDBGEN_CAPTURE").unwrap_or_else(|_| "shim".to_string()),
+            on_conflict: env_parsed("CDBGEN_ON_CONFLICT", str::parse).unwrap_or(Conflict::Replace),
+            spelling: env_parsed("CDBGEN_PATHS", str::parse).unwrap_or(Spelling::AsSpelled),
+            prefix_map: env_parsed("CDBGEN_PREFIX_MAP", str::parse).unwrap_or(PrefixMap::Off),
+            include_paths: env_parsed("CDBGEN_INCLUDE_PATHS", str::parse)
+                .unwrap_or(IncludePaths::AsSpelled),
+            response_files: env_parsed("CDBGEN_RESPONSE_FILES", str::parse)
+                .unwrap_or(ResponseFiles::Keep),
+            android_abi: env_parsed("CDBGEN_ANDROID_ABI", android::parse_selection),
+            cc_wrapper: env_parsed("CDBGEN_CC_WRAPPER", str::parse).unwrap_or(CcWrapper::Keep),
+            ccache_basedir: env::var_os("CCACHE_BASEDIR")
+                .filter(|_| env_flag("CDBGEN_RESTORE_BASEDIR"))
+                .map(PathBuf::from)
+                .filter(|basedir| basedir.is_absolute())
+                .map(|basedir| paths::normalize(&basedir)),
+            implicit_includes: env_flag("CDBGEN_IMPLICIT_INCLUDES"),
+            preset: env::var("CDBGEN_PRESET").ok().filter(|p| !p.is_empty()),
+            preset_dirs: env_parsed("CDBGEN_PRESET_DIRS", presets::parse_dirs).unwrap_or_default(),
+            links: env_flag("CDBGEN_LINKS"),
+            link_output: env_parsed("CDBGEN_LINK_OUTPUT", str::parse).unwrap_or(LinkOutput::Keep),
+            header_deps: env_flag("CDBGEN_HEADER_DEPS"),
+            modules: env_flag("CDBGEN_MODULES"),
+            protoc: env_flag("CDBGEN_PROTOC"),
+            resources: env_flag("CDBGEN_RESOURCES"),
+            tools: env::var("CDBGEN_TOOLS")
+                .map(|tools| tools.split(',').map(String::from).collect())
+                .unwrap_or_default(),
+            record_cache: env_flag("CDBGEN_RECORD_CACHE"),
+            memory_limit: env_parsed("CDBGEN_MEMORY_LIMIT", parse_size),
+            strict: env_flag("CDBGEN_STRICT"),
+        }
+    }
+}
+
+impl Config {
+    /// Returns true if anything has to be recorded in the metadata sidecar.
+    pub fn records_meta(&self) -> bool {
+        self.meta
+            || !self.environment.is_empty()
+            || self.timing
+            || self.session.is_some()
+            || self.prune_after.is_some()
+            || self.spelling == Spelling::Linked
+    }
+}
+
+pub fn default_database() -> PathBuf {
+    env::var_os("CDBGEN")
+        .unwrap_or_else(|| "compile_commands.json".into())
+        .into()
+}
@@ -0,0 +1,261 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Serialize;
+
+use crate::args;
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::parallel::{self, Outcome, Runner};
+use crate::paths;
+use crate::relocate;
+use crate::vscode;
+
+/// Language standards Cppcheck knows about.
+const STANDARDS: &[&str] = &[
+    "c89", "c99", "c11", "c++03", "c++11", "c++14", "c++17", "c++20",
+];
+
+/// Separates the fields of a finding in the output of Cppcheck.
+const SEP: &str = "\u{1f}";
+
+/// Maps the value of `-std=` to a standard Cppcheck accepts, if any.
+fn standard(std: &str) -> Option<String> {
+    let std = vscode::standard(std);
+    let std = match std.strip_prefix("gnu") {
+        Some(version) => format!("c{}", version),
+        None => std,
+    };
+    let std = match std.as_str() {
+        "c90" | "iso9899:1990" => "c89".to_string(),
+        "c++98" => "c++03".to_string(),
+        "c17" | "c23" => "c11".to_string(),
+        "c++23" => "c++20".to_string(),
+        _ => std,
+    };
+    if STANDARDS.contains(&std.as_str()) {
+        Some(std)
+    } else {
+        None
+    }
+}
+
+/// Returns the flags of `entry` Cppcheck understands, i.e., macros, include paths, the language
+/// standard, and the data model.  Everything else, e.g. GCC-only options Cppcheck chokes on, is
+/// dropped.  Paths are made absolute.
+fn sanitize(entry: &Entry) -> Vec<args::Flag> {
+    let arguments = entry.arguments.get(1..).unwrap_or_default();
+    let mut result = Vec::new();
+    for mut flag in args::flags(arguments) {
+        match flag.name.as_str() {
+            "-D" | "-U" => (),
+            "-I" | "-isystem" | "-iquote" | "-idirafter" | "-include" => {
+                if let Some(path) = &flag.value {
+                    let path = paths::resolve(&entry.directory, path);
+                    flag.value = Some(path.to_string_lossy().into_owned());
+                }
+            }
+            "-m32" | "-m64" => (),
+            name if name.starts_with("-std=") => match standard(&name["-std=".len()..]) {
+                Some(std) => flag.name = format!("-std={}", std),
+                None => continue,
+            },
+            _ => continue,
+        }
+        if flag.value.is_some() || flag.name.starts_with("-m") || flag.name.starts_with("-std=") {
+            result.push(flag);
+        }
+    }
+    result
+}
+
+/// Returns the arguments of a Cppcheck run checking `entry`.
+fn cppcheck_args(entry: &Entry) -> Vec<String> {
+    let mut result = Vec::new();
+    let arguments = entry.arguments.get(1..).unwrap_or_default();
+    match args::language(arguments, &entry.file).as_deref() {
+        Some("c") => result.push("--language=c".to_string()),
+        Some("c++") => result.push("--language=c++".to_string()),
+        _ => (),
+    }
+    for flag in sanitize(entry) {
+        let value = flag.value.unwrap_or_default();
+        result.push(match flag.name.as_str() {
+            "-D" => format!("-D{}", value),
+            "-U" => format!("-U{}", value),
+            "-include" => format!("--include={}", value),
+            "-m32" => "--platform=unix32".to_string(),
+            "-m64" => "--platform=unix64".to_string(),
+            name if name.starts_with("-std=") => format!("-{}", name),
+            _ => format!("-I{}", value),
+        });
+    }
+    result
+}
+
+/// A single finding as reported by Cppcheck.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+struct Finding {
+    file: String,
+    line: u64,
+    column: u64,
+    severity: String,
+    id: String,
+    message: String,
+}
+
+fn parse_findings(output: &str) -> Vec<Finding> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.strip_prefix("cdbgen")?.strip_prefix(SEP)?.split(SEP);
+            Some(Finding {
+                file: fields.next()?.to_string(),
+                line: fields.next()?.parse().unwrap_or(0),
+                column: fields.next()?.parse().unwrap_or(0),
+                severity: fields.next()?.to_string(),
+                id: fields.next()?.to_string(),
+                message: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(
+        args,
+        &[&["--json", "--fail"], parallel::RUNNER_FLAGS].concat(),
+        &[&["--db", "--cppcheck", "--export"], parallel::RUNNER_VALUED].concat(),
+    )?;
+    let cwd = env::current_dir()?;
+    let json_path = paths::normalize(&cwd.join(opts.database()));
+    let db_dir = json_path.parent().unwrap_or(&cwd).to_path_buf();
+    let entries: Vec<Entry> = db::read_entries(&json_path)?
+        .iter()
+        .map(|e| relocate::absolutize(e, &db_dir))
+        .collect();
+
+    if let Some(export) = opts.value("--export") {
+        let sanitized: BTreeSet<Entry> = entries
+            .into_iter()
+            .map(|e| {
+                let mut arguments: Vec<String> = e.arguments.iter().take(1).cloned().collect();
+                arguments.extend(sanitize(&e).iter().map(args::Flag::joined));
+                arguments.push(e.file.clone());
+                Entry { arguments, ..e }
+            })
+            .collect();
+        fs::write(export, serde_json::to_string_pretty(&sanitized)? + "\n")?;
+        return Ok(());
+    }
+
+    let cppcheck = PathBuf::from(opts.value("--cppcheck").unwrap_or("cppcheck"));
+    let runner = Runner::from_options(&opts)?;
+    let extra = opts.rest.clone();
+    let label = |e: &Entry| e.file.clone();
+    let runs = parallel::run(entries, &runner, label, move |e| {
+        let output = Command::new(&cppcheck)
+            .arg("--quiet")
+            .arg(format!(
+                "--template=cdbgen{0}{{file}}{0}{{line}}{0}{{column}}{0}{{severity}}{0}{{id}}{0}{{message}}",
+                SEP
+            ))
+            .args(cppcheck_args(e))
+            .args(&extra)
+            .arg(&e.file)
+            .current_dir(Path::new(&e.directory))
+            .output()
+            .map_err(|error| format!("cannot run '{}': {}", cppcheck.display(), error))?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let mut findings = parse_findings(&stderr);
+        for f in &mut findings {
+            f.file = paths::resolve(&e.directory, &f.file)
+                .to_string_lossy()
+                .into_owned();
+        }
+        Ok(findings)
+    });
+
+    // Headers are checked along with every unit including them, hence, report each finding once.
+    let mut findings = BTreeSet::new();
+    for run in runs {
+        match run {
+            Outcome::Done(f) => findings.extend(f),
+            Outcome::Failed(error) => return Err(error.into()),
+            Outcome::Cancelled => (),
+        }
+    }
+    if opts.has("--json") {
+        println!("{}", serde_json::to_string_pretty(&findings)?);
+    } else {
+        for f in &findings {
+            println!(
+                "{}:{}:{}: {}: {} [{}]",
+                f.file, f.line, f.column, f.severity, f.message, f.id
+            );
+        }
+    }
+    if opts.has("--fail") && !findings.is_empty() {
+        return Err(format!("{} finding(s)", findings.len()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cppcheck_args() {
+        let entry = db::entry(
+            "/proj",
+            "a.cc",
+            &[
+                "g++",
+                "-DFOO=1",
+                "-I",
+                "inc",
+                "-std=gnu++1z",
+                "-fno-semantic-interposition",
+                "-Wl,--gc-sections",
+                "-m32",
+                "-include",
+                "config.h",
+                "-c",
+                "a.cc",
+            ],
+        );
+        assert_eq!(
+            super::cppcheck_args(&entry),
+            [
+                "--language=c++",
+                "-DFOO=1",
+                "-I/proj/inc",
+                "--std=c++17",
+                "--platform=unix32",
+                "--include=/proj/config.h",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_findings() {
+        let output = "Checking a.c ...\n\
+                      cdbgen\u{1f}a.c\u{1f}3\u{1f}5\u{1f}error\u{1f}nullPointer\u{1f}Null pointer dereference\n";
+        assert_eq!(
+            super::parse_findings(output),
+            [Finding {
+                file: "a.c".to_string(),
+                line: 3,
+                column: 5,
+                severity: "error".to_string(),
+                id: "nullPointer".to_string(),
+                message: "Null pointer dereference".to_string(),
+            }]
+        );
+    }
+}
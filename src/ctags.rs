@@ -0,0 +1,199 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::args;
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::paths;
+use crate::relocate;
+
+const HEADER_EXTENSIONS: &[&str] = &["h", "hh", "hpp", "hxx", "h++", "inl"];
+
+/// Returns the macros defined by `entry` in the form universal-ctags expects for `-D`.
+fn macros(entry: &Entry) -> Vec<String> {
+    let mut macros: BTreeMap<String, String> = BTreeMap::new();
+    for flag in args::flags(entry.arguments.get(1..).unwrap_or_default()) {
+        match (flag.name.as_str(), flag.value) {
+            ("-D", Some(define)) => {
+                let (name, value) = define.split_once('=').unwrap_or((&define, "1"));
+                macros.insert(name.to_string(), value.to_string());
+            }
+            ("-U", Some(name)) => {
+                macros.remove(&name);
+            }
+            _ => (),
+        }
+    }
+    macros
+        .into_iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect()
+}
+
+/// Returns the headers residing directly in the user include directories of `entry`.
+fn headers(entry: &Entry) -> Vec<PathBuf> {
+    let mut headers = Vec::new();
+    for flag in args::flags(entry.arguments.get(1..).unwrap_or_default()) {
+        let dir = match (flag.name.as_str(), flag.value) {
+            ("-I" | "-iquote", Some(dir)) => paths::resolve(&entry.directory, &dir),
+            _ => continue,
+        };
+        let dir_entries = match fs::read_dir(&dir) {
+            Ok(dir_entries) => dir_entries,
+            Err(_) => continue,
+        };
+        for e in dir_entries.flatten() {
+            let path = e.path();
+            let is_header = path.extension().map_or(false, |ext| {
+                HEADER_EXTENSIONS.contains(&&*ext.to_string_lossy())
+            });
+            if is_header && path.is_file() {
+                headers.push(path);
+            }
+        }
+    }
+    headers
+}
+
+/// Groups the files to be tagged by the macros they are compiled with such that ctags has to be
+/// run once per distinct set of macros only.  Each file is tagged once, i.e., a file compiled
+/// with different macros ends up in the first group only.
+fn groups(entries: &BTreeSet<Entry>, with_headers: bool) -> BTreeMap<Vec<String>, Vec<PathBuf>> {
+    let mut groups: BTreeMap<Vec<String>, Vec<PathBuf>> = BTreeMap::new();
+    for e in entries {
+        let files = groups.entry(macros(e)).or_default();
+        files.push(paths::resolve(&e.directory, &e.file));
+        if with_headers {
+            files.extend(headers(e));
+        }
+    }
+    let mut seen = BTreeSet::new();
+    for files in groups.values_mut() {
+        files.retain(|f| seen.insert(f.clone()));
+    }
+    groups.retain(|_, files| !files.is_empty());
+    groups
+}
+
+/// Merges the tag lines produced by several runs of ctags into a single sorted tags file.
+fn merge(outputs: &[String]) -> String {
+    let mut lines: Vec<&str> = outputs
+        .iter()
+        .flat_map(|o| o.lines())
+        .filter(|l| !l.is_empty() && !l.starts_with("!_"))
+        .collect();
+    lines.sort_unstable();
+    lines.dedup();
+    let mut tags = String::from(
+        "!_TAG_FILE_FORMAT\t2\t/extended format/\n\
+         !_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted, 2=foldcase/\n",
+    );
+    for l in lines {
+        tags.push_str(l);
+        tags.push('\n');
+    }
+    tags
+}
+
+/// Runs ctags on `files` with `macros` defined and returns the produced tag lines.
+fn run_ctags(
+    ctags: &Path,
+    dir: &Path,
+    macros: &[String],
+    files: &[PathBuf],
+) -> Result<String, Box<dyn Error>> {
+    let mut child = Command::new(ctags)
+        .args(["-f", "-", "-L", "-", "--fields=+l"])
+        .args(macros.iter().flat_map(|m| ["-D", m.as_str()]))
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|error| format!("cannot run '{}': {}", ctags.display(), error))?;
+    let mut list = String::new();
+    for f in files {
+        list.push_str(&paths::relative(f, dir).to_string_lossy());
+        list.push('\n');
+    }
+    // The list is written while the tags are read since ctags may fill the pipe of its output
+    // before it read the whole list.
+    let mut stdin = child.stdin.take().ok_or("cannot write to ctags")?;
+    let writer = thread::spawn(move || stdin.write_all(list.as_bytes()));
+    let output = child.wait_with_output()?;
+    let written = writer.join().map_err(|_| "cannot write to ctags")?;
+    if !output.status.success() {
+        return Err(format!("'{}' failed with {}", ctags.display(), output.status).into());
+    }
+    written?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &["--no-headers"], &["--db", "-o", "--ctags"])?;
+    let cwd = env::current_dir()?;
+    let json_path = paths::normalize(&cwd.join(opts.database()));
+    let output = paths::normalize(&cwd.join(opts.value("-o").unwrap_or("tags")));
+    let dir = output.parent().unwrap_or(&cwd).to_path_buf();
+    let ctags = PathBuf::from(opts.value("--ctags").unwrap_or("ctags"));
+
+    let db_dir = json_path.parent().unwrap_or(&cwd);
+    let entries: BTreeSet<Entry> = db::read_entries(&json_path)?
+        .iter()
+        .map(|e| relocate::absolutize(e, db_dir))
+        .collect();
+    let mut outputs = Vec::new();
+    for (macros, files) in groups(&entries, !opts.has("--no-headers")) {
+        outputs.push(run_ctags(&ctags, &dir, &macros, &files)?);
+    }
+    fs::write(&output, merge(&outputs))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups() {
+        let entries: BTreeSet<_> = [
+            db::entry(
+                "/proj",
+                "a.c",
+                &["cc", "-DFOO", "-D", "BAR=2", "-UBAZ", "a.c"],
+            ),
+            db::entry("/proj", "b.c", &["cc", "-DBAR=2", "-DFOO=1", "b.c"]),
+            db::entry("/proj", "c.c", &["cc", "-DBAZ", "-UBAZ", "c.c"]),
+            db::entry("/proj", "c.c", &["cc", "-DOTHER", "c.c"]),
+        ]
+        .into_iter()
+        .collect();
+        let groups = super::groups(&entries, false);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(
+            groups[&vec!["BAR=2".to_string(), "FOO=1".to_string()]],
+            [Path::new("/proj/a.c"), Path::new("/proj/b.c")]
+        );
+        assert_eq!(groups[&Vec::new()], [Path::new("/proj/c.c")]);
+    }
+
+    #[test]
+    fn merge() {
+        let outputs = [
+            "main\ta.c\t/^int main()$/;\"\tf\n".to_string(),
+            "!_TAG_PROGRAM_NAME\tUniversal Ctags\t//\nfoo\tb.c\t/^void foo()$/;\"\tf\n".to_string(),
+        ];
+        assert_eq!(
+            super::merge(&outputs),
+            "!_TAG_FILE_FORMAT\t2\t/extended format/\n\
+             !_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted, 2=foldcase/\n\
+             foo\tb.c\t/^void foo()$/;\"\tf\n\
+             main\ta.c\t/^int main()$/;\"\tf\n"
+        );
+    }
+}
@@ -0,0 +1,1303 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, Metadata};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::args;
+use crate::cache;
+use crate::coexist;
+use crate::config::{
+    Coexist, Config, Conflict, IncludePaths, LinkOutput, PrefixMap, Session, Spelling, Storage,
+};
+use crate::diff;
+use crate::fragment;
+use crate::implicit;
+use crate::link;
+use crate::meta::{self, Header};
+use crate::output;
+use crate::overhead::{self, Phase};
+use crate::paths;
+use crate::presets;
+use crate::prune;
+use crate::relocate;
+use crate::route;
+use crate::shard;
+use crate::staging;
+use crate::stream;
+use crate::summary::{self, Changes};
+use crate::sweep;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub directory: String,
+    pub file: String,
+    pub arguments: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+/// Returns the entry of `file` compiled in `directory` by `arguments` for the tests.
+#[cfg(test)]
+pub fn entry(directory: &str, file: &str, arguments: &[&str]) -> Entry {
+    Entry {
+        directory: directory.to_string(),
+        file: file.to_string(),
+        arguments: arguments.iter().map(|s| s.to_string()).collect(),
+        output: None,
+    }
+}
+
+/// Locks `file` exclusively and waits until the lock is granted.
+pub fn lock(file: &mut File) -> Result<(), Box<dyn Error>> {
+    overhead::measure(Phase::LockWait, || lock_file(file))
+}
+
+fn lock_file(file: &mut File) -> Result<(), Box<dyn Error>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsRawFd;
+        let ret = unsafe { libc::lockf(file.as_raw_fd(), libc::F_LOCK, 0) };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::io::AsRawHandle;
+        use windows::Win32::Foundation::HANDLE;
+        use windows::Win32::Storage::FileSystem::{LockFileEx, LOCKFILE_EXCLUSIVE_LOCK};
+        unsafe {
+            let mut overlapped = std::mem::zeroed();
+            let ret = LockFileEx(
+                HANDLE(file.as_raw_handle() as isize),
+                LOCKFILE_EXCLUSIVE_LOCK,
+                0,
+                !0,
+                !0,
+                &mut overlapped,
+            );
+            if ret.0 == 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            return Ok(());
+        };
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    compile_error!("File (un)locking only supported on Unix and Windows");
+}
+
+/// Appends `line` to the JSON Lines log at `path`, creating it if missing.  Lines of concurrent
+/// compiles must not interleave.  The lock is held by the file itself since closing a duplicate
+/// would release it.
+pub fn append_jsonl(path: &Path, line: &str) -> Result<(), Box<dyn Error>> {
+    let mut file = File::options().create(true).append(true).open(path)?;
+    lock(&mut file)?;
+    writeln!(&file, "{}", line)?;
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn unlock(file: &mut File) -> Result<(), Box<dyn Error>> {
+    use std::os::windows::io::AsRawHandle;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::Storage::FileSystem::UnlockFile;
+    let ret = unsafe { UnlockFile(HANDLE(file.as_raw_handle() as isize), 0, 0, !0, !0) };
+    if ret.0 == 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+pub fn parse_entries(data: &str) -> Result<BTreeSet<Entry>, Box<dyn Error>> {
+    overhead::measure(Phase::Parse, || {
+        if data.trim().is_empty() {
+            Ok(BTreeSet::new())
+        } else {
+            Ok(serde_json::from_str(data)?)
+        }
+    })
+}
+
+/// Reads all entries of the database at `json_path`.  The database is locked while reading in
+/// order not to observe a half-written file.  If the database is not writable, it is read without
+/// taking the lock.  A sharded database is read from its shards.  Pending fragments are merged
+/// first.
+pub fn read_entries(json_path: &Path) -> Result<BTreeSet<Entry>, Box<dyn Error>> {
+    merge_fragments(json_path)?;
+    if shard::is_sharded(json_path) {
+        return shard::read_all(json_path);
+    }
+    let mut data = String::new();
+    match File::options().read(true).write(true).open(json_path) {
+        Ok(mut json_file) => {
+            lock(&mut json_file)?;
+            json_file.read_to_string(&mut data)?;
+            #[cfg(windows)]
+            unlock(&mut json_file)?;
+        }
+        Err(error) if error.kind() == ErrorKind::PermissionDenied => {
+            File::open(json_path)?.read_to_string(&mut data)?;
+        }
+        Err(error) => {
+            return Err(format!("cannot open database '{}': {}", json_path.display(), error).into())
+        }
+    }
+    parse_entries(&data)
+}
+
+/// Runs `f` while holding the lock of the database at `json_path` which is created if necessary.
+pub fn with_lock<T, F>(json_path: &Path, f: F) -> Result<T, Box<dyn Error>>
+where
+    F: FnOnce(&mut File) -> Result<T, Box<dyn Error>>,
+{
+    if let Err(error) = File::options().write(true).create_new(true).open(json_path) {
+        match error.kind() {
+            ErrorKind::AlreadyExists => (),
+            _ => return Err(error.into()),
+        }
+    }
+
+    let mut json_file = File::options().read(true).write(true).open(json_path)?;
+    lock(&mut json_file)?;
+
+    let result = f(&mut json_file);
+
+    // On Unix there is no need to explicitly release the lock since this is done implicitly once
+    // the file is closed.  On Windows this is more or less the same except that the time between
+    // closing the file and releasing the lock may be arbitrarily long.  Thus it is suggested to
+    // explicitly unlock the file.
+    #[cfg(windows)]
+    unlock(&mut json_file)?;
+
+    result
+}
+
+/// Locks the database at `json_path`, creating it if necessary, and replaces its entries by the
+/// ones computed by `f` from the current entries.  The file is only rewritten if the entries
+/// actually changed.  Pending fragments are merged first.
+pub fn update<F>(json_path: &Path, f: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnOnce(&BTreeSet<Entry>) -> Result<BTreeSet<Entry>, Box<dyn Error>>,
+{
+    merge_fragments(json_path)?;
+    update_file(json_path, f)
+}
+
+fn update_file<F>(json_path: &Path, f: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnOnce(&BTreeSet<Entry>) -> Result<BTreeSet<Entry>, Box<dyn Error>>,
+{
+    update_file_then(json_path, f, |_, _| Ok(()))
+}
+
+/// What tells the version of a database file apart from the one another tool wrote, i.e., its
+/// size, modification time, and, on Unix, the file itself in case it was replaced.
+#[derive(Debug, PartialEq)]
+pub struct Stamp {
+    len: u64,
+    modified: Option<SystemTime>,
+    #[cfg(unix)]
+    inode: (u64, u64),
+}
+
+impl Stamp {
+    pub fn of(metadata: &Metadata) -> Self {
+        #[cfg(unix)]
+        use std::os::unix::fs::MetadataExt;
+        Stamp {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            #[cfg(unix)]
+            inode: (metadata.dev(), metadata.ino()),
+        }
+    }
+}
+
+/// Returns the entries another tool wrote as `foreign` while `new` was computed from `old`, with
+/// the changes from `old` to `new` applied, i.e., where neither the entries of the other tool
+/// nor the new ones are lost.
+fn remerge(
+    old: &BTreeSet<Entry>,
+    new: &BTreeSet<Entry>,
+    foreign: BTreeSet<Entry>,
+) -> Result<BTreeSet<Entry>, Box<dyn Error>> {
+    let mut entries: BTreeSet<Entry> = foreign
+        .into_iter()
+        .filter(|e| !old.contains(e) || new.contains(e))
+        .collect();
+    merge(
+        &mut entries,
+        new.difference(old).cloned(),
+        Conflict::Replace,
+    )?;
+    Ok(entries)
+}
+
+/// Like `update_file` but runs `then` with the new entries and the database file once it is
+/// written, while still holding the lock.  Tools which do not take the lock may rewrite or
+/// replace the database meanwhile in which case the changes are merged with theirs.
+fn update_file_then<F, G>(json_path: &Path, f: F, then: G) -> Result<(), Box<dyn Error>>
+where
+    F: FnOnce(&BTreeSet<Entry>) -> Result<BTreeSet<Entry>, Box<dyn Error>>,
+    G: FnOnce(&BTreeSet<Entry>, &File) -> Result<(), Box<dyn Error>>,
+{
+    with_lock(json_path, |json_file| {
+        let mut data = String::new();
+        json_file.read_to_string(&mut data)?;
+        let stamp = Stamp::of(&json_file.metadata()?);
+
+        let old_entries = parse_entries(&data)?;
+        let mut new_entries = f(&old_entries)?;
+
+        if new_entries == old_entries {
+            return then(&new_entries, json_file);
+        }
+        let mut replaced = None;
+        if let Some((file, foreign)) = replaced_value(json_path, &stamp, parse_entries)? {
+            new_entries = remerge(&old_entries, &new_entries, foreign)?;
+            replaced = Some(file);
+        }
+        let json_file = replaced.as_mut().unwrap_or(json_file);
+        overhead::measure(Phase::Write, || {
+            rewrite(json_file, &serde_json::to_string_pretty(&new_entries)?)
+        })?;
+        then(&new_entries, json_file)
+    })
+}
+
+/// Returns the file at `json_path` locked if another tool, which does not take the lock,
+/// rewrote or replaced it since it was read as of `stamp`.
+pub fn replaced(json_path: &Path, stamp: &Stamp) -> Result<Option<File>, Box<dyn Error>> {
+    if fs::metadata(json_path).map(|m| Stamp::of(&m)).ok().as_ref() == Some(stamp) {
+        return Ok(None);
+    }
+    let mut file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(json_path)?;
+    lock(&mut file)?;
+    output::warn(&format!(
+        "{} was modified by another tool meanwhile; merging its entries",
+        json_path.display()
+    ));
+    Ok(Some(file))
+}
+
+/// Like `replaced` but returns the value of the file parsed by `parse`, too.
+fn replaced_value<T, P>(
+    json_path: &Path,
+    stamp: &Stamp,
+    parse: P,
+) -> Result<Option<(File, T)>, Box<dyn Error>>
+where
+    P: FnOnce(&str) -> Result<T, Box<dyn Error>>,
+{
+    let mut file = match replaced(json_path, stamp)? {
+        Some(file) => file,
+        None => return Ok(None),
+    };
+    let mut data = String::new();
+    file.read_to_string(&mut data)?;
+    let foreign = parse(&data).map_err(|error| {
+        format!(
+            "{} was modified by another tool meanwhile and cannot be merged: {}",
+            json_path.display(),
+            error
+        )
+    })?;
+    Ok(Some((file, foreign)))
+}
+
+/// Replaces the content of the locked `file` by `json_string`.
+fn rewrite(file: &mut File, json_string: &str) -> Result<(), Box<dyn Error>> {
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    writeln!(file, "{}", json_string)?;
+    Ok(())
+}
+
+/// Reads the value of the JSON file at `json_path` while holding its lock, the default if the
+/// file does not exist or is empty.
+pub fn read_json<T>(json_path: &Path) -> Result<T, Box<dyn Error>>
+where
+    T: DeserializeOwned + Default,
+{
+    if !json_path.exists() {
+        return Ok(T::default());
+    }
+    with_lock(json_path, |file| {
+        let mut data = String::new();
+        file.read_to_string(&mut data)?;
+        if data.trim().is_empty() {
+            return Ok(T::default());
+        }
+        Ok(serde_json::from_str(&data)?)
+    })
+}
+
+/// Locks the JSON file at `json_path`, creating it if necessary, and updates its value, the
+/// default if the file is empty, by `f`.  The file is only rewritten if the value changed.  Tools
+/// which do not take the lock may rewrite or replace the file meanwhile in which case `f` is run
+/// again on their value.
+pub fn update_json<T, R, F>(json_path: &Path, f: F) -> Result<R, Box<dyn Error>>
+where
+    T: Serialize + DeserializeOwned + Default + Clone + PartialEq,
+    F: FnMut(&mut T) -> Result<R, Box<dyn Error>>,
+{
+    update_json_or(json_path, |error| Err(error.into()), f)
+}
+
+/// Like `update_json` but takes the value of a file which does not parse from `recover`.
+pub fn update_json_or<T, R, E, F>(
+    json_path: &Path,
+    recover: E,
+    mut f: F,
+) -> Result<R, Box<dyn Error>>
+where
+    T: Serialize + DeserializeOwned + Default + Clone + PartialEq,
+    E: Fn(serde_json::Error) -> Result<T, Box<dyn Error>>,
+    F: FnMut(&mut T) -> Result<R, Box<dyn Error>>,
+{
+    let parse = |data: &str| -> Result<T, Box<dyn Error>> {
+        if data.trim().is_empty() {
+            return Ok(T::default());
+        }
+        serde_json::from_str(data).or_else(&recover)
+    };
+    with_lock(json_path, |json_file| {
+        let mut data = String::new();
+        json_file.read_to_string(&mut data)?;
+        let stamp = Stamp::of(&json_file.metadata()?);
+
+        let old = parse(&data)?;
+        let mut new = old.clone();
+        let mut result = f(&mut new)?;
+
+        if new == old {
+            return Ok(result);
+        }
+        let mut replaced = None;
+        if let Some((file, mut foreign)) = replaced_value(json_path, &stamp, parse)? {
+            result = f(&mut foreign)?;
+            new = foreign;
+            replaced = Some(file);
+        }
+        let json_file = replaced.as_mut().unwrap_or(json_file);
+        rewrite(json_file, &serde_json::to_string_pretty(&new)?)?;
+        Ok(result)
+    })
+}
+
+/// Like `update` but for sharded databases, too.
+pub fn update_any<F>(json_path: &Path, f: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnOnce(&BTreeSet<Entry>) -> Result<BTreeSet<Entry>, Box<dyn Error>>,
+{
+    if shard::is_sharded(json_path) {
+        merge_fragments(json_path)?;
+        shard::update_all(json_path, f)
+    } else {
+        update(json_path, f)
+    }
+}
+
+/// Merges the entries staged for the database at `json_path` with `CDBGEN_STORAGE=shm` and the
+/// fragments recorded with `CDBGEN_STORAGE=fragments` into it and removes them.  Later entries
+/// replace earlier ones.
+pub fn merge_fragments(json_path: &Path) -> Result<(), Box<dyn Error>> {
+    let fragments = fragment::list(json_path)?;
+    let staged = staging::is_pending(json_path)?;
+    if fragments.is_empty() && !staged {
+        return Ok(());
+    }
+    let f = |old_entries: &BTreeSet<Entry>| {
+        let mut entries = old_entries.clone();
+        // Drained while holding the lock, i.e., by a single writer.
+        if staged {
+            merge(&mut entries, staging::drain(json_path)?, Conflict::Replace)?;
+        }
+        for path in &fragments {
+            merge(&mut entries, fragment::read(path)?, Conflict::Replace)?;
+        }
+        Ok(entries)
+    };
+    if shard::is_sharded(json_path) {
+        shard::update_all(json_path, f)?;
+    } else if let Some(limit) = Config::from_env().memory_limit {
+        let batches = staged
+            .then(|| staging::drain(json_path))
+            .into_iter()
+            .chain(fragments.iter().map(|path| fragment::read(path)));
+        stream::merge(json_path, limit, batches, Conflict::Replace)?;
+    } else {
+        update_file(json_path, f)?;
+    }
+    if staged {
+        staging::finish(json_path)?;
+    }
+    fragment::remove(&fragments)
+}
+
+/// A compiler invocation which is recorded into the database.
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    /// The command line as cdbgen was invoked.
+    pub argv: Vec<String>,
+    /// The command line as recorded, i.e., with the compiler resolved.
+    pub arguments: Vec<String>,
+    pub directory: String,
+    /// Source files mapped to the output they are compiled into.
+    pub files: BTreeMap<String, Option<String>>,
+    /// How long the compiler took if timing capture is enabled.
+    pub duration: Option<Duration>,
+    /// The build session the invocation is part of, if any.
+    pub session: Option<Session>,
+}
+
+impl Invocation {
+    pub fn entries(&self) -> impl Iterator<Item = Entry> + '_ {
+        self.files.iter().map(move |(f, output)| Entry {
+            directory: self.directory.clone(),
+            file: f.to_string(),
+            arguments: self.arguments.clone(),
+            output: output.clone(),
+        })
+    }
+}
+
+/// Returns the output file named by `-o`.  Without `-o` the output of `-c` and `-S` is derived the
+/// same way the driver does, i.e., from the base name of the source file.  Output written to
+/// stdout, e.g. by `-o -` or by `-E`, is no output file.
+fn output_file(args: &[String], file: &str) -> Option<String> {
+    let mut output = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "-o" {
+            output = iter.next().cloned();
+        } else if let Some(o) = arg.strip_prefix("-o") {
+            output = Some(o.to_string());
+        }
+    }
+    match output.as_deref() {
+        Some("-" | "/dev/stdout") => return None,
+        Some(_) => return output,
+        None => (),
+    }
+    let stem = Path::new(file).file_stem()?.to_string_lossy();
+    if args.iter().any(|a| a == "-c") {
+        Some(format!("{}.o", stem))
+    } else if args.iter().any(|a| a == "-S") {
+        Some(format!("{}.s", stem))
+    } else {
+        None
+    }
+}
+
+/// Returns true if `file` has the extension of a source file.
+fn is_source(file: &str) -> bool {
+    #[cfg(not(windows))]
+    let x = file;
+    #[cfg(windows)]
+    let x = file.to_lowercase();
+    #[allow(clippy::case_sensitive_file_extension_comparisons)]
+    let source = x.ends_with(".c")
+        || x.ends_with(".cc")
+        || x.ends_with(".cpp")
+        || x.ends_with(".cppm")
+        || x.ends_with(".ixx")
+        || file.ends_with(".S")
+        || x.ends_with(".sx");
+    source
+}
+
+/// Languages selected by `-x` whose inputs are recorded regardless of their extension.
+const SOURCE_LANGUAGES: &[&str] = &[
+    "c",
+    "c++",
+    "objective-c",
+    "objective-c++",
+    "c++-module",
+    "assembler-with-cpp",
+];
+
+/// Returns the source files compiled by an invocation with arguments `args` (without the
+/// compiler) mapped to the output they are compiled into.  Only inputs are considered, i.e.,
+/// neither the values of options like `-MF dep.c` nor `-o foo.c.o`, but all arguments after `--`.
+/// An input following `-x c` is a source whatever its extension, one following `-x none` only if
+/// its extension says so.
+pub fn source_files(args: &[String]) -> BTreeMap<String, Option<String>> {
+    let options = match args.iter().position(|a| a == "--") {
+        Some(i) => &args[..i],
+        None => args,
+    };
+    args::inputs(args)
+        .into_iter()
+        // A source read from stdin leaves no file for tools to open.
+        .filter(|(input, language)| {
+            input != "-"
+                && match language {
+                    Some(language) => SOURCE_LANGUAGES.contains(&language.as_str()),
+                    None => is_source(input),
+                }
+        })
+        .map(|(f, _)| {
+            let output = output_file(options, &f);
+            (f, output)
+        })
+        .collect()
+}
+
+/// Returns the source files compiled by the invocation `arguments` (the compiler first) mapped to
+/// the output they are compiled into, like `source_files` but also for cl and clang-cl.
+pub fn compiled_files(arguments: &[String]) -> BTreeMap<String, Option<String>> {
+    let rest = arguments.get(1..).unwrap_or_default();
+    if args::is_cl(arguments) {
+        cl_source_files(rest)
+    } else {
+        source_files(rest)
+    }
+}
+
+/// Returns the source files compiled by cl with `args` (without the compiler), i.e., the inputs
+/// with the extension of a source and the values of `/Tc` and `/Tp`, mapped to their object
+/// files.  An object file is named by `/Fo`, by `/Fo` naming a directory, i.e., ending in a
+/// separator, or, with `/c`, after the source.  The arguments following `/link` are not compiled.
+fn cl_source_files(args: &[String]) -> BTreeMap<String, Option<String>> {
+    let flags = args::cl_flags(args);
+    let fo = flags
+        .iter()
+        .rev()
+        .find(|f| f.name == "/Fo")
+        .and_then(|f| f.value.clone());
+    let compile_only = flags.iter().any(|f| f.name == "/c" || f.name == "-c");
+    let sources: Vec<String> = flags
+        .iter()
+        .filter_map(|f| match f.name.as_str() {
+            "/Tc" | "/Tp" => f.value.clone(),
+            _ if f.is_input() && is_source(&f.name) => Some(f.name.clone()),
+            _ => None,
+        })
+        .collect();
+    let single = sources.len() == 1;
+    sources
+        .into_iter()
+        .map(|source| {
+            let stem = Path::new(&source)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned());
+            let output = match (&fo, stem) {
+                (Some(fo), Some(stem)) if fo.ends_with(['/', '\\']) => {
+                    Some(format!("{}{}.obj", fo, stem))
+                }
+                (Some(fo), _) if single => Some(fo.clone()),
+                (_, Some(stem)) if compile_only => Some(format!("{}.obj", stem)),
+                _ => None,
+            };
+            (source, output)
+        })
+        .collect()
+}
+
+/// Replaces the entries for the files of `invocation` in `entries` by the ones of `invocation`.
+pub fn replace(entries: &mut BTreeSet<Entry>, invocation: &Invocation) {
+    entries
+        .retain(|e| e.directory != invocation.directory || !invocation.files.contains_key(&e.file));
+    entries.extend(invocation.entries());
+}
+
+/// Describes how the arguments of `entry` drifted from the ones of `old`, i.e., the flags removed
+/// and added, word-level highlighted if `color` is set.
+fn drift_message(old: &Entry, entry: &Entry, color: bool) -> String {
+    let mut message = format!(
+        "arguments of '{}' changed",
+        paths::resolve(&entry.directory, &entry.file).display()
+    );
+    let (removed, added) = args::drift(&old.arguments, &entry.arguments);
+    if removed.is_empty() && added.is_empty() {
+        message += "\n  flags reordered";
+    }
+    let changes: Vec<_> = removed
+        .iter()
+        .map(|f| diff::Change::Removed(f))
+        .chain(added.iter().map(|f| diff::Change::Added(f)))
+        .collect();
+    for line in diff::render(&changes, color) {
+        message += &format!("\n  {}", line);
+    }
+    message
+}
+
+/// The error of a merge with `Conflict::Fail` finding the arguments of a file changed.
+#[derive(Debug)]
+pub struct ConflictError(String);
+
+impl fmt::Display for ConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for ConflictError {}
+
+/// Merges `new` into `entries` where each new entry replaces the ones of the same file.  If the
+/// arguments of a file changed, `policy` decides whether the old entry is kept as well, whether a
+/// warning is printed, or whether the merge fails.
+pub fn merge<I>(
+    entries: &mut BTreeSet<Entry>,
+    new: I,
+    policy: Conflict,
+) -> Result<Changes, Box<dyn Error>>
+where
+    I: IntoIterator<Item = Entry>,
+{
+    let mut changes = Changes::default();
+    for entry in new {
+        let same_file = |e: &Entry| e.directory == entry.directory && e.file == entry.file;
+        if entries.contains(&entry) {
+            changes.unchanged += 1;
+        } else if entries.iter().any(same_file) {
+            changes.updated += 1;
+        } else {
+            changes.added += 1;
+        }
+        if let Some(old) = entries
+            .iter()
+            .find(|e| same_file(e) && e.arguments != entry.arguments)
+        {
+            match policy {
+                Conflict::Fail => {
+                    return Err(ConflictError(drift_message(old, &entry, false)).into())
+                }
+                Conflict::Warn => output::warn(&drift_message(old, &entry, diff::color_enabled(2))),
+                Conflict::Replace | Conflict::Keep => (),
+            }
+        }
+        entries.retain(|e| {
+            !same_file(e) || (policy == Conflict::Keep && e.arguments != entry.arguments)
+        });
+        entries.insert(entry);
+    }
+    Ok(changes)
+}
+
+fn deferred(entries: usize) -> Changes {
+    Changes {
+        deferred: entries as u64,
+        ..Changes::default()
+    }
+}
+
+/// Records each invocation into its database.  The databases are locked one after the other and
+/// only written once all of them are locked and merged, such that either every database is
+/// updated or, e.g. on a conflict, none is.
+fn record_all(config: &Config, targets: &[(PathBuf, Invocation)]) -> Result<(), Box<dyn Error>> {
+    let ((json_path, invocation), rest) = match targets.split_first() {
+        Some(first) => first,
+        None => return Ok(()),
+    };
+    if config.storage.is_deferred() {
+        // Neither the database nor the sidecar is touched until the fragments are merged.  A
+        // full staging buffer falls back to fragments.
+        let entries: Vec<_> = invocation.entries().collect();
+        summary::note(json_path, deferred(entries.len()));
+        overhead::measure(Phase::Write, || {
+            if config.storage != Storage::Shm || !staging::append(json_path, &entries)? {
+                fragment::write(json_path, entries)?;
+            }
+            Ok::<_, Box<dyn Error>>(())
+        })?;
+        fragment::schedule(json_path, &config.compaction)?;
+        return record_all(config, rest);
+    }
+    let sharded = config.storage == Storage::Sharded || shard::is_sharded(json_path);
+    let header = Header::new(&config.capture, if sharded { "sharded" } else { "file" });
+    let then = || {
+        record_all(config, rest)?;
+        if config.records_meta() {
+            let linked = config.spelling == Spelling::Linked;
+            let environment = meta::environment(&config.environment);
+            meta::record(json_path, invocation, header, &environment, linked)
+        } else if !meta::path(json_path).exists() {
+            // Only the header, i.e., do not read the sidecar on every compile.
+            meta::ensure_header(json_path, header)
+        } else {
+            Ok(())
+        }
+    };
+    if sharded {
+        let changes = shard::record(json_path, invocation, config.on_conflict, then)?;
+        summary::note(json_path, changes);
+        return Ok(());
+    }
+    if let Some(limit) = config.memory_limit {
+        merge_fragments(json_path)?;
+        summary::note(json_path, deferred(invocation.entries().count()));
+        // The other databases and the sidecar are recorded while the database is locked, i.e.,
+        // as the last batch which is empty.
+        let batches = iter::once(Ok(invocation.entries().collect()))
+            .chain(iter::once_with(|| then().map(|_| Vec::new())));
+        return stream::merge(json_path, limit, batches, config.on_conflict);
+    }
+    let f = |old_entries: &BTreeSet<Entry>| {
+        let mut new_entries = old_entries.clone();
+        let changes = merge(&mut new_entries, invocation.entries(), config.on_conflict)?;
+        summary::note(json_path, changes);
+        then()?;
+        Ok(new_entries)
+    };
+    if config.record_cache && !config.records_meta() {
+        // Without the sidecar an unchanged entry needs no update at all.
+        if cache::is_recorded(json_path, invocation) {
+            let changes = Changes {
+                unchanged: invocation.entries().count() as u64,
+                ..Changes::default()
+            };
+            summary::note(json_path, changes);
+            return record_all(config, rest);
+        }
+        merge_fragments(json_path)?;
+        update_file_then(json_path, f, |entries, json_file| {
+            cache::store(json_path, json_file, &invocation.directory, entries)
+        })
+    } else {
+        update(json_path, f)
+    }
+}
+
+/// Returns `invocation` with the paths of its source files made absolute.  Canonical paths are
+/// substituted on the command line as well.
+fn respell(invocation: &Invocation, spelling: Spelling) -> Invocation {
+    let mut respelled = invocation.clone();
+    respelled.files.clear();
+    for (file, output) in &invocation.files {
+        let path = match spelling {
+            Spelling::Canonical => paths::canonical(&invocation.directory, file),
+            _ => paths::resolve(&invocation.directory, file),
+        };
+        let path = path.to_string_lossy().into_owned();
+        if spelling == Spelling::Canonical {
+            for arg in respelled.arguments.iter_mut().skip(1) {
+                if arg == file {
+                    *arg = path.clone();
+                }
+            }
+        }
+        respelled.files.insert(path, output.clone());
+    }
+    respelled
+}
+
+/// Returns `invocation` with the paths `ccache` made relative to `basedir` restored, i.e., made
+/// absolute again.
+fn restore_basedir(invocation: &Invocation, basedir: &Path) -> Invocation {
+    let restore = |path: &String| {
+        let resolved = paths::resolve(&invocation.directory, path);
+        if Path::new(path).is_relative() && resolved.starts_with(basedir) {
+            resolved.to_string_lossy().into_owned()
+        } else {
+            path.clone()
+        }
+    };
+    Invocation {
+        arguments: relocate::restore_basedir(&invocation.arguments, &invocation.directory, basedir),
+        files: invocation
+            .files
+            .iter()
+            .map(|(file, output)| (restore(file), output.as_ref().map(restore)))
+            .collect(),
+        ..invocation.clone()
+    }
+}
+
+/// Returns `invocation` with `root` as its directory, if it is run below `root`, and its relative
+/// paths adjusted accordingly, such that compiles of a recursive Make are recorded alike from
+/// whichever subdirectory they run in.  Invocations whose paths cannot all be rewritten are kept
+/// as they are.
+fn rebase(invocation: &Invocation, root: &Path) -> Invocation {
+    let directory = paths::normalize(Path::new(&invocation.directory));
+    if !directory.starts_with(root) || directory == root {
+        return invocation.clone();
+    }
+    let arguments = match relocate::rebase(&invocation.arguments, &invocation.directory, root) {
+        Some(arguments) => arguments,
+        None => return invocation.clone(),
+    };
+    let rebase = |path: &String| {
+        if Path::new(path).is_relative() {
+            let resolved = paths::resolve(&invocation.directory, path);
+            paths::relative(&resolved, root)
+                .to_string_lossy()
+                .into_owned()
+        } else {
+            path.clone()
+        }
+    };
+    Invocation {
+        directory: root.to_string_lossy().into_owned(),
+        arguments,
+        files: invocation
+            .files
+            .iter()
+            .map(|(file, output)| (rebase(file), output.as_ref().map(rebase)))
+            .collect(),
+        ..invocation.clone()
+    }
+}
+
+/// Splits the compile-and-link invocation `invocation` into one invocation compiling just each
+/// of its sources, see `link::compile_only`.  Returns `None` if the invocation does not link.
+fn strip_link(invocation: &Invocation) -> Option<Vec<Invocation>> {
+    invocation
+        .files
+        .keys()
+        .map(|file| {
+            let arguments = link::compile_only(&invocation.arguments, file)?;
+            let output = output_file(&arguments[1..], file);
+            Some(Invocation {
+                arguments,
+                files: BTreeMap::from([(file.clone(), output)]),
+                ..invocation.clone()
+            })
+        })
+        .collect()
+}
+
+/// Returns `invocation` with its directory and the paths of its source files mapped by the
+/// prefix maps on its command line according to `mode`.
+fn remap_prefixes(invocation: &Invocation, mode: PrefixMap) -> Invocation {
+    let mut maps = args::prefix_maps(&invocation.arguments);
+    if mode == PrefixMap::Invert {
+        maps = maps.into_iter().map(|(old, new)| (new, old)).collect();
+    }
+    let mut remapped = invocation.clone();
+    if let Some(directory) = paths::remap(&invocation.directory, &maps) {
+        remapped.directory = directory;
+    }
+    remapped.files = invocation
+        .files
+        .iter()
+        .map(|(file, output)| {
+            let file = paths::remap(file, &maps).unwrap_or_else(|| file.clone());
+            (file, output.clone())
+        })
+        .collect();
+    remapped
+}
+
+pub fn process_compile_commands_json(
+    config: &Config,
+    invocation: &Invocation,
+) -> Result<(), Box<dyn Error>> {
+    // The invocations split off compile only, i.e., are not split again.
+    if config.link_output == LinkOutput::Strip {
+        if let Some(invocations) = strip_link(invocation) {
+            for invocation in &invocations {
+                process_compile_commands_json(config, invocation)?;
+            }
+            return Ok(());
+        }
+    }
+    let remapped;
+    let invocation = match config.prefix_map {
+        PrefixMap::Off => invocation,
+        mode => {
+            remapped = remap_prefixes(invocation, mode);
+            &remapped
+        }
+    };
+    let restored;
+    let invocation = match &config.ccache_basedir {
+        Some(basedir) => {
+            restored = restore_basedir(invocation, basedir);
+            &restored
+        }
+        None => invocation,
+    };
+    let rebased;
+    let invocation = match &config.rebase {
+        Some(root) => {
+            let db_dir = paths::normalize(&env::current_dir()?.join(&config.database));
+            let root = paths::normalize(&db_dir.parent().unwrap_or(&db_dir).join(root));
+            rebased = rebase(invocation, &root);
+            &rebased
+        }
+        None => invocation,
+    };
+    let absolutized;
+    let invocation = if config.include_paths == IncludePaths::Absolute {
+        absolutized = Invocation {
+            arguments: relocate::absolutize_includes(&invocation.arguments, &invocation.directory),
+            ..invocation.clone()
+        };
+        &absolutized
+    } else {
+        invocation
+    };
+    let translated;
+    let invocation = match presets::for_invocation(config, invocation)? {
+        Some(preset) => {
+            translated = Invocation {
+                arguments: preset.apply(&invocation.arguments),
+                ..invocation.clone()
+            };
+            &translated
+        }
+        None => invocation,
+    };
+    let embedded;
+    let invocation = if config.implicit_includes {
+        embedded = Invocation {
+            arguments: implicit::embed(
+                &config.database,
+                &invocation.directory,
+                &invocation.arguments,
+                invocation.files.keys(),
+            ),
+            ..invocation.clone()
+        };
+        &embedded
+    } else {
+        invocation
+    };
+    let respelled;
+    let invocation = match config.spelling {
+        Spelling::Absolute | Spelling::Canonical => {
+            respelled = respell(invocation, config.spelling);
+            &respelled
+        }
+        Spelling::AsSpelled | Spelling::Linked => invocation,
+    };
+    let cwd = env::current_dir()?;
+    let mut targets: BTreeMap<PathBuf, Invocation> = route::split(config, invocation)
+        .into_iter()
+        .map(|(json_path, invocation)| (paths::normalize(&cwd.join(json_path)), invocation))
+        .collect();
+    for json_path in &config.also {
+        targets
+            .entry(paths::normalize(&cwd.join(json_path)))
+            .or_insert_with(|| invocation.clone());
+    }
+    // Databases are locked in the order of their paths such that concurrent compiles cannot
+    // deadlock.
+    let mut targets: Vec<_> = targets.into_iter().collect();
+    let coexisting: Vec<PathBuf> = if config.coexist == Coexist::Cmake {
+        targets
+            .iter_mut()
+            .map(|(json_path, _)| std::mem::replace(json_path, coexist::owned(json_path)))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    record_all(config, &targets)?;
+    // Deferred entries are added by `cdbgen finalize`.
+    if !config.storage.is_deferred() {
+        for json_path in &coexisting {
+            coexist::sync(json_path)?;
+        }
+    }
+    for (json_path, invocation) in &targets {
+        if let Some(mark_file) = &config.mark {
+            sweep::mark(mark_file, json_path, invocation)?;
+        }
+        if let Some(age) = config.prune_after {
+            prune::auto(json_path, age)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge() {
+        let old: BTreeSet<_> = [
+            entry("/src", "a.c", &["cc", "-O2", "a.c"]),
+            entry("/src", "b.c", &["cc", "b.c"]),
+        ]
+        .into_iter()
+        .collect();
+        let new = || vec![entry("/src", "a.c", &["cc", "-O3", "a.c"])];
+
+        let mut entries = old.clone();
+        let changes = super::merge(&mut entries, new(), Conflict::Replace).unwrap();
+        assert_eq!(
+            changes,
+            Changes {
+                updated: 1,
+                ..Changes::default()
+            }
+        );
+        assert_eq!(entries.len(), 2);
+        assert!(entries.contains(&new()[0]));
+
+        let mut entries = old.clone();
+        super::merge(&mut entries, new(), Conflict::Keep).unwrap();
+        assert_eq!(entries.len(), 3);
+        super::merge(&mut entries, new(), Conflict::Keep).unwrap();
+        assert_eq!(entries.len(), 3);
+
+        let mut entries = old.clone();
+        assert!(super::merge(&mut entries, new(), Conflict::Fail).is_err());
+        super::merge(
+            &mut entries,
+            vec![entry("/src", "b.c", &["cc", "b.c"])],
+            Conflict::Fail,
+        )
+        .unwrap();
+        assert_eq!(entries, old);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn foreign_modification() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let json_path = temp.path().join("compile_commands.json");
+        let write = |path: &Path, entries: &[Entry]| {
+            fs::write(path, serde_json::to_string(entries).unwrap()).unwrap()
+        };
+        write(&json_path, &[entry("/src", "a.c", &["cc", "a.c"])]);
+        let tmp = temp.path().join("tmp.json");
+        // The other tool rewrites the database in place, and replaces it with one more entry.
+        for replace in [false, true] {
+            update_file(&json_path, |old| {
+                let mut entries: Vec<Entry> = old.iter().cloned().collect();
+                entries.push(entry("/src", &format!("{}.c", replace), &["cc"]));
+                if replace {
+                    write(&tmp, &entries);
+                    fs::rename(&tmp, &json_path)?;
+                } else {
+                    write(&json_path, &entries);
+                }
+                let mut new = old.clone();
+                new.retain(|e| e.file != "a.c");
+                new.insert(entry(
+                    "/src",
+                    "a.c",
+                    &["cc", if replace { "-O2" } else { "-O1" }, "a.c"],
+                ));
+                Ok(new)
+            })
+            .unwrap();
+        }
+        let files: Vec<_> = read_entries(&json_path)
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.file, e.arguments.join(" ")))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                ("a.c".to_string(), "cc -O2 a.c".to_string()),
+                ("false.c".to_string(), "cc".to_string()),
+                ("true.c".to_string(), "cc".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn update_json() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let json_path = temp.path().join("links.json");
+        let tmp = temp.path().join("tmp.json");
+        let mut runs = 0;
+        super::update_json(&json_path, |values: &mut BTreeSet<String>| {
+            runs += 1;
+            if runs == 1 {
+                fs::write(&tmp, "[\"theirs\"]").unwrap();
+                fs::rename(&tmp, &json_path)?;
+            }
+            values.insert("ours".to_string());
+            Ok(())
+        })
+        .unwrap();
+        // Run again on the values of the tool which replaced the file.
+        assert_eq!(runs, 2);
+        let values: BTreeSet<String> = read_json(&json_path).unwrap();
+        assert_eq!(values.into_iter().collect::<Vec<_>>(), ["ours", "theirs"]);
+    }
+
+    #[test]
+    fn control_characters() {
+        let entries: BTreeSet<_> = [entry(
+            "/src",
+            "a.c",
+            &["cc", "-DX=\"a\nb\"", "-DT=\t\r\u{1}\u{7f}", "a.c"],
+        )]
+        .into_iter()
+        .collect();
+        let json = serde_json::to_string_pretty(&entries).unwrap();
+        assert!(!json.contains(|c: char| c < ' ' && c != '\n'));
+        assert_eq!(parse_entries(&json).unwrap(), entries);
+    }
+
+    #[test]
+    fn source_files() {
+        let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            super::source_files(&args(&[
+                "-MD", "-MF", "dep.c", "-include", "pre.c", "-c", "a.c", "-o", "b.c"
+            ])),
+            [("a.c".to_string(), Some("b.c".to_string()))]
+                .into_iter()
+                .collect()
+        );
+        assert_eq!(
+            super::source_files(&args(&["-c", "--", "-a.c", "b.cc"]))
+                .into_keys()
+                .collect::<Vec<_>>(),
+            ["-a.c", "b.cc"]
+        );
+        assert_eq!(
+            super::source_files(&args(&[
+                "-x", "c", "foo.ext", "-x", "none", "bar.c", "baz.h"
+            ]))
+            .into_keys()
+            .collect::<Vec<_>>(),
+            ["bar.c", "foo.ext"]
+        );
+        assert!(super::source_files(&args(&["-x", "c-header", "a.c"])).is_empty());
+        for stdin in [&["-x", "c", "-c", "-"][..], &["-c", "--", "-"]] {
+            assert!(super::source_files(&args(stdin)).is_empty());
+        }
+        for stdout in [
+            &["-c", "a.c", "-o", "-"][..],
+            &["-S", "-o-", "a.c"],
+            &["-E", "a.c"],
+        ] {
+            assert_eq!(super::source_files(&args(stdout))["a.c"], None);
+        }
+    }
+
+    #[test]
+    fn remap_prefixes() {
+        let invocation = Invocation {
+            argv: Vec::new(),
+            arguments: [
+                "cc",
+                "-ffile-prefix-map=/tmp/b=/src",
+                "-c",
+                "/tmp/b/a.c",
+                "x.c",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            directory: "/tmp/b/out".to_string(),
+            files: [("/tmp/b/a.c".to_string(), None), ("x.c".to_string(), None)]
+                .into_iter()
+                .collect(),
+            duration: None,
+            session: None,
+        };
+        let applied = super::remap_prefixes(&invocation, PrefixMap::Apply);
+        assert_eq!(applied.directory, "/src/out");
+        assert_eq!(
+            applied.files.keys().collect::<Vec<_>>(),
+            ["/src/a.c", "x.c"]
+        );
+        let inverted = super::remap_prefixes(&applied, PrefixMap::Invert);
+        assert_eq!(inverted.directory, invocation.directory);
+        assert_eq!(inverted.files, invocation.files);
+    }
+
+    #[test]
+    fn strip_link() {
+        let strings = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let invocation = Invocation {
+            argv: Vec::new(),
+            arguments: strings(&["gcc", "-O2", "main.c", "util.c", "-o", "app", "-lm"]),
+            directory: "/src".to_string(),
+            files: [("main.c".to_string(), None), ("util.c".to_string(), None)]
+                .into_iter()
+                .collect(),
+            duration: None,
+            session: None,
+        };
+        let entries: Vec<Entry> = super::strip_link(&invocation)
+            .unwrap()
+            .iter()
+            .flat_map(|i| i.entries().collect::<Vec<_>>())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].arguments, ["gcc", "-O2", "-c", "util.c"]);
+        assert_eq!(entries[1].output.as_deref(), Some("util.o"));
+        let compile = Invocation {
+            arguments: strings(&["gcc", "-c", "main.c"]),
+            ..invocation
+        };
+        assert!(super::strip_link(&compile).is_none());
+    }
+
+    #[test]
+    fn rebase() {
+        let strings = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let invocation = Invocation {
+            argv: Vec::new(),
+            arguments: strings(&[
+                "cc",
+                "-I.",
+                "-I",
+                "../inc",
+                "-I/usr/include",
+                "@opts.rsp",
+                "-fsanitize-ignorelist=ign.txt",
+                "-c",
+                "a.c",
+                "-o",
+                "a.o",
+            ]),
+            directory: "/src/lib/".to_string(),
+            files: [("a.c".to_string(), Some("a.o".to_string()))]
+                .into_iter()
+                .collect(),
+            duration: None,
+            session: None,
+        };
+        let rebased = super::rebase(&invocation, Path::new("/src"));
+        assert_eq!(rebased.directory, "/src");
+        assert_eq!(
+            rebased.arguments,
+            strings(&[
+                "cc",
+                "-Ilib",
+                "-I",
+                "inc",
+                "-I/usr/include",
+                "@lib/opts.rsp",
+                "-fsanitize-ignorelist=lib/ign.txt",
+                "-c",
+                "lib/a.c",
+                "-o",
+                "lib/a.o"
+            ])
+        );
+        assert_eq!(
+            rebased.files.into_iter().collect::<Vec<_>>(),
+            [("lib/a.c".to_string(), Some("lib/a.o".to_string()))]
+        );
+        let outside = super::rebase(&invocation, Path::new("/other"));
+        assert_eq!(outside.directory, invocation.directory);
+
+        // The paths passed through to the preprocessor cannot be told apart.
+        let mut passed = invocation.clone();
+        passed.arguments.push("-Wp,-MD,a.d".to_string());
+        let kept = super::rebase(&passed, Path::new("/src"));
+        assert_eq!(kept.directory, invocation.directory);
+        assert_eq!(kept.arguments, passed.arguments);
+    }
+}
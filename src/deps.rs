@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::args;
+use crate::db::{self, Entry, Invocation};
+use crate::paths;
+
+/// Returns the database header dependencies are recorded into, i.e., `header_deps.json` next to
+/// the database at `json_path`.
+pub fn database(json_path: &Path) -> PathBuf {
+    json_path.with_file_name("header_deps.json")
+}
+
+/// Parses a dependency file in the Makefile syntax written by `-MD` and friends into its rules,
+/// i.e., targets together with their prerequisites.
+pub fn parse(data: &str) -> Vec<(Vec<String>, Vec<String>)> {
+    let mut rules = Vec::new();
+    let data = data.replace("\\\r\n", " ").replace("\\\n", " ");
+    for line in data.lines() {
+        let mut words = Vec::new();
+        let mut word = String::new();
+        let mut colon = None;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if matches!(chars.peek(), Some(' ' | '#' | '\\')) => {
+                    word.push(chars.next().unwrap())
+                }
+                '$' if chars.peek() == Some(&'$') => word.push(chars.next().unwrap()),
+                // A colon followed by a space separates the targets, others are part of a path,
+                // e.g. `C:\foo.h`.
+                ':' if colon.is_none() && matches!(chars.peek(), None | Some(' ' | '\t')) => {
+                    if !word.is_empty() {
+                        words.push(std::mem::take(&mut word));
+                    }
+                    colon = Some(words.len());
+                }
+                ' ' | '\t' => {
+                    if !word.is_empty() {
+                        words.push(std::mem::take(&mut word));
+                    }
+                }
+                c => word.push(c),
+            }
+        }
+        if !word.is_empty() {
+            words.push(word);
+        }
+        if let Some(colon) = colon {
+            let prerequisites = words.split_off(colon);
+            rules.push((words, prerequisites));
+        }
+    }
+    rules
+}
+
+/// Returns the dependency file written by the compile of `entry`, i.e., the one given by `-MF`
+/// or else, with `-MD` or `-MMD`, the one named after the output.
+pub fn depfile(entry: &Entry) -> Option<PathBuf> {
+    let flags = args::flags(entry.arguments.get(1..)?);
+    let mut depfile = None;
+    let mut generated = false;
+    for flag in &flags {
+        match flag.name.as_str() {
+            "-MF" => depfile = flag.value.clone(),
+            "-MD" | "-MMD" => generated = true,
+            // The preprocessor takes the file as next argument, e.g. `-Wp,-MD,a.d`.
+            name => {
+                let mut args = name.strip_prefix("-Wp,").unwrap_or_default().split(',');
+                if let (Some("-MD" | "-MMD"), Some(file)) = (args.next(), args.next()) {
+                    depfile = Some(file.to_string());
+                }
+            }
+        }
+    }
+    let depfile = match depfile {
+        Some(depfile) => depfile,
+        None if generated => {
+            let output = match &entry.output {
+                Some(output) => PathBuf::from(output),
+                None => PathBuf::from(Path::new(&entry.file).file_name()?),
+            };
+            output.with_extension("d").to_string_lossy().into_owned()
+        }
+        None => return None,
+    };
+    Some(paths::resolve(&entry.directory, &depfile))
+}
+
+/// Returns the headers the compile of `entry` included according to its dependency file, if the
+/// compile writes one and it exists.
+pub fn headers(entry: &Entry) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+    let depfile = match depfile(entry) {
+        Some(depfile) => depfile,
+        None => return Ok(None),
+    };
+    let data = match fs::read_to_string(&depfile) {
+        Ok(data) => data,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(format!("{}: {}", depfile.display(), error).into()),
+    };
+    let source = paths::resolve(&entry.directory, &entry.file);
+    let mut headers = Vec::new();
+    // The phony targets of `-MP` have no prerequisites and thus add nothing.
+    for (_, prerequisites) in parse(&data) {
+        for p in prerequisites {
+            let header = paths::resolve(&entry.directory, &p)
+                .to_string_lossy()
+                .into_owned();
+            if Path::new(&header) != source && !headers.contains(&header) {
+                headers.push(header);
+            }
+        }
+    }
+    Ok(Some(headers))
+}
+
+/// Records the headers of the translation units of `invocation` into the database at
+/// `json_path` which maps each source to the headers it included.  Translation units without a
+/// dependency file are left alone.
+pub fn record(json_path: &Path, invocation: &Invocation) -> Result<(), Box<dyn Error>> {
+    let mut found = BTreeMap::new();
+    for entry in invocation.entries() {
+        if let Some(headers) = headers(&entry)? {
+            let source = paths::resolve(&entry.directory, &entry.file);
+            found.insert(source.to_string_lossy().into_owned(), headers);
+        }
+    }
+    if found.is_empty() {
+        return Ok(());
+    }
+    db::update_json(json_path, |all: &mut BTreeMap<String, Vec<String>>| {
+        all.extend(found.clone());
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse() {
+        let data = "foo.o: foo.c include/foo.h \\\n  /usr/include/my\\ dir/bar.h C:\\x.h\n\ninclude/foo.h:\n";
+        assert_eq!(
+            super::parse(data),
+            [
+                (
+                    strings(&["foo.o"]),
+                    strings(&[
+                        "foo.c",
+                        "include/foo.h",
+                        "/usr/include/my dir/bar.h",
+                        "C:\\x.h"
+                    ])
+                ),
+                (strings(&["include/foo.h"]), Vec::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn depfile() {
+        let entry = |arguments: &[&str], output: Option<&str>| Entry {
+            output: output.map(str::to_string),
+            ..db::entry("/build", "src/a.c", arguments)
+        };
+        assert_eq!(
+            super::depfile(&entry(
+                &["cc", "-MD", "-MF", "deps/a.d", "-c", "src/a.c"],
+                None
+            )),
+            Some(PathBuf::from("/build/deps/a.d"))
+        );
+        assert_eq!(
+            super::depfile(&entry(&["cc", "-MMD", "-c", "src/a.c"], Some("obj/a.o"))),
+            Some(PathBuf::from("/build/obj/a.d"))
+        );
+        assert_eq!(
+            super::depfile(&entry(&["cc", "-MD", "-c", "src/a.c"], None)),
+            Some(PathBuf::from("/build/a.d"))
+        );
+        assert_eq!(
+            super::depfile(&entry(&["cc", "-Wp,-MD,deps/.a.d", "-c", "src/a.c"], None)),
+            Some(PathBuf::from("/build/deps/.a.d"))
+        );
+        assert_eq!(super::depfile(&entry(&["cc", "-c", "src/a.c"], None)), None);
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::output::{self, Profile};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Change<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Computes a minimal diff between the argument vectors `old` and `new` by means of a longest
+/// common subsequence.
+pub fn diff<'a>(old: &'a [String], new: &'a [String]) -> Vec<Change<'a>> {
+    let (n, m) = (old.len(), new.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            changes.push(Change::Same(&old[i]));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            changes.push(Change::Removed(&old[i]));
+            i += 1;
+        } else {
+            changes.push(Change::Added(&new[j]));
+            j += 1;
+        }
+    }
+    changes
+}
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const BOLD: &str = "\x1b[1m";
+const REVERSE: &str = "\x1b[7m";
+const RESET: &str = "\x1b[0m";
+
+/// Returns true if output to the file descriptor `fd` is colored, i.e., if it is a terminal
+/// other than a dumb one and `NO_COLOR` is not set.  `CLICOLOR_FORCE` colors in any case unless
+/// the output profile is `ci`.
+pub fn color_enabled(fd: i32) -> bool {
+    if output::profile() == Profile::Ci {
+        return false;
+    }
+    if env::var_os("NO_COLOR").map_or(false, |v| !v.is_empty()) {
+        return false;
+    }
+    if env::var_os("CLICOLOR_FORCE").map_or(false, |v| !v.is_empty() && v != "0") {
+        return true;
+    }
+    if env::var("TERM").map_or(false, |term| term == "dumb") {
+        return false;
+    }
+    #[cfg(unix)]
+    {
+        unsafe { libc::isatty(fd) == 1 }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = fd;
+        false
+    }
+}
+
+/// Renders `arg` prefixed by `sign` in `color` where the part differing from `other`, i.e.,
+/// all but the common prefix and suffix, is highlighted.
+fn highlight(sign: char, arg: &str, other: &str, color: &str) -> String {
+    let prefix = arg
+        .char_indices()
+        .zip(other.chars())
+        .take_while(|((_, a), b)| a == b)
+        .last()
+        .map_or(0, |((i, a), _)| i + a.len_utf8());
+    let suffix = arg[prefix..]
+        .chars()
+        .rev()
+        .zip(other[prefix.min(other.len())..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len_utf8())
+        .sum::<usize>();
+    let end = arg.len() - suffix;
+    format!(
+        "{}{} {}{}{}{}{}{}",
+        color,
+        sign,
+        &arg[..prefix],
+        REVERSE,
+        &arg[prefix..end],
+        RESET,
+        color,
+        &arg[end..]
+    ) + RESET
+}
+
+/// Renders `changes` one argument per line, in color if `color` is set.  A removed argument
+/// directly replaced by an added one, e.g. `-O2` by `-O3`, has the characters differing
+/// highlighted.
+pub fn render(changes: &[Change], color: bool) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut i = 0;
+    while i < changes.len() {
+        let removed = changes[i..]
+            .iter()
+            .take_while(|c| matches!(c, Change::Removed(_)))
+            .count();
+        let added = changes[i + removed..]
+            .iter()
+            .take_while(|c| matches!(c, Change::Added(_)))
+            .count();
+        if removed == 0 && added == 0 {
+            if let Change::Same(arg) = changes[i] {
+                lines.push(format!("  {}", arg));
+            }
+            i += 1;
+            continue;
+        }
+        let removed_args = &changes[i..i + removed];
+        let added_args = &changes[i + removed..i + removed + added];
+        let pairs = removed.min(added);
+        let text = |c: &Change<'_>| match *c {
+            Change::Same(a) | Change::Removed(a) | Change::Added(a) => a.to_string(),
+        };
+        for (k, c) in removed_args.iter().enumerate() {
+            let arg = text(c);
+            lines.push(match (color, k < pairs) {
+                (false, _) => format!("- {}", arg),
+                (true, true) => highlight('-', &arg, &text(&added_args[k]), RED),
+                (true, false) => format!("{}- {}{}", RED, arg, RESET),
+            });
+        }
+        for (k, c) in added_args.iter().enumerate() {
+            let arg = text(c);
+            lines.push(match (color, k < pairs) {
+                (false, _) => format!("+ {}", arg),
+                (true, true) => highlight('+', &arg, &text(&removed_args[k]), GREEN),
+                (true, false) => format!("{}+ {}{}", GREEN, arg, RESET),
+            });
+        }
+        i += removed + added;
+    }
+    lines
+}
+
+/// Writes the argument changes between two variants of the same entry, one argument per line,
+/// in color if `color` is set.
+pub fn write_entry_diff(
+    out: &mut dyn Write,
+    old: &Entry,
+    new: &Entry,
+    color: bool,
+) -> io::Result<()> {
+    let (bold, reset) = if color { (BOLD, RESET) } else { ("", "") };
+    writeln!(out, "{}--- {}/{}{}", bold, old.directory, old.file, reset)?;
+    writeln!(out, "{}+++ {}/{}{}", bold, new.directory, new.file, reset)?;
+    for line in render(&diff(&old.arguments, &new.arguments), color) {
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Entries are matched by their directory and file.
+type Key<'a> = (&'a str, &'a str);
+
+fn by_key(entries: &BTreeSet<Entry>) -> BTreeMap<Key<'_>, Vec<&Entry>> {
+    let mut map: BTreeMap<Key<'_>, Vec<&Entry>> = BTreeMap::new();
+    for e in entries {
+        map.entry((&e.directory, &e.file)).or_default().push(e);
+    }
+    map
+}
+
+/// Writes the differences between the databases `old` and `new`, i.e., the entries removed,
+/// added, and changed.  Returns the number of entries which differ.
+fn write_db_diff(
+    out: &mut dyn Write,
+    old: &BTreeSet<Entry>,
+    new: &BTreeSet<Entry>,
+    color: bool,
+) -> io::Result<usize> {
+    let (old, new) = (by_key(old), by_key(new));
+    let (red, green, reset) = if color {
+        (RED, GREEN, RESET)
+    } else {
+        ("", "", "")
+    };
+    let mut count = 0;
+    let keys: BTreeSet<_> = old.keys().chain(new.keys()).collect();
+    for key in keys {
+        match (old.get(key), new.get(key)) {
+            (Some(o), Some(n)) if o == n => (),
+            (Some(o), Some(n)) => {
+                // Variants recorded by `CDBGEN_ON_CONFLICT=keep` are compared pairwise.
+                for (o, n) in o.iter().zip(n.iter()) {
+                    if o != n {
+                        write_entry_diff(out, o, n, color)?;
+                    }
+                }
+                count += 1;
+            }
+            (Some(_), None) => {
+                writeln!(out, "{}removed: {}/{}{}", red, key.0, key.1, reset)?;
+                count += 1;
+            }
+            (None, Some(_)) => {
+                writeln!(out, "{}added: {}/{}{}", green, key.0, key.1, reset)?;
+                count += 1;
+            }
+            (None, None) => (),
+        }
+    }
+    Ok(count)
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &[], &["--color", "--db"])?;
+    let (old, new) = match opts.positional.as_slice() {
+        [old] => (PathBuf::from(old), opts.database()),
+        [old, new] => (PathBuf::from(old), PathBuf::from(new)),
+        _ => return Err("usage: cdbgen diff [--color auto|always|never] OLD [NEW]".into()),
+    };
+    let color = match opts.value("--color").unwrap_or("auto") {
+        "auto" => color_enabled(1),
+        "always" => true,
+        "never" => false,
+        when => return Err(format!("unknown color mode '{}'", when).into()),
+    };
+    let mut stdout = io::stdout();
+    let count = write_db_diff(
+        &mut stdout,
+        &db::read_entries(&old)?,
+        &db::read_entries(&new)?,
+        color,
+    )?;
+    println!("{} entries differ", count);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn diff() {
+        let old = strings(&["cc", "-O2", "-Werror", "a.c"]);
+        let new = strings(&["cc", "-O3", "a.c", "-g"]);
+        assert_eq!(
+            super::diff(&old, &new),
+            [
+                Change::Same("cc"),
+                Change::Removed("-O2"),
+                Change::Removed("-Werror"),
+                Change::Added("-O3"),
+                Change::Same("a.c"),
+                Change::Added("-g"),
+            ]
+        );
+    }
+
+    #[test]
+    fn render() {
+        let old = strings(&["cc", "-O2", "-Werror", "a.c"]);
+        let new = strings(&["cc", "-O3", "a.c"]);
+        let changes = super::diff(&old, &new);
+        assert_eq!(
+            super::render(&changes, false),
+            ["  cc", "- -O2", "- -Werror", "+ -O3", "  a.c"]
+        );
+        let colored = super::render(&changes, true);
+        assert_eq!(colored[1], "\x1b[31m- -O\x1b[7m2\x1b[0m\x1b[31m\x1b[0m");
+        assert_eq!(colored[2], "\x1b[31m- -Werror\x1b[0m");
+        assert_eq!(colored[3], "\x1b[32m+ -O\x1b[7m3\x1b[0m\x1b[32m\x1b[0m");
+    }
+}
@@ -0,0 +1,258 @@
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use which::which;
+
+use crate::cli::Options;
+use crate::config::{Config, Storage};
+use crate::db;
+use crate::install::{self, Status};
+use crate::paths;
+use crate::shims::{self, KNOWN_COMPILERS};
+use crate::staging;
+
+/// Result of a single check.
+#[derive(Debug, PartialEq)]
+struct Check {
+    name: &'static str,
+    /// What was found, or what is wrong.
+    detail: String,
+    /// How to fix the problem, unless everything is fine.
+    fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: String) -> Self {
+        Check {
+            name,
+            detail,
+            fix: None,
+        }
+    }
+
+    fn failed(name: &'static str, detail: String, fix: String) -> Self {
+        Check {
+            name,
+            detail,
+            fix: Some(fix),
+        }
+    }
+}
+
+/// Checks that every shim resolves to a compiler and refers to an existing cdbgen binary.
+fn check_shims(search: &std::ffi::OsStr) -> Vec<Check> {
+    let shims = match install::find_all(search) {
+        Ok(shims) => shims,
+        Err(error) => {
+            return vec![Check::failed(
+                "shims",
+                format!("cannot inspect shims: {}", error),
+                "check the permissions of the directories in PATH".to_string(),
+            )]
+        }
+    };
+    let mut checks: Vec<_> = shims
+        .iter()
+        .filter_map(|shim| match shim.status {
+            Status::Ok => None,
+            Status::Broken => Some(Check::failed(
+                "shims",
+                format!("{} does not refer to cdbgen anymore", shim.path.display()),
+                "run 'cdbgen install --repair' or 'cdbgen shims clean'".to_string(),
+            )),
+            Status::Stale => Some(Check::failed(
+                "shims",
+                format!("{} names no compiler on PATH", shim.path.display()),
+                "install the compiler or run 'cdbgen shims clean'".to_string(),
+            )),
+        })
+        .collect();
+    if checks.is_empty() {
+        checks.push(Check::ok(
+            "shims",
+            format!("{} shim(s) resolve to a compiler", shims.len()),
+        ));
+    }
+    checks
+}
+
+/// Returns the directory of the first masquerading shim named `compiler` on `search` and the
+/// program found first if it is not cdbgen, i.e., if the shim is shadowed.
+fn shadowed(compiler: &str, search: &std::ffi::OsStr, cdbgen: &Path) -> Option<(PathBuf, PathBuf)> {
+    let is_cdbgen = |p: &Path| p.canonicalize().map_or(false, |p| p == cdbgen);
+    let candidates: Vec<PathBuf> = env::split_paths(search)
+        .map(|dir| dir.join(compiler))
+        .filter(|p| shims::is_executable(p))
+        .collect();
+    let shim = candidates.iter().find(|p| is_cdbgen(p))?;
+    let first = candidates.first()?;
+    if is_cdbgen(first) {
+        None
+    } else {
+        Some((shim.parent()?.to_path_buf(), first.clone()))
+    }
+}
+
+/// Checks that shims masquerading as compilers come first on `PATH`.
+fn check_path(search: &std::ffi::OsStr) -> Check {
+    let cdbgen = match env::current_exe().and_then(|p| p.canonicalize()) {
+        Ok(cdbgen) => cdbgen,
+        Err(error) => {
+            return Check::failed(
+                "path",
+                format!("cannot locate cdbgen itself: {}", error),
+                "reinstall cdbgen".to_string(),
+            )
+        }
+    };
+    for compiler in KNOWN_COMPILERS {
+        if let Some((dir, first)) = shadowed(compiler, search, &cdbgen) {
+            return Check::failed(
+                "path",
+                format!(
+                    "shim {} is shadowed by {}",
+                    dir.join(compiler).display(),
+                    first.display()
+                ),
+                format!("put {} in front of PATH", dir.display()),
+            );
+        }
+    }
+    Check::ok("path", "no shim is shadowed by a compiler".to_string())
+}
+
+/// Checks that files in `dir` can be locked the way databases are.
+fn check_locking(dir: &Path) -> Check {
+    let probe = dir.join(format!(".cdbgen-doctor.{}", std::process::id()));
+    let result = File::create(&probe)
+        .map_err(Box::<dyn Error>::from)
+        // The lock is released once the file is closed.
+        .and_then(|mut file| db::lock(&mut file));
+    let _ = fs::remove_file(&probe);
+    match result {
+        Ok(()) => Check::ok(
+            "locking",
+            format!("files in {} can be locked", dir.display()),
+        ),
+        Err(error) => Check::failed(
+            "locking",
+            format!("cannot lock files in {}: {}", dir.display(), error),
+            "put the database on a local filesystem or use CDBGEN_STORAGE=fragments".to_string(),
+        ),
+    }
+}
+
+/// Checks that the database at `json_path` is writable and parses, if it exists.
+fn check_database(json_path: &Path) -> Check {
+    if !json_path.exists() {
+        return Check::ok(
+            "database",
+            format!("{} does not exist yet", json_path.display()),
+        );
+    }
+    if let Err(error) = File::options().append(true).open(json_path) {
+        return Check::failed(
+            "database",
+            format!("{} is not writable: {}", json_path.display(), error),
+            "fix its permissions or point CDBGEN elsewhere".to_string(),
+        );
+    }
+    let parsed = fs::read_to_string(json_path)
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|data| db::parse_entries(&data));
+    match parsed {
+        Ok(entries) => Check::ok(
+            "database",
+            format!("{} holds {} entries", json_path.display(), entries.len()),
+        ),
+        Err(error) => Check::failed(
+            "database",
+            format!("{} does not parse: {}", json_path.display(), error),
+            "remove it and rebuild, or restore it from a backup".to_string(),
+        ),
+    }
+}
+
+/// Checks that `compiler` runs and reports its version.
+fn check_compiler(compiler: &str) -> Option<Check> {
+    let path = which(compiler).ok()?;
+    let check = match Command::new(&path).arg("--version").output() {
+        Ok(output) if output.status.success() => Check::ok(
+            "compiler",
+            format!(
+                "{}: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+            ),
+        ),
+        Ok(output) => Check::failed(
+            "compiler",
+            format!("{} --version failed with {}", path.display(), output.status),
+            "check the installation of the compiler".to_string(),
+        ),
+        Err(error) => Check::failed(
+            "compiler",
+            format!("cannot run {}: {}", path.display(), error),
+            "check the installation of the compiler".to_string(),
+        ),
+    };
+    Some(check)
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &[], &["--db"])?;
+    let config = Config::from_env();
+    let cwd = env::current_dir()?;
+    let json_path = paths::normalize(&cwd.join(opts.database()));
+    let search = env::var_os("PATH").unwrap_or_default();
+
+    let mut checks = check_shims(&search);
+    checks.push(check_path(&search));
+    checks.push(check_locking(json_path.parent().unwrap_or(&cwd)));
+    if config.storage == Storage::Shm {
+        if let Some(dir) = staging::path(&json_path)?.parent() {
+            checks.push(check_locking(dir));
+        }
+    }
+    checks.push(check_database(&json_path));
+    checks.extend(KNOWN_COMPILERS.iter().filter_map(|c| check_compiler(c)));
+
+    for check in &checks {
+        let status = if check.fix.is_none() { "ok" } else { "FAIL" };
+        println!("{:<4}  {}: {}", status, check.name, check.detail);
+        if let Some(fix) = &check.fix {
+            println!("      fix: {}", fix);
+        }
+    }
+    let failed = checks.iter().filter(|c| c.fix.is_some()).count();
+    if failed > 0 {
+        return Err(format!("{} check(s) failed", failed).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_database() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let json_path = temp.path().join("compile_commands.json");
+        assert!(super::check_database(&json_path).fix.is_none());
+        fs::write(&json_path, "[]\n").unwrap();
+        assert_eq!(
+            super::check_database(&json_path).detail,
+            format!("{} holds 0 entries", json_path.display())
+        );
+        fs::write(&json_path, "[{").unwrap();
+        assert!(super::check_database(&json_path).fix.is_some());
+        assert!(super::check_locking(temp.path()).fix.is_none());
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::args;
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::deps;
+use crate::implicit;
+use crate::output;
+use crate::paths;
+
+/// The passes of `cdbgen enrich`, each of which may be turned off.
+struct Passes {
+    split: bool,
+    strip_deps: bool,
+    implicit: bool,
+    headers: bool,
+}
+
+/// Returns the arguments of `entry` with the other sources of a multi-source compile and, if
+/// `strip_deps` is set, the dependency generation flags removed.
+fn normalize(entry: &Entry, passes: &Passes) -> Vec<String> {
+    let (compiler, rest) = match entry.arguments.split_first() {
+        Some(split) => split,
+        None => return entry.arguments.clone(),
+    };
+    let sources = db::source_files(rest);
+    let mut arguments = vec![compiler.clone()];
+    for flag in args::flags(rest) {
+        let other_source =
+            flag.is_input() && flag.name != entry.file && sources.contains_key(&flag.name);
+        let dependency = args::is_dependency(&flag);
+        if !(passes.split && other_source || passes.strip_deps && dependency) {
+            arguments.extend(flag.args);
+        }
+    }
+    arguments
+}
+
+/// Returns the entry of `header` derived from the one of the source `entry` including it, i.e.,
+/// the header compiled with the same flags as header of the language of the source.
+fn header_entry(entry: &Entry, header: &str) -> Option<Entry> {
+    let (compiler, rest) = entry.arguments.split_first()?;
+    let language = args::language(rest, &entry.file)?;
+    if !matches!(
+        language.as_str(),
+        "c" | "c++" | "objective-c" | "objective-c++"
+    ) {
+        return None;
+    }
+    let mut arguments = vec![compiler.clone()];
+    if !rest.iter().any(|a| a.starts_with("-x")) {
+        arguments.extend(["-x".to_string(), format!("{}-header", language)]);
+    }
+    for flag in args::flags(rest) {
+        if flag.name == "-o" {
+            continue;
+        }
+        if flag.is_input() && flag.name == entry.file {
+            arguments.push(header.to_string());
+        } else {
+            arguments.extend(flag.args);
+        }
+    }
+    Some(Entry {
+        directory: entry.directory.clone(),
+        file: header.to_string(),
+        arguments,
+        output: None,
+    })
+}
+
+/// Returns the headers included by the source of `entry` according to `header_deps.json`
+/// (`recorded`) or else to its dependency file.
+fn included(
+    entry: &Entry,
+    recorded: &BTreeMap<String, Vec<String>>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let source = paths::resolve(&entry.directory, &entry.file);
+    match recorded.get(&*source.to_string_lossy()) {
+        Some(headers) => Ok(headers.clone()),
+        None => Ok(deps::headers(entry)?.unwrap_or_default()),
+    }
+}
+
+/// Applies `passes` to `entries` of the database at `json_path` (absolute).  Headers are given
+/// entries only if they reside below the directory of the database, i.e., not for the headers
+/// of the system, and each from the first source including it.
+fn enrich(
+    json_path: &Path,
+    entries: &BTreeSet<Entry>,
+    passes: &Passes,
+) -> Result<BTreeSet<Entry>, Box<dyn Error>> {
+    let recorded: BTreeMap<String, Vec<String>> =
+        match fs::read_to_string(deps::database(json_path)) {
+            Ok(data) if passes.headers => serde_json::from_str(&data)?,
+            _ => BTreeMap::new(),
+        };
+    let root = json_path.parent().unwrap_or_else(|| Path::new("/"));
+    let known: BTreeSet<PathBuf> = entries
+        .iter()
+        .map(|e| paths::resolve(&e.directory, &e.file))
+        .collect();
+    let mut result = BTreeSet::new();
+    let mut headers = BTreeMap::new();
+    for entry in entries {
+        // The dependency file is named by the flags the normalization may strip.
+        let included = if passes.headers {
+            included(entry, &recorded)?
+        } else {
+            Vec::new()
+        };
+        let mut arguments = normalize(entry, passes);
+        if passes.implicit {
+            arguments = implicit::embed(json_path, &entry.directory, &arguments, [&entry.file]);
+        }
+        let entry = Entry {
+            arguments,
+            ..entry.clone()
+        };
+        for header in included {
+            let path = paths::resolve(&entry.directory, &header);
+            if path.starts_with(root) && !known.contains(&path) && !headers.contains_key(&path) {
+                if let Some(header_entry) = header_entry(&entry, &path.to_string_lossy()) {
+                    headers.insert(path, header_entry);
+                }
+            }
+        }
+        result.insert(entry);
+    }
+    result.extend(headers.into_values());
+    Ok(result)
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(
+        args,
+        &[
+            "--no-split",
+            "--no-strip-deps",
+            "--no-implicit",
+            "--no-headers",
+        ],
+        &["--db", "-o"],
+    )?;
+    let passes = Passes {
+        split: !opts.has("--no-split"),
+        strip_deps: !opts.has("--no-strip-deps"),
+        implicit: !opts.has("--no-implicit"),
+        headers: !opts.has("--no-headers"),
+    };
+    let cwd = env::current_dir()?;
+    let json_path = paths::normalize(&cwd.join(opts.database()));
+    match opts.value("-o") {
+        Some(out) => {
+            let entries = enrich(&json_path, &db::read_entries(&json_path)?, &passes)?;
+            fs::write(out, serde_json::to_string_pretty(&entries)? + "\n")?;
+            output::info(&format!("wrote {} entries to {}", entries.len(), out));
+        }
+        None => {
+            db::update_any(&json_path, |entries| enrich(&json_path, entries, &passes))?;
+            output::info(&format!("enriched {}", json_path.display()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enrich() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let dir = temp.path().to_str().unwrap().to_string();
+        fs::write(
+            temp.path().join("a.d"),
+            "a.o: a.c a.h /usr/include/stdio.h\n",
+        )
+        .unwrap();
+        // Kbuild passes the dependency options to the preprocessor.
+        let entry = |file: &str, deps: &[&str]| {
+            db::entry(
+                &dir,
+                file,
+                &[&["no-such-cc"], deps, &["-c", "a.c", "b.c"]].concat(),
+            )
+        };
+        let passes = Passes {
+            split: true,
+            strip_deps: true,
+            implicit: true,
+            headers: true,
+        };
+        let json_path = temp.path().join("compile_commands.json");
+        let entries = super::enrich(
+            &json_path,
+            &[
+                entry("a.c", &["-MD", "-MF", "a.d"]),
+                entry("b.c", &["-Wp,-MD,a.d"]),
+            ]
+            .into(),
+            &passes,
+        )
+        .unwrap();
+        let header = temp.path().join("a.h").to_str().unwrap().to_string();
+        let arguments: Vec<_> = entries
+            .iter()
+            .map(|e| (e.file.clone(), e.arguments.join(" ")))
+            .collect();
+        assert_eq!(
+            arguments,
+            [
+                (
+                    header.clone(),
+                    format!("no-such-cc -x c-header -c {}", header)
+                ),
+                ("a.c".to_string(), "no-such-cc -c a.c".to_string()),
+                ("b.c".to_string(), "no-such-cc -c b.c".to_string()),
+            ]
+        );
+    }
+}
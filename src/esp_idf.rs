@@ -0,0 +1,110 @@
+use std::env;
+use std::error::Error;
+use std::process::Command;
+
+use crate::cli::Options;
+use crate::output;
+use crate::paths;
+use crate::wrap;
+
+/// Returns the arguments of `idf.py` for the project in `project` where cdbgen at `cdbgen` is the
+/// compiler launcher of CMake, running `args` or else `build`.
+fn idf_args(project: &str, args: &[String], cdbgen: &str) -> Vec<String> {
+    let mut result = vec!["-C".to_string(), project.to_string()];
+    for language in ["C", "CXX"] {
+        result.push(format!("-DCMAKE_{}_COMPILER_LAUNCHER={}", language, cdbgen));
+    }
+    if args.is_empty() {
+        result.push("build".to_string());
+    }
+    result.extend(args.iter().cloned());
+    result
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse_command(args, &[], &["--db", "--idf", "-C"])?;
+    let idf = opts.value("--idf").unwrap_or("idf.py");
+    let cwd = env::current_dir()?;
+    let project = paths::normalize(&cwd.join(opts.value("-C").unwrap_or(".")));
+    if !project.join("CMakeLists.txt").is_file() {
+        return Err(format!("{} is no ESP-IDF project", project.display()).into());
+    }
+    let database = match opts.value("--db") {
+        Some(db) => paths::normalize(&cwd.join(db)),
+        None => project.join("compile_commands.json"),
+    };
+    let build = match opts.rest.split_first() {
+        Some((sep, build)) if sep == "--" => build,
+        _ => &opts.rest[..],
+    };
+    let cdbgen = env::current_exe()?;
+    let mut command = Command::new(idf);
+    command
+        .args(idf_args(
+            &project.to_string_lossy(),
+            build,
+            &cdbgen.to_string_lossy(),
+        ))
+        .env("CDBGEN", &database)
+        .env("CDBGEN_CAPTURE", "esp-idf")
+        // The include directories of all components may be passed by response files of Ninja.
+        .env("CDBGEN_RESPONSE_FILES", "expand");
+    if env::var_os("CDBGEN_PRESET").is_none() {
+        command.env("CDBGEN_PRESET", "auto");
+    }
+    let status = command
+        .status()
+        .map_err(|error| format!("cannot run '{}': {}", idf, error))?;
+    if !status.success() {
+        wrap::exit_like(status);
+    }
+    output::info(&format!("recorded into {}", database.display()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::presets;
+
+    #[test]
+    fn idf_args() {
+        assert_eq!(
+            super::idf_args("/p", &[], "/bin/cdbgen").join(" "),
+            "-C /p -DCMAKE_C_COMPILER_LAUNCHER=/bin/cdbgen \
+             -DCMAKE_CXX_COMPILER_LAUNCHER=/bin/cdbgen build"
+        );
+
+        let apply = |compiler: &str, args: &[&str]| {
+            let arguments: Vec<String> = [compiler]
+                .iter()
+                .chain(args)
+                .map(|s| s.to_string())
+                .collect();
+            presets::detect(compiler).unwrap().apply(&arguments)[1..].join(" ")
+        };
+        assert_eq!(
+            apply(
+                "riscv32-esp-elf-gcc",
+                &[
+                    "-march=rv32imc_zicsr_zifencei",
+                    "-fno-tree-switch-conversion",
+                    "-c",
+                    "a.c"
+                ]
+            ),
+            "--target=riscv32-esp-elf -march=rv32imc_zicsr_zifencei -c a.c"
+        );
+        assert_eq!(
+            apply(
+                "xtensa-esp-elf-gcc",
+                &[
+                    "-mdynconfig=/opt/esp/lib/xtensa_esp32s3.so",
+                    "-mlongcalls",
+                    "-c",
+                    "a.c"
+                ]
+            ),
+            "--target=xtensa-esp-elf -mcpu=esp32s3 -c a.c"
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::db;
+use crate::overhead::{self, Phase};
+use crate::summary::{self, Changes};
+use crate::usage;
+
+thread_local! {
+    static FILES: RefCell<Vec<String>> = RefCell::new(Vec::new());
+    static FAILURE: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Notes the source files the invocation records.
+pub fn note_files<'a, I: IntoIterator<Item = &'a String>>(files: I) {
+    FILES.with(|f| f.borrow_mut().extend(files.into_iter().cloned()));
+}
+
+/// Notes that recording failed with `error` even though the compiler is run anyway.
+pub fn note_failure(error: &dyn Error) {
+    FAILURE.with(|f| *f.borrow_mut() = Some(error.to_string()));
+}
+
+/// What the recorder did for a single invocation.
+#[derive(Debug, Serialize)]
+struct Event<'a> {
+    timestamp: u64,
+    pid: u32,
+    /// The command cdbgen was invoked as, e.g. `cdbgen-gcc`.
+    shim: String,
+    /// The compiler run in the end, unless it was not found.
+    compiler: Option<&'a Path>,
+    directory: PathBuf,
+    files: Vec<String>,
+    databases: BTreeMap<PathBuf, Changes>,
+    /// The time of the phases of recording in microseconds, see `overhead::Phase`.
+    resolve_us: u64,
+    lock_wait_us: u64,
+    parse_us: u64,
+    write_us: u64,
+    /// The time cdbgen took altogether without the compile.
+    overhead_us: u64,
+    /// Either `recorded`, `skipped` if nothing was to be recorded, or `failed`.
+    outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Appends the event of the invocation of `compiler` to the log named by `CDBGEN_EVENTS`, if any,
+/// and counts it in the statistics named by `CDBGEN_SELF_STATS`, if any.  `compile` is the time
+/// of the compile itself if it was run as child and `error` the reason recording failed, if it
+/// did and was not noted by `note_failure` already.
+pub fn log(
+    compiler: Option<&Path>,
+    compile: Duration,
+    error: Option<&dyn Error>,
+) -> Result<(), Box<dyn Error>> {
+    let log = env::var_os("CDBGEN_EVENTS");
+    let stats = usage::path();
+    if log.is_none() && stats.is_none() {
+        return Ok(());
+    }
+    let files = FILES.with(|f| f.borrow().clone());
+    let error = error
+        .map(|e| e.to_string())
+        .or_else(|| FAILURE.with(|f| f.borrow().clone()));
+    let outcome = if error.is_some() {
+        "failed"
+    } else if files.is_empty() {
+        "skipped"
+    } else {
+        "recorded"
+    };
+    let event = Event {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        pid: std::process::id(),
+        shim: env::args().next().unwrap_or_default(),
+        compiler,
+        directory: env::current_dir().unwrap_or_default(),
+        files,
+        databases: summary::recorded(),
+        resolve_us: overhead::micros(Phase::Resolve),
+        lock_wait_us: overhead::micros(Phase::LockWait),
+        parse_us: overhead::micros(Phase::Parse),
+        write_us: overhead::micros(Phase::Write),
+        overhead_us: overhead::total(compile).as_micros() as u64,
+        outcome,
+        error,
+    };
+    if let Some(stats) = stats {
+        let name = compiler
+            .and_then(|c| c.file_name())
+            .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+        usage::count(
+            &stats,
+            &usage::Invocation {
+                timestamp: event.timestamp,
+                compiler: &name,
+                directory: &event.directory,
+                outcome,
+                overhead_us: event.overhead_us,
+                lock_wait_us: event.lock_wait_us,
+            },
+        )?;
+    }
+    if let Some(log) = log {
+        db::append_jsonl(Path::new(&log), &serde_json::to_string(&event)?)?;
+    }
+    Ok(())
+}
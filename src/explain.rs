@@ -0,0 +1,212 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::error::Error;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::meta::{self, Meta, Record};
+use crate::paths;
+use crate::stats;
+use crate::time;
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    /// The entry is in the database as it was recorded last.
+    Current,
+    /// The entry is in the database but was modified since it was recorded, e.g. by
+    /// `rewrite-flags` or by some other tool.
+    Modified,
+    /// The entry is in the database but there is no provenance for it.
+    Unknown,
+    /// The entry was recorded once but is not part of the database anymore.
+    Removed,
+}
+
+#[derive(Debug, Serialize)]
+struct Explanation {
+    directory: String,
+    file: String,
+    status: Status,
+    arguments: Option<Vec<String>>,
+    history: Vec<Record>,
+}
+
+fn explain(entries: &BTreeSet<Entry>, meta: &Meta, path: &Path) -> Vec<Explanation> {
+    let matches = |directory: &str, file: &str| {
+        let p = paths::resolve(directory, file);
+        p == path || (path.is_relative() && p.ends_with(path))
+    };
+    let mut result: Vec<_> = entries
+        .iter()
+        .filter(|e| matches(&e.directory, &e.file))
+        .map(|e| {
+            let history = meta
+                .find(&e.directory, &e.file)
+                .map(|m| m.history.clone())
+                .unwrap_or_default();
+            let status = match history.last() {
+                Some(last) if last.arguments == e.arguments => Status::Current,
+                Some(_) => Status::Modified,
+                None => Status::Unknown,
+            };
+            Explanation {
+                directory: e.directory.clone(),
+                file: e.file.clone(),
+                status,
+                arguments: Some(e.arguments.clone()),
+                history,
+            }
+        })
+        .collect();
+    result.extend(
+        meta.entries
+            .iter()
+            .filter(|m| matches(&m.directory, &m.file))
+            .filter(|m| {
+                !entries
+                    .iter()
+                    .any(|e| e.directory == m.directory && e.file == m.file)
+            })
+            .map(|m| Explanation {
+                directory: m.directory.clone(),
+                file: m.file.clone(),
+                status: Status::Removed,
+                arguments: None,
+                history: m.history.clone(),
+            }),
+    );
+    result
+}
+
+fn print(explanation: &Explanation) {
+    println!(
+        "{} (directory {})",
+        paths::resolve(&explanation.directory, &explanation.file).display(),
+        explanation.directory
+    );
+    match &explanation.status {
+        Status::Current => println!("  status: current"),
+        Status::Modified => println!("  status: modified since it was recorded last"),
+        Status::Unknown => println!("  status: no provenance recorded (is CDBGEN_META set?)"),
+        Status::Removed => println!("  status: removed from the database"),
+    }
+    if let Some(arguments) = &explanation.arguments {
+        println!("  arguments: {}", arguments.join(" "));
+    }
+    let n = explanation.history.len();
+    for (i, r) in explanation.history.iter().enumerate().rev() {
+        let superseded = if i + 1 == n { "" } else { " (superseded)" };
+        println!(
+            "  recorded {} (last seen {}) by cdbgen {}{}",
+            time::format(r.recorded_at),
+            time::format(r.last_seen),
+            r.cdbgen_version,
+            superseded
+        );
+        println!("    invocation: {}", r.invocation.join(" "));
+        if let Some(ms) = r.duration_ms {
+            println!("    compile time: {:.2}s", ms as f64 / 1000.0);
+        }
+        for (name, value) in &r.environment {
+            println!("    environment: {}={}", name, value);
+        }
+        if r.invocation.get(1..) != r.arguments.get(1..) {
+            println!("    arguments: {}", r.arguments.join(" "));
+        }
+    }
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &["--json"], &["--db"])?;
+    if opts.positional.is_empty() {
+        return Err("usage: cdbgen explain [--json] FILE...".into());
+    }
+    let json_path = opts.database();
+    let entries = db::read_entries(&json_path)?;
+    let meta = meta::read(&json_path)?;
+
+    let cwd = env::current_dir()?;
+    let mut explanations = Vec::new();
+    for file in &opts.positional {
+        let found = explain(&entries, &meta, &paths::normalize(Path::new(file)));
+        let found = if found.is_empty() && Path::new(file).is_relative() {
+            explain(&entries, &meta, &paths::normalize(&cwd.join(file)))
+        } else {
+            found
+        };
+        if found.is_empty() {
+            return Err(format!("no entry found for '{}'", file).into());
+        }
+        explanations.extend(found);
+    }
+
+    if opts.has("--json") {
+        println!("{}", serde_json::to_string_pretty(&explanations)?);
+    } else {
+        if let Some(header) = &meta.header {
+            stats::print_header(header);
+        }
+        for e in &explanations {
+            print(e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Invocation;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn explain() {
+        let invocation = |file: &str, arguments: &[&str]| Invocation {
+            argv: strings(arguments),
+            arguments: strings(arguments),
+            directory: "/src".to_string(),
+            files: [(file.to_string(), None)].into_iter().collect(),
+            duration: None,
+            session: None,
+        };
+        let mut meta = Meta::default();
+        meta::merge(&mut meta, &invocation("a.c", &["cc", "-O2", "a.c"]), 1);
+        meta::merge(&mut meta, &invocation("b.c", &["cc", "b.c"]), 1);
+        meta::merge(&mut meta, &invocation("c.c", &["cc", "c.c"]), 1);
+        let entries: BTreeSet<_> = invocation("a.c", &["cc", "-O2", "a.c"])
+            .entries()
+            .chain(invocation("b.c", &["cc", "-O3", "b.c"]).entries())
+            .chain(invocation("d.c", &["cc", "d.c"]).entries())
+            .collect();
+
+        let status = |path: &str| {
+            let found = super::explain(&entries, &meta, Path::new(path));
+            assert_eq!(found.len(), 1);
+            found.into_iter().next().unwrap().status
+        };
+        assert_eq!(status("/src/a.c"), Status::Current);
+        assert_eq!(status("b.c"), Status::Modified);
+        assert_eq!(status("c.c"), Status::Removed);
+        assert_eq!(status("d.c"), Status::Unknown);
+    }
+
+    #[test]
+    fn out_of_tree() {
+        let entries: BTreeSet<_> = ["../../src/foo.c", "/proj/src/bar.c"]
+            .iter()
+            .map(|file| db::entry("/proj/build/sub", file, &["cc", "-c", file]))
+            .collect();
+        let meta = Meta::default();
+        for path in ["/proj/src/foo.c", "src/foo.c", "/proj/src/bar.c", "bar.c"] {
+            assert_eq!(super::explain(&entries, &meta, Path::new(path)).len(), 1);
+        }
+        assert!(super::explain(&entries, &meta, Path::new("/proj/build/src/foo.c")).is_empty());
+    }
+}
@@ -0,0 +1,316 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::cli::Options;
+use crate::coexist;
+use crate::config::{Coexist, Compaction, Config};
+use crate::db::{self, Entry};
+use crate::output;
+use crate::staging;
+
+/// Age after which the marker of a compaction is taken to be left over by a crashed one.
+const STALE_COMPACTION: Duration = Duration::from_secs(10 * 60);
+
+/// Time in nanoseconds since the epoch the fragments of this process are named by instead of the
+/// time they are written, if not zero.  See `stamp`.
+static STAMP: AtomicU64 = AtomicU64::new(0);
+
+/// Names the fragments written by this process by `time` instead of the time they are written,
+/// e.g. by the time of the compile whose entries are recorded in the background, such that they
+/// are merged in the order of the compiles.
+pub fn stamp(time: SystemTime) {
+    if let Ok(nanos) = time.duration_since(UNIX_EPOCH) {
+        STAMP.store(nanos.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Directory holding the fragments of the databases in the directory of `json_path`.
+pub fn dir(json_path: &Path) -> PathBuf {
+    json_path.with_file_name(".cdbgen")
+}
+
+/// Returns the prefix of the names of the fragments of the database at `json_path`.
+fn prefix(json_path: &Path) -> String {
+    format!(
+        "{}.",
+        json_path.file_name().unwrap_or_default().to_string_lossy()
+    )
+}
+
+/// Writes `entries` into a new fragment of the database at `json_path`.  The fragment is written
+/// under a temporary name and renamed afterwards such that readers never observe a partial
+/// fragment.  No lock is taken.
+pub fn write(json_path: &Path, entries: Vec<Entry>) -> Result<(), Box<dyn Error>> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let dir = dir(json_path);
+    fs::create_dir_all(&dir)?;
+    let nanos = match STAMP.load(Ordering::Relaxed) {
+        0 => SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos(),
+        stamp => stamp as u128,
+    };
+    // Names sort by time such that later fragments are merged last.
+    let name = format!(
+        "{}{:024}.{}.{}.json",
+        prefix(json_path),
+        nanos,
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let temp = dir.join(format!(".{}.tmp", name));
+    fs::write(&temp, serde_json::to_string(&entries)? + "\n")?;
+    fs::rename(&temp, dir.join(name))?;
+    Ok(())
+}
+
+/// Lists the fragments of the database at `json_path` in the order they were written.
+pub fn list(json_path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let entries = match fs::read_dir(dir(json_path)) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error.into()),
+    };
+    let prefix = prefix(json_path);
+    let mut fragments: Vec<_> = entries
+        .flatten()
+        .filter(|e| {
+            let name = e.file_name().to_string_lossy().into_owned();
+            name.starts_with(&prefix) && name.ends_with(".json")
+        })
+        .map(|e| e.path())
+        .collect();
+    fragments.sort();
+    Ok(fragments)
+}
+
+/// Reads the entries of the fragment at `path` unless it was merged and removed meanwhile.
+pub fn read(path: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+    match fs::read_to_string(path) {
+        Ok(data) => Ok(serde_json::from_str(&data)?),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Removes the merged `fragments`.
+pub fn remove(fragments: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    for f in fragments {
+        match fs::remove_file(f) {
+            Err(error) if error.kind() != ErrorKind::NotFound => return Err(error.into()),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Path of the marker of a running compaction of the database at `json_path`.
+fn marker(json_path: &Path) -> PathBuf {
+    dir(json_path).join(format!(".{}compact", prefix(json_path)))
+}
+
+/// Takes the marker of a compaction unless another compaction holds it.
+fn take_marker(json_path: &Path) -> Result<bool, Box<dyn Error>> {
+    let marker = marker(json_path);
+    let stale = fs::metadata(&marker)
+        .and_then(|m| m.modified())
+        .map_or(false, |t| {
+            t.elapsed().unwrap_or_default() > STALE_COMPACTION
+        });
+    if stale {
+        let _ = fs::remove_file(&marker);
+    }
+    match fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(&marker)
+    {
+        Ok(_) => Ok(true),
+        Err(error) if error.kind() == ErrorKind::AlreadyExists => Ok(false),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Returns true if the pending `fragments` exceed the count or size threshold of `compaction`.
+fn exceeds(compaction: &Compaction, fragments: &[PathBuf]) -> bool {
+    if compaction.fragments.map_or(false, |n| fragments.len() >= n) {
+        return true;
+    }
+    compaction.bytes.map_or(false, |bytes| {
+        let total: u64 = fragments
+            .iter()
+            .filter_map(|f| fs::metadata(f).ok())
+            .map(|m| m.len())
+            .sum();
+        total >= bytes
+    })
+}
+
+/// Starts a compaction of the database at `json_path` in the background if a threshold of
+/// `compaction` is exceeded, or else waits for the build to become idle, unless a compaction is
+/// running already.  The compile does not wait for it.
+pub fn schedule(json_path: &Path, compaction: &Compaction) -> Result<(), Box<dyn Error>> {
+    if compaction == &Compaction::default() || marker(json_path).exists() {
+        return Ok(());
+    }
+    let idle = if exceeds(compaction, &list(json_path)?) {
+        0
+    } else {
+        match compaction.idle {
+            Some(idle) => idle,
+            None => return Ok(()),
+        }
+    };
+    let mut command = Command::new(env::current_exe()?);
+    // Invoked by another name, e.g. as a copied shim, cdbgen would not run the subcommand.
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::arg0(&mut command, "cdbgen");
+    command.arg("finalize").arg("--db").arg(json_path).args([
+        "--background",
+        "--idle",
+        &idle.to_string(),
+    ]);
+    if let Some(max_staleness) = compaction.max_staleness {
+        command.args(["--max-staleness", &max_staleness.to_string()]);
+    }
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+/// Waits until no fragment was written for `idle` seconds, but at most `max_staleness` seconds
+/// such that a build which never pauses is compacted now and then.
+fn wait_idle(
+    json_path: &Path,
+    idle: u64,
+    max_staleness: Option<u64>,
+) -> Result<(), Box<dyn Error>> {
+    let idle = Duration::from_secs(idle);
+    let deadline = max_staleness.map(|s| Instant::now() + Duration::from_secs(s));
+    loop {
+        let newest = list(json_path)?
+            .iter()
+            .filter_map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+            .max();
+        let elapsed = match newest {
+            Some(newest) => newest.elapsed().unwrap_or_default(),
+            None => return Ok(()),
+        };
+        if elapsed >= idle || deadline.map_or(false, |d| Instant::now() >= d) {
+            return Ok(());
+        }
+        // Keep the marker fresh such that it is not taken to be stale.
+        fs::File::create(marker(json_path))?;
+        let sleep = idle - elapsed;
+        thread::sleep(match deadline {
+            Some(d) => sleep.min(d.saturating_duration_since(Instant::now())),
+            None => sleep,
+        });
+    }
+}
+
+pub fn main_finalize(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(
+        args,
+        &["--background"],
+        &["--db", "--idle", "--max-staleness"],
+    )?;
+    let json_path = opts.database();
+    if opts.has("--background") {
+        // Fragments written while compacting are left for the next compaction.
+        if !take_marker(&json_path)? {
+            return Ok(());
+        }
+        let idle = opts.value("--idle").unwrap_or("0").parse()?;
+        let max_staleness = opts.value("--max-staleness").map(str::parse).transpose()?;
+        let result = wait_idle(&json_path, idle, max_staleness)
+            .and_then(|_| db::merge_fragments(&json_path));
+        fs::remove_file(marker(&json_path))?;
+        return result;
+    }
+    // The fragments are the ones of the entries owned by cdbgen next to a database of CMake.
+    let coexist = Config::from_env().coexist == Coexist::Cmake;
+    let cmake_path = json_path;
+    let json_path = if coexist {
+        coexist::owned(&cmake_path)
+    } else {
+        cmake_path.clone()
+    };
+    let n = list(&json_path)?.len();
+    let staged = if staging::is_pending(&json_path)? {
+        " and the staging buffer"
+    } else {
+        ""
+    };
+    db::merge_fragments(&json_path)?;
+    if coexist {
+        coexist::sync(&cmake_path)?;
+    }
+    // Fails if fragments of other databases are left.
+    let _ = fs::remove_dir(dir(&json_path));
+    output::info(&format!(
+        "merged {} fragments{} into {}",
+        n,
+        staged,
+        json_path.display()
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn merge_on_read() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let json_path = temp.path().join("compile_commands.json");
+        let entry = |file: &str, arguments: &[&str]| crate::db::entry("/src", file, arguments);
+        super::write(&json_path, vec![entry("a.c", &["cc", "-O2", "a.c"])]).unwrap();
+        super::write(&json_path, vec![entry("a.c", &["cc", "-O3", "a.c"])]).unwrap();
+        super::write(&json_path, vec![entry("b.c", &["cc", "b.c"])]).unwrap();
+        super::write(&temp.path().join("other.json"), Vec::new()).unwrap();
+        assert_eq!(super::list(&json_path).unwrap().len(), 3);
+
+        let entries = crate::db::read_entries(&json_path).unwrap();
+        assert_eq!(
+            entries.into_iter().collect::<Vec<_>>(),
+            [
+                entry("a.c", &["cc", "-O3", "a.c"]),
+                entry("b.c", &["cc", "b.c"])
+            ]
+        );
+        assert!(super::list(&json_path).unwrap().is_empty());
+        assert_eq!(
+            super::list(&temp.path().join("other.json")).unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn exceeds() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let json_path = temp.path().join("compile_commands.json");
+        for _ in 0..3 {
+            super::write(&json_path, Vec::new()).unwrap();
+        }
+        let fragments = super::list(&json_path).unwrap();
+        let compaction = |fragments, bytes| crate::config::Compaction {
+            fragments,
+            bytes,
+            idle: None,
+            max_staleness: None,
+        };
+        assert!(super::exceeds(&compaction(Some(3), None), &fragments));
+        assert!(!super::exceeds(&compaction(Some(4), None), &fragments));
+        assert!(super::exceeds(&compaction(None, Some(9)), &fragments));
+        assert!(!super::exceeds(&compaction(None, Some(10)), &fragments));
+        assert!(!super::exceeds(&compaction(None, None), &fragments));
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::args;
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::git;
+use crate::paths;
+use crate::pattern;
+
+const USAGE: &str =
+    "usage: cdbgen verify-fresh [--db DB] [--json] [--pattern GLOB]... [--exclude GLOB]...";
+
+/// Returns true if the tracked `file` is a source unless patterns are given, i.e., if the driver
+/// takes it for C, C++, or Objective-C by its extension.
+fn is_source(file: &str) -> bool {
+    matches!(
+        args::language_of_extension(file),
+        Some("c" | "c++" | "objective-c" | "objective-c++")
+    )
+}
+
+/// Why the database is not fresh.
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct Report {
+    /// Tracked sources without an entry, relative to the root of the work tree.
+    missing: Vec<String>,
+    /// Files of entries which do not exist anymore.
+    deleted: Vec<PathBuf>,
+}
+
+impl Report {
+    fn is_fresh(&self) -> bool {
+        self.missing.is_empty() && self.deleted.is_empty()
+    }
+}
+
+/// Returns the files tracked by git in the work tree containing the current directory together
+/// with the root of the work tree.
+fn tracked() -> Result<(PathBuf, Vec<String>), Box<dyn Error>> {
+    let root = PathBuf::from(git::run(Path::new("."), &["rev-parse", "--show-toplevel"])?);
+    let files = git::run(&root, &["ls-files", "-z"])?
+        .split('\0')
+        .filter(|f| !f.is_empty())
+        .map(String::from)
+        .collect();
+    Ok((root, files))
+}
+
+/// Checks `entries` against the `sources` of the work tree at `root`.
+fn check(root: &Path, sources: &[&String], entries: &BTreeSet<Entry>) -> Report {
+    let recorded: BTreeSet<PathBuf> = entries
+        .iter()
+        .map(|e| paths::canonical(&e.directory, &e.file))
+        .collect();
+    let root = root.to_string_lossy();
+    let missing = sources
+        .iter()
+        .filter(|f| !recorded.contains(&paths::canonical(&root, f)))
+        .map(|f| f.to_string())
+        .collect();
+    let deleted: BTreeSet<PathBuf> = entries
+        .iter()
+        .map(|e| paths::resolve(&e.directory, &e.file))
+        .filter(|f| !f.exists())
+        .collect();
+    Report {
+        missing,
+        deleted: deleted.into_iter().collect(),
+    }
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &["--json"], &["--db", "--pattern", "--exclude"])?;
+    if !opts.positional.is_empty() {
+        return Err(USAGE.into());
+    }
+    let json_path = env::current_dir()?.join(opts.database());
+    let entries = db::read_entries(&json_path)?;
+    let patterns = opts.values("--pattern");
+    let excludes = opts.values("--exclude");
+    let (root, files) = tracked()?;
+    let sources: Vec<&String> = files
+        .iter()
+        .filter(|f| match patterns.is_empty() {
+            true => is_source(f),
+            false => patterns.iter().any(|p| pattern::glob_match(p, f)),
+        })
+        .filter(|f| !excludes.iter().any(|p| pattern::glob_match(p, f)))
+        .collect();
+    let report = check(&root, &sources, &entries);
+    if opts.has("--json") {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for file in &report.missing {
+            println!("missing\t{}", file);
+        }
+        for file in &report.deleted {
+            println!("deleted\t{}", file.display());
+        }
+    }
+    if !report.is_fresh() {
+        return Err(format!(
+            "{} of {} sources have no entry and {} entries refer to deleted files",
+            report.missing.len(),
+            sources.len(),
+            report.deleted.len()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn check() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let root = temp.path().canonicalize().unwrap();
+        fs::create_dir(root.join("src")).unwrap();
+        for file in ["src/a.c", "src/b.c"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        let entry = |directory: &Path, file: &str| {
+            db::entry(directory.to_str().unwrap(), file, &["cc", file])
+        };
+        let entries = [entry(&root.join("src"), "a.c"), entry(&root, "gone.c")].into();
+        let sources = ["src/a.c".to_string(), "src/b.c".to_string()];
+        let report = super::check(&root, &sources.iter().collect::<Vec<_>>(), &entries);
+        assert_eq!(
+            report,
+            Report {
+                missing: vec!["src/b.c".to_string()],
+                deleted: vec![root.join("gone.c")],
+            }
+        );
+        assert!(!report.is_fresh());
+        let sources: Vec<_> = ["a.c", "b.C", "c.cp", "d.CPP", "e.M", "f.h", "g.s"]
+            .into_iter()
+            .filter(|f| is_source(f))
+            .collect();
+        assert_eq!(sources, ["a.c", "b.C", "c.cp", "d.CPP", "e.M"]);
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde_json::{json, Value};
+use which::which;
+
+use crate::db::{self, Entry};
+use crate::git;
+use crate::meta;
+use crate::paths;
+
+/// Tells which source files disappeared below a root directory.
+pub enum Monitor {
+    /// Queries Watchman for the files deleted since `clock`.
+    Watchman { root: PathBuf, clock: String },
+    /// Asks git for the tracked files deleted from the work tree which is fast if
+    /// `core.fsmonitor` is configured.  `seen` are the ones reported already.
+    Git {
+        root: PathBuf,
+        seen: BTreeSet<PathBuf>,
+    },
+}
+
+/// Runs the Watchman `command` and returns its response.
+fn watchman(command: &Value) -> Result<Value, Box<dyn Error>> {
+    let mut child = Command::new("watchman")
+        .args(["-j", "--no-pretty"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("cannot run watchman: {}", e))?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(command.to_string().as_bytes())?;
+    let output = child.wait_with_output()?;
+    let response: Value = serde_json::from_slice(&output.stdout)?;
+    if let Some(error) = response.get("error") {
+        return Err(format!("watchman: {}", error.as_str().unwrap_or_default()).into());
+    }
+    Ok(response)
+}
+
+/// Returns the new clock and the absolute paths of the deleted files of the response to a
+/// `since` query of the directory `root`.
+fn parse_since(response: &Value, root: &Path) -> (Option<String>, BTreeSet<PathBuf>) {
+    let clock = response["clock"].as_str().map(String::from);
+    let deleted = response["files"]
+        .as_array()
+        .map(|files| {
+            files
+                .iter()
+                .filter(|f| f["exists"] == json!(false))
+                .filter_map(|f| f["name"].as_str())
+                .map(|name| root.join(name))
+                .collect()
+        })
+        .unwrap_or_default();
+    (clock, deleted)
+}
+
+impl Monitor {
+    /// Starts monitoring `root` by `kind`, i.e., `watchman`, `git`, or `auto` for Watchman if it
+    /// is installed and git otherwise.
+    pub fn new(kind: &str, root: &Path) -> Result<Self, Box<dyn Error>> {
+        let root = root.canonicalize()?;
+        let kind = match kind {
+            "auto" if which("watchman").is_ok() => "watchman",
+            "auto" => "git",
+            kind => kind,
+        };
+        match kind {
+            "watchman" => {
+                let watch = watchman(&json!(["watch-project", root]))?;
+                let project = PathBuf::from(watch["watch"].as_str().ok_or("watchman: no watch")?);
+                let clock = watchman(&json!(["clock", project]))?["clock"]
+                    .as_str()
+                    .ok_or("watchman: no clock")?
+                    .to_string();
+                Ok(Monitor::Watchman {
+                    root: project,
+                    clock,
+                })
+            }
+            "git" => {
+                let root = git::run(&root, &["rev-parse", "--show-toplevel"])
+                    .map_err(|_| format!("{} is not in a git work tree", root.display()))?;
+                let root = PathBuf::from(root);
+                Ok(Monitor::Git {
+                    root,
+                    seen: BTreeSet::new(),
+                })
+            }
+            kind => Err(format!("unknown file monitor '{}'", kind).into()),
+        }
+    }
+
+    /// Returns the absolute paths of the files deleted or renamed away since the last call.
+    pub fn deleted(&mut self) -> Result<BTreeSet<PathBuf>, Box<dyn Error>> {
+        match self {
+            Monitor::Watchman { root, clock } => {
+                let query = json!(["query", root, {
+                    "since": clock,
+                    "expression": ["not", "exists"],
+                    "fields": ["name", "exists"],
+                    "empty_on_fresh_instance": true,
+                }]);
+                let (new_clock, deleted) = parse_since(&watchman(&query)?, root);
+                if let Some(new_clock) = new_clock {
+                    *clock = new_clock;
+                }
+                Ok(deleted)
+            }
+            Monitor::Git { root, seen } => {
+                let now: BTreeSet<PathBuf> = git::run(root, &["ls-files", "--deleted", "-z"])?
+                    .split('\0')
+                    .filter(|name| !name.is_empty())
+                    .map(|name| root.join(name))
+                    .collect();
+                let deleted = now.difference(seen).cloned().collect();
+                // Files restored are reported again once deleted again.
+                *seen = now;
+                Ok(deleted)
+            }
+        }
+    }
+}
+
+/// Returns the absolute path of the source file of `entry` with the directory canonicalized.
+fn location(entry: &Entry) -> PathBuf {
+    let directory =
+        fs::canonicalize(&entry.directory).unwrap_or_else(|_| PathBuf::from(&entry.directory));
+    paths::normalize(&directory.join(&entry.file))
+}
+
+/// Removes the entries of the database at `json_path` whose source file is one of `deleted` and
+/// does not exist anymore.  Returns the number of entries removed.
+pub fn prune_deleted(
+    json_path: &Path,
+    deleted: &BTreeSet<PathBuf>,
+) -> Result<usize, Box<dyn Error>> {
+    if deleted.is_empty() || !json_path.exists() {
+        return Ok(0);
+    }
+    let mut removed = 0;
+    db::update_any(json_path, |entries| {
+        let kept: BTreeSet<Entry> = entries
+            .iter()
+            .filter(|e| {
+                let file = location(e);
+                !deleted.contains(&file) || file.exists()
+            })
+            .cloned()
+            .collect();
+        if kept.len() < entries.len() {
+            meta::retain_unlocked(json_path, &kept)?;
+        }
+        removed = entries.len() - kept.len();
+        Ok(kept)
+    })?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_since() {
+        let response = json!({
+            "clock": "c:1:2",
+            "files": [
+                {"name": "src/gone.c", "exists": false},
+                {"name": "src/here.c", "exists": true},
+            ],
+        });
+        let (clock, deleted) = super::parse_since(&response, Path::new("/repo"));
+        assert_eq!(clock.as_deref(), Some("c:1:2"));
+        assert_eq!(
+            deleted.into_iter().collect::<Vec<_>>(),
+            [PathBuf::from("/repo/src/gone.c")]
+        );
+    }
+
+    #[test]
+    fn prune_deleted() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let dir = temp.path().canonicalize().unwrap();
+        let json_path = dir.join("compile_commands.json");
+        fs::write(dir.join("kept.c"), "").unwrap();
+        let entry = |file: &str| db::entry(dir.to_str().unwrap(), file, &["cc", file]);
+        let entries = [entry("kept.c"), entry("gone.c"), entry("other.c")];
+        fs::write(&json_path, serde_json::to_string(&entries).unwrap()).unwrap();
+        // A file reported deleted but recreated since is kept.
+        let deleted = [dir.join("gone.c"), dir.join("kept.c")].into();
+        assert_eq!(super::prune_deleted(&json_path, &deleted).unwrap(), 1);
+        let files: Vec<_> = db::read_entries(&json_path)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.file)
+            .collect();
+        assert_eq!(files, ["kept.c", "other.c"]);
+    }
+}
@@ -0,0 +1,17 @@
+use std::error::Error;
+use std::path::Path;
+use std::process::Command;
+
+/// Runs git in `dir` and returns its output without the trailing newline.
+pub fn run(dir: &Path, args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )
+        .into());
+    }
+    Ok(String::from_utf8(output.stdout)?.trim_end().to_string())
+}
@@ -0,0 +1,216 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::cli::Options;
+use crate::fsmonitor;
+use crate::git;
+use crate::output;
+use crate::paths;
+use crate::shell;
+
+const USAGE: &str = "usage: cdbgen git-hooks [--db DB] [--refresh COMMAND] install | uninstall";
+
+/// Hooks run after the work tree changed due to a branch switch or a merge.
+const HOOKS: [&str; 2] = ["post-checkout", "post-merge"];
+
+/// Marks hooks written by cdbgen, which alone are replaced or removed.
+const MARKER: &str = "# Written by 'cdbgen git-hooks install'.";
+
+/// Returns the hook script running `cdbgen` for `hook` on the database at `json_path`, followed
+/// by `refresh` if given.  A failure of cdbgen does not fail the checkout.
+fn script(cdbgen: &Path, hook: &str, json_path: &Path, refresh: Option<&str>) -> String {
+    let mut command = vec![
+        cdbgen.to_string_lossy().into_owned(),
+        "git-hooks".to_string(),
+        "--db".to_string(),
+        json_path.to_string_lossy().into_owned(),
+    ];
+    if let Some(refresh) = refresh {
+        command.extend(["--refresh".to_string(), refresh.to_string()]);
+    }
+    command.extend(["run".to_string(), hook.to_string()]);
+    format!(
+        "#!/bin/sh\n{}\n{} \"$@\" || true\n",
+        MARKER,
+        shell::join(&command)
+    )
+}
+
+fn install(
+    hooks_dir: &Path,
+    json_path: &Path,
+    refresh: Option<&str>,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let cdbgen = env::current_exe()?;
+    fs::create_dir_all(hooks_dir)?;
+    let mut installed = Vec::new();
+    for hook in HOOKS {
+        let path = hooks_dir.join(hook);
+        if let Ok(existing) = fs::read_to_string(&path) {
+            if !existing.contains(MARKER) {
+                return Err(format!(
+                    "{} exists already; add 'cdbgen git-hooks run {} \"$@\"' to it yourself",
+                    path.display(),
+                    hook
+                )
+                .into());
+            }
+        }
+        fs::write(&path, script(&cdbgen, hook, json_path, refresh))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        }
+        installed.push(path);
+    }
+    Ok(installed)
+}
+
+fn uninstall(hooks_dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut removed = Vec::new();
+    for hook in HOOKS {
+        let path = hooks_dir.join(hook);
+        if fs::read_to_string(&path).map_or(false, |s| s.contains(MARKER)) {
+            fs::remove_file(&path)?;
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
+
+/// Returns the revisions the work tree changed between for `hook` called with `args`, unless
+/// nothing is to be done, e.g. for checking out single files.
+fn revisions<'a>(hook: &str, args: &'a [String]) -> Option<(&'a str, &'a str)> {
+    match (hook, args) {
+        ("post-checkout", [old, new, branch, ..]) if branch == "1" && old != new => {
+            Some((old, new))
+        }
+        ("post-merge", _) => Some(("ORIG_HEAD", "HEAD")),
+        _ => None,
+    }
+}
+
+/// Parses the output of `git diff --name-status -z --no-renames` into the files deleted and the
+/// directories of the files added or deleted, all absolute with respect to `root`.
+fn changes(status: &str, root: &Path) -> (BTreeSet<PathBuf>, BTreeSet<PathBuf>) {
+    let mut deleted = BTreeSet::new();
+    let mut directories = BTreeSet::new();
+    let mut fields = status.split('\0').filter(|f| !f.is_empty());
+    while let (Some(kind), Some(name)) = (fields.next(), fields.next()) {
+        let path = paths::normalize(&root.join(name));
+        if kind == "D" {
+            deleted.insert(path.clone());
+        }
+        if kind == "D" || kind == "A" {
+            if let Some(dir) = path.parent() {
+                directories.insert(dir.to_path_buf());
+            }
+        }
+    }
+    (deleted, directories)
+}
+
+/// Prunes the entries of files deleted by the checkout or merge `hook` and runs `refresh` in
+/// the directories in which files were added or deleted.
+fn run(
+    hook: &str,
+    args: &[String],
+    json_path: &Path,
+    refresh: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let (old, new) = match revisions(hook, args) {
+        Some(revisions) => revisions,
+        None => return Ok(()),
+    };
+    let cwd = env::current_dir()?;
+    let root = PathBuf::from(git::run(&cwd, &["rev-parse", "--show-toplevel"])?);
+    let status = git::run(
+        &root,
+        &["diff", "--name-status", "-z", "--no-renames", old, new],
+    )?;
+    let (deleted, directories) = changes(&status, &root);
+    let removed = fsmonitor::prune_deleted(json_path, &deleted)?;
+    if removed > 0 {
+        output::info(&format!(
+            "cdbgen: pruned {} entries of files deleted by {}",
+            removed, hook
+        ));
+    }
+    if let Some(refresh) = refresh {
+        for dir in directories.iter().filter(|d| d.is_dir()) {
+            let status = Command::new("sh")
+                .args(["-c", refresh])
+                .current_dir(dir)
+                .status()?;
+            if !status.success() {
+                output::warn(&format!("'{}' failed in {}", refresh, dir.display()));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse_command(args, &[], &["--db", "--refresh"])?;
+    let cwd = env::current_dir()?;
+    let json_path = paths::normalize(&cwd.join(opts.database()));
+    let refresh = opts.value("--refresh");
+    match opts.rest.split_first() {
+        Some((command, [])) if command == "install" || command == "uninstall" => {
+            let hooks_dir = cwd.join(git::run(&cwd, &["rev-parse", "--git-path", "hooks"])?);
+            let changed = if command == "install" {
+                install(&hooks_dir, &json_path, refresh)?
+            } else {
+                uninstall(&hooks_dir)?
+            };
+            for path in changed {
+                output::info(&format!(
+                    "{} {}",
+                    if command == "install" {
+                        "installed"
+                    } else {
+                        "removed"
+                    },
+                    path.display()
+                ));
+            }
+            Ok(())
+        }
+        Some((command, rest)) if command == "run" => match rest.split_first() {
+            Some((hook, args)) => run(hook, args, &json_path, refresh),
+            None => Err(USAGE.into()),
+        },
+        _ => Err(USAGE.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes() {
+        let strings = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            revisions("post-checkout", &strings(&["a", "b", "1"])),
+            Some(("a", "b"))
+        );
+        assert_eq!(revisions("post-checkout", &strings(&["a", "b", "0"])), None);
+
+        let status = "D\0src/gone.c\0M\0src/kept.c\0A\0lib/new.c\0";
+        let (deleted, directories) = super::changes(status, Path::new("/repo"));
+        assert_eq!(
+            deleted.into_iter().collect::<Vec<_>>(),
+            [PathBuf::from("/repo/src/gone.c")]
+        );
+        assert_eq!(
+            directories.into_iter().collect::<Vec<_>>(),
+            [PathBuf::from("/repo/lib"), PathBuf::from("/repo/src")]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::args;
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::link::{self, LinkEntry};
+use crate::paths;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Node {
+    pub id: String,
+    pub kind: &'static str,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+fn artifact_kind(path: &str) -> &'static str {
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("o" | "obj" | "lo") => "object",
+        Some("a" | "lib" | "so" | "dylib" | "dll") => "library",
+        _ => "binary",
+    }
+}
+
+/// Builds the graph of the compile `entries` and the `links` of their artifacts.
+pub fn build(entries: &BTreeSet<Entry>, links: &BTreeSet<LinkEntry>) -> Graph {
+    let mut nodes = BTreeMap::new();
+    let mut edges = BTreeSet::new();
+    for e in entries {
+        let source = paths::resolve(&e.directory, &e.file)
+            .to_string_lossy()
+            .into_owned();
+        nodes.insert(source.clone(), "source");
+        if let Some(output) = &e.output {
+            let output = paths::resolve(&e.directory, output)
+                .to_string_lossy()
+                .into_owned();
+            nodes.insert(output.clone(), artifact_kind(&output));
+            edges.insert((source, output));
+        }
+    }
+    for l in links {
+        let output = paths::resolve(&l.directory, &l.output)
+            .to_string_lossy()
+            .into_owned();
+        nodes.insert(output.clone(), artifact_kind(&output));
+        for input in &l.inputs {
+            let input = paths::resolve(&l.directory, input)
+                .to_string_lossy()
+                .into_owned();
+            nodes.entry(input.clone()).or_insert_with(|| {
+                match args::language_of_extension(&input) {
+                    Some(_) => "source",
+                    None => artifact_kind(&input),
+                }
+            });
+            edges.insert((input, output.clone()));
+        }
+        for library in &l.libraries {
+            let library = format!("-l{}", library);
+            nodes.insert(library.clone(), "library");
+            edges.insert((library, output.clone()));
+        }
+    }
+    Graph {
+        nodes: nodes
+            .into_iter()
+            .map(|(id, kind)| Node { id, kind })
+            .collect(),
+        edges: edges
+            .into_iter()
+            .map(|(from, to)| Edge { from, to })
+            .collect(),
+    }
+}
+
+/// Reads the graph of the database at `json_path` and the link commands next to it.
+pub fn read(json_path: &Path) -> Result<Graph, Box<dyn Error>> {
+    let links = link::read(&json_path.with_file_name("link_commands.json"))?;
+    Ok(build(&db::read_entries(json_path)?, &links))
+}
+
+fn dot_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn write_dot(graph: &Graph, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "digraph cdbgen {{")?;
+    writeln!(out, "    rankdir=LR;")?;
+    for n in &graph.nodes {
+        let shape = match n.kind {
+            "source" => "note",
+            "object" => "box",
+            "library" => "box3d",
+            _ => "doubleoctagon",
+        };
+        writeln!(out, "    {} [shape={}];", dot_quote(&n.id), shape)?;
+    }
+    for e in &graph.edges {
+        writeln!(out, "    {} -> {};", dot_quote(&e.from), dot_quote(&e.to))?;
+    }
+    writeln!(out, "}}")
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &[], &["--db", "--format", "-o"])?;
+    let graph = read(&opts.database())?;
+
+    let mut out: Box<dyn Write> = match opts.value("-o") {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    match opts.value("--format").unwrap_or("dot") {
+        "dot" => write_dot(&graph, &mut out)?,
+        "json" => writeln!(out, "{}", serde_json::to_string_pretty(&graph)?)?,
+        format => return Err(format!("unknown graph format '{}'", format).into()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(file: &str, output: Option<&str>) -> Entry {
+        Entry {
+            output: output.map(str::to_string),
+            ..db::entry("/build", file, &["cc"])
+        }
+    }
+
+    #[test]
+    fn build() {
+        let entries: BTreeSet<_> = [
+            entry("../src/a.c", Some("a.o")),
+            entry("b.c", Some("app")),
+            entry("c.c", None),
+        ]
+        .into_iter()
+        .collect();
+        let graph = super::build(&entries, &BTreeSet::new());
+        assert_eq!(
+            graph.nodes,
+            [
+                Node {
+                    id: "/build/a.o".into(),
+                    kind: "object"
+                },
+                Node {
+                    id: "/build/app".into(),
+                    kind: "binary"
+                },
+                Node {
+                    id: "/build/b.c".into(),
+                    kind: "source"
+                },
+                Node {
+                    id: "/build/c.c".into(),
+                    kind: "source"
+                },
+                Node {
+                    id: "/src/a.c".into(),
+                    kind: "source"
+                },
+            ]
+        );
+        assert_eq!(graph.edges.len(), 2);
+
+        let mut dot = Vec::new();
+        write_dot(&graph, &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.contains("\"/build/b.c\" -> \"/build/app\";"));
+    }
+
+    #[test]
+    fn build_with_links() {
+        let entries: BTreeSet<_> = [entry("a.c", Some("a.o"))].into_iter().collect();
+        let links: BTreeSet<_> = [LinkEntry {
+            directory: "/build".to_string(),
+            output: "app".to_string(),
+            inputs: vec!["a.o".to_string(), "libx.a".to_string()],
+            libraries: vec!["m".to_string()],
+            arguments: Vec::new(),
+        }]
+        .into_iter()
+        .collect();
+        let graph = super::build(&entries, &links);
+        let kind = |id: &str| graph.nodes.iter().find(|n| n.id == id).unwrap().kind;
+        assert_eq!(kind("/build/app"), "binary");
+        assert_eq!(kind("/build/libx.a"), "library");
+        assert_eq!(kind("-lm"), "library");
+        assert_eq!(graph.edges.len(), 4);
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+
+use serde::Serialize;
+
+use crate::args;
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::paths;
+use crate::pattern::Matcher;
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Match {
+    directory: String,
+    file: String,
+    flags: Vec<String>,
+}
+
+/// Returns the flags of `entry` matching any of `matchers`.  A flag matches if either its joined
+/// spelling (`-DFOO=1`) or its separate spelling (`-D FOO=1`) matches.
+fn matching_flags(entry: &Entry, matchers: &[Matcher]) -> Vec<String> {
+    let arguments = entry.arguments.get(1..).unwrap_or_default();
+    args::flags(arguments)
+        .into_iter()
+        .filter(|f| {
+            let (joined, separate) = (f.joined(), f.separate());
+            matchers
+                .iter()
+                .any(|m| m.is_match(&joined) || m.is_match(&separate))
+        })
+        .map(|f| f.separate())
+        .collect()
+}
+
+fn search(
+    entries: &BTreeSet<Entry>,
+    matchers: &[Matcher],
+    files: Option<&Matcher>,
+    invert: bool,
+) -> Vec<Match> {
+    entries
+        .iter()
+        .filter(|e| {
+            files.map_or(true, |m| {
+                m.is_match(&paths::resolve(&e.directory, &e.file).to_string_lossy())
+            })
+        })
+        .filter_map(|e| {
+            let flags = matching_flags(e, matchers);
+            if flags.is_empty() == invert {
+                Some(Match {
+                    directory: e.directory.clone(),
+                    file: e.file.clone(),
+                    flags,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(
+        args,
+        &["--regex", "-E", "--invert", "-v", "--count", "--json"],
+        &["--db", "--file"],
+    )?;
+    let regex = opts.has("--regex") || opts.has("-E");
+    let patterns: Vec<_> = opts.positional.iter().chain(&opts.rest).collect();
+    if patterns.is_empty() {
+        return Err("usage: cdbgen grep-flags [OPTIONS] -- PATTERN...".into());
+    }
+    let matchers = patterns
+        .iter()
+        .map(|p| Matcher::new(p, regex))
+        .collect::<Result<Vec<_>, _>>()?;
+    let files = opts
+        .value("--file")
+        .map(|g| Matcher::new(g, false))
+        .transpose()?;
+
+    let entries = db::read_entries(&opts.database())?;
+    let matches = search(
+        &entries,
+        &matchers,
+        files.as_ref(),
+        opts.has("--invert") || opts.has("-v"),
+    );
+
+    if opts.has("--json") {
+        println!("{}", serde_json::to_string_pretty(&matches)?);
+    } else if opts.has("--count") {
+        println!("{}", matches.len());
+    } else {
+        for m in &matches {
+            let path = paths::resolve(&m.directory, &m.file);
+            if m.flags.is_empty() {
+                println!("{}", path.display());
+            } else {
+                println!("{}: {}", path.display(), m.flags.join(" "));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search() {
+        let entries: BTreeSet<_> = [
+            db::entry(
+                "/src",
+                "a.cc",
+                &["g++", "-std=c++11", "-D", "FOO=1", "a.cc"],
+            ),
+            db::entry("/src", "b.cc", &["g++", "-std=c++17", "-DFOO=2", "b.cc"]),
+            db::entry("/src", "c.cc", &["g++", "-std=c++17", "c.cc"]),
+        ]
+        .into_iter()
+        .collect();
+
+        let std11 = [Matcher::new("-std=c++11", false).unwrap()];
+        let found = super::search(&entries, &std11, None, false);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].file, "a.cc");
+
+        let foo = [Matcher::new("-D FOO=*", false).unwrap()];
+        let found = super::search(&entries, &foo, None, false);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[1].flags, ["-D FOO=2"]);
+
+        let found = super::search(&entries, &foo, None, true);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].file, "c.cc");
+
+        let regex = [Matcher::new(r"^-std=c\+\+1[17]$", true).unwrap()];
+        let only_b = Matcher::new("*/b.*", false).unwrap();
+        let found = super::search(&entries, &regex, Some(&only_b), false);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].file, "b.cc");
+    }
+}
@@ -0,0 +1,260 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+use which::which;
+
+use crate::args;
+use crate::fragment;
+use crate::hash;
+use crate::paths;
+
+/// The search paths and the target a compiler uses without being told, as reported by
+/// `-E -v`.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Defaults {
+    /// Directories searched for `#include <...>` in the order given.
+    includes: Vec<String>,
+    /// The target triple, e.g. `arm-none-eabi`.
+    target: Option<String>,
+}
+
+/// The defaults of a compiler as of its modification time in nanoseconds.
+#[derive(Debug, Serialize, Deserialize)]
+struct Cached {
+    modified: u128,
+    defaults: Defaults,
+}
+
+/// Parses the diagnostics of `-E -v` of GCC and Clang.
+fn parse(stderr: &str) -> Defaults {
+    let mut defaults = Defaults::default();
+    let mut in_list = false;
+    for line in stderr.lines() {
+        if let Some(target) = line.strip_prefix("Target: ") {
+            defaults.target = Some(target.trim().to_string());
+        } else if line.starts_with("#include <...> search starts here:") {
+            in_list = true;
+        } else if line.starts_with("End of search list.") {
+            in_list = false;
+        } else if in_list && !line.ends_with("(framework directory)") {
+            defaults.includes.push(line.trim().to_string());
+        }
+    }
+    defaults
+}
+
+/// Returns the options of `arguments` changing the defaults of the compiler, e.g. its target or
+/// sysroot, with relative paths resolved against `directory` such that they are passed on as
+/// the compiler is queried.
+fn query_flags(arguments: &[String], directory: &str) -> Vec<String> {
+    let resolve = |value: &Option<String>| {
+        paths::resolve(directory, value.as_deref().unwrap_or_default())
+            .to_string_lossy()
+            .into_owned()
+    };
+    let mut result = Vec::new();
+    for flag in args::flags(arguments) {
+        match flag.name.as_str() {
+            "--sysroot" | "--sysroot=" => {
+                result.push(format!("--sysroot={}", resolve(&flag.value)))
+            }
+            "-isysroot" => result.extend(["-isysroot".to_string(), resolve(&flag.value)]),
+            "-target" | "-m16" | "-m32" | "-m64" | "-mx32" => result.extend(flag.args),
+            name if name.starts_with("--target=") || name.starts_with("-stdlib=") => {
+                result.extend(flag.args)
+            }
+            _ => (),
+        }
+    }
+    result
+}
+
+/// Returns the defaults of `compiler` with `flags` for `language`, i.e., `c` or `c++`, which are
+/// cached in `.cdbgen/implicit/` next to the database at `json_path` until the compiler changes.
+fn defaults(
+    json_path: &Path,
+    compiler: &str,
+    flags: &[String],
+    language: &str,
+) -> Option<Defaults> {
+    let program = which(compiler).ok()?;
+    let modified = fs::metadata(&program)
+        .ok()?
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_nanos();
+    let key = format!("{}\0{}\0{}", program.display(), language, flags.join("\0"));
+    let path: PathBuf = fragment::dir(json_path)
+        .join("implicit")
+        .join(format!("{:016x}", hash::fnv1a(key.as_bytes())));
+    if let Some(cached) = fs::read(&path)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<Cached>(&data).ok())
+        .filter(|c| c.modified == modified)
+    {
+        return Some(cached.defaults);
+    }
+    let output = Command::new(&program)
+        .args(flags)
+        .args(["-E", "-v", "-x", language, "/dev/null"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let cached = Cached {
+        modified,
+        defaults: parse(&String::from_utf8_lossy(&output.stderr)),
+    };
+    // The cache is only an optimization, hence failing to store it is fine.
+    if fs::create_dir_all(path.parent().unwrap()).is_ok() {
+        let tmp = path.with_extension("tmp");
+        if let Ok(data) = serde_json::to_vec(&cached) {
+            if fs::write(&tmp, data).is_ok() {
+                let _ = fs::rename(&tmp, &path);
+            }
+        }
+    }
+    Some(cached.defaults)
+}
+
+/// Inserts the defaults into `arguments` right after the compiler, i.e., `--target=` for Clang
+/// unless a target is given already and `-isystem` for each implicit include directory unless
+/// the standard directories are disabled by `-nostdinc`.  GCC does not know `--target=`, i.e.,
+/// the target of a GCC is left to be told by the name of the compiler.
+fn insert(arguments: &[String], defaults: &Defaults) -> Vec<String> {
+    let mut result: Vec<String> = arguments.iter().take(1).cloned().collect();
+    let flags = args::flags(arguments.get(1..).unwrap_or_default());
+    let has = |name: &str| flags.iter().any(|f| f.name == name);
+    let clang = arguments.first().map_or(false, |compiler| {
+        Path::new(compiler)
+            .file_name()
+            .map_or(false, |name| name.to_string_lossy().contains("clang"))
+    });
+    if let (Some(target), true) = (&defaults.target, clang) {
+        if !has("-target") && !flags.iter().any(|f| f.name.starts_with("--target=")) {
+            result.push(format!("--target={}", target));
+        }
+    }
+    if !has("-nostdinc") {
+        for dir in &defaults.includes {
+            result.extend(["-isystem".to_string(), dir.clone()]);
+        }
+    }
+    result.extend(arguments.iter().skip(1).cloned());
+    result
+}
+
+/// Returns `arguments` with the implicit include directories and the target of the compiler
+/// embedded such that tools like clangd understand entries of cross compilers without
+/// `--query-driver`.  The compiler is queried for the language of the first of `files` with the
+/// options of `arguments` changing its defaults, i.e., run in `directory`.  Arguments are
+/// returned unchanged if the compiler cannot be queried.
+pub fn embed<'a, I>(
+    json_path: &Path,
+    directory: &str,
+    arguments: &[String],
+    files: I,
+) -> Vec<String>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    let compiler = match arguments.first() {
+        Some(compiler) => compiler,
+        None => return arguments.to_vec(),
+    };
+    let rest = &arguments[1..];
+    let language = match files
+        .into_iter()
+        .next()
+        .and_then(|file| args::language(rest, file))
+    {
+        Some(language) if language.contains("c++") => "c++",
+        _ => "c",
+    };
+    match defaults(json_path, compiler, &query_flags(rest, directory), language) {
+        Some(defaults) => insert(arguments, &defaults),
+        None => arguments.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_insert() {
+        let stderr = "\
+Using built-in specs.
+Target: arm-none-eabi
+#include \"...\" search starts here:
+#include <...> search starts here:
+ /opt/arm/lib/gcc/arm-none-eabi/12.2.1/include
+ /opt/arm/arm-none-eabi/include
+ /System/Library/Frameworks (framework directory)
+End of search list.
+";
+        let defaults = super::parse(stderr);
+        assert_eq!(
+            defaults,
+            Defaults {
+                includes: vec![
+                    "/opt/arm/lib/gcc/arm-none-eabi/12.2.1/include".to_string(),
+                    "/opt/arm/arm-none-eabi/include".to_string()
+                ],
+                target: Some("arm-none-eabi".to_string()),
+            }
+        );
+        let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            insert(&args(&["clang", "-c", "a.c"]), &defaults),
+            args(&[
+                "clang",
+                "--target=arm-none-eabi",
+                "-isystem",
+                "/opt/arm/lib/gcc/arm-none-eabi/12.2.1/include",
+                "-isystem",
+                "/opt/arm/arm-none-eabi/include",
+                "-c",
+                "a.c"
+            ])
+        );
+        assert_eq!(
+            insert(
+                &args(&["clang", "--target=x", "-nostdinc", "a.c"]),
+                &defaults
+            ),
+            args(&["clang", "--target=x", "-nostdinc", "a.c"])
+        );
+        assert_eq!(
+            insert(&args(&["gcc", "-nostdinc", "a.c"]), &defaults),
+            args(&["gcc", "-nostdinc", "a.c"])
+        );
+        assert_eq!(
+            query_flags(
+                &args(&[
+                    "-m32",
+                    "--sysroot",
+                    "sys",
+                    "-O2",
+                    "-stdlib=libc++",
+                    "-target",
+                    "x"
+                ]),
+                "/src"
+            ),
+            args(&[
+                "-m32",
+                "--sysroot=/src/sys",
+                "-stdlib=libc++",
+                "-target",
+                "x"
+            ])
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::args;
+use crate::cli::Options;
+use crate::db::{self, Invocation};
+use crate::interpose;
+use crate::meta::{self, Header};
+use crate::output;
+use crate::paths;
+use crate::shell;
+use crate::shims;
+
+/// Separators of the execution traces written by libear: groups (one per execution), records
+/// (fields of an execution), and units (arguments of a command).
+const GS: char = '\u{1d}';
+const RS: char = '\u{1e}';
+const US: char = '\u{1f}';
+
+/// A process execution as captured by an interception tool.
+#[derive(Debug, PartialEq)]
+struct Execution {
+    directory: String,
+    arguments: Vec<String>,
+}
+
+/// Parses an execution trace (`*.cmd`) of libear.  Each execution consists of the records pid,
+/// ppid, function, working directory, and command.
+fn parse_cmd(data: &str) -> Vec<Execution> {
+    data.split(GS)
+        .filter(|g| !g.is_empty())
+        .filter_map(|group| {
+            let records: Vec<&str> = group.split(RS).collect();
+            let directory = records.get(3)?.to_string();
+            let mut arguments: Vec<String> = records.get(4)?.split(US).map(String::from).collect();
+            // Every argument is terminated by a unit separator.
+            if arguments.last().map_or(false, String::is_empty) {
+                arguments.pop();
+            }
+            Some(Execution {
+                directory,
+                arguments,
+            })
+        })
+        .collect()
+}
+
+fn strings(value: &Value) -> Option<Vec<String>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|a| a.as_str().map(String::from))
+        .collect()
+}
+
+/// Parses a single JSON object describing an execution.  Understood are the events of Bear's
+/// intercept (`{"started": {"execution": {...}}}`) as well as plain objects with a working
+/// directory and a command.
+fn parse_object(value: &Value) -> Option<Execution> {
+    if let Some(execution) = value.pointer("/started/execution") {
+        let mut arguments = strings(execution.get("arguments")?)?;
+        if let (Some(first), Some(executable)) = (
+            arguments.first_mut(),
+            execution.get("executable").and_then(Value::as_str),
+        ) {
+            *first = executable.to_string();
+        }
+        return Some(Execution {
+            directory: execution.get("working_dir")?.as_str()?.to_string(),
+            arguments,
+        });
+    }
+    let directory = ["directory", "cwd", "working_dir"]
+        .iter()
+        .find_map(|k| value.get(k)?.as_str())?;
+    let arguments = ["command", "cmd", "arguments"]
+        .iter()
+        .find_map(|k| strings(value.get(k)?))?;
+    Some(Execution {
+        directory: directory.to_string(),
+        arguments,
+    })
+}
+
+/// Parses a JSON capture which is either a single object, an array of objects, or one object per
+/// line.
+fn parse_json(data: &str) -> Result<Vec<Execution>, Box<dyn Error>> {
+    let values: Vec<Value> = match serde_json::from_str(data) {
+        Ok(Value::Array(values)) => values,
+        Ok(value) => vec![value],
+        Err(_) => data
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?,
+    };
+    Ok(values.iter().filter_map(parse_object).collect())
+}
+
+fn invocation(execution: Execution) -> Option<Invocation> {
+    if !shims::is_compiler(execution.arguments.first()?) {
+        return None;
+    }
+    let arguments = interpose::unwrap(execution.arguments.clone());
+    let files = db::compiled_files(&arguments);
+    if files.is_empty() {
+        return None;
+    }
+    let arguments = args::tag_assembler(&arguments);
+    Some(Invocation {
+        argv: execution.arguments,
+        arguments,
+        directory: execution.directory,
+        files,
+        duration: None,
+        session: None,
+    })
+}
+
+/// An entry of a database written by Bear, which has a `command` string in older versions and
+/// `arguments` in newer ones.
+#[derive(Deserialize)]
+struct BearEntry {
+    directory: String,
+    file: String,
+    #[serde(default)]
+    arguments: Option<Vec<String>>,
+    #[serde(default)]
+    command: Option<String>,
+}
+
+/// Parses a database written by Bear into invocations of one file each.  Launchers like ccache
+/// are stripped and the file is spelled as on the command line, like cdbgen records it.  Bear
+/// writes an entry per layer of wrappers a compilation went through, outer ones first, that all
+/// compile the same file; as later invocations replace earlier ones, the innermost one is kept.
+fn parse_bear(data: &str) -> Result<Vec<Invocation>, Box<dyn Error>> {
+    let entries: Vec<BearEntry> = serde_json::from_str(data)?;
+    let mut invocations = Vec::new();
+    for entry in entries {
+        let arguments = match (entry.arguments, entry.command) {
+            (Some(arguments), _) => arguments,
+            (None, Some(command)) => shell::split(&command)?,
+            (None, None) => return Err(format!("no command for '{}'", entry.file).into()),
+        };
+        let directory = entry.directory;
+        let file = paths::canonical(&directory, &entry.file);
+        // Bear records launchers like other compilers.
+        let arguments = interpose::unwrap(arguments);
+        if let Some(mut invocation) = invocation(Execution {
+            directory: directory.clone(),
+            arguments,
+        }) {
+            invocation
+                .files
+                .retain(|f, _| paths::canonical(&directory, f) == file);
+            if !invocation.files.is_empty() {
+                invocations.push(invocation);
+            }
+        }
+    }
+    Ok(invocations)
+}
+
+/// Collects the capture files in `path`, i.e., `path` itself if it is a file or else the
+/// `*.cmd` and `*.json` files within it.
+fn capture_files(path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    for e in fs::read_dir(path)? {
+        let p = e?.path();
+        if p.extension().map_or(false, |x| x == "cmd" || x == "json") {
+            files.push(p);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub fn main_intercept(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &[], &["--db"])?;
+    if opts.positional.is_empty() {
+        return Err("usage: cdbgen import-intercept [--db DB] CAPTURE...".into());
+    }
+    let mut invocations = Vec::new();
+    for path in &opts.positional {
+        for file in capture_files(Path::new(path))? {
+            let data = fs::read_to_string(&file)?;
+            let executions = if file.extension().map_or(false, |x| x == "cmd") {
+                parse_cmd(&data)
+            } else {
+                parse_json(&data).map_err(|e| format!("{}: {}", file.display(), e))?
+            };
+            invocations.extend(executions.into_iter().filter_map(invocation));
+        }
+    }
+    let count: usize = invocations.iter().map(|i| i.files.len()).sum();
+    let json_path = opts.database();
+    db::update_any(&json_path, |old_entries| {
+        meta::ensure_header(&json_path, Header::new("import-intercept", "file"))?;
+        let mut entries = old_entries.clone();
+        for invocation in &invocations {
+            db::replace(&mut entries, invocation);
+        }
+        Ok(entries)
+    })?;
+    output::info(&format!("imported {} entries", count));
+    Ok(())
+}
+
+pub fn main_bear(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &[], &["--db"])?;
+    if opts.positional.is_empty() {
+        return Err("usage: cdbgen import-bear [--db DB] DATABASE...".into());
+    }
+    let mut invocations = Vec::new();
+    for path in &opts.positional {
+        let data = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        invocations.extend(parse_bear(&data).map_err(|e| format!("{}: {}", path, e))?);
+    }
+    let json_path = opts.database();
+    db::update_any(&json_path, |old_entries| {
+        meta::ensure_header(&json_path, Header::new("import-bear", "file"))?;
+        let mut entries = old_entries.clone();
+        for invocation in &invocations {
+            db::replace(&mut entries, invocation);
+        }
+        Ok(entries)
+    })?;
+    output::info(&format!("imported {} entries", invocations.len()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_cmd() {
+        let data = "1\u{1e}0\u{1e}execve\u{1e}/src\u{1e}gcc\u{1f}-c\u{1f}a.c\u{1f}\u{1d}\
+                    2\u{1e}1\u{1e}execve\u{1e}/src\u{1e}as\u{1f}-o\u{1f}a.o\u{1f}\u{1d}";
+        let executions = super::parse_cmd(data);
+        assert_eq!(executions.len(), 2);
+        assert_eq!(executions[0].directory, "/src");
+        assert_eq!(executions[0].arguments, ["gcc", "-c", "a.c"]);
+        assert!(invocation(executions.into_iter().nth(1).unwrap()).is_none());
+    }
+
+    #[test]
+    fn parse_json() {
+        let data = r#"{"rid": 1, "started": {"pid": 7, "execution": {"executable": "/usr/bin/cc", "arguments": ["cc", "-c", "b.c"], "working_dir": "/src", "environment": {}}}}
+{"rid": 1, "terminated": {"status": 0}}
+"#;
+        let executions = super::parse_json(data).unwrap();
+        assert_eq!(
+            executions,
+            [Execution {
+                directory: "/src".to_string(),
+                arguments: ["/usr/bin/cc", "-c", "b.c"].map(String::from).to_vec(),
+            }]
+        );
+        let data = r#"[{"cwd": "/x", "cmd": ["clang++-15", "-c", "c.cc"]}]"#;
+        let invocation = invocation(super::parse_json(data).unwrap().remove(0)).unwrap();
+        assert_eq!(invocation.directory, "/x");
+        assert_eq!(
+            invocation.files.into_iter().collect::<Vec<_>>(),
+            [("c.cc".to_string(), Some("c.o".to_string()))]
+        );
+    }
+
+    #[test]
+    fn parse_bear() {
+        use std::collections::BTreeSet;
+
+        let data = r#"[
+            {"directory": "/src", "file": "/src/a.c", "output": "/src/a.o",
+             "command": "ccache cc -c \"-DMSG=\\\"a b\\\"\" -o a.o a.c"},
+            {"directory": "/src", "file": "a.c", "arguments": ["cc", "-c", "-o", "a.o", "a.c"]},
+            {"directory": "/src", "file": "b.c", "arguments": ["cc", "a.c", "b.c"]},
+            {"directory": "/src", "file": "x.c", "arguments": ["./build.sh", "x.c"]}
+        ]"#;
+        let invocations = super::parse_bear(data).unwrap();
+        assert_eq!(invocations.len(), 3);
+        assert_eq!(
+            invocations[0].arguments[1..],
+            ["-c", "-DMSG=\"a b\"", "-o", "a.o", "a.c"]
+        );
+        assert_eq!(invocations[2].files.keys().collect::<Vec<_>>(), ["b.c"]);
+        let mut entries = BTreeSet::new();
+        for invocation in &invocations {
+            db::replace(&mut entries, invocation);
+        }
+        assert_eq!(entries.len(), 2);
+    }
+}
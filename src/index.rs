@@ -0,0 +1,134 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::fragment;
+use crate::output;
+use crate::parallel;
+use crate::paths;
+use crate::relocate;
+
+/// Returns the arguments of `clangd-indexer` indexing all entries of the database in `db_dir`
+/// with `jobs` threads.
+fn indexer_args(db_dir: &Path, jobs: usize) -> Vec<String> {
+    vec![
+        "--executor=all-TUs".to_string(),
+        format!("--execute-concurrency={}", jobs),
+        "--format=binary".to_string(),
+        db_dir
+            .join("compile_commands.json")
+            .to_string_lossy()
+            .into_owned(),
+    ]
+}
+
+/// Builds the index of the database at `json_path` (absolute) by running `indexer` and writes it
+/// to `index`.  The indexer gets a copy of the database with absolute paths since it does not
+/// resolve directories against the database.
+fn build(
+    json_path: &Path,
+    indexer: &Path,
+    jobs: usize,
+    index: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let db_dir = json_path.parent().unwrap_or_else(|| Path::new("/"));
+    let entries: BTreeSet<Entry> = db::read_entries(json_path)?
+        .iter()
+        .map(|e| relocate::absolutize(e, db_dir))
+        .collect();
+    let work = fragment::dir(json_path).join(format!("index.{}", std::process::id()));
+    fs::create_dir_all(&work)?;
+    fs::write(
+        work.join("compile_commands.json"),
+        serde_json::to_string_pretty(&entries)?,
+    )?;
+    if let Some(dir) = index.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Readers of a shared index never see a partial one.  The index is written next to its target
+    // since it cannot be renamed across file systems.
+    let name = index.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = index.with_file_name(format!(".{}.{}", name, std::process::id()));
+    let result = Command::new(indexer)
+        .args(indexer_args(&work, jobs))
+        .stdout(File::create(&tmp)?)
+        .status()
+        .map_err(|error| format!("cannot run '{}': {}", indexer.display(), error).into())
+        .and_then(|status| {
+            if status.success() {
+                Ok(fs::rename(&tmp, index)?)
+            } else {
+                Err(format!("'{}' failed with {}", indexer.display(), status).into())
+            }
+        });
+    let _ = fs::remove_dir_all(&work);
+    let _ = fs::remove_file(&tmp);
+    result
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &[], &["--db", "--clangd-indexer", "-j", "-o"])?;
+    let cwd = env::current_dir()?;
+    let json_path = paths::normalize(&cwd.join(opts.database()));
+    let index = match opts.value("-o") {
+        Some(index) => cwd.join(index),
+        None => json_path
+            .parent()
+            .unwrap_or(&cwd)
+            .join(".cache/clangd/index.dex"),
+    };
+    let indexer = PathBuf::from(opts.value("--clangd-indexer").unwrap_or("clangd-indexer"));
+    let jobs = parallel::jobs(opts.value("-j"))?;
+    build(&json_path, &indexer, jobs, &index)?;
+    output::info(&format!(
+        "wrote {}; let clangd load it by 'Index: {{External: {{File: {}}}}}' in .clangd",
+        index.display(),
+        index.display()
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn build() {
+        use std::os::unix::fs::PermissionsExt;
+        let temp = assert_fs::TempDir::new().unwrap();
+        let json_path = temp.path().join("compile_commands.json");
+        fs::write(
+            &json_path,
+            r#"[{"directory": ".", "file": "a.c", "arguments": ["cc", "a.c"]}]"#,
+        )
+        .unwrap();
+        // Prints the concurrency and the directory of the first entry as index.
+        let indexer = temp.path().join("indexer");
+        fs::write(
+            &indexer,
+            "#!/bin/sh\necho \"$2\"; grep -o '\"directory\": \"[^\"]*\"' \"$4\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&indexer, fs::Permissions::from_mode(0o755)).unwrap();
+        let index = temp.path().join("out/index.dex");
+        super::build(&json_path, &indexer, 3, &index).unwrap();
+        assert_eq!(
+            fs::read_to_string(&index).unwrap(),
+            format!(
+                "--execute-concurrency=3\n\"directory\": \"{}\"\n",
+                temp.path().display()
+            )
+        );
+        assert!(!fragment::dir(&json_path)
+            .join(format!("index.{}", std::process::id()))
+            .exists());
+        assert!(super::build(&json_path, Path::new("/no/such/indexer"), 1, &index).is_err());
+        assert_eq!(fs::read_dir(temp.path().join("out")).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,138 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use serde::Serialize;
+
+use crate::args;
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::paths;
+use crate::relocate;
+use crate::shell;
+use crate::wrap;
+
+/// Options (or prefixes thereof) which clang, the frontend of Infer, rejects or which make the
+/// capture fail for no reason.
+const DROPPED: &[&str] = &[
+    "-Werror",
+    "-flto",
+    "-fno-tree-",
+    "-ftree-",
+    "-fipa-",
+    "-fno-ipa-",
+    "-fconserve-stack",
+    "-fno-var-tracking",
+    "-fvar-tracking",
+    "-fno-allow-store-data-races",
+    "-fmerge-constants",
+    "-fno-semantic-interposition",
+    "-fsched-",
+    "-mindirect-branch",
+    "-mfunction-return",
+    "-mpreferred-stack-boundary",
+    "-mrecord-mcount",
+    "-fplugin",
+];
+
+/// An entry in the form Infer reads most reliably, i.e., with a `command` instead of `arguments`.
+#[derive(Debug, PartialEq, Serialize)]
+struct InferEntry {
+    directory: String,
+    file: String,
+    command: String,
+}
+
+fn is_dropped(flag: &args::Flag) -> bool {
+    args::is_dependency(flag) || DROPPED.iter().any(|d| flag.name.starts_with(d))
+}
+
+/// Converts `entry` with an absolute directory for Infer or returns `None` if Infer cannot
+/// capture the unit, e.g. because it is not written in a C family language.
+fn convert(entry: &Entry) -> Option<InferEntry> {
+    let (compiler, arguments) = entry.arguments.split_first()?;
+    let language = args::language(arguments, &entry.file)?;
+    if !matches!(
+        language.as_str(),
+        "c" | "c++" | "objective-c" | "objective-c++"
+    ) {
+        return None;
+    }
+    let mut command = vec![compiler.clone()];
+    for flag in args::flags(arguments) {
+        if !is_dropped(&flag) {
+            command.extend(flag.args);
+        }
+    }
+    Some(InferEntry {
+        directory: entry.directory.clone(),
+        file: paths::resolve(&entry.directory, &entry.file)
+            .to_string_lossy()
+            .into_owned(),
+        command: shell::join(&command),
+    })
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &["--capture"], &["--db", "-o", "--infer"])?;
+    let cwd = env::current_dir()?;
+    let json_path = paths::normalize(&cwd.join(opts.database()));
+    let db_dir = json_path.parent().unwrap_or(&cwd).to_path_buf();
+    let output =
+        paths::normalize(&cwd.join(opts.value("-o").unwrap_or("infer_compile_commands.json")));
+
+    let entries: Vec<InferEntry> = db::read_entries(&json_path)?
+        .iter()
+        .filter_map(|e| convert(&relocate::absolutize(e, &db_dir)))
+        .collect();
+    if entries.is_empty() {
+        return Err("no entries Infer could capture".into());
+    }
+    fs::write(&output, serde_json::to_string_pretty(&entries)? + "\n")?;
+
+    if opts.has("--capture") {
+        let infer = PathBuf::from(opts.value("--infer").unwrap_or("infer"));
+        let status = Command::new(&infer)
+            .arg("capture")
+            .arg("--compilation-database")
+            .arg(&output)
+            .args(&opts.rest)
+            .status()
+            .map_err(|error| format!("cannot run '{}': {}", infer.display(), error))?;
+        if !status.success() {
+            wrap::exit_like(status);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert() {
+        let entry = |file: &str, args: &[&str]| db::entry("/proj", file, args);
+        let converted = super::convert(&entry(
+            "src/a.c",
+            &[
+                "gcc",
+                "-Werror=format",
+                "-DMSG=\"hi there\"",
+                "-MD",
+                "-MF",
+                "a.d",
+                "-fno-tree-loop-distribute-patterns",
+                "-O2",
+                "-c",
+                "src/a.c",
+            ],
+        ))
+        .unwrap();
+        assert_eq!(converted.file, "/proj/src/a.c");
+        assert_eq!(converted.command, "gcc '-DMSG=\"hi there\"' -O2 -c src/a.c");
+        assert!(super::convert(&entry("b.S", &["gcc", "-c", "b.S"])).is_none());
+    }
+}
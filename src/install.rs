@@ -0,0 +1,402 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cli::Options;
+use crate::output;
+use crate::shims::{self, Link};
+use crate::sidecar::{self, ShimConfig};
+
+/// What `cdbgen install` installed into a directory.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct State {
+    /// Compilers mapped to how their shim refers to the cdbgen binary.
+    shims: BTreeMap<String, Link>,
+}
+
+fn state_path(dir: &Path) -> PathBuf {
+    dir.join("installed.json")
+}
+
+fn read_state(dir: &Path) -> Result<State, Box<dyn Error>> {
+    match fs::read_to_string(state_path(dir)) {
+        Ok(data) => Ok(serde_json::from_str(&data)?),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(State::default()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn write_state(dir: &Path, state: &State) -> Result<(), Box<dyn Error>> {
+    if state.shims.is_empty() {
+        match fs::remove_file(state_path(dir)) {
+            Err(error) if error.kind() != ErrorKind::NotFound => return Err(error.into()),
+            _ => (),
+        }
+        // Only succeeds if nothing else is left.
+        let _ = fs::remove_dir(dir);
+        return Ok(());
+    }
+    fs::write(state_path(dir), serde_json::to_string_pretty(state)? + "\n")?;
+    Ok(())
+}
+
+/// The directory shims are installed into by default.
+fn default_dir() -> Result<PathBuf, Box<dyn Error>> {
+    let home = env::var_os("HOME").ok_or("HOME is not set (pass --dir)")?;
+    Ok(Path::new(&home).join(".local/libexec/cdbgen"))
+}
+
+fn dir(opts: &Options) -> Result<PathBuf, Box<dyn Error>> {
+    match opts.value("--dir") {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => default_dir(),
+    }
+}
+
+/// Returns true if the shim for `compiler` in `dir` still refers to the running cdbgen binary.
+fn is_intact(dir: &Path, compiler: &str, link: Link) -> bool {
+    let shim = dir.join(shims::shim_name(compiler));
+    let cdbgen = match env::current_exe() {
+        Ok(cdbgen) => cdbgen,
+        Err(_) => return false,
+    };
+    match link {
+        Link::Symlink => fs::canonicalize(&shim).ok() == fs::canonicalize(&cdbgen).ok(),
+        Link::Hardlink | Link::Copy => match (fs::metadata(&shim), fs::metadata(&cdbgen)) {
+            (Ok(a), Ok(b)) => a.len() == b.len(),
+            _ => false,
+        },
+    }
+}
+
+/// Installs shims for `compilers` into `dir`.  With `repair` additionally every broken shim
+/// installed before is recreated.  Returns the shims created.
+fn install(dir: &Path, compilers: &[String], repair: bool) -> Result<Vec<String>, Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let mut state = read_state(dir)?;
+    let mut todo: Vec<String> = compilers.to_vec();
+    if repair {
+        todo.extend(
+            state
+                .shims
+                .iter()
+                .filter(|&(c, &link)| !is_intact(dir, c, link))
+                .map(|(c, _)| c.clone()),
+        );
+    }
+    todo.sort();
+    todo.dedup();
+    for compiler in &todo {
+        let (_, link) = shims::create_shim(dir, compiler)?;
+        state.shims.insert(compiler.clone(), link);
+    }
+    write_state(dir, &state)?;
+    Ok(todo)
+}
+
+/// Removes the shims for `compilers`, or all if empty, which were installed into `dir`.  Shims
+/// which were not installed by `cdbgen install` are left alone.
+fn uninstall(dir: &Path, compilers: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut state = read_state(dir)?;
+    let todo: Vec<String> = if compilers.is_empty() {
+        state.shims.keys().cloned().collect()
+    } else {
+        for c in compilers {
+            if !state.shims.contains_key(c) {
+                return Err(format!("no shim for '{}' installed in {}", c, dir.display()).into());
+            }
+        }
+        compilers.to_vec()
+    };
+    for compiler in &todo {
+        match fs::remove_file(dir.join(shims::shim_name(compiler))) {
+            Err(error) if error.kind() != ErrorKind::NotFound => return Err(error.into()),
+            _ => (),
+        }
+        state.shims.remove(compiler);
+    }
+    if !todo.is_empty() {
+        write_state(dir, &state)?;
+    }
+    Ok(todo)
+}
+
+pub fn main_install(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &["--repair"], &["--dir"])?;
+    if opts.positional.is_empty() && !opts.has("--repair") {
+        return Err("usage: cdbgen install [--dir DIR] [--repair] COMPILER...".into());
+    }
+    let dir = dir(&opts)?;
+    for compiler in install(&dir, &opts.positional, opts.has("--repair"))? {
+        output::info(&format!(
+            "installed {}",
+            dir.join(shims::shim_name(&compiler)).display()
+        ));
+    }
+    let on_path =
+        env::var_os("PATH").map_or(false, |path| env::split_paths(&path).any(|p| p == dir));
+    if !on_path {
+        output::info(&format!(
+            "note: add {} to PATH in order to use the shims",
+            dir.display()
+        ));
+    }
+    Ok(())
+}
+
+pub fn main_uninstall(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &[], &["--dir"])?;
+    let dir = dir(&opts)?;
+    for compiler in uninstall(&dir, &opts.positional)? {
+        output::info(&format!(
+            "removed {}",
+            dir.join(shims::shim_name(&compiler)).display()
+        ));
+    }
+    Ok(())
+}
+
+/// Condition of a shim found by `cdbgen shims`.
+#[derive(Debug, PartialEq)]
+pub enum Status {
+    Ok,
+    /// The shim does not refer to an existing cdbgen binary anymore.
+    Broken,
+    /// The compiler of the shim cannot be found.
+    Stale,
+}
+
+#[derive(Debug)]
+pub struct Shim {
+    pub path: PathBuf,
+    /// Where a symbolic link points to.
+    pub link: Option<PathBuf>,
+    /// The compiler the shim resolves to.
+    pub compiler: Option<PathBuf>,
+    /// The directory of `cdbgen install` the shim was installed into.
+    pub installed: Option<PathBuf>,
+    pub status: Status,
+}
+
+/// Inspects the file at `path` and returns it as shim if it is one, i.e., if its name has the
+/// prefix `cdbgen-` or if it is a link to the running cdbgen binary masquerading as a compiler.
+/// The compiler of a shim configured by a file next to it is the configured one.
+fn inspect(path: &Path, search: &std::ffi::OsStr) -> Option<Shim> {
+    let name = path.file_name()?.to_string_lossy().into_owned();
+    let link = fs::read_link(path).ok();
+    // The configuration of a shim is none itself.
+    if name.ends_with(".toml") {
+        return None;
+    }
+    if !name.starts_with("cdbgen-") {
+        let cdbgen = env::current_exe().ok()?.canonicalize().ok()?;
+        if name == "cdbgen" || path.canonicalize().ok()? != cdbgen {
+            return None;
+        }
+    }
+    let mut unreadable = false;
+    let resolved = match sidecar::read_for(path) {
+        _ if !path.exists() => None,
+        Ok(Some(ShimConfig {
+            compiler: Some(compiler),
+            ..
+        })) => Some(compiler).filter(|c| c.is_file()),
+        Ok(_) => match name.strip_prefix("cdbgen-") {
+            Some(compiler) => {
+                which::which_in(compiler.trim_end_matches(".exe"), Some(search), "/").ok()
+            }
+            None => shims::find_masqueraded(&name, search),
+        },
+        // Not taken as stale such that a shim whose configuration is mistyped is not cleaned.
+        Err(error) => {
+            output::warn(&format!(
+                "cannot read the configuration of {}: {}",
+                path.display(),
+                error
+            ));
+            unreadable = true;
+            None
+        }
+    };
+    let status = if !path.exists() {
+        Status::Broken
+    } else if resolved.is_none() && !unreadable {
+        Status::Stale
+    } else {
+        Status::Ok
+    };
+    Some(Shim {
+        path: path.to_path_buf(),
+        link,
+        compiler: resolved,
+        installed: None,
+        status,
+    })
+}
+
+/// Finds the shims installed into `installed` and those in the directories of `search`.
+fn discover(installed: &[PathBuf], search: &std::ffi::OsStr) -> Result<Vec<Shim>, Box<dyn Error>> {
+    let mut found: BTreeMap<PathBuf, Shim> = BTreeMap::new();
+    for dir in installed {
+        for compiler in read_state(dir)?.shims.keys() {
+            let path = dir.join(shims::shim_name(compiler));
+            let mut shim = inspect(&path, search).unwrap_or(Shim {
+                path: path.clone(),
+                link: None,
+                compiler: None,
+                installed: None,
+                status: Status::Broken,
+            });
+            shim.installed = Some(dir.clone());
+            found.insert(path, shim);
+        }
+    }
+    for dir in env::split_paths(search) {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if found.contains_key(&path) {
+                continue;
+            }
+            if let Some(shim) = inspect(&path, search) {
+                found.insert(path, shim);
+            }
+        }
+    }
+    Ok(found.into_values().collect())
+}
+
+/// Finds the shims installed by `cdbgen install` into the default directory and those in the
+/// directories of `search`.
+pub fn find_all(search: &std::ffi::OsStr) -> Result<Vec<Shim>, Box<dyn Error>> {
+    let installed: Vec<_> = default_dir().into_iter().collect();
+    discover(&installed, search)
+}
+
+/// Removes the broken and stale shims.  Shims installed by `cdbgen install` are forgotten as well.
+fn clean(shims: &[Shim]) -> Result<usize, Box<dyn Error>> {
+    let mut count = 0;
+    for shim in shims.iter().filter(|s| s.status != Status::Ok) {
+        fs::remove_file(&shim.path)?;
+        if let Some(dir) = &shim.installed {
+            let mut state = read_state(dir)?;
+            let name = shim.path.file_name().unwrap_or_default().to_string_lossy();
+            let compiler = name.trim_start_matches("cdbgen-").trim_end_matches(".exe");
+            state.shims.remove(compiler);
+            write_state(dir, &state)?;
+        }
+        output::info(&format!("removed {}", shim.path.display()));
+        count += 1;
+    }
+    Ok(count)
+}
+
+pub fn main_shims(args: &[String]) -> Result<(), Box<dyn Error>> {
+    const USAGE: &str = "usage: cdbgen shims [--dir DIR] list|clean";
+    let opts = Options::parse(args, &[], &["--dir"])?;
+    let mut installed = vec![default_dir()?];
+    installed.extend(opts.value("--dir").map(PathBuf::from));
+    let search = env::var_os("PATH").unwrap_or_default();
+    let shims = discover(&installed, &search)?;
+    match opts.positional.first().map(String::as_str) {
+        Some("list") => {
+            for shim in &shims {
+                let status = match shim.status {
+                    Status::Ok => "ok",
+                    Status::Broken => "broken",
+                    Status::Stale => "stale",
+                };
+                let compiler = shim
+                    .compiler
+                    .as_ref()
+                    .map_or_else(|| "(no compiler)".to_string(), |c| c.display().to_string());
+                print!("{:<6}  {} -> {}", status, shim.path.display(), compiler);
+                if let Some(link) = &shim.link {
+                    print!("  (links to {})", link.display());
+                }
+                if shim.installed.is_some() {
+                    print!("  [installed]");
+                }
+                println!();
+            }
+        }
+        Some("clean") => {
+            let count = clean(&shims)?;
+            output::info(&format!("removed {} of {} shims", count, shims.len()));
+        }
+        _ => return Err(USAGE.into()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn install_and_uninstall() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let dir = temp.path().join("libexec/cdbgen");
+        install(&dir, &strings(&["gcc", "g++"]), false).unwrap();
+        assert_eq!(read_state(&dir).unwrap().shims.len(), 2);
+        assert!(install(&dir, &[], true).unwrap().is_empty());
+
+        fs::remove_file(dir.join(shims::shim_name("gcc"))).unwrap();
+        assert_eq!(install(&dir, &[], true).unwrap(), ["gcc"]);
+        assert!(dir.join(shims::shim_name("gcc")).exists());
+
+        fs::write(dir.join("cdbgen-cc"), "").unwrap();
+        assert!(uninstall(&dir, &strings(&["cc"])).is_err());
+        assert_eq!(uninstall(&dir, &strings(&["g++"])).unwrap(), ["g++"]);
+        assert_eq!(uninstall(&dir, &[]).unwrap(), ["gcc"]);
+        assert!(!state_path(&dir).exists());
+        assert!(dir.join("cdbgen-cc").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn discover() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let dir = temp.path().join("bin");
+        install(
+            &dir,
+            &strings(&["sh", "no-such-compiler", "vendor-cc"]),
+            false,
+        )
+        .unwrap();
+        // Runs the compiler of its configuration.
+        fs::write(
+            dir.join("cdbgen-vendor-cc.toml"),
+            "compiler = \"/bin/sh\"\n",
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(temp.path().join("gone"), dir.join("cdbgen-cc")).unwrap();
+        fs::write(dir.join("unrelated"), "").unwrap();
+
+        let search = env::join_paths([dir.clone(), PathBuf::from("/bin")]).unwrap();
+        let shims = super::discover(std::slice::from_ref(&dir), &search).unwrap();
+        let status: Vec<_> = shims
+            .iter()
+            .map(|s| (s.path.file_name().unwrap().to_str().unwrap(), &s.status))
+            .collect();
+        assert_eq!(
+            status,
+            [
+                ("cdbgen-cc", &Status::Broken),
+                ("cdbgen-no-such-compiler", &Status::Stale),
+                ("cdbgen-sh", &Status::Ok),
+                ("cdbgen-vendor-cc", &Status::Ok)
+            ]
+        );
+        assert_eq!(clean(&shims).unwrap(), 2);
+        assert_eq!(read_state(&dir).unwrap().shims.len(), 2);
+    }
+}
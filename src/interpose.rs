@@ -0,0 +1,185 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Launchers which run the compiler given as their first operand, together with the options
+/// that take a value and may precede it.
+const LAUNCHERS: &[(&str, &[&str])] = &[
+    ("ccache", &[]),
+    ("sccache", &[]),
+    ("buildcache", &[]),
+    ("distcc", &[]),
+    ("icecc", &[]),
+    (
+        "cov-build",
+        &["--dir", "--config", "-c", "--tmpdir", "--emit-server"],
+    ),
+    ("cov-translate", &["--dir", "--config", "-c", "--tmpdir"]),
+];
+
+/// Wrappers which stand in for the compiler, together with the environment variable naming the
+/// real one and its default.
+const WRAPPERS: &[(&str, &str, &str)] = &[
+    ("intercept-cc", "INTERCEPT_BUILD_CC", "cc"),
+    ("intercept-c++", "INTERCEPT_BUILD_CXX", "c++"),
+    ("analyze-cc", "ANALYZE_BUILD_CC", "cc"),
+    ("analyze-c++", "ANALYZE_BUILD_CXX", "c++"),
+    ("ccc-analyzer", "CCC_CC", "clang"),
+    ("c++-analyzer", "CCC_CXX", "clang++"),
+];
+
+fn program_name(path: &str) -> String {
+    let name = Path::new(path)
+        .file_name()
+        .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+    match name.strip_suffix(".exe") {
+        Some(stem) => stem.to_string(),
+        None => name,
+    }
+}
+
+/// Returns the launcher the compiler at `path` masquerades as, e.g. `ccache` for
+/// `/usr/lib/ccache/gcc` being a link to it.
+fn masquerade(path: &Path) -> Option<&'static str> {
+    let target = path.canonicalize().ok()?;
+    let name = program_name(&target.to_string_lossy());
+    if name == program_name(&path.to_string_lossy()) {
+        return None;
+    }
+    LAUNCHERS.iter().map(|&(l, _)| l).find(|&l| l == name)
+}
+
+/// Searches `name` in `search` skipping masquerading launchers.
+fn find_real(name: &str, search: &[PathBuf]) -> Option<PathBuf> {
+    search
+        .iter()
+        .map(|dir| dir.join(name))
+        .find(|p| p.is_file() && masquerade(p).is_none())
+}
+
+/// Strips the layers of launchers and compiler wrappers, e.g. of ccache, distcc, Coverity, or
+/// intercept-build, from the compiler invocation `arguments` such that the entry names the real
+/// compiler.  `var` looks up environment variables and `search` is the list of directories in
+/// which compilers are looked up.
+pub fn unwrap_with<V>(mut arguments: Vec<String>, var: V, search: &[PathBuf]) -> Vec<String>
+where
+    V: Fn(&str) -> Option<String>,
+{
+    // Each round removes one layer.  Bound the number of rounds in case a wrapper refers to
+    // itself.
+    for _ in 0..8 {
+        let first = match arguments.first() {
+            Some(first) => first.clone(),
+            None => break,
+        };
+        let name = program_name(&first);
+        if let Some(&(_, valued)) = LAUNCHERS.iter().find(|&&(l, _)| l == name) {
+            let mut i = 1;
+            while i < arguments.len() && arguments[i].starts_with('-') {
+                i += if valued.contains(&arguments[i].as_str()) {
+                    2
+                } else {
+                    1
+                };
+            }
+            if i >= arguments.len() {
+                break;
+            }
+            arguments.drain(..i);
+            if let Some(real) = find_real(&arguments[0], search) {
+                arguments[0] = real.to_string_lossy().into_owned();
+            }
+        } else if let Some(&(_, variable, default)) = WRAPPERS.iter().find(|&&(w, _, _)| w == name)
+        {
+            let compiler = var(variable).unwrap_or_else(|| default.to_string());
+            let mut words: Vec<String> = compiler.split_whitespace().map(String::from).collect();
+            if words.is_empty() {
+                break;
+            }
+            if let Some(real) = find_real(&words[0], search) {
+                words[0] = real.to_string_lossy().into_owned();
+            }
+            arguments.splice(..1, words);
+        } else if masquerade(Path::new(&first)).is_some() {
+            match find_real(&name, search) {
+                Some(real) => arguments[0] = real.to_string_lossy().into_owned(),
+                None => break,
+            }
+        } else {
+            break;
+        }
+    }
+    arguments
+}
+
+/// Like `unwrap_with` for the environment of the current process.
+pub fn unwrap(arguments: Vec<String>) -> Vec<String> {
+    let search: Vec<PathBuf> = env::var_os("PATH")
+        .map(|p| env::split_paths(&p).collect())
+        .unwrap_or_default();
+    unwrap_with(arguments, |v| env::var(v).ok(), &search)
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unwrap_with() {
+        // `masq` mimics /usr/lib/ccache with links to ccache named like compilers.
+        let masq = assert_fs::TempDir::new().unwrap();
+        let bin = assert_fs::TempDir::new().unwrap();
+        masq.child("ccache").touch().unwrap();
+        masq.child("gcc")
+            .symlink_to_file(masq.child("ccache").path())
+            .unwrap();
+        bin.child("gcc").touch().unwrap();
+        bin.child("clang").touch().unwrap();
+        let search = [masq.path().to_path_buf(), bin.path().to_path_buf()];
+        let gcc = bin.child("gcc").path().to_string_lossy().into_owned();
+        let clang = bin.child("clang").path().to_string_lossy().into_owned();
+        let no_var = |_: &str| None;
+
+        assert_eq!(
+            super::unwrap_with(
+                args(&["ccache", "distcc", "gcc", "-c", "a.c"]),
+                no_var,
+                &search
+            ),
+            [gcc.as_str(), "-c", "a.c"]
+        );
+        let masq_gcc = masq.child("gcc").path().to_string_lossy().into_owned();
+        assert_eq!(
+            super::unwrap_with(args(&[&masq_gcc, "-c", "a.c"]), no_var, &search),
+            [gcc.as_str(), "-c", "a.c"]
+        );
+        let var = |v: &str| (v == "INTERCEPT_BUILD_CC").then(|| "clang --target=arm".to_string());
+        assert_eq!(
+            super::unwrap_with(
+                args(&[
+                    "/opt/cov/bin/cov-build",
+                    "--dir",
+                    "cov",
+                    "intercept-cc",
+                    "-O2",
+                    "a.c"
+                ]),
+                var,
+                &search
+            ),
+            [clang.as_str(), "--target=arm", "-O2", "a.c"]
+        );
+        assert_eq!(
+            super::unwrap_with(args(&["ccache", "-s"]), no_var, &search),
+            ["ccache", "-s"]
+        );
+        assert_eq!(
+            super::unwrap_with(args(&["/usr/bin/gcc", "a.c"]), no_var, &search),
+            ["/usr/bin/gcc", "a.c"]
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(unix)]
+use std::env;
+#[cfg(unix)]
+use std::fs::OpenOptions;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+/// How to reach the jobserver of GNU make as given by `--jobserver-auth` in `MAKEFLAGS`.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(not(unix), allow(dead_code))]
+enum Auth {
+    /// File descriptors of a pipe inherited from make, i.e., `R,W`.
+    Fds(i32, i32),
+    /// Named pipe created by make 4.4 and later, i.e., `fifo:PATH`.
+    Fifo(String),
+}
+
+/// Parses the jobserver options of `makeflags` where later ones win.  Older versions of make
+/// call the option `--jobserver-fds`.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn parse(makeflags: &str) -> Option<Auth> {
+    let value = makeflags.split_whitespace().rev().find_map(|word| {
+        word.strip_prefix("--jobserver-auth=")
+            .or_else(|| word.strip_prefix("--jobserver-fds="))
+    })?;
+    if let Some(path) = value.strip_prefix("fifo:") {
+        return Some(Auth::Fifo(path.to_string()));
+    }
+    let (read, write) = value.split_once(',')?;
+    Some(Auth::Fds(read.parse().ok()?, write.parse().ok()?))
+}
+
+/// A client of the jobserver of GNU make.  Each job but one needs a token from make, i.e., a byte
+/// read from the jobserver pipe, which is written back once the job finished.  The remaining job
+/// runs with the token make implicitly granted cdbgen itself.
+#[derive(Debug)]
+pub struct Client {
+    read: File,
+    write: File,
+    implicit: AtomicBool,
+}
+
+/// Permission to run a job which is returned to the jobserver when dropped.
+pub struct Token<'a> {
+    client: &'a Client,
+    byte: Option<u8>,
+}
+
+impl Drop for Token<'_> {
+    fn drop(&mut self) {
+        match self.byte {
+            Some(byte) => {
+                // Make waits for its tokens, hence this must not be lost.
+                let _ = (&self.client.write).write_all(&[byte]);
+            }
+            None => self.client.implicit.store(true, Ordering::Release),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn dup(fd: i32) -> Option<File> {
+    // Descriptors not passed on by make, e.g. if the rule is not marked recursive, are invalid.
+    let fd = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if fd < 0 {
+        None
+    } else {
+        Some(unsafe { File::from_raw_fd(fd) })
+    }
+}
+
+impl Client {
+    /// Connects to the jobserver of the make running cdbgen, if any.
+    #[cfg(unix)]
+    pub fn from_env() -> Option<Self> {
+        let (read, write) = match parse(&env::var("MAKEFLAGS").ok()?)? {
+            Auth::Fds(read, write) => (dup(read)?, dup(write)?),
+            Auth::Fifo(path) => {
+                let fifo = OpenOptions::new().read(true).write(true).open(path).ok()?;
+                (fifo.try_clone().ok()?, fifo)
+            }
+        };
+        Some(Client {
+            read,
+            write,
+            implicit: AtomicBool::new(true),
+        })
+    }
+
+    /// The jobserver of make is only supported on Unix.
+    #[cfg(not(unix))]
+    pub fn from_env() -> Option<Self> {
+        None
+    }
+
+    /// Waits until a job may be run.
+    pub fn acquire(&self) -> io::Result<Token<'_>> {
+        if self.implicit.swap(false, Ordering::Acquire) {
+            return Ok(Token {
+                client: self,
+                byte: None,
+            });
+        }
+        let byte = self.read_byte()?;
+        Ok(Token {
+            client: self,
+            byte: Some(byte),
+        })
+    }
+
+    #[cfg(unix)]
+    fn read_byte(&self) -> io::Result<u8> {
+        let fd = self.read.as_raw_fd();
+        let mut byte = 0u8;
+        loop {
+            let n = unsafe { libc::read(fd, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+            if n == 1 {
+                return Ok(byte);
+            } else if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "jobserver closed",
+                ));
+            }
+            let error = io::Error::last_os_error();
+            match error.kind() {
+                io::ErrorKind::Interrupted => (),
+                // Make 4.3 and later may leave the pipe non-blocking.
+                io::ErrorKind::WouldBlock => {
+                    let mut pfd = libc::pollfd {
+                        fd,
+                        events: libc::POLLIN,
+                        revents: 0,
+                    };
+                    unsafe { libc::poll(&mut pfd, 1, -1) };
+                }
+                _ => return Err(error),
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn read_byte(&self) -> io::Result<u8> {
+        let _ = &self.read;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            super::parse(" -j8 --jobserver-auth=3,4"),
+            Some(Auth::Fds(3, 4))
+        );
+        assert_eq!(
+            super::parse("-j --jobserver-fds=5,6 --jobserver-auth=fifo:/tmp/GMfifo1"),
+            Some(Auth::Fifo("/tmp/GMfifo1".to_string()))
+        );
+        assert_eq!(super::parse("-k -j4"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn acquire() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let client = Client {
+            read: unsafe { File::from_raw_fd(fds[0]) },
+            write: unsafe { File::from_raw_fd(fds[1]) },
+            implicit: AtomicBool::new(true),
+        };
+        (&client.write).write_all(b"+").unwrap();
+        // Both tokens are returned and can be taken again.
+        for _ in 0..2 {
+            let first = client.acquire().unwrap();
+            let second = client.acquire().unwrap();
+            assert_eq!((first.byte, second.byte), (None, Some(b'+')));
+        }
+        assert!(client.implicit.load(Ordering::Relaxed));
+    }
+}
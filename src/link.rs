@@ -0,0 +1,253 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::args;
+use crate::config::Config;
+use crate::db;
+
+/// A link command, i.e., an invocation of the compiler driver which produces a binary or a
+/// shared library from objects, libraries, and possibly sources, or an invocation of an
+/// archiver which produces a static library from objects.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct LinkEntry {
+    pub directory: String,
+    pub output: String,
+    /// Objects, archives, and sources given on the command line respectively the members of an
+    /// archive.
+    pub inputs: Vec<String>,
+    /// Libraries linked via `-l`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub libraries: Vec<String>,
+    pub arguments: Vec<String>,
+}
+
+/// Returns the database link commands are recorded into, i.e., `link_commands.json` next to the
+/// database of `config`.
+pub fn database(config: &Config) -> PathBuf {
+    config.database.with_file_name("link_commands.json")
+}
+
+/// Returns the link performed by the compiler invocation `arguments` (the compiler first) unless
+/// it stops before linking, e.g. due to `-c`.
+pub fn link(directory: &str, arguments: &[String]) -> Option<LinkEntry> {
+    let flags = args::flags(arguments.get(1..)?);
+    let mut output = "a.out".to_string();
+    let mut inputs = Vec::new();
+    let mut libraries = Vec::new();
+    for flag in &flags {
+        match flag.name.as_str() {
+            "-c" | "-S" | "-E" | "-M" | "-MM" | "-fsyntax-only" => return None,
+            "-o" => output = flag.value.clone()?,
+            "-l" => libraries.push(flag.value.clone()?),
+            _ if flag.is_input() => inputs.push(flag.name.clone()),
+            _ => (),
+        }
+    }
+    if inputs.is_empty() {
+        return None;
+    }
+    Some(LinkEntry {
+        directory: directory.to_string(),
+        output,
+        inputs,
+        libraries,
+        arguments: arguments.to_vec(),
+    })
+}
+
+/// Options which only affect the link.
+const LINK_ONLY: &[&str] = &[
+    "-o",
+    "-l",
+    "-L",
+    "-Xlinker",
+    "-T",
+    "-shared",
+    "-static",
+    "-static-libgcc",
+    "-static-libstdc++",
+    "-rdynamic",
+    "-pie",
+    "-no-pie",
+    "-s",
+    "-nostdlib",
+    "-nostartfiles",
+    "-nodefaultlibs",
+];
+
+/// Returns the arguments compiling just `file` for the compile-and-link invocation `arguments`
+/// (the compiler first), i.e., without the other inputs and the options only affecting the link
+/// but with `-c`.  Returns `None` if the invocation does not link.
+pub fn compile_only(arguments: &[String], file: &str) -> Option<Vec<String>> {
+    link(".", arguments)?;
+    let (compiler, rest) = arguments.split_first()?;
+    let mut result = vec![compiler.clone()];
+    for flag in args::flags(rest) {
+        if flag.is_input() && flag.name == file {
+            result.push("-c".to_string());
+        } else if flag.is_input()
+            || LINK_ONLY.contains(&flag.name.as_str())
+            || flag.name.starts_with("-Wl,")
+        {
+            continue;
+        }
+        result.extend(flag.args);
+    }
+    Some(result)
+}
+
+/// Returns the static library created or updated by the archiver invocation `arguments` (the
+/// archiver first), e.g. `ar rcs libfoo.a foo.o` or `lib.exe /OUT:foo.lib foo.obj`.  Other
+/// operations like listing or extracting members are ignored.
+pub fn archive(directory: &str, arguments: &[String]) -> Option<LinkEntry> {
+    let program = Path::new(arguments.first()?).file_name()?.to_string_lossy();
+    let mut output = None;
+    let mut inputs = Vec::new();
+    let mut iter = arguments[1..].iter();
+    if program.ends_with("lib.exe") || program.starts_with("llvm-lib") {
+        for arg in iter {
+            let lower = arg.to_ascii_lowercase();
+            if let Some(out) = lower
+                .strip_prefix("/out:")
+                .or_else(|| lower.strip_prefix("-out:"))
+            {
+                output = Some(arg[arg.len() - out.len()..].to_string());
+            } else if !arg.starts_with('/') && !arg.starts_with('-') {
+                inputs.push(arg.clone());
+            }
+        }
+        // Without /OUT the library is named after the first member.
+        if output.is_none() {
+            output = Some(
+                Path::new(inputs.first()?)
+                    .with_extension("lib")
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+        }
+    } else {
+        let mut operation = None;
+        while let Some(arg) = iter.next() {
+            if arg == "--plugin" || arg == "--target" || arg == "--output" {
+                iter.next();
+            } else if arg.starts_with("--") {
+                // Other long options take no value.
+                continue;
+            } else if operation.is_none() {
+                let op = arg.trim_start_matches('-');
+                // Modifiers taking an argument before the archive.
+                if op.contains(['a', 'b', 'i', 'N']) {
+                    iter.next();
+                }
+                operation = Some(op.to_string());
+            } else if output.is_none() {
+                output = Some(arg.clone());
+            } else {
+                inputs.push(arg.clone());
+            }
+        }
+        let operation = operation?;
+        if !operation.contains(['r', 'q']) {
+            return None;
+        }
+    }
+    if inputs.is_empty() {
+        return None;
+    }
+    Some(LinkEntry {
+        directory: directory.to_string(),
+        output: output?,
+        inputs,
+        libraries: Vec::new(),
+        arguments: arguments.to_vec(),
+    })
+}
+
+/// Reads the link commands of `json_path`.
+pub fn read(json_path: &Path) -> Result<BTreeSet<LinkEntry>, Box<dyn Error>> {
+    db::read_json(json_path)
+}
+
+/// Records `link` into the database at `json_path` where it replaces the link of the same output.
+pub fn record(json_path: &Path, link: LinkEntry) -> Result<(), Box<dyn Error>> {
+    db::update_json(json_path, |links: &mut BTreeSet<LinkEntry>| {
+        links.retain(|l| l.directory != link.directory || l.output != link.output);
+        links.insert(link.clone());
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn link() {
+        let link = super::link(
+            "/build",
+            &strings(&[
+                "cc", "-o", "app", "main.o", "util.c", "-L", "lib", "-lm", "-l", "z",
+            ]),
+        )
+        .unwrap();
+        assert_eq!(link.output, "app");
+        assert_eq!(link.inputs, ["main.o", "util.c"]);
+        assert_eq!(link.libraries, ["m", "z"]);
+        assert_eq!(
+            super::link("/build", &strings(&["cc", "x.o"]))
+                .unwrap()
+                .output,
+            "a.out"
+        );
+        assert!(super::link("/build", &strings(&["cc", "-c", "x.c"])).is_none());
+        assert!(super::link("/build", &strings(&["cc", "--version"])).is_none());
+    }
+
+    #[test]
+    fn compile_only() {
+        let arguments = strings(&[
+            "gcc",
+            "-O2",
+            "main.c",
+            "util.c",
+            "-Wl,--as-needed",
+            "-o",
+            "app",
+            "-lm",
+        ]);
+        assert_eq!(
+            super::compile_only(&arguments, "util.c").unwrap(),
+            ["gcc", "-O2", "-c", "util.c"]
+        );
+        assert!(super::compile_only(&strings(&["gcc", "-c", "a.c"]), "a.c").is_none());
+    }
+
+    #[test]
+    fn archive() {
+        let archive =
+            |args: &[&str]| super::archive("/build", &strings(args)).map(|a| (a.output, a.inputs));
+        assert_eq!(
+            archive(&["ar", "rcs", "libx.a", "a.o", "b.o"]),
+            Some(("libx.a".to_string(), strings(&["a.o", "b.o"])))
+        );
+        assert_eq!(
+            archive(&["gcc-ar", "--plugin", "p.so", "-rb", "a.o", "libx.a", "c.o"]),
+            Some(("libx.a".to_string(), strings(&["c.o"])))
+        );
+        assert_eq!(archive(&["ar", "t", "libx.a"]), None);
+        assert_eq!(archive(&["ar", "x", "libx.a", "a.o"]), None);
+        assert_eq!(
+            archive(&["lib.exe", "/nologo", "/OUT:x.lib", "a.obj"]),
+            Some(("x.lib".to_string(), strings(&["a.obj"])))
+        );
+        assert_eq!(
+            archive(&["llvm-lib", "a.obj"]),
+            Some(("a.lib".to_string(), strings(&["a.obj"])))
+        );
+    }
+}
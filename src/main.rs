@@ -1,159 +1,184 @@
-use std::collections::BTreeSet;
 use std::env;
 use std::error::Error;
-use std::fs::File;
-use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::{Duration, Instant, SystemTime};
 
-use serde::{Deserialize, Serialize};
 use which::which;
 
-#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Serialize, Deserialize)]
-struct Entry {
-    directory: String,
-    file: String,
-    arguments: Vec<String>,
-}
-
-fn lock(file: &mut File) -> Result<(), Box<dyn Error>> {
-    #[cfg(unix)]
-    {
-        use std::os::unix::io::AsRawFd;
-        let ret = unsafe { libc::lockf(file.as_raw_fd(), libc::F_LOCK, 0) };
-        if ret != 0 {
-            return Err(std::io::Error::last_os_error().into());
-        }
-        Ok(())
-    }
-
-    #[cfg(windows)]
-    {
-        use std::os::windows::io::AsRawHandle;
-        use windows::Win32::Foundation::HANDLE;
-        use windows::Win32::Storage::FileSystem::{LockFileEx, LOCKFILE_EXCLUSIVE_LOCK};
-        unsafe {
-            let mut overlapped = std::mem::zeroed();
-            let ret = LockFileEx(
-                HANDLE(file.as_raw_handle() as isize),
-                LOCKFILE_EXCLUSIVE_LOCK,
-                0,
-                !0,
-                !0,
-                &mut overlapped,
-            );
-            if ret.0 == 0 {
-                return Err(std::io::Error::last_os_error().into());
-            }
-            return Ok(());
-        };
-    }
-
-    #[cfg(not(any(unix, windows)))]
-    compile_error!("File (un)locking only supported on Unix and Windows");
-}
-
-#[cfg(windows)]
-fn unlock(file: &mut File) -> Result<(), Box<dyn Error>> {
-    use std::os::windows::io::AsRawHandle;
-    use windows::Win32::Foundation::HANDLE;
-    use windows::Win32::Storage::FileSystem::UnlockFile;
-    let ret = unsafe { UnlockFile(HANDLE(file.as_raw_handle() as isize), 0, 0, !0, !0) };
-    if ret.0 == 0 {
-        return Err(std::io::Error::last_os_error().into());
-    }
-    Ok(())
-}
-
-fn process_compile_commands_json(
-    json_path: &Path,
-    directory: &Path,
-    arguments: &[String],
-    files: &BTreeSet<String>,
-) -> Result<(), Box<dyn Error>> {
-    if let Err(error) = File::options()
-        .write(true)
-        .create_new(true)
-        .open(&json_path)
-    {
-        match error.kind() {
-            ErrorKind::AlreadyExists => (),
-            _ => return Err(error.into()),
-        }
-    }
-
-    let directory = directory
-        .to_path_buf()
-        .into_os_string()
-        .into_string()
-        .unwrap();
+mod android;
+mod args;
+mod artifacts;
+mod background;
+mod buildroot;
+mod cache;
+mod check;
+mod cli;
+mod codeql;
+mod coexist;
+mod config;
+mod cppcheck;
+mod ctags;
+mod db;
+mod deps;
+mod diff;
+mod doctor;
+mod enrich;
+mod esp_idf;
+mod events;
+mod explain;
+mod fragment;
+mod fresh;
+mod fsmonitor;
+mod git;
+mod githooks;
+mod graph;
+mod grep;
+mod hash;
+mod implicit;
+mod import;
+mod index;
+mod infer;
+mod install;
+mod interpose;
+mod jobserver;
+mod link;
+mod meta;
+mod modules;
+mod ninja;
+mod nix;
+mod output;
+mod overhead;
+mod parallel;
+mod parse;
+mod paths;
+mod pattern;
+mod platformio;
+mod presets;
+mod protoc;
+mod prune;
+mod relocate;
+mod remote;
+mod resource;
+mod rewrite;
+mod route;
+mod serve;
+mod session;
+mod setup;
+mod shard;
+mod shell;
+mod shims;
+mod sidecar;
+mod sourcetrail;
+mod staging;
+mod stats;
+mod stream;
+mod summary;
+mod sweep;
+mod time;
+mod tools;
+mod top;
+mod usage;
+mod vscode;
+mod watch;
+mod wrap;
+mod yocto;
+mod zephyr;
 
-    let mut json_file = File::options().read(true).write(true).open(json_path)?;
-    lock(&mut json_file)?;
-
-    let mut data = String::new();
-    json_file.read_to_string(&mut data)?;
-
-    let old_entries: BTreeSet<Entry> = if data.trim().is_empty() {
-        BTreeSet::new()
-    } else {
-        serde_json::from_str(&data)?
-    };
-    let mut new_entries: BTreeSet<Entry> = old_entries
-        .iter()
-        .filter(|&e| e.directory != directory || !files.contains(&e.file))
-        .cloned()
-        .collect();
-    for f in files {
-        new_entries.insert(Entry {
-            directory: directory.clone(),
-            file: f.to_string(),
-            arguments: arguments.to_owned(),
-        });
-    }
-
-    if new_entries != old_entries {
-        let json_string = serde_json::to_string_pretty(&new_entries)?;
-        json_file.set_len(0)?;
-        json_file.seek(SeekFrom::Start(0))?;
-        writeln!(&mut json_file, "{}", json_string)?;
-    }
-
-    // On Unix there is no need to explicitly release the lock since this is done implicitly once
-    // the file is closed.  On Windows this is more or less the same except that the time between
-    // closing the file and releasing the lock may be arbitrarily long.  Thus it is suggested to
-    // explicitly unlock the file.
-    #[cfg(windows)]
-    unlock(&mut json_file)?;
-
-    Ok(())
-}
+use config::{CcWrapper, Config, Conflict, ResponseFiles, Storage};
+#[cfg(all(test, unix))]
+use db::Entry;
+use db::{process_compile_commands_json, Invocation};
+use overhead::Phase;
+use sidecar::ShimConfig;
 
 fn find_compiler(cmd: &Path) -> Result<PathBuf, Box<dyn Error>> {
     let file_name = cmd.file_name().unwrap();
     let file_name_str = file_name.to_os_string().into_string().unwrap();
 
     if let Some(compiler) = file_name_str.strip_prefix("cdbgen-") {
-        Ok(which(compiler)?)
+        if compiler.is_empty() {
+            return Err("shim 'cdbgen-' names no compiler, e.g. call it cdbgen-gcc".into());
+        }
+        let error = match which(compiler) {
+            Ok(path) => return Ok(path),
+            Err(error) => error,
+        };
+        let fallbacks = match env::var("CDBGEN_FALLBACK") {
+            Ok(chain) if chain == "off" || chain == "0" => Vec::new(),
+            Ok(chain) => chain.split(',').map(String::from).collect(),
+            Err(_) => shims::fallbacks(compiler, &env::var_os("PATH").unwrap_or_default()),
+        };
+        for fallback in fallbacks {
+            if let Ok(path) = which(&fallback) {
+                output::warn(&format!(
+                    "compiler '{}' not found, using '{}' instead",
+                    compiler,
+                    path.display()
+                ));
+                return Ok(path);
+            }
+        }
+        let path = env::var_os("PATH").unwrap_or_default();
+        let mut message = format!(
+            "compiler '{}' not found ({}) in PATH={}",
+            compiler,
+            error,
+            path.to_string_lossy()
+        );
+        let suggestions = shims::suggestions(compiler, &path);
+        if !suggestions.is_empty() {
+            message += &format!("; did you mean '{}'?", suggestions.join("', '"));
+        }
+        Err(message.into())
     } else {
-        Err(format!("command '{}' misses prefix 'cdbgen-'", file_name_str).into())
+        // Masquerading as the compiler, e.g. as `gcc` in a directory put in front of `PATH`.
+        shims::find_masqueraded(&file_name_str, &env::var_os("PATH").unwrap_or_default())
+            .ok_or_else(|| {
+                format!(
+                    "no compiler '{}' found on PATH to masquerade as; cdbgen runs the compiler \
+                     its file name names, i.e., it has to be called cdbgen-COMPILER or COMPILER, \
+                     or cdbgen for the subcommands, see 'cdbgen help'",
+                    file_name_str
+                )
+                .into()
+            })
+    }
+}
+
+/// Tells what the invocation recorded if verbose.
+fn report() {
+    for (json_path, c) in summary::recorded() {
+        output::verbose(&format!(
+            "cdbgen: {} added, {} updated, {} unchanged, {} deferred in {}",
+            c.added,
+            c.updated,
+            c.unchanged,
+            c.deferred,
+            json_path.display()
+        ));
     }
 }
 
-fn exec(compiler: &Path) -> Result<(), Box<dyn Error>> {
+fn exec(compiler: &Path, args: &[String]) -> Result<(), Box<dyn Error>> {
+    report();
+    log(compiler, Duration::ZERO);
+    replace(compiler, args)
+}
+
+/// Runs the compiler in place of the recorder, or as child where processes cannot be replaced,
+/// without logging the invocation.
+fn replace(compiler: &Path, args: &[String]) -> Result<(), Box<dyn Error>> {
     #[cfg(unix)]
     {
         use std::os::unix::process::CommandExt;
-        Err(Command::new(compiler)
-            .args(env::args_os().skip(1))
-            .exec()
-            .into())
+        Err(Command::new(compiler).args(args).exec().into())
     }
 
     #[cfg(not(unix))]
     {
         let status = Command::new(compiler)
-            .args(env::args_os().skip(1))
+            .args(args)
             .status()
             .expect("failed to execute process");
         if status.success() {
@@ -167,46 +192,291 @@ fn exec(compiler: &Path) -> Result<(), Box<dyn Error>> {
     }
 }
 
+/// Runs the compiler as child process, measures how long it takes, and exits with its status
+/// after `f` was called with the elapsed time.
+fn run_timed<F>(compiler: &Path, args: &[String], f: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnOnce(Duration) -> Result<(), Box<dyn Error>>,
+{
+    let start = Instant::now();
+    let status = Command::new(compiler).args(args).status()?;
+    let duration = start.elapsed();
+    f(duration)?;
+    report();
+    log(compiler, duration);
+    if status.success() {
+        return Ok(());
+    }
+    wrap::exit_like(status)
+}
+
+/// Logs the event of the invocation of `compiler` including its overhead.  A log which cannot be
+/// written is warned about only such that the compile is run anyway.
+fn log(compiler: &Path, duration: Duration) {
+    if let Err(error) = events::log(Some(compiler), duration, None) {
+        output::warn(&format!("cannot log event: {}", error));
+    }
+}
+
+fn is_cdbgen(cmd: &Path) -> bool {
+    cmd.file_stem().map_or(false, |s| s == "cdbgen")
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut args: Vec<_> = env::args().collect();
+    let args: Vec<_> = env::args().collect();
+
+    if is_cdbgen(Path::new(&args[0])) {
+        return cli::run(&args[1..]);
+    }
+
+    overhead::start();
+    let (shim_config, compiler, arguments) = overhead::measure(Phase::Resolve, || {
+        let shim_config = sidecar::read(&args[0])?.unwrap_or_default();
+        let compiler = match &shim_config.compiler {
+            Some(compiler) => compiler.clone(),
+            None => find_compiler(Path::new(&args[0]))?,
+        };
+        let mut arguments = args.clone();
+        arguments[0] = compiler.to_str().unwrap().to_string();
+        let arguments = interpose::unwrap(arguments);
+        Ok::<_, Box<dyn Error>>((shim_config, compiler, arguments))
+    })
+    .map_err(|error| log_failure(None, error))?;
+    compile(args.clone(), arguments, &shim_config, &compiler, &args[1..])
+        .map_err(|error| log_failure(Some(&compiler), error))
+}
+
+/// Logs that the invocation of `compiler` failed with `error` and returns it.
+fn log_failure(compiler: Option<&Path>, error: Box<dyn Error>) -> Box<dyn Error> {
+    if let Err(log_error) = events::log(compiler, Duration::ZERO, Some(error.as_ref())) {
+        output::warn(&format!("cannot log event: {}", log_error));
+    }
+    error
+}
 
-    let compiler = find_compiler(Path::new(&args[0]))?;
+/// Records the compiler invocation `arguments` (the compiler first) as invoked by `argv` unless
+/// no source file is compiled, and finally runs `program` with `program_args`.
+fn compile(
+    argv: Vec<String>,
+    mut arguments: Vec<String>,
+    shim_config: &ShimConfig,
+    program: &Path,
+    program_args: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let mut config = Config::from_env();
+    if let Some(database) = &shim_config.database {
+        config.database = database.clone();
+    }
+    if let Some(preset) = &shim_config.preset {
+        config.preset = Some(preset.clone());
+    }
+    if config.strict {
+        if let Some(invalid) = config::invalid().into_iter().next() {
+            return Err(invalid.into());
+        }
+    }
+    let cwd = env::current_dir()?;
+    let directory = cwd.to_str().unwrap().to_string();
 
-    #[allow(clippy::case_sensitive_file_extension_comparisons)]
-    let files: BTreeSet<_> = args[1..]
-        .iter()
-        .filter(|arg| {
-            #[cfg(not(windows))]
-            let x = arg;
-            #[cfg(windows)]
-            let x = arg.to_lowercase();
-            x.ends_with(".c") || x.ends_with(".cc") || x.ends_with(".cpp")
-        })
-        .cloned()
-        .collect();
-    if !files.is_empty() {
-        let json_path = env::var_os("CDBGEN").unwrap_or_else(|| "compile_commands.json".into());
-        let json_path = Path::new(&json_path);
+    let files = db::compiled_files(&arguments);
+    if config.protoc && protoc::is_protoc(&arguments[0]) {
+        let protos = protoc::entries(&directory, &arguments);
+        if !protos.is_empty() {
+            tolerate(&config, protoc::record(&protoc::database(&config), protos))?;
+        }
+    } else if tools::is_tool(&config, &arguments[0]) {
+        let files = tools::inputs(&arguments[1..], &cwd);
+        if !files.is_empty() {
+            let invocation = Invocation {
+                argv,
+                arguments,
+                directory,
+                files,
+                duration: None,
+                session: None,
+            };
+            tolerate(
+                &config,
+                tools::record(&tools::database(&config), &invocation),
+            )?;
+        }
+    } else if config.resources && resource::is_resource_compiler(&arguments[0]) {
+        let files = resource::scripts(&arguments[1..]);
+        if !files.is_empty() {
+            let invocation = Invocation {
+                argv,
+                arguments,
+                directory,
+                files,
+                duration: None,
+                session: None,
+            };
+            tolerate(
+                &config,
+                tools::record(&resource::database(&config), &invocation),
+            )?;
+        }
+    } else {
+        if config.links {
+            let link = if shims::is_archiver(&program.to_string_lossy()) {
+                link::archive(&directory, &arguments)
+            } else {
+                link::link(&directory, &arguments)
+            };
+            if let Some(link) = link {
+                tolerate(&config, link::record(&link::database(&config), link))?;
+            }
+        }
+        if files.is_empty() {
+            return exec(program, program_args);
+        }
+        if config.response_files == ResponseFiles::Expand {
+            arguments = args::expand_response_files(&arguments, &directory);
+        }
+        arguments = args::tag_assembler(&modules::expand(&arguments, &directory));
+        arguments.splice(1..1, shim_config.extra_flags.iter().cloned());
+        if config.cc_wrapper != CcWrapper::Keep {
+            arguments = nix::materialize(&arguments, config.cc_wrapper);
+        }
+        if let Some(selection) = &config.android_abi {
+            match android::abi(&arguments) {
+                Some(abi) if selection == "split" => {
+                    config.database = android::database(&config.database, abi)
+                }
+                Some(abi) if abi != selection => return exec(program, program_args),
+                _ => (),
+            }
+        }
 
-        let directory = env::current_dir()?;
+        events::note_files(files.keys());
+        let mut invocation = Invocation {
+            argv,
+            arguments,
+            directory,
+            files,
+            duration: None,
+            session: config.session.clone(),
+        };
+        // The compile would have to wait for the outcome of recording if strict, and for the
+        // compiler if timed.  Fragments carry neither the metadata sidecar nor shards.
+        let background = config.background
+            && !(config.timing || config.header_deps || config.strict)
+            && config.on_conflict != Conflict::Fail
+            && !config.records_meta()
+            && !(config.storage == Storage::Sharded || shard::is_sharded(&config.database));
+        if background {
+            // Fragments merged by the time of the compile keep the order of the compiles.
+            fragment::stamp(SystemTime::now());
+            let mut config = config.clone();
+            config.storage = Storage::Fragments;
+            let compiler = program.to_path_buf();
+            // The event tells what was recorded, i.e., is logged by the helper.
+            background::spawn(move || {
+                let mut result = Ok(());
+                if config.modules {
+                    result = tolerate(
+                        &config,
+                        modules::record(&modules::database(&config), &invocation),
+                    );
+                }
+                let result = result.and_then(|()| {
+                    tolerate(&config, process_compile_commands_json(&config, &invocation))
+                });
+                match result {
+                    Ok(()) => {
+                        log(&compiler, Duration::ZERO);
+                        Ok(())
+                    }
+                    Err(error) => Err(log_failure(Some(&compiler), error)),
+                }
+            })?;
+            return replace(program, program_args);
+        }
+        if config.modules {
+            tolerate(
+                &config,
+                modules::record(&modules::database(&config), &invocation),
+            )?;
+        }
+        if config.timing || config.header_deps {
+            return run_timed(program, program_args, |duration| {
+                if config.timing {
+                    invocation.duration = Some(duration);
+                }
+                tolerate(&config, process_compile_commands_json(&config, &invocation))?;
+                if config.header_deps {
+                    // The dependency files are written by the compile itself.
+                    tolerate(
+                        &config,
+                        deps::record(&deps::database(&config.database), &invocation),
+                    )?;
+                }
+                Ok(())
+            });
+        }
+        tolerate(&config, process_compile_commands_json(&config, &invocation))?;
+    }
 
-        args[0] = compiler.to_str().unwrap().to_string();
+    exec(program, program_args)
+}
 
-        process_compile_commands_json(json_path, &directory, &args, &files)?;
+/// Turns an error of recording into a warning such that the compile is run anyway, unless
+/// `CDBGEN_STRICT` is set or the error is a conflict which is to fail the compile.
+fn tolerate(config: &Config, result: Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+    match result {
+        Ok(()) => Ok(()),
+        Err(error) if config.strict || error.is::<db::ConflictError>() => Err(error),
+        Err(error) => {
+            output::warn(&format!("cannot record: {}; compiling anyway", error));
+            events::note_failure(error.as_ref());
+            Ok(())
+        }
     }
+}
 
-    exec(&compiler)
+/// Runs cdbgen as launcher of `chain`, e.g. `cdbgen sccache clang++ -c foo.c` as set up by
+/// `CMAKE_CXX_COMPILER_LAUNCHER`.  The entry names the real compiler at the end of the chain
+/// whereas the chain itself is run unchanged.
+fn launch(chain: &[String]) -> Result<(), Box<dyn Error>> {
+    overhead::start();
+    let (first, rest) = chain.split_first().ok_or("missing command to launch")?;
+    let program = which(first)?;
+    let mut arguments = chain.to_vec();
+    arguments[0] = program.to_string_lossy().into_owned();
+    let arguments = interpose::unwrap(arguments);
+    let argv = env::args().take(1).chain(chain.iter().cloned()).collect();
+    compile(argv, arguments, &ShimConfig::default(), &program, rest)
+        .map_err(|error| log_failure(Some(&program), error))
 }
 
-#[cfg(test)]
+// The shims of the tests are found on a Unix PATH.
+#[cfg(all(test, unix))]
 mod tests {
     use super::*;
     use assert_cmd::prelude::*;
     use assert_fs::prelude::*;
-    use std::ffi::OsString;
-    use std::fs::OpenOptions;
+    use std::fs::{File, OpenOptions};
+    use std::io::Read;
     use std::os::unix::fs::OpenOptionsExt;
 
+    /// Returns the path of the cdbgen binary under test.
+    fn cdbgen() -> PathBuf {
+        let cmd = Command::cargo_bin("cdbgen").unwrap();
+        Path::new(cmd.get_program()).canonicalize().unwrap()
+    }
+
+    /// Returns a temporary directory holding a shim of each of `names`, e.g. `cdbgen-true`, and a
+    /// `PATH` which finds them first.
+    fn shims(names: &[&str]) -> (assert_fs::TempDir, String) {
+        let cdbgen_path = cdbgen();
+        let temp = assert_fs::TempDir::new().unwrap();
+        for name in names {
+            temp.child(name).symlink_to_file(&cdbgen_path).unwrap();
+        }
+        let path = format!("{}:/bin:/usr/bin", temp.path().display());
+        (temp, path)
+    }
+
     #[test]
     fn find_compiler() {
         let bindir0 = assert_fs::TempDir::new().unwrap();
@@ -216,6 +486,7 @@ mod tests {
         OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .mode(0o700)
             .open(&foobar1)
             .unwrap();
@@ -225,6 +496,7 @@ mod tests {
         OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .mode(0o700)
             .open(&foobar2)
             .unwrap();
@@ -234,6 +506,7 @@ mod tests {
         OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .mode(0o700)
             .open(&foobar3)
             .unwrap();
@@ -244,7 +517,7 @@ mod tests {
             bindir2.path(),
             bindir3.path(),
         ];
-        let old_path = env::var_os("PATH").unwrap_or_else(OsString::new);
+        let old_path = env::var_os("PATH").unwrap_or_default();
         let new_path = env::join_paths(paths.iter()).unwrap();
         env::set_var("PATH", &new_path);
 
@@ -258,14 +531,7 @@ mod tests {
 
     #[test]
     fn main() {
-        let cmd = Command::cargo_bin("cdbgen").unwrap();
-        let cdbgen_path = Path::new(cmd.get_program()).canonicalize().unwrap();
-        let temp = assert_fs::TempDir::new().unwrap();
-        temp.child("cdbgen-true")
-            .symlink_to_file(cdbgen_path)
-            .unwrap();
-
-        let path = format!("{}:/bin:/usr/bin", temp.path().display());
+        let (temp, path) = shims(&["cdbgen-true"]);
 
         let n = 100;
 
@@ -316,9 +582,13 @@ mod tests {
 
         assert_eq!(entries.len(), n);
 
-        for i in 0..n {
-            assert_eq!(entries[i].directory, temp.path().to_string_lossy());
-            assert_eq!(entries[i].file, format!("foo{:03}.c", i));
+        for (i, entry) in entries.iter().enumerate() {
+            assert_eq!(entry.directory, temp.path().to_string_lossy());
+            assert_eq!(entry.file, format!("foo{:03}.c", i));
+            assert_eq!(
+                entry.output.as_deref(),
+                Some(format!("foo{:03}", i).as_str())
+            );
             let args = [
                 "/bin/true",
                 "-O2",
@@ -326,20 +596,13 @@ mod tests {
                 &format!("foo{:03}", i),
                 &format!("foo{:03}.c", i),
             ];
-            assert_eq!(entries[i].arguments, args);
+            assert_eq!(entry.arguments, args);
         }
     }
 
     #[test]
     fn mutiple_compilation_units() {
-        let cmd = Command::cargo_bin("cdbgen").unwrap();
-        let cdbgen_path = Path::new(cmd.get_program()).canonicalize().unwrap();
-        let temp = assert_fs::TempDir::new().unwrap();
-        temp.child("cdbgen-true")
-            .symlink_to_file(cdbgen_path)
-            .unwrap();
-
-        let path = format!("{}:/bin:/usr/bin", temp.path().display());
+        let (temp, path) = shims(&["cdbgen-true"]);
 
         let status = Command::new("cdbgen-true")
             .args(["-O2", "baz.c", "-o", "foo.c", "bar.c"])
@@ -356,9 +619,634 @@ mod tests {
         let mut entries: Vec<Entry> = serde_json::from_str(&data).unwrap();
         entries.sort();
 
-        assert_eq!(entries.len(), 3);
+        // The output is not a source even though it is named like one.
+        assert_eq!(entries.len(), 2);
         assert_eq!(entries[0].file, "bar.c");
         assert_eq!(entries[1].file, "baz.c");
-        assert_eq!(entries[2].file, "foo.c");
+        assert_eq!(entries[1].output.as_deref(), Some("foo.c"));
+    }
+
+    #[test]
+    fn explain() {
+        let cdbgen_path = cdbgen();
+        let (temp, path) = shims(&["cdbgen-true"]);
+
+        for opt in ["-O2", "-O3"] {
+            let status = Command::new("cdbgen-true")
+                .args([opt, "-c", "foo.c"])
+                .env("PATH", &path)
+                .env("CDBGEN_META", "1")
+                .current_dir(temp.path())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+
+        let output = Command::new(&cdbgen_path)
+            .args(["explain", "foo.c"])
+            .current_dir(temp.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("status: current"));
+        assert!(stdout.contains("invocation: cdbgen-true -O3 -c foo.c"));
+        assert!(stdout.contains("(superseded)"));
+    }
+
+    #[test]
+    fn wrap() {
+        let cdbgen_path = cdbgen();
+        let temp = assert_fs::TempDir::new().unwrap();
+
+        let status = Command::new(&cdbgen_path)
+            .args(["wrap", "sh", "-c", "$CC -O2 -c foo.c"])
+            .env("CC", "true")
+            .env_remove("CDBGEN")
+            .current_dir(temp.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let entries = db::read_entries(&temp.path().join("compile_commands.json")).unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = entries.iter().next().unwrap();
+        assert_eq!(entry.file, "foo.c");
+        assert_eq!(entry.arguments[1..], ["-O2", "-c", "foo.c"]);
+
+        // Sweep the entry of the removed source but keep the one of another directory.
+        let mut entries = db::read_entries(&temp.path().join("compile_commands.json")).unwrap();
+        let stale = Entry {
+            file: "removed.c".to_string(),
+            ..entries.iter().next().unwrap().clone()
+        };
+        let other = Entry {
+            directory: "/elsewhere".to_string(),
+            ..stale.clone()
+        };
+        entries.extend([stale, other]);
+        std::fs::write(
+            temp.path().join("compile_commands.json"),
+            serde_json::to_string(&entries).unwrap(),
+        )
+        .unwrap();
+        let status = Command::new(&cdbgen_path)
+            .args(["wrap", "--sweep", "sh", "-c", "$CC -O2 -c foo.c"])
+            .env("CC", "true")
+            .env_remove("CDBGEN")
+            .current_dir(temp.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let entries = db::read_entries(&temp.path().join("compile_commands.json")).unwrap();
+        let files: Vec<_> = entries.iter().map(|e| e.file.as_str()).collect();
+        assert_eq!(files, ["removed.c", "foo.c"]);
+        assert_eq!(entries.iter().next().unwrap().directory, "/elsewhere");
+
+        let status = Command::new(&cdbgen_path)
+            .args(["wrap", "--", "sh", "-c", "exit 3"])
+            .current_dir(temp.path())
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[test]
+    fn codeql() {
+        let cdbgen_path = cdbgen();
+        let temp = assert_fs::TempDir::new().unwrap();
+        // A stand-in for codeql which merely runs the build command.
+        let codeql = temp.child("codeql");
+        codeql
+            .write_str("#!/bin/sh\nfor a; do case $a in --command=*) cmd=${a#--command=};; esac; done\nexec $cmd\n")
+            .unwrap();
+        std::fs::set_permissions(&codeql, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+
+        let status = Command::new(&cdbgen_path)
+            .arg("codeql")
+            .arg("--codeql")
+            .arg(codeql.path())
+            .args(["codeql-db", "--", "sh", "-c", "$CC -c 'foo bar.c'"])
+            .env("CC", "true")
+            .env_remove("CDBGEN")
+            .current_dir(temp.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let entries = db::read_entries(&temp.path().join("compile_commands.json")).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries.iter().next().unwrap().file, "foo bar.c");
+    }
+
+    #[test]
+    fn route() {
+        let (temp, path) = shims(&["cdbgen-true"]);
+        temp.child("a/.cdbgen-root").touch().unwrap();
+        temp.child("b/.cdbgen-root").touch().unwrap();
+        let status = Command::new("cdbgen-true")
+            .args(["-c", "a/x.c", "b/y.c", "z.c"])
+            .env("PATH", &path)
+            .env("CDBGEN_ROUTE", "1")
+            .env_remove("CDBGEN")
+            .current_dir(temp.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        for (db, file) in [("a", "a/x.c"), ("b", "b/y.c"), (".", "z.c")] {
+            let json_path = temp.path().join(db).join("compile_commands.json");
+            let entries = db::read_entries(&json_path).unwrap();
+            let files: Vec<_> = entries.iter().map(|e| e.file.as_str()).collect();
+            assert_eq!(files, [file]);
+        }
+    }
+
+    #[test]
+    fn also() {
+        let (temp, path) = shims(&["cdbgen-true"]);
+        let compile = |flag: &str| {
+            Command::new("cdbgen-true")
+                .args(["-c", flag, "x.c"])
+                .env("PATH", &path)
+                .env_remove("CDBGEN")
+                .env("CDBGEN_ALSO", temp.path().join("global.json"))
+                .env("CDBGEN_ON_CONFLICT", "fail")
+                .current_dir(temp.path())
+                .status()
+                .unwrap()
+        };
+        assert!(compile("-O2").success());
+        assert!(!compile("-O3").success());
+
+        for db in ["compile_commands.json", "global.json"] {
+            let entries = db::read_entries(&temp.path().join(db)).unwrap();
+            assert_eq!(entries.len(), 1);
+            assert!(entries
+                .iter()
+                .all(|e| e.arguments.contains(&"-O2".to_string())));
+        }
+
+        // A mistyped policy is warned about rather than taken as the default silently.
+        let mistyped = |strict: &str| {
+            Command::new("cdbgen-true")
+                .args(["-c", "-O3", "x.c"])
+                .env("PATH", &path)
+                .env_remove("CDBGEN")
+                .env("CDBGEN_ON_CONFLICT", "fial")
+                .env("CDBGEN_STRICT", strict)
+                .current_dir(temp.path())
+                .output()
+                .unwrap()
+        };
+        assert!(!mistyped("1").status.success());
+        let output = mistyped("0");
+        assert!(output.status.success());
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("ignoring CDBGEN_ON_CONFLICT=fial")
+        );
+    }
+
+    #[test]
+    fn resilient() {
+        let (temp, path) = shims(&["cdbgen-true"]);
+        temp.child("compile_commands.json").write_str("[{").unwrap();
+        let compile = |strict: &str| {
+            Command::new("cdbgen-true")
+                .args(["-c", "x.c"])
+                .env("PATH", &path)
+                .env_remove("CDBGEN")
+                .env("CDBGEN_STRICT", strict)
+                .current_dir(temp.path())
+                .output()
+                .unwrap()
+        };
+        let output = compile("0");
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("compiling anyway"));
+        assert!(!compile("1").status.success());
+    }
+
+    #[test]
+    fn canonical_paths() {
+        let (temp, path) = shims(&["cdbgen-true"]);
+        temp.child("real/x.c").touch().unwrap();
+        temp.child("vendor")
+            .symlink_to_dir(temp.path().join("real"))
+            .unwrap();
+        for file in ["real/x.c", "vendor/x.c"] {
+            let status = Command::new("cdbgen-true")
+                .args(["-c", file])
+                .env("PATH", &path)
+                .env_remove("CDBGEN")
+                .env("CDBGEN_PATHS", "canonical")
+                .current_dir(temp.path())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+
+        let entries = db::read_entries(&temp.path().join("compile_commands.json")).unwrap();
+        let real = temp.path().canonicalize().unwrap().join("real/x.c");
+        let files: Vec<_> = entries.iter().map(|e| Path::new(&e.file)).collect();
+        assert_eq!(files, [real.as_path()]);
+        assert!(entries.iter().all(|e| e.arguments[2] == e.file));
+    }
+
+    #[test]
+    fn out_of_tree() {
+        let (temp, path) = shims(&["cdbgen-true"]);
+        temp.child("build/sub").create_dir_all().unwrap();
+        let build = temp.path().join("build/sub");
+        let status = Command::new("cdbgen-true")
+            .args(["-c", "../../src/foo.c"])
+            .env("PATH", &path)
+            .env("CDBGEN", temp.path().join("compile_commands.json"))
+            .env("CDBGEN_PATHS", "absolute")
+            .current_dir(&build)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let entries = db::read_entries(&temp.path().join("compile_commands.json")).unwrap();
+        let entry = entries.iter().next().unwrap();
+        assert_eq!(Path::new(&entry.directory), build);
+        assert_eq!(Path::new(&entry.file), temp.path().join("src/foo.c"));
+        assert_eq!(entry.arguments[2], "../../src/foo.c");
+    }
+
+    #[test]
+    fn sidecar() {
+        let cdbgen_path = cdbgen();
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("bin").create_dir_all().unwrap();
+        temp.child("bin/cdbgen-firmware-cc")
+            .symlink_to_file(&cdbgen_path)
+            .unwrap();
+        temp.child("bin/cdbgen-firmware-cc.toml")
+            .write_str(
+                "compiler = \"true\"\n\
+                 extra_flags = [\"-DFIRMWARE\"]\n\
+                 database = \"../fw.json\"\n",
+            )
+            .unwrap();
+
+        let path = format!("{}:/bin:/usr/bin", temp.path().join("bin").display());
+        let status = Command::new("cdbgen-firmware-cc")
+            .args(["-c", "x.c"])
+            .env("PATH", &path)
+            .env_remove("CDBGEN")
+            .current_dir(temp.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        assert!(!temp.path().join("compile_commands.json").exists());
+        let entries = db::read_entries(&temp.path().join("fw.json")).unwrap();
+        let entry = entries.iter().next().unwrap();
+        assert!(entry.arguments[0].ends_with("/true"));
+        assert_eq!(entry.arguments[1..], ["-DFIRMWARE", "-c", "x.c"]);
+    }
+
+    #[test]
+    fn launch() {
+        let cdbgen_path = cdbgen();
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("sccache")
+            .write_str("#!/bin/sh\nexec \"$@\"\n")
+            .unwrap();
+        std::fs::set_permissions(
+            temp.path().join("sccache"),
+            std::os::unix::fs::PermissionsExt::from_mode(0o755),
+        )
+        .unwrap();
+
+        let path = format!("{}:/bin:/usr/bin", temp.path().display());
+        let status = Command::new(&cdbgen_path)
+            .args(["sccache", "true", "-c", "x.c"])
+            .env("PATH", &path)
+            .env_remove("CDBGEN")
+            .current_dir(temp.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let entries = db::read_entries(&temp.path().join("compile_commands.json")).unwrap();
+        let entry = entries.iter().next().unwrap();
+        assert_eq!(Path::new(&entry.arguments[0]).file_name().unwrap(), "true");
+        assert_eq!(entry.arguments[1..], ["-c", "x.c"]);
+    }
+
+    #[test]
+    fn help() {
+        let cdbgen_path = cdbgen();
+        let output = Command::new(&cdbgen_path).output().unwrap();
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).starts_with("usage: cdbgen"));
+
+        let output = Command::new(&cdbgen_path)
+            .arg("no-such-subcommand")
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("see 'cdbgen help'"));
+    }
+
+    #[test]
+    fn events() {
+        let (temp, path) = shims(&["cdbgen-true", "cdbgen-no-such-compiler"]);
+        let log = temp.path().join("events.jsonl");
+        let run = |shim: &str, args: &[&str]| {
+            Command::new(shim)
+                .args(args)
+                .env("PATH", &path)
+                .env("CDBGEN_EVENTS", &log)
+                .env_remove("CDBGEN")
+                .current_dir(temp.path())
+                .status()
+                .unwrap()
+        };
+        assert!(run("cdbgen-true", &["-c", "a.c"]).success());
+        assert!(run("cdbgen-true", &["a.o", "-o", "a"]).success());
+        assert!(!run("cdbgen-no-such-compiler", &["-c", "a.c"]).success());
+
+        let events: Vec<serde_json::Value> = std::fs::read_to_string(&log)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let outcomes: Vec<_> = events.iter().map(|e| e["outcome"].clone()).collect();
+        assert_eq!(outcomes, ["recorded", "skipped", "failed"]);
+        assert_eq!(events[0]["files"], serde_json::json!(["a.c"]));
+        let json_path = temp.path().join("compile_commands.json");
+        assert_eq!(
+            events[0]["databases"][json_path.to_str().unwrap()]["added"],
+            1
+        );
+        assert!(events[2]["error"].as_str().unwrap().contains("not found"));
+
+        // A log which cannot be written does not fail the compile.
+        let status = Command::new("cdbgen-true")
+            .args(["-c", "b.c"])
+            .env("PATH", &path)
+            .env(
+                "CDBGEN_EVENTS",
+                temp.path().join("no/such/dir/events.jsonl"),
+            )
+            .env_remove("CDBGEN")
+            .current_dir(temp.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn output_profiles() {
+        let (temp, path) = shims(&["cdbgen-echo"]);
+        let run = |profile: &str| {
+            Command::new("cdbgen-echo")
+                .args(["-c", "a.c"])
+                .env("PATH", &path)
+                .env("CDBGEN_OUTPUT", profile)
+                .env_remove("CDBGEN")
+                .current_dir(temp.path())
+                .output()
+                .unwrap()
+        };
+        // The output of the compiler is passed through untouched.
+        let output = run("verbose");
+        assert_eq!(output.stdout, b"-c a.c\n");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("1 added, 0 updated"));
+        let output = run("quiet");
+        assert_eq!(output.stdout, b"-c a.c\n");
+        assert!(output.stderr.is_empty());
+    }
+
+    #[test]
+    fn tools() {
+        let (temp, path) = shims(&["cdbgen-true"]);
+        temp.child("parser.y").touch().unwrap();
+        let status = Command::new("cdbgen-true")
+            .args(["-d", "-o", "parser.c", "parser.y"])
+            .env("PATH", &path)
+            .env_remove("CDBGEN")
+            .env("CDBGEN_TOOLS", "bison,tr*")
+            .current_dir(temp.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        assert!(!temp.path().join("compile_commands.json").exists());
+        let entries = db::read_entries(&temp.path().join("tool_commands.json")).unwrap();
+        let entry = entries.iter().next().unwrap();
+        assert_eq!(entry.file, "parser.y");
+        assert_eq!(entry.output.as_deref(), Some("parser.c"));
+    }
+
+    #[test]
+    fn links() {
+        let (temp, path) = shims(&["cdbgen-true"]);
+        for args in [&["-c", "a.c"][..], &["-o", "app", "a.o", "b.c", "-lm"]] {
+            let status = Command::new("cdbgen-true")
+                .args(args)
+                .env("PATH", &path)
+                .env_remove("CDBGEN")
+                .env("CDBGEN_LINKS", "1")
+                .current_dir(temp.path())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+
+        let entries = db::read_entries(&temp.path().join("compile_commands.json")).unwrap();
+        assert_eq!(entries.len(), 2);
+        let links = link::read(&temp.path().join("link_commands.json")).unwrap();
+        let links: Vec<_> = links
+            .iter()
+            .map(|l| (&l.output, &l.inputs, &l.libraries))
+            .collect();
+        assert_eq!(
+            links,
+            [(
+                &"app".to_string(),
+                &vec!["a.o".to_string(), "b.c".to_string()],
+                &vec!["m".to_string()]
+            )]
+        );
+    }
+
+    #[test]
+    fn header_deps() {
+        use std::os::unix::fs::PermissionsExt;
+        let (temp, path) = shims(&["cdbgen-mkdep"]);
+        let compiler = temp.child("mkdep");
+        compiler
+            .write_str("#!/bin/sh\necho 'a.o: a.c inc/a.h' > a.d\n")
+            .unwrap();
+        std::fs::set_permissions(compiler.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+        let status = Command::new("cdbgen-mkdep")
+            .args(["-MD", "-c", "a.c"])
+            .env("PATH", &path)
+            .env_remove("CDBGEN")
+            .env("CDBGEN_HEADER_DEPS", "1")
+            .current_dir(temp.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let data = std::fs::read_to_string(temp.path().join("header_deps.json")).unwrap();
+        let deps: std::collections::BTreeMap<String, Vec<String>> =
+            serde_json::from_str(&data).unwrap();
+        let dir = temp.path().canonicalize().unwrap();
+        assert_eq!(
+            deps[&dir.join("a.c").to_string_lossy().into_owned()],
+            [dir.join("inc/a.h").to_string_lossy().into_owned()]
+        );
+    }
+
+    #[test]
+    fn compaction() {
+        let (temp, path) = shims(&["cdbgen-true"]);
+        let status = Command::new("cdbgen-true")
+            .args(["-c", "a.c"])
+            .env("PATH", &path)
+            .env_remove("CDBGEN")
+            .env("CDBGEN_STORAGE", "fragments")
+            .env("CDBGEN_COMPACT_FRAGMENTS", "1")
+            .current_dir(temp.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // The compaction runs in the background.
+        let json_path = temp.path().join("compile_commands.json");
+        for _ in 0..100 {
+            if fragment::list(&json_path).unwrap().is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert!(fragment::list(&json_path).unwrap().is_empty());
+        let data = std::fs::read_to_string(&json_path).unwrap();
+        assert_eq!(db::parse_entries(&data).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn background() {
+        let (temp, path) = shims(&["cdbgen-true"]);
+        for source in ["a.c", "b.c"] {
+            let status = Command::new("cdbgen-true")
+                .args(["-c", source])
+                .env("PATH", &path)
+                .env_remove("CDBGEN")
+                .env("CDBGEN_BACKGROUND", "1")
+                .current_dir(temp.path())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+
+        // The entries are recorded as fragments by the detached helpers.
+        let json_path = temp.path().join("compile_commands.json");
+        for _ in 0..100 {
+            if fragment::list(&json_path).unwrap().len() == 2 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        let entries = db::read_entries(&json_path).unwrap();
+        let files: Vec<_> = entries.iter().map(|e| e.file.as_str()).collect();
+        assert_eq!(files, ["a.c", "b.c"]);
+
+        // The wrapper waits for the helpers, whose events tell what they recorded.
+        let output = Command::new(cdbgen())
+            .args(["wrap", "--summary-json", "summary.json"])
+            .args(["sh", "-c", "$CC -c c.c"])
+            .env("CC", "true")
+            .env_remove("CDBGEN")
+            .env("CDBGEN_BACKGROUND", "1")
+            .current_dir(temp.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.contains("1 entries deferred until merged"),
+            "{}",
+            stderr
+        );
+        assert!(!stderr.contains("recorded no entries"), "{}", stderr);
+        let data = std::fs::read_to_string(temp.path().join("summary.json")).unwrap();
+        let summary: serde_json::Value = serde_json::from_str(&data).unwrap();
+        assert_eq!(summary["deferred"], 1);
+        assert_eq!(summary["failed"], 0);
+    }
+
+    /// Like `main` but with 10k invocations staged in shared memory.  Run by
+    /// `cargo test -- --ignored` since it takes a while.
+    #[test]
+    #[ignore]
+    fn staging() {
+        let (temp, path) = shims(&["cdbgen-true"]);
+        let shm = assert_fs::TempDir::new().unwrap();
+        let n = 10_000;
+        for batch in (0..n).collect::<Vec<_>>().chunks(500) {
+            let handles: Vec<_> = batch
+                .iter()
+                .map(|i| {
+                    Command::new("cdbgen-true")
+                        .args(["-O2", "-c", &format!("foo{:05}.c", i)])
+                        .env("PATH", &path)
+                        .env_remove("CDBGEN")
+                        .env("CDBGEN_STORAGE", "shm")
+                        .env("CDBGEN_SHM_DIR", shm.path())
+                        .current_dir(temp.path())
+                        .spawn()
+                        .unwrap()
+                })
+                .collect();
+            for mut h in handles {
+                assert!(h.wait().unwrap().success());
+            }
+        }
+
+        let status = Command::new(cdbgen())
+            .arg("finalize")
+            .env("CDBGEN_SHM_DIR", shm.path())
+            .current_dir(temp.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let data = std::fs::read_to_string(temp.path().join("compile_commands.json")).unwrap();
+        let entries = db::parse_entries(&data).unwrap();
+        assert_eq!(entries.len(), n);
+        assert!(std::fs::read_dir(shm.path()).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn overhead() {
+        let (temp, path) = shims(&["cdbgen-true"]);
+        for timing in ["0", "1"] {
+            let status = Command::new("cdbgen-true")
+                .args(["-c", "a.c"])
+                .env("PATH", &path)
+                .env_remove("CDBGEN")
+                .env("CDBGEN_TIMING", timing)
+                .env("CDBGEN_EVENTS", "events.jsonl")
+                .current_dir(temp.path())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+
+        let log = std::fs::read_to_string(temp.path().join("events.jsonl")).unwrap();
+        assert_eq!(log.lines().count(), 2);
+        for line in log.lines() {
+            let value: serde_json::Value = serde_json::from_str(line).unwrap();
+            for key in ["resolve_us", "lock_wait_us", "parse_us", "write_us"] {
+                assert!(value[key].as_u64().unwrap() <= value["overhead_us"].as_u64().unwrap());
+            }
+        }
     }
 }
@@ -0,0 +1,329 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::{self, Entry, Invocation};
+use crate::paths;
+use crate::pattern;
+use crate::time;
+
+/// Maximal number of records kept per entry.
+const MAX_HISTORY: usize = 10;
+
+/// How an entry came into existence.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    /// Time at which the arguments were recorded first.
+    pub recorded_at: u64,
+    /// Time at which the arguments were recorded last.
+    pub last_seen: u64,
+    pub cdbgen_version: String,
+    /// The command line as cdbgen was invoked.
+    pub invocation: Vec<String>,
+    /// The arguments as they were recorded into the database.
+    pub arguments: Vec<String>,
+    /// How long the compile took when the entry was seen last, if timing capture was enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// The build (`CDBGEN_BUILD_ID`) and session (`CDBGEN_SESSION`) which recorded the entry last.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// The variables of `CDBGEN_ENVIRONMENT` as set when the entry was seen last.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub environment: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryMeta {
+    pub directory: String,
+    pub file: String,
+    /// Records with the most recent one last.
+    pub history: Vec<Record>,
+    /// The canonical path of the file if it is recorded as spelled but linked
+    /// (`CDBGEN_PATHS=linked`).  Entries of the same canonical path are the same physical file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canonical: Option<String>,
+}
+
+/// Metadata about the database as a whole.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    /// Time at which the database was created.
+    pub created_at: u64,
+    /// Version of cdbgen which created the database.
+    pub cdbgen_version: String,
+    /// How entries are captured, e.g. `shim`, `wrap`, or `import-intercept`.
+    pub capture: String,
+    /// How the database is stored, i.e., `file` or `sharded`.
+    pub storage: String,
+    /// Rewrites of paths applied to the database, e.g. by `cdbgen relativize`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub path_remaps: Vec<String>,
+}
+
+impl Header {
+    pub fn new(capture: &str, storage: &str) -> Self {
+        Header {
+            created_at: time::now(),
+            cdbgen_version: env!("CARGO_PKG_VERSION").to_string(),
+            capture: capture.to_string(),
+            storage: storage.to_string(),
+            path_remaps: Vec::new(),
+        }
+    }
+}
+
+/// Contents of the metadata sidecar which lives next to the database.  Standard consumers of
+/// compilation databases never look at it.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Meta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<Header>,
+    #[serde(default)]
+    pub entries: Vec<EntryMeta>,
+    /// Time at which the database was pruned last.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub last_pruned: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+impl Meta {
+    pub fn find(&self, directory: &str, file: &str) -> Option<&EntryMeta> {
+        self.entries
+            .iter()
+            .find(|e| e.directory == directory && e.file == file)
+    }
+}
+
+/// Path of the sidecar of the database at `json_path`, e.g. `compile_commands.meta.json` for
+/// `compile_commands.json`.
+pub fn path(json_path: &Path) -> PathBuf {
+    json_path.with_extension("meta.json")
+}
+
+/// Reads the sidecar of the database at `json_path`.  The caller is expected to hold the lock of
+/// the database.
+pub fn read_unlocked(json_path: &Path) -> Result<Meta, Box<dyn Error>> {
+    match fs::read_to_string(path(json_path)) {
+        Ok(data) if data.trim().is_empty() => Ok(Meta::default()),
+        Ok(data) => Ok(serde_json::from_str(&data)?),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(Meta::default()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+pub fn write_unlocked(json_path: &Path, meta: &Meta) -> Result<(), Box<dyn Error>> {
+    fs::write(path(json_path), serde_json::to_string_pretty(meta)? + "\n")?;
+    Ok(())
+}
+
+/// Drops the provenance of the entries not in `kept` from the sidecar of the database at
+/// `json_path`, if any.  The caller is expected to hold the lock of the database.
+pub fn retain_unlocked(json_path: &Path, kept: &BTreeSet<Entry>) -> Result<(), Box<dyn Error>> {
+    if !path(json_path).exists() {
+        return Ok(());
+    }
+    let keys: BTreeSet<(&str, &str)> = kept
+        .iter()
+        .map(|k| (k.directory.as_str(), k.file.as_str()))
+        .collect();
+    let mut meta = read_unlocked(json_path)?;
+    meta.entries
+        .retain(|e| keys.contains(&(e.directory.as_str(), e.file.as_str())));
+    write_unlocked(json_path, &meta)
+}
+
+/// Reads the sidecar of the database at `json_path` while holding the lock of the database.
+pub fn read(json_path: &Path) -> Result<Meta, Box<dyn Error>> {
+    if !json_path.exists() {
+        return Ok(Meta::default());
+    }
+    db::with_lock(json_path, |_| read_unlocked(json_path))
+}
+
+/// Merges the provenance of `invocation` into `meta`.
+pub fn merge(meta: &mut Meta, invocation: &Invocation, now: u64) {
+    let mut index: BTreeMap<(String, String), EntryMeta> = meta
+        .entries
+        .drain(..)
+        .map(|e| ((e.directory.clone(), e.file.clone()), e))
+        .collect();
+    let duration_ms = invocation.duration.map(|d| d.as_millis() as u64);
+    let build_id = invocation.session.as_ref().map(|s| s.build_id.clone());
+    let session = invocation.session.as_ref().map(|s| s.id.clone());
+    for file in invocation.files.keys() {
+        let entry = index
+            .entry((invocation.directory.clone(), file.clone()))
+            .or_insert_with(|| EntryMeta {
+                directory: invocation.directory.clone(),
+                file: file.clone(),
+                history: Vec::new(),
+                canonical: None,
+            });
+        match entry.history.last_mut() {
+            Some(last) if last.arguments == invocation.arguments => {
+                last.last_seen = now;
+                last.invocation = invocation.argv.clone();
+                last.duration_ms = duration_ms;
+                last.build_id = build_id.clone();
+                last.session = session.clone();
+            }
+            _ => {
+                entry.history.push(Record {
+                    recorded_at: now,
+                    last_seen: now,
+                    cdbgen_version: env!("CARGO_PKG_VERSION").to_string(),
+                    invocation: invocation.argv.clone(),
+                    arguments: invocation.arguments.clone(),
+                    duration_ms,
+                    build_id: build_id.clone(),
+                    session: session.clone(),
+                    environment: BTreeMap::new(),
+                });
+                let excess = entry.history.len().saturating_sub(MAX_HISTORY);
+                entry.history.drain(..excess);
+            }
+        }
+    }
+    meta.entries = index.into_values().collect();
+}
+
+/// Returns the variables of the environment whose names match one of the globs `names`.
+pub fn environment(names: &[String]) -> BTreeMap<String, String> {
+    if names.is_empty() {
+        return BTreeMap::new();
+    }
+    env::vars()
+        .filter(|(name, _)| names.iter().any(|n| pattern::glob_match(n, name)))
+        .collect()
+}
+
+/// Notes `environment` in the latest record of every file of `invocation` in `meta`.
+pub fn note_environment(
+    meta: &mut Meta,
+    invocation: &Invocation,
+    environment: &BTreeMap<String, String>,
+) {
+    for entry in &mut meta.entries {
+        if entry.directory == invocation.directory && invocation.files.contains_key(&entry.file) {
+            if let Some(last) = entry.history.last_mut() {
+                last.environment = environment.clone();
+            }
+        }
+    }
+}
+
+/// Notes the canonical path of every file of `invocation` in `meta`.
+pub fn link(meta: &mut Meta, invocation: &Invocation) {
+    for entry in &mut meta.entries {
+        if entry.directory == invocation.directory && invocation.files.contains_key(&entry.file) {
+            let canonical = paths::canonical(&entry.directory, &entry.file);
+            entry.canonical = Some(canonical.to_string_lossy().into_owned());
+        }
+    }
+}
+
+/// Records the provenance of `invocation` in the sidecar of the database at `json_path`.  The
+/// header is set to `header` unless present, `environment` is noted, and canonical paths are
+/// noted if `linked`.  The caller is expected to hold the lock of the database.
+pub fn record(
+    json_path: &Path,
+    invocation: &Invocation,
+    header: Header,
+    environment: &BTreeMap<String, String>,
+    linked: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut meta = read_unlocked(json_path)?;
+    meta.header.get_or_insert(header);
+    merge(&mut meta, invocation, time::now());
+    note_environment(&mut meta, invocation, environment);
+    if linked {
+        link(&mut meta, invocation);
+    }
+    write_unlocked(json_path, &meta)
+}
+
+/// Makes sure the sidecar of the database at `json_path` has a header, i.e., sets it to `header`
+/// unless present.  The caller is expected to hold the lock of the database.
+pub fn ensure_header(json_path: &Path, header: Header) -> Result<(), Box<dyn Error>> {
+    let mut meta = read_unlocked(json_path)?;
+    if meta.header.is_none() {
+        meta.header = Some(header);
+        write_unlocked(json_path, &meta)?;
+    }
+    Ok(())
+}
+
+/// Notes that the paths in the database at `json_path` were rewritten by `remap`.  The caller is
+/// expected to hold the lock of the database.
+pub fn note_remap(json_path: &Path, remap: &str) -> Result<(), Box<dyn Error>> {
+    let mut meta = read_unlocked(json_path)?;
+    meta.header
+        .get_or_insert_with(|| Header::new("unknown", "file"))
+        .path_remaps
+        .push(remap.to_string());
+    write_unlocked(json_path, &meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invocation(arguments: &[&str]) -> Invocation {
+        let arguments: Vec<String> = arguments.iter().map(|s| s.to_string()).collect();
+        Invocation {
+            argv: arguments.clone(),
+            arguments,
+            directory: "/src".to_string(),
+            files: [("a.c".to_string(), None)].into_iter().collect(),
+            duration: None,
+            session: None,
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let mut meta = Meta::default();
+        super::merge(&mut meta, &invocation(&["cc", "-O2", "a.c"]), 10);
+        super::merge(&mut meta, &invocation(&["cc", "-O2", "a.c"]), 20);
+        super::merge(&mut meta, &invocation(&["cc", "-O3", "a.c"]), 30);
+        let entry = meta.find("/src", "a.c").unwrap();
+        assert_eq!(entry.history.len(), 2);
+        assert_eq!(entry.history[0].recorded_at, 10);
+        assert_eq!(entry.history[0].last_seen, 20);
+        assert_eq!(entry.history[1].recorded_at, 30);
+
+        for i in 0..2 * MAX_HISTORY {
+            super::merge(&mut meta, &invocation(&["cc", &format!("-D{}", i)]), 40);
+        }
+        assert_eq!(meta.find("/src", "a.c").unwrap().history.len(), MAX_HISTORY);
+
+        let environment: BTreeMap<_, _> = [("SDKROOT".to_string(), "/sdk".to_string())].into();
+        note_environment(&mut meta, &invocation(&["cc", "a.c"]), &environment);
+        let history = &meta.find("/src", "a.c").unwrap().history;
+        assert_eq!(history.last().unwrap().environment, environment);
+        assert!(history[0].environment.is_empty());
+    }
+
+    #[test]
+    fn header() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let json_path = temp.path().join("compile_commands.json");
+        ensure_header(&json_path, Header::new("wrap", "file")).unwrap();
+        ensure_header(&json_path, Header::new("shim", "file")).unwrap();
+        note_remap(&json_path, "relativize --root /src").unwrap();
+        let header = read_unlocked(&json_path).unwrap().header.unwrap();
+        assert_eq!(header.capture, "wrap");
+        assert_eq!(header.path_remaps, ["relativize --root /src"]);
+    }
+}
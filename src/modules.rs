@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::db::{self, Invocation};
+use crate::paths;
+use crate::shell;
+
+/// Returns the sidecar module metadata is recorded into, i.e., `modules.json` next to the
+/// database of `config`.
+pub fn database(config: &Config) -> PathBuf {
+    config.database.with_file_name("modules.json")
+}
+
+/// The C++20 modules of a translation unit.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Modules {
+    /// The BMI written for the module interface the unit provides, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bmi: Option<String>,
+    /// The modules the unit imports mapped to their BMIs.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub imports: BTreeMap<String, String>,
+}
+
+/// Replaces the `.modmap` response files written by the module scanning of CMake for Clang and
+/// MSVC by the flags they contain, such that the entry is self-contained even once the build
+/// directory is gone.  Response files which cannot be read are kept.
+pub fn expand(arguments: &[String], directory: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    for arg in arguments {
+        let flags = arg
+            .strip_prefix('@')
+            .filter(|path| path.ends_with(".modmap"))
+            .and_then(|path| fs::read_to_string(paths::resolve(directory, path)).ok())
+            .and_then(|data| shell::split(&data).ok());
+        match flags {
+            Some(flags) => result.extend(flags),
+            None => result.push(arg.clone()),
+        }
+    }
+    result
+}
+
+/// Parses a module mapper file of GCC as written by CMake, i.e., a line `MODULE BMI` per module
+/// where relative BMIs are relative to the `$root` line, if any.
+fn parse_mapper(data: &str, directory: &str) -> Vec<(String, String)> {
+    let mut root = directory.to_string();
+    let mut modules = Vec::new();
+    for line in data.lines() {
+        match line.split_once(' ') {
+            Some(("$root", dir)) => {
+                root = paths::resolve(directory, dir.trim())
+                    .to_string_lossy()
+                    .into_owned()
+            }
+            Some((name, bmi)) if !name.starts_with('#') => modules.push((
+                name.to_string(),
+                paths::resolve(&root, bmi.trim())
+                    .to_string_lossy()
+                    .into_owned(),
+            )),
+            _ => (),
+        }
+    }
+    modules
+}
+
+/// Returns the modules of a unit compiled in `directory` with the expanded `arguments`, i.e.,
+/// from `-fmodule-output=` and `-fmodule-file=NAME=BMI` of Clang, `-ifcOutput` and
+/// `-reference NAME=BMI` of MSVC, or the mapper file of `-fmodule-mapper=` of GCC.
+pub fn modules(arguments: &[String], directory: &str) -> Modules {
+    let absolute = |path: &str| {
+        paths::resolve(directory, path)
+            .to_string_lossy()
+            .into_owned()
+    };
+    let mut modules = Modules::default();
+    let mut iter = arguments.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        let reference = match arg.as_str() {
+            "-reference" | "/reference" => iter.next().map(String::as_str),
+            "-ifcOutput" | "/ifcOutput" => {
+                modules.bmi = iter.next().map(|bmi| absolute(bmi));
+                continue;
+            }
+            _ => arg.strip_prefix("-fmodule-file="),
+        };
+        if let Some((name, bmi)) = reference.and_then(|r| r.split_once('=')) {
+            modules.imports.insert(name.to_string(), absolute(bmi));
+        } else if let Some(bmi) = arg.strip_prefix("-fmodule-output=") {
+            modules.bmi = Some(absolute(bmi));
+        } else if let Some(mapper) = arg.strip_prefix("-fmodule-mapper=") {
+            // The mapper does not tell the provided module from the imported ones.
+            if let Ok(data) = fs::read_to_string(absolute(mapper)) {
+                modules.imports.extend(parse_mapper(&data, directory));
+            }
+        }
+    }
+    modules
+}
+
+/// Records the modules of the translation units of `invocation` into the sidecar at `json_path`
+/// which maps the absolute path of each source to its modules.  Units without modules are left
+/// alone.
+pub fn record(json_path: &Path, invocation: &Invocation) -> Result<(), Box<dyn Error>> {
+    let modules = modules(&invocation.arguments, &invocation.directory);
+    if modules == Modules::default() {
+        return Ok(());
+    }
+    db::update_json(json_path, |all: &mut BTreeMap<String, Modules>| {
+        for entry in invocation.entries() {
+            let source = paths::resolve(&entry.directory, &entry.file);
+            all.insert(
+                source.to_string_lossy().into_owned(),
+                Modules {
+                    bmi: modules.bmi.clone(),
+                    imports: modules.imports.clone(),
+                },
+            );
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn expand_and_parse() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let dir = temp.path().to_str().unwrap();
+        fs::write(
+            temp.path().join("a.cppm.o.modmap"),
+            "-x c++-module\n-fmodule-output=a.pcm\n-fmodule-file=b=\"dir/b.pcm\"\n",
+        )
+        .unwrap();
+        let arguments = expand(
+            &strings(&[
+                "clang++",
+                "@a.cppm.o.modmap",
+                "@gone.modmap",
+                "-c",
+                "a.cppm",
+            ]),
+            dir,
+        );
+        assert_eq!(
+            arguments,
+            strings(&[
+                "clang++",
+                "-x",
+                "c++-module",
+                "-fmodule-output=a.pcm",
+                "-fmodule-file=b=dir/b.pcm",
+                "@gone.modmap",
+                "-c",
+                "a.cppm"
+            ])
+        );
+        let modules = super::modules(&arguments, dir);
+        assert_eq!(modules.bmi, Some(format!("{}/a.pcm", dir)));
+        assert_eq!(
+            modules.imports.into_iter().collect::<Vec<_>>(),
+            [("b".to_string(), format!("{}/dir/b.pcm", dir))]
+        );
+
+        let mapper = "$root /build\na CMakeFiles/a.dir/a.gcm\nb /bmi/b.gcm\n";
+        assert_eq!(
+            parse_mapper(mapper, "/src"),
+            [
+                ("a".to_string(), "/build/CMakeFiles/a.dir/a.gcm".to_string()),
+                ("b".to_string(), "/bmi/b.gcm".to_string())
+            ]
+        );
+    }
+}
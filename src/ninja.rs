@@ -0,0 +1,84 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::deps;
+use crate::shell;
+
+/// An entry in the shape `ninja -t compdb` emits, i.e., with the command as single string and
+/// the keys in the order ninja writes them.
+#[derive(Debug, PartialEq, Serialize)]
+struct NinjaEntry {
+    directory: String,
+    command: String,
+    file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+    /// Headers gathered from the dependency file of the compile with `--deps`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dependencies: Option<Vec<String>>,
+}
+
+fn convert(entries: &BTreeSet<Entry>, with_deps: bool) -> Result<Vec<NinjaEntry>, Box<dyn Error>> {
+    let mut result = Vec::new();
+    for e in entries {
+        result.push(NinjaEntry {
+            directory: e.directory.clone(),
+            command: shell::join(&e.arguments),
+            file: e.file.clone(),
+            output: e.output.clone(),
+            dependencies: if with_deps { deps::headers(e)? } else { None },
+        });
+    }
+    Ok(result)
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &["--deps"], &["--db", "-o"])?;
+    let entries = convert(&db::read_entries(&opts.database())?, opts.has("--deps"))?;
+    let mut out: Box<dyn Write> = match opts.value("-o") {
+        Some(path) if path != "-" => Box::new(File::create(path)?),
+        _ => Box::new(io::stdout()),
+    };
+    writeln!(out, "{}", serde_json::to_string_pretty(&entries)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn convert() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("a.d").write_str("a.o: a.c a.h\n").unwrap();
+        let directory = temp.path().to_string_lossy().into_owned();
+        let entries: BTreeSet<_> = [Entry {
+            directory: directory.clone(),
+            file: "a.c".to_string(),
+            arguments: ["cc", "-MD", "-DX=a b", "-c", "a.c", "-o", "a.o"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            output: Some("a.o".to_string()),
+        }]
+        .into_iter()
+        .collect();
+        let converted = super::convert(&entries, true).unwrap();
+        assert_eq!(converted[0].command, "cc -MD '-DX=a b' -c a.c -o a.o");
+        assert_eq!(
+            converted[0].dependencies,
+            Some(vec![format!("{}/a.h", directory)])
+        );
+        let json = serde_json::to_string(&super::convert(&entries, false).unwrap()).unwrap();
+        assert!(json.starts_with(&format!("[{{\"directory\":\"{}\",\"command\":", directory)));
+        assert!(json.ends_with("\"file\":\"a.c\",\"output\":\"a.o\"}]"));
+    }
+}
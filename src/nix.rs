@@ -0,0 +1,147 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::CcWrapper;
+use crate::shell;
+
+/// Returns the directory of the cc-wrapper of Nix `compiler` belongs to, i.e., the one with the
+/// `nix-support` naming the wrapped compiler.  Links like the ones of a profile are followed.
+fn wrapper_dir(compiler: &Path) -> Option<PathBuf> {
+    let dir = |compiler: &Path| {
+        let dir = compiler.parent()?.parent()?;
+        dir.join("nix-support/orig-cc")
+            .is_file()
+            .then(|| dir.to_path_buf())
+    };
+    dir(compiler).or_else(|| dir(&fs::canonicalize(compiler).ok()?))
+}
+
+/// Returns the flags in the file `name` of `nix-support` in `dir`, if any.
+fn file_flags(dir: &Path, name: &str) -> Vec<String> {
+    fs::read_to_string(dir.join("nix-support").join(name))
+        .ok()
+        .and_then(|data| shell::split(&data).ok())
+        .unwrap_or_default()
+}
+
+/// Returns whether `compiler` is one of C++.
+fn is_cxx(compiler: &Path) -> bool {
+    let name = compiler.file_name().unwrap_or_default().to_string_lossy();
+    name.ends_with("++")
+}
+
+/// Returns `arguments` (the compiler first) with the flags a compiler adds from the environment
+/// of the build made explicit, looking variables up by `var`: the ones the cc-wrapper of Nix
+/// injects from `nix-support` and `NIX_CFLAGS_COMPILE` for its role, and the directories of
+/// `C_INCLUDE_PATH` and `CPLUS_INCLUDE_PATH` GCC searches, e.g. in a profile of Guix.  With
+/// `CcWrapper::Unwrap` the wrapper is replaced by the compiler it wraps.
+fn materialize_with<F>(arguments: &[String], mode: CcWrapper, var: F) -> Vec<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let (compiler, rest) = match arguments.split_first() {
+        Some(split) => split,
+        None => return arguments.to_vec(),
+    };
+    let mut compiler = PathBuf::from(compiler);
+    let cxx = is_cxx(&compiler);
+    let words = |name: &str| -> Vec<String> {
+        var(name)
+            .map(|value| value.split_whitespace().map(String::from).collect())
+            .unwrap_or_default()
+    };
+    let mut before = Vec::new();
+    let mut after = Vec::new();
+    if let Some(dir) = wrapper_dir(&compiler) {
+        // Each wrapper reads the variables of its role, e.g. of the build or the host platform.
+        let salt = file_flags(&dir, "suffix-salt").join("");
+        let role = |name: &str| {
+            var(&format!("{}_{}", name, salt))
+                .map(|_| format!("{}_{}", name, salt))
+                .unwrap_or_else(|| name.to_string())
+        };
+        before = words(&role("NIX_CFLAGS_COMPILE_BEFORE"));
+        after.extend(file_flags(&dir, "cc-cflags"));
+        after.extend(file_flags(&dir, "libc-cflags"));
+        if cxx {
+            after.extend(file_flags(&dir, "libcxx-cxxflags"));
+        }
+        after.extend(words(&role("NIX_CFLAGS_COMPILE")));
+        if mode == CcWrapper::Unwrap {
+            let orig = fs::read_to_string(dir.join("nix-support/orig-cc")).unwrap_or_default();
+            let wrapped = Path::new(orig.trim())
+                .join("bin")
+                .join(compiler.file_name().unwrap_or_default());
+            if wrapped.is_file() {
+                compiler = wrapped;
+            }
+        }
+    }
+    let include_path = if cxx {
+        "CPLUS_INCLUDE_PATH"
+    } else {
+        "C_INCLUDE_PATH"
+    };
+    for dir in env::split_paths(&var(include_path).unwrap_or_default()) {
+        if !dir.as_os_str().is_empty() {
+            after.extend(["-isystem".to_string(), dir.to_string_lossy().into_owned()]);
+        }
+    }
+    std::iter::once(compiler.to_string_lossy().into_owned())
+        .chain(before)
+        .chain(rest.iter().cloned())
+        .chain(after)
+        .collect()
+}
+
+/// Like `materialize_with` for the environment of cdbgen.
+pub fn materialize(arguments: &[String], mode: CcWrapper) -> Vec<String> {
+    materialize_with(arguments, mode, |name| env::var(name).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn materialize() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let orig = temp.path().join("gcc-13");
+        let wrapper = temp.path().join("gcc-wrapper-13");
+        fs::create_dir_all(orig.join("bin")).unwrap();
+        fs::write(orig.join("bin/gcc"), "").unwrap();
+        fs::create_dir_all(wrapper.join("nix-support")).unwrap();
+        fs::create_dir_all(wrapper.join("bin")).unwrap();
+        let support = |name: &str, data: &str| {
+            fs::write(wrapper.join("nix-support").join(name), data).unwrap()
+        };
+        support("orig-cc", &format!("{}\n", orig.display()));
+        support("suffix-salt", "x86_64_unknown_linux_gnu\n");
+        support("libc-cflags", "-isystem /glibc-dev/include\n");
+
+        let arguments = vec![
+            wrapper.join("bin/gcc").to_string_lossy().into_owned(),
+            "-c".to_string(),
+            "a.c".to_string(),
+        ];
+        let var = |name: &str| match name {
+            "NIX_CFLAGS_COMPILE" => Some("-isystem /wrong".to_string()),
+            "NIX_CFLAGS_COMPILE_x86_64_unknown_linux_gnu" => {
+                Some("-isystem /zlib-dev/include".to_string())
+            }
+            "C_INCLUDE_PATH" => Some("/guix/include".to_string()),
+            _ => None,
+        };
+        let tail = "-c a.c -isystem /glibc-dev/include -isystem /zlib-dev/include \
+                    -isystem /guix/include";
+        assert_eq!(
+            materialize_with(&arguments, CcWrapper::Flags, var).join(" "),
+            format!("{} {}", arguments[0], tail)
+        );
+        assert_eq!(
+            materialize_with(&arguments, CcWrapper::Unwrap, var)[0],
+            orig.join("bin/gcc").to_string_lossy()
+        );
+    }
+}
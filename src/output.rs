@@ -0,0 +1,104 @@
+use std::cell::Cell;
+use std::env;
+
+/// How much cdbgen writes to stderr, as set by `CDBGEN_OUTPUT`.  Stdout is never written to by a
+/// shim since build systems parse the output of the compiler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// Errors only.
+    Quiet,
+    /// Errors, warnings, and what subcommands did.
+    Normal,
+    /// In addition, what each shim recorded.
+    Verbose,
+    /// Like `Normal` but without color, and warnings become annotations on GitHub Actions.
+    Ci,
+}
+
+impl Profile {
+    fn parse(value: Option<&str>, ci: bool) -> Result<Profile, String> {
+        match value {
+            Some("quiet") => Ok(Profile::Quiet),
+            Some("verbose") => Ok(Profile::Verbose),
+            Some("ci") => Ok(Profile::Ci),
+            Some("normal") => Ok(Profile::Normal),
+            Some(value) => Err(format!("unknown output profile '{}'", value)),
+            None if ci => Ok(Profile::Ci),
+            None => Ok(Profile::Normal),
+        }
+    }
+}
+
+thread_local! {
+    static WARNED: Cell<bool> = Cell::new(false);
+}
+
+/// Returns the profile given by `CDBGEN_OUTPUT` which defaults to `ci` if `CI` is set, as done by
+/// most CI services, and to `normal` otherwise.  An invalid profile is warned about once and the
+/// default applies.
+pub fn profile() -> Profile {
+    let ci = env::var("CI").map_or(false, |ci| !ci.is_empty() && ci != "false" && ci != "0");
+    Profile::parse(env::var("CDBGEN_OUTPUT").ok().as_deref(), ci).unwrap_or_else(|error| {
+        // Not by `warn` which asks for the profile in turn.
+        if !WARNED.with(|warned| warned.replace(true)) {
+            eprintln!("cdbgen: warning: ignoring CDBGEN_OUTPUT: {}", error);
+        }
+        Profile::parse(None, ci).unwrap()
+    })
+}
+
+pub fn is_quiet() -> bool {
+    profile() == Profile::Quiet
+}
+
+/// Formats `message` as a warning annotation of GitHub Actions which has to be a single line.
+fn annotation(message: &str) -> String {
+    let escaped = message
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A");
+    format!("::warning title=cdbgen::{}", escaped)
+}
+
+/// Prints the warning `message` unless quiet.
+pub fn warn(message: &str) {
+    match profile() {
+        Profile::Quiet => (),
+        Profile::Ci if env::var_os("GITHUB_ACTIONS").is_some() => {
+            eprintln!("{}", annotation(message))
+        }
+        _ => eprintln!("cdbgen: warning: {}", message),
+    }
+}
+
+/// Prints `message` telling what a subcommand did unless quiet.
+pub fn info(message: &str) {
+    if !is_quiet() {
+        eprintln!("{}", message);
+    }
+}
+
+/// Prints `message` if verbose.
+pub fn verbose(message: &str) {
+    if profile() == Profile::Verbose {
+        eprintln!("{}", message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Profile::parse(Some("quiet"), true), Ok(Profile::Quiet));
+        assert_eq!(Profile::parse(None, true), Ok(Profile::Ci));
+        assert_eq!(Profile::parse(Some("normal"), true), Ok(Profile::Normal));
+        assert_eq!(Profile::parse(None, false), Ok(Profile::Normal));
+        assert!(Profile::parse(Some("silent"), false).is_err());
+        assert_eq!(
+            annotation("arguments of 'a.c' changed\n  - -O2\n  + -O3 100%"),
+            "::warning title=cdbgen::arguments of 'a.c' changed%0A  - -O2%0A  + -O3 100%25"
+        );
+    }
+}
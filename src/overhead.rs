@@ -0,0 +1,56 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The phases of recording an invocation whose overhead is measured.
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    /// Finding the compiler and reading the shim configuration.
+    Resolve = 0,
+    /// Waiting for the lock of a database.
+    LockWait = 1,
+    /// Parsing a database.
+    Parse = 2,
+    /// Serializing and writing a database.
+    Write = 3,
+}
+
+static TOTALS: [AtomicU64; 4] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// Time cdbgen started at in nanoseconds since the epoch.
+static START: AtomicU64 = AtomicU64::new(0);
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// Notes that handling the invocation started now.
+pub fn start() {
+    START.store(now(), Ordering::Relaxed);
+}
+
+/// Runs `f` and adds the time it took to `phase`.
+pub fn measure<T, F: FnOnce() -> T>(phase: Phase, f: F) -> T {
+    let start = Instant::now();
+    let result = f();
+    TOTALS[phase as usize].fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    result
+}
+
+/// Returns the time spent in `phase` so far in microseconds.
+pub fn micros(phase: Phase) -> u64 {
+    TOTALS[phase as usize].load(Ordering::Relaxed) / 1000
+}
+
+/// Returns the time spent since `start` without the time of the compile itself, if it was run as
+/// child.
+pub fn total(compile: Duration) -> Duration {
+    Duration::from_nanos(now().saturating_sub(START.load(Ordering::Relaxed)))
+        .saturating_sub(compile)
+}
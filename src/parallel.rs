@@ -0,0 +1,256 @@
+use std::env;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::cli::Options;
+use crate::jobserver;
+use crate::output;
+
+/// Returns the number of online processors or 1 if unknown.
+pub fn cpus() -> usize {
+    #[cfg(unix)]
+    {
+        let n = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+        if n > 0 {
+            return n as usize;
+        }
+    }
+    #[cfg(windows)]
+    {
+        if let Some(n) = std::env::var("NUMBER_OF_PROCESSORS")
+            .ok()
+            .and_then(|n| n.parse().ok())
+        {
+            return n;
+        }
+    }
+    1
+}
+
+/// Parses the value of a `-j` option where `None` means one job per processor.
+pub fn jobs(value: Option<&str>) -> Result<usize, String> {
+    match value {
+        None => Ok(cpus()),
+        Some(n) => match n.parse() {
+            Ok(0) | Err(_) => Err(format!("invalid number of jobs '{}'", n)),
+            Ok(n) => Ok(n),
+        },
+    }
+}
+
+/// Applies `f` to all `items` using up to `jobs` threads and returns the results in the order of
+/// the items.
+fn map<T, R, F>(items: Vec<T>, jobs: usize, f: F) -> Vec<R>
+where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: Fn(T) -> R + Send + Sync + 'static,
+{
+    let n = items.len();
+    let queue = Arc::new(Mutex::new(items.into_iter().enumerate()));
+    let f = Arc::new(f);
+    let handles: Vec<_> = (0..jobs.max(1).min(n))
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let f = Arc::clone(&f);
+            thread::spawn(move || {
+                let mut results = Vec::new();
+                loop {
+                    let next = queue.lock().unwrap().next();
+                    match next {
+                        Some((i, item)) => results.push((i, f(item))),
+                        None => break results,
+                    }
+                }
+            })
+        })
+        .collect();
+    let mut results: Vec<_> = handles
+        .into_iter()
+        .flat_map(|h| h.join().expect("worker thread panicked"))
+        .collect();
+    results.sort_by_key(|&(i, _)| i);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Returns the number of jobs `make -jN` runs with if cdbgen is run by it, i.e., `N` as found in
+/// `MAKEFLAGS`.
+fn make_jobs(makeflags: &str) -> Option<usize> {
+    makeflags
+        .split_whitespace()
+        .rev()
+        .filter_map(|word| word.strip_prefix("-j")?.parse().ok())
+        .find(|&n| n > 0)
+}
+
+/// Options of the subcommands running a job per entry, e.g. `cdbgen check-compile`.
+#[derive(Debug, Clone)]
+pub struct Runner {
+    /// Number of jobs run at the same time (`-j`).
+    pub jobs: usize,
+    /// Print each finished job along with the number of jobs done (`--progress`).
+    pub progress: bool,
+    /// No more jobs are started once one failed (`--fail-fast`).
+    pub fail_fast: bool,
+    /// The jobserver of the make running cdbgen which each job takes a token from.
+    pub jobserver: Option<Arc<jobserver::Client>>,
+}
+
+/// Flags `Runner::from_options` reads, to be passed to `Options::parse`.
+pub const RUNNER_FLAGS: &[&str] = &["--progress", "--fail-fast"];
+/// Valued options `Runner::from_options` reads.
+pub const RUNNER_VALUED: &[&str] = &["-j"];
+
+impl Runner {
+    /// Reads the runner options from `opts`.  Without `-j` as many jobs are run as `make` does
+    /// if cdbgen is run by it, or else one per processor.  Jobs then share the tokens of the
+    /// jobserver of make, if any, with the other jobs of the build.
+    pub fn from_options(opts: &Options) -> Result<Self, Box<dyn Error>> {
+        let (jobs, jobserver) = match opts.value("-j") {
+            Some(n) => (jobs(Some(n))?, None),
+            None => (
+                env::var("MAKEFLAGS")
+                    .ok()
+                    .and_then(|flags| make_jobs(&flags))
+                    .unwrap_or_else(cpus),
+                jobserver::Client::from_env().map(Arc::new),
+            ),
+        };
+        Ok(Runner {
+            jobs,
+            progress: opts.has("--progress"),
+            fail_fast: opts.has("--fail-fast"),
+            jobserver,
+        })
+    }
+}
+
+/// Result of the job of a single item.
+#[derive(Debug, PartialEq)]
+pub enum Outcome<R> {
+    Done(R),
+    Failed(String),
+    /// Not run since an earlier job failed with `--fail-fast`.
+    Cancelled,
+}
+
+impl<R> Outcome<R> {
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Outcome::Failed(_))
+    }
+}
+
+/// Runs `f` for all `items` as configured by `runner` and returns the outcomes in the order of
+/// the items.  `label` names an item in the progress report.  A job fails if `f` returns an
+/// error and, with `--fail-fast`, cancels all jobs not started yet.
+pub fn run<T, R, F, L>(items: Vec<T>, runner: &Runner, label: L, f: F) -> Vec<Outcome<R>>
+where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: Fn(&T) -> Result<R, String> + Send + Sync + 'static,
+    L: Fn(&T) -> String + Send + Sync + 'static,
+{
+    let total = items.len();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let done = Arc::new(AtomicUsize::new(0));
+    let (progress, fail_fast) = (runner.progress, runner.fail_fast);
+    let jobserver = runner.jobserver.clone();
+    map(items, runner.jobs, {
+        let cancelled = Arc::clone(&cancelled);
+        move |item| {
+            let token = jobserver.as_ref().map(|j| j.acquire()).transpose();
+            if cancelled.load(Ordering::Relaxed) {
+                return Outcome::Cancelled;
+            }
+            let result = match token {
+                Ok(_token) => f(&item),
+                Err(error) => Err(format!("cannot take a token from the jobserver: {}", error)),
+            };
+            let outcome = match result {
+                Ok(r) => Outcome::Done(r),
+                Err(error) => {
+                    if fail_fast {
+                        cancelled.store(true, Ordering::Relaxed);
+                    }
+                    Outcome::Failed(error)
+                }
+            };
+            if progress && !output::is_quiet() {
+                let n = done.fetch_add(1, Ordering::Relaxed) + 1;
+                let status = if outcome.is_failed() { "FAIL" } else { "ok" };
+                eprintln!("[{}/{}] {} {}", n, total, status, label(&item));
+            }
+            outcome
+        }
+    })
+}
+
+/// Returns a line summing up `outcomes`, e.g. `10 ok, 1 failed, 3 cancelled`.
+pub fn summary<R>(outcomes: &[Outcome<R>]) -> String {
+    let failed = outcomes.iter().filter(|o| o.is_failed()).count();
+    let cancelled = outcomes
+        .iter()
+        .filter(|o| matches!(o, Outcome::Cancelled))
+        .count();
+    let mut summary = format!(
+        "{} ok, {} failed",
+        outcomes.len() - failed - cancelled,
+        failed
+    );
+    if cancelled > 0 {
+        summary += &format!(", {} cancelled", cancelled);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map() {
+        let items: Vec<u64> = (0..100).collect();
+        let squares = super::map(items, 8, |i| i * i);
+        assert_eq!(squares.len(), 100);
+        assert!(squares
+            .iter()
+            .enumerate()
+            .all(|(i, &s)| s == (i * i) as u64));
+        assert!(super::map(Vec::<u8>::new(), 4, |i| i).is_empty());
+    }
+
+    #[test]
+    fn run() {
+        let runner = Runner {
+            jobs: 1,
+            progress: false,
+            fail_fast: true,
+            jobserver: None,
+        };
+        let outcomes = super::run(
+            vec![1, 2, 3],
+            &runner,
+            |i| i.to_string(),
+            |&i| {
+                if i == 2 {
+                    Err("two".to_string())
+                } else {
+                    Ok(i)
+                }
+            },
+        );
+        assert_eq!(
+            outcomes,
+            [
+                Outcome::Done(1),
+                Outcome::Failed("two".to_string()),
+                Outcome::Cancelled
+            ]
+        );
+        assert_eq!(summary(&outcomes), "1 ok, 1 failed, 1 cancelled");
+        assert_eq!(make_jobs(" -j8 --jobserver-auth=3,4"), Some(8));
+        assert_eq!(make_jobs("k -j"), None);
+    }
+}
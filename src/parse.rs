@@ -0,0 +1,284 @@
+use std::error::Error;
+
+use crate::args::{self, Flag};
+use crate::cli::Options;
+use crate::db;
+use crate::shell;
+
+/// What cdbgen takes an argument of a compiler invocation for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Compiler,
+    Source,
+    Output,
+    Include,
+    Define,
+    /// Anything not interpreted, e.g. warning flags or object files to link.
+    Ignored,
+}
+
+impl Class {
+    fn name(self) -> &'static str {
+        match self {
+            Class::Compiler => "compiler",
+            Class::Source => "source",
+            Class::Output => "output",
+            Class::Include => "include",
+            Class::Define => "define",
+            Class::Ignored => "ignored",
+        }
+    }
+}
+
+/// Classifies the arguments of the compiler invocation `arguments`, i.e., each flag with the
+/// argument(s) it is spelled with, as they are taken by the recorder.  The sources are the ones
+/// `db::compiled_files` records.
+pub fn classify(arguments: &[String]) -> Vec<(Class, Flag)> {
+    let (compiler, rest) = match arguments.split_first() {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+    let mut result = vec![(
+        Class::Compiler,
+        Flag {
+            name: compiler.clone(),
+            value: None,
+            args: vec![compiler.clone()],
+        },
+    )];
+    let sources = db::compiled_files(arguments);
+    if args::is_cl(arguments) {
+        for flag in args::cl_flags(rest) {
+            let class = match flag.name.as_str() {
+                "/Fo" => Class::Output,
+                "/I" | "/FI" => Class::Include,
+                "/D" | "/U" => Class::Define,
+                "/link" => Class::Ignored,
+                "/Tc" | "/Tp"
+                    if flag
+                        .value
+                        .as_ref()
+                        .map_or(false, |v| sources.contains_key(v)) =>
+                {
+                    Class::Source
+                }
+                _ if flag.is_input() && sources.contains_key(&flag.name) => Class::Source,
+                _ => Class::Ignored,
+            };
+            result.push((class, flag));
+        }
+        return result;
+    }
+    let (options, after) = match rest.iter().position(|a| a == "--") {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, &[][..]),
+    };
+    let inputs = after.iter().skip(1).map(|arg| Flag {
+        name: arg.clone(),
+        value: None,
+        args: vec![arg.clone()],
+    });
+    let separator = after.first().map(|arg| Flag {
+        name: arg.clone(),
+        value: None,
+        args: vec![arg.clone()],
+    });
+    for flag in args::flags(options)
+        .into_iter()
+        .chain(separator)
+        .chain(inputs)
+    {
+        let class = match flag.name.as_str() {
+            "-o" => Class::Output,
+            "-I" | "-isystem" | "-iquote" | "-idirafter" | "-include" | "-imacros" => {
+                Class::Include
+            }
+            "-D" | "-U" => Class::Define,
+            _ if flag.is_input() && sources.contains_key(&flag.name) => Class::Source,
+            _ => Class::Ignored,
+        };
+        result.push((class, flag));
+    }
+    result
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse_command(args, &[], &["--command"])?;
+    let arguments = match opts.value("--command") {
+        Some(command) => shell::split(command)?,
+        None => opts.rest.clone(),
+    };
+    if arguments.is_empty() {
+        return Err("missing compiler invocation, e.g. 'cdbgen parse -- cc -c a.c'".into());
+    }
+    for (class, flag) in classify(&arguments) {
+        match &flag.value {
+            Some(value) => println!(
+                "{:<8} {}  [{}]",
+                class.name(),
+                shell::join(&flag.args),
+                shell::quote(value)
+            ),
+            None => println!("{:<8} {}", class.name(), shell::join(&flag.args)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pseudo-random numbers of a xorshift generator such that failures are reproducible.
+    struct Random(u64);
+
+    impl Random {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+
+        fn pick<'a>(&mut self, choices: &[&'a str]) -> &'a str {
+            choices[self.below(choices.len())]
+        }
+
+        /// Returns a name of up to 8 characters including ones which need quoting.
+        fn name(&mut self) -> String {
+            let n = 1 + self.below(8);
+            (0..n)
+                .map(|i| match self.below(if i == 0 { 4 } else { 12 }) {
+                    0..=3 => self.pick(&["a", "b", "x", "_"]),
+                    4 => " ",
+                    5 => "\"",
+                    6 => "'",
+                    7 => "$",
+                    8 => "=",
+                    9 => "\\",
+                    _ => self.pick(&["1", "/", "."]),
+                })
+                .collect()
+        }
+    }
+
+    /// Generates `cases` invocations by `generate` spelling a flag of a given class, and checks
+    /// that each flag is classified as generated, that no argument is lost, and that the
+    /// invocation is classified the same when given as a quoted command.
+    fn check(compiler: &str, cases: usize, generate: fn(&mut Random) -> (Class, Vec<String>)) {
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        for _ in 0..cases {
+            let mut arguments = vec![compiler.to_string()];
+            let mut expected = vec![Class::Compiler];
+            for _ in 0..random.below(12) {
+                let (class, args) = generate(&mut random);
+                expected.push(class);
+                arguments.extend(args);
+            }
+            let classified = classify(&arguments);
+            let classes: Vec<Class> = classified.iter().map(|(c, _)| *c).collect();
+            assert_eq!(classes, expected, "{:?}", arguments);
+            let args: Vec<String> = classified.into_iter().flat_map(|(_, f)| f.args).collect();
+            assert_eq!(args, arguments);
+            let split = shell::split(&shell::join(&arguments)).unwrap();
+            assert_eq!(split, arguments);
+        }
+    }
+
+    #[test]
+    fn gcc_grammar() {
+        check("gcc", 2000, |random| {
+            let value = random.name();
+            // Only the short options may have their value joined.
+            let spellings = |name: &str, value: String| match name.len() {
+                2 => vec![format!("{}{}", name, value)],
+                _ => vec![name.to_string(), value],
+            };
+            match random.below(6) {
+                0 => (Class::Define, spellings(random.pick(&["-D", "-U"]), value)),
+                1 => (
+                    Class::Include,
+                    spellings(
+                        random.pick(&["-I", "-isystem", "-iquote", "-include"]),
+                        value,
+                    ),
+                ),
+                2 => (Class::Output, spellings("-o", value)),
+                3 => (
+                    Class::Source,
+                    vec![format!("{}.{}", value, random.pick(&["c", "cc", "cpp"]))],
+                ),
+                4 => (
+                    Class::Ignored,
+                    vec![format!("{}.{}", value, random.pick(&["o", "a", "h"]))],
+                ),
+                _ => (
+                    Class::Ignored,
+                    vec![random
+                        .pick(&["-c", "-O2", "-Wall", "-g", "-fPIC", "-MD", "-std=c11"])
+                        .to_string()],
+                ),
+            }
+        });
+    }
+
+    #[test]
+    fn cl_grammar() {
+        check("cl.exe", 2000, |random| {
+            let value = random.name();
+            let prefix = random.pick(&["/", "-"]);
+            let joined = random.below(2) == 0;
+            let spellings = |name: &str, value: String| match joined {
+                true => vec![format!("{}{}{}", prefix, name, value)],
+                false => vec![format!("{}{}", prefix, name), value],
+            };
+            match random.below(7) {
+                0 => (Class::Define, spellings(random.pick(&["D", "U"]), value)),
+                1 => (Class::Include, spellings(random.pick(&["I", "FI"]), value)),
+                2 => (
+                    Class::Output,
+                    vec![format!("{}Fo{}{}", prefix, random.pick(&["", ":"]), value)],
+                ),
+                3 => (Class::Source, spellings(random.pick(&["Tc", "Tp"]), value)),
+                // Not starting with a letter of a valued option.
+                4 => (
+                    Class::Source,
+                    vec![format!("{}.{}", random.pick(&["src/a", "b"]), "cpp")],
+                ),
+                5 => (Class::Ignored, vec![format!("{}.obj", value)]),
+                _ => (
+                    Class::Ignored,
+                    vec![format!(
+                        "{}{}",
+                        prefix,
+                        random.pick(&["c", "nologo", "EHsc", "W4", "O2", "Zi", "MD"])
+                    )],
+                ),
+            }
+        });
+        let args: Vec<String> = ["cl", "/c", "/Tpx.inl", "a.cpp", "/Foout/", "/link", "b.cpp"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let classes: Vec<Class> = classify(&args).into_iter().map(|(c, _)| c).collect();
+        assert_eq!(
+            classes,
+            [
+                Class::Compiler,
+                Class::Ignored,
+                Class::Source,
+                Class::Source,
+                Class::Output,
+                Class::Ignored
+            ]
+        );
+        // The same as recorded.
+        assert_eq!(
+            db::compiled_files(&args).into_iter().collect::<Vec<_>>(),
+            [
+                ("a.cpp".to_string(), Some("out/a.obj".to_string())),
+                ("x.inl".to_string(), Some("out/x.obj".to_string()))
+            ]
+        );
+    }
+}
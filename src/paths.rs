@@ -0,0 +1,108 @@
+use std::path::{Component, Path, PathBuf};
+
+/// Lexically normalizes `path`, i.e., removes `.` components and resolves `..` components
+/// without consulting the file system.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => match result.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    result.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => (),
+                _ => result.push(".."),
+            },
+            _ => result.push(component.as_os_str()),
+        }
+    }
+    if result.as_os_str().is_empty() {
+        result.push(".");
+    }
+    result
+}
+
+/// Resolves `path` relative to `directory` unless it is already absolute.
+pub fn resolve(directory: &str, path: &str) -> PathBuf {
+    normalize(&Path::new(directory).join(path))
+}
+
+/// Resolves `path` relative to `directory` and follows symbolic links if it exists, i.e., returns
+/// the same path for every spelling of a file.
+pub fn canonical(directory: &str, path: &str) -> PathBuf {
+    let resolved = resolve(directory, path);
+    resolved.canonicalize().unwrap_or(resolved)
+}
+
+/// Maps the prefix of `path` like the compiler does for `-ffile-prefix-map`, i.e., by plain string
+/// comparison where the last matching mapping `(from, to)` wins.
+pub fn remap(path: &str, maps: &[(String, String)]) -> Option<String> {
+    maps.iter()
+        .rev()
+        .find_map(|(from, to)| Some(format!("{}{}", to, path.strip_prefix(from.as_str())?)))
+}
+
+/// Computes the relative path leading from `base` to `path` where both are expected to be
+/// absolute and normalized.
+pub fn relative(path: &Path, base: &Path) -> PathBuf {
+    let mut path_components = path.components().peekable();
+    let mut base_components = base.components().peekable();
+    while let (Some(p), Some(b)) = (path_components.peek(), base_components.peek()) {
+        if p != b {
+            break;
+        }
+        path_components.next();
+        base_components.next();
+    }
+    let mut result: PathBuf = base_components.map(|_| Component::ParentDir).collect();
+    result.extend(path_components);
+    if result.as_os_str().is_empty() {
+        result.push(".");
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize() {
+        assert_eq!(
+            super::normalize(Path::new("/a/./b/../c")),
+            Path::new("/a/c")
+        );
+        assert_eq!(super::normalize(Path::new("/../a")), Path::new("/a"));
+        assert_eq!(
+            super::normalize(Path::new("../a/../../b")),
+            Path::new("../../b")
+        );
+        assert_eq!(super::normalize(Path::new("a/..")), Path::new("."));
+        assert_eq!(
+            resolve("/build/sub", "../src/foo.c"),
+            Path::new("/build/src/foo.c")
+        );
+        assert_eq!(resolve("/build", "/src/foo.c"), Path::new("/src/foo.c"));
+    }
+
+    #[test]
+    fn remap() {
+        let maps = [
+            ("/tmp/build".to_string(), "/src".to_string()),
+            ("/tmp/build/sub".to_string(), ".".to_string()),
+        ];
+        assert_eq!(super::remap("/tmp/build/a.c", &maps).unwrap(), "/src/a.c");
+        assert_eq!(super::remap("/tmp/build/sub/b.c", &maps).unwrap(), "./b.c");
+        assert_eq!(super::remap("/home/a.c", &maps), None);
+    }
+
+    #[test]
+    fn relative() {
+        let rel = |p, b| super::relative(Path::new(p), Path::new(b));
+        assert_eq!(rel("/a/b/c.c", "/a"), Path::new("b/c.c"));
+        assert_eq!(rel("/a/src/c.c", "/a/build"), Path::new("../src/c.c"));
+        assert_eq!(rel("/a", "/a"), Path::new("."));
+        assert_eq!(rel("/x/y", "/a/b"), Path::new("../../x/y"));
+    }
+}
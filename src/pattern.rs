@@ -0,0 +1,68 @@
+use std::error::Error;
+
+use regex::Regex;
+
+/// Matches `text` against the shell-style `pattern` where `*` matches any sequence of characters,
+/// `?` matches a single character, and everything else matches literally.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((bp, bt)) = backtrack {
+            p = bp + 1;
+            t = bt + 1;
+            backtrack = Some((bp, bt + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Either a glob or a regular expression.
+pub enum Matcher {
+    Glob(String),
+    Regex(Regex),
+}
+
+impl Matcher {
+    pub fn new(pattern: &str, regex: bool) -> Result<Self, Box<dyn Error>> {
+        if regex {
+            Ok(Matcher::Regex(Regex::new(pattern)?))
+        } else {
+            Ok(Matcher::Glob(pattern.to_string()))
+        }
+    }
+
+    /// Globs have to match the whole text whereas regular expressions may match any substring.
+    pub fn is_match(&self, text: &str) -> bool {
+        match self {
+            Matcher::Glob(pattern) => glob_match(pattern, text),
+            Matcher::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn glob_match() {
+        assert!(super::glob_match("-DFOO=*", "-DFOO=1"));
+        assert!(super::glob_match("-DFOO=*", "-DFOO="));
+        assert!(!super::glob_match("-DFOO=*", "-DFOOBAR=1"));
+        assert!(super::glob_match("*-gcc", "arm-none-eabi-gcc"));
+        assert!(super::glob_match("g?c*", "gcc-12"));
+        assert!(super::glob_match("*a*b*", "xaybz"));
+        assert!(!super::glob_match("*a*b", "xaybz"));
+        assert!(super::glob_match("", ""));
+        assert!(!super::glob_match("", "x"));
+    }
+}
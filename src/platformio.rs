@@ -0,0 +1,138 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::cli::Options;
+use crate::fragment;
+use crate::output;
+use crate::paths;
+use crate::wrap;
+
+/// Variables of the recorder passed on to the compiles, since SCons runs them in an environment
+/// of its own.
+const FORWARDED: &[&str] = &[
+    "CDBGEN_PRESET",
+    "CDBGEN_IMPLICIT_INCLUDES",
+    "CDBGEN_EVENTS",
+    "CDBGEN_OUTPUT",
+    "CDBGEN_STRICT",
+];
+
+/// Returns the root of the PlatformIO project containing `dir`, i.e., the innermost directory
+/// with a `platformio.ini`.
+fn project_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|d| d.join("platformio.ini").is_file())
+        .map(Path::to_path_buf)
+}
+
+/// Quotes `s` as Python string literal.
+fn python_string(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_default()
+}
+
+/// Returns the extra script of SCons which makes cdbgen at `cdbgen` the launcher of the compilers
+/// of all environments, which PlatformIO runs by absolute path or from the directories of its
+/// toolchain packages in front of `PATH`.  `vars` are set for the compiles.
+fn script(cdbgen: &Path, vars: &[(String, String)]) -> String {
+    let mut script = format!(
+        "# Written by 'cdbgen platformio'.\n\
+         Import(\"env\")\n\
+         cdbgen = {}\n\
+         launcher = '\"%s\"' % cdbgen\n\
+         for var in (\"CC\", \"CXX\"):\n    \
+             if env.get(var) and not str(env[var]).startswith(launcher):\n        \
+                 env.Replace(**{{var: '%s %s' % (launcher, env[var])}})\n",
+        python_string(&cdbgen.to_string_lossy())
+    );
+    for (name, value) in vars {
+        script += &format!(
+            "env[\"ENV\"][{}] = {}\n",
+            python_string(name),
+            python_string(value)
+        );
+    }
+    script
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse_command(args, &[], &["--db", "--pio", "-d"])?;
+    let pio = opts.value("--pio").unwrap_or("pio");
+    let cwd = env::current_dir()?;
+    let dir = paths::normalize(&cwd.join(opts.value("-d").unwrap_or(".")));
+    let root = project_root(&dir)
+        .ok_or_else(|| format!("no platformio.ini in {} or above", dir.display()))?;
+    let database = match opts.value("--db") {
+        Some(db) => paths::normalize(&cwd.join(db)),
+        None => root.join("compile_commands.json"),
+    };
+    let mut vars = vec![
+        (
+            "CDBGEN".to_string(),
+            database.to_string_lossy().into_owned(),
+        ),
+        ("CDBGEN_CAPTURE".to_string(), "platformio".to_string()),
+    ];
+    if env::var_os("CDBGEN_PRESET").is_none() {
+        vars.push(("CDBGEN_PRESET".to_string(), "auto".to_string()));
+    }
+    vars.extend(
+        FORWARDED
+            .iter()
+            .filter_map(|&name| Some((name.to_string(), env::var(name).ok()?))),
+    );
+    let script_path = fragment::dir(&database).join("platformio.py");
+    fs::create_dir_all(script_path.parent().unwrap_or(&root))?;
+    fs::write(&script_path, script(&env::current_exe()?, &vars))?;
+    let build = match opts.rest.split_first() {
+        Some((sep, build)) if sep == "--" => build,
+        _ => &opts.rest[..],
+    };
+    // PlatformIO appends the extra scripts of the environment to the ones of `platformio.ini`,
+    // whereas `-O extra_scripts=...` would replace them.
+    let mut extra_scripts = env::var("PLATFORMIO_EXTRA_SCRIPTS").unwrap_or_default();
+    if !extra_scripts.is_empty() {
+        extra_scripts.push('\n');
+    }
+    extra_scripts += &format!("post:{}", script_path.display());
+    let status = Command::new(pio)
+        .arg("run")
+        .arg("-d")
+        .arg(&root)
+        .args(build)
+        .env("PLATFORMIO_EXTRA_SCRIPTS", extra_scripts)
+        .status();
+    fs::remove_file(&script_path)?;
+    let status = status.map_err(|error| format!("cannot run '{}': {}", pio, error))?;
+    if !status.success() {
+        wrap::exit_like(status);
+    }
+    output::info(&format!("recorded into {}", database.display()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        fs::create_dir_all(temp.path().join("src/lib")).unwrap();
+        fs::write(temp.path().join("platformio.ini"), "[env:uno]\n").unwrap();
+        assert_eq!(
+            project_root(&temp.path().join("src/lib")).unwrap(),
+            temp.path()
+        );
+
+        let vars = [("CDBGEN".to_string(), "/p/compile_commands.json".to_string())];
+        let script = super::script(Path::new("/bin/cdbgen"), &vars);
+        assert!(script.contains("cdbgen = \"/bin/cdbgen\"\n"));
+        // The compilers are not wrapped twice, e.g. by a later environment sharing them.
+        assert!(script.contains("startswith(launcher)"));
+        assert!(script.contains("launcher = '\"%s\"' % cdbgen\n"));
+        assert!(script.ends_with("env[\"ENV\"][\"CDBGEN\"] = \"/p/compile_commands.json\"\n"));
+    }
+}
@@ -0,0 +1,808 @@
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::db::Invocation;
+use crate::paths;
+use crate::pattern;
+
+/// How a vendor flag is translated for clang.  Flags given with a trailing `*` match every flag
+/// starting with them.
+enum Rule {
+    /// Removes the flag.
+    Drop(&'static str),
+    /// Removes the flag together with its value, which follows either as next argument or joined
+    /// by `=`.
+    DropValued(&'static str),
+    /// Replaces the flag by the given ones.
+    Replace(&'static str, &'static [&'static str]),
+    /// Replaces the flag (with its value as next argument or joined by `=`) by the prefix given
+    /// followed by the value in lower case, e.g. `--cpu Cortex-M4` by `-mcpu=cortex-m4`.
+    Value(&'static str, &'static str),
+    /// Replaces the flag (with its value as next argument, joined by `=`, or, if the flag ends with
+    /// `*`, the rest of the argument) by the flags derived from the value.
+    Map(&'static str, fn(&str) -> Vec<String>),
+}
+
+impl Rule {
+    fn flag(&self) -> &'static str {
+        match self {
+            Rule::Drop(flag)
+            | Rule::DropValued(flag)
+            | Rule::Replace(flag, _)
+            | Rule::Value(flag, _)
+            | Rule::Map(flag, _) => flag,
+        }
+    }
+
+    /// Returns whether `arg` is the flag of this rule on its own (`Some(None)`) or with a value
+    /// joined by `=` (`Some(Some(value))`).
+    fn matches<'a>(&self, arg: &'a str) -> Option<Option<&'a str>> {
+        let flag = self.flag();
+        if let Some(prefix) = flag.strip_suffix('*') {
+            return match self {
+                // An empty rest is taken as the value following as next argument.
+                Rule::Map(_, _) => arg
+                    .strip_prefix(prefix)
+                    .map(|rest| Some(rest).filter(|r| !r.is_empty())),
+                _ => arg.starts_with(prefix).then(|| None),
+            };
+        }
+        if arg == flag {
+            return Some(None);
+        }
+        match self {
+            Rule::DropValued(_) | Rule::Value(_, _) | Rule::Map(_, _) => arg
+                .strip_prefix(flag)
+                .and_then(|rest| rest.strip_prefix('='))
+                .map(Some),
+            _ => None,
+        }
+    }
+}
+
+/// Translates the flags of a toolchain clang does not understand into ones it does.
+pub struct Preset {
+    pub name: &'static str,
+    /// Globs matching the names of the compilers of the toolchain, without directory and `.exe`.
+    compilers: &'static [&'static str],
+    /// The target clang parses for, inserted as `--target=` unless the entry names one.
+    target: Option<&'static str>,
+    /// Flags added after the target, e.g. the macros the vendor compiler predefines.
+    flags: &'static [&'static str],
+    /// Derives further flags from the name of the compiler, e.g. the CPU of a compiler built for a
+    /// single chip.
+    derive: Option<fn(&str) -> Vec<String>>,
+    rules: &'static [Rule],
+}
+
+const PRESETS: &[Preset] = &[
+    Preset {
+        name: "arm-gcc",
+        compilers: &["arm-none-eabi-gcc*", "arm-none-eabi-g++*"],
+        target: Some("arm-none-eabi"),
+        flags: &[],
+        derive: None,
+        rules: &[
+            Rule::Drop("-specs=*"),
+            Rule::Drop("--specs=*"),
+            Rule::Drop("-mthumb-interwork"),
+            Rule::Drop("-mno-thumb-interwork"),
+            Rule::Drop("-mpoke-function-name"),
+            Rule::Drop("-fstack-usage"),
+            Rule::Drop("-fcallgraph-info*"),
+            Rule::Drop("-fcyclomatic-complexity"),
+            Rule::Drop("-fno-tree-*"),
+        ],
+    },
+    Preset {
+        name: "iar",
+        compilers: &["iccarm"],
+        target: Some("arm-none-eabi"),
+        flags: &["-D__ICCARM__", "-D__IAR_SYSTEMS_ICC__=9"],
+        derive: None,
+        rules: &[
+            Rule::Value("--cpu", "-mcpu="),
+            Rule::Replace("--cpu_mode=thumb", &["-mthumb"]),
+            Rule::Replace("--cpu_mode=arm", &["-marm"]),
+            Rule::Replace("--endian=little", &["-mlittle-endian"]),
+            Rule::Replace("--endian=big", &["-mbig-endian"]),
+            Rule::Replace("--debug", &["-g"]),
+            Rule::Replace("--c++", &["-x", "c++"]),
+            Rule::Replace("--ec++", &["-x", "c++"]),
+            Rule::Replace("--eec++", &["-x", "c++"]),
+            Rule::Replace("-Ohz", &["-Oz"]),
+            Rule::Replace("-Ohs", &["-O3"]),
+            Rule::Replace("-Oh", &["-O2"]),
+            Rule::Replace("-Om", &["-O1"]),
+            Rule::Replace("-Ol", &["-O1"]),
+            Rule::Replace("-On", &["-O0"]),
+            // Language extensions, most of them keywords clang rejects anyway.
+            Rule::Drop("-e"),
+            Rule::Drop("--vla"),
+            Rule::DropValued("--fpu"),
+            Rule::DropValued("--dlib_config"),
+            Rule::DropValued("--diag_suppress"),
+            Rule::DropValued("--diag_warning"),
+            Rule::DropValued("--diag_error"),
+            Rule::DropValued("--dependencies"),
+            Rule::DropValued("-l"),
+            Rule::Drop("--no_*"),
+            Rule::Drop("--silent"),
+        ],
+    },
+    Preset {
+        name: "armcc",
+        compilers: &["armcc"],
+        target: Some("arm-none-eabi"),
+        flags: &["-D__CC_ARM", "-D__ARMCC_VERSION=5060750"],
+        derive: None,
+        rules: &[
+            Rule::Value("--cpu", "-mcpu="),
+            Rule::Replace("--c90", &["-std=c89"]),
+            Rule::Replace("--c99", &["-std=c99"]),
+            Rule::Replace("--cpp", &["-x", "c++"]),
+            Rule::Replace("--cpp11", &["-x", "c++", "-std=c++11"]),
+            Rule::Replace("--thumb", &["-mthumb"]),
+            Rule::Replace("--arm", &["-marm"]),
+            Rule::Replace("--li", &["-mlittle-endian"]),
+            Rule::Replace("--bi", &["-mbig-endian"]),
+            Rule::Replace("--split_sections", &["-ffunction-sections"]),
+            Rule::Drop("-Otime"),
+            Rule::Replace("-Ospace", &["-Os"]),
+            Rule::Drop("--gnu"),
+            Rule::Drop("--strict"),
+            Rule::Drop("--md"),
+            Rule::Drop("--apcs=*"),
+            Rule::DropValued("--fpu"),
+            Rule::DropValued("--depend"),
+            Rule::DropValued("--omf_browse"),
+            Rule::DropValued("--diag_suppress"),
+            Rule::DropValued("--diag_warning"),
+            Rule::DropValued("--diag_error"),
+            Rule::Value("--preinclude", "-include"),
+        ],
+    },
+    Preset {
+        name: "avr",
+        compilers: &["avr-gcc*", "avr-g++*"],
+        target: Some("avr"),
+        flags: &[],
+        derive: None,
+        rules: &[
+            // Clang derives the macros of the device from `-mmcu` like GCC, so it is kept.
+            Rule::Drop("-mcall-prologues"),
+            Rule::Drop("-mshort-calls"),
+            Rule::Drop("-mstrict-X"),
+            Rule::Drop("-mtiny-stack"),
+            Rule::Drop("-mgas-isr-prologues"),
+            Rule::Drop("-mn-flash=*"),
+            Rule::Drop("-fno-split-wide-types"),
+            Rule::Drop("-fno-tree-*"),
+            Rule::Drop("-fstack-usage"),
+        ],
+    },
+    Preset {
+        name: "sdcc",
+        compilers: &["sdcc"],
+        // No target of clang is an 8-bit one, so the one of the host parses the code.
+        target: None,
+        flags: &[
+            "-D__SDCC",
+            "-DSDCC",
+            // Storage classes, address spaces, and function attributes of SDCC.
+            "-D__data=",
+            "-D__idata=",
+            "-D__pdata=",
+            "-D__xdata=",
+            "-D__code=",
+            "-D__near=",
+            "-D__far=",
+            "-D__naked=",
+            "-D__reentrant=",
+            "-D__critical=",
+            "-D__banked=",
+            "-D__nonbanked=",
+            "-D__at(x)=",
+            "-D__interrupt(x)=",
+            "-D__using(x)=",
+            "-D__bit=_Bool",
+            "-D__sbit=volatile _Bool",
+            "-D__sfr=volatile unsigned char",
+            "-D__sfr16=volatile unsigned short",
+            "-D__sfr32=volatile unsigned long",
+        ],
+        derive: None,
+        rules: &[
+            Rule::Replace("-mmcs51", &["-D__SDCC_mcs51"]),
+            Rule::Replace("-mds390", &["-D__SDCC_ds390"]),
+            Rule::Replace("-mz80", &["-D__SDCC_z80"]),
+            Rule::Replace("-mz180", &["-D__SDCC_z180"]),
+            Rule::Replace("-mgbz80", &["-D__SDCC_gbz80"]),
+            Rule::Replace("-mhc08", &["-D__SDCC_hc08"]),
+            Rule::Replace("-ms08", &["-D__SDCC_s08"]),
+            Rule::Replace("-mstm8", &["-D__SDCC_stm8"]),
+            Rule::Replace("-mpdk14", &["-D__SDCC_pdk14"]),
+            Rule::Replace("--model-small", &["-D__SDCC_MODEL_SMALL"]),
+            Rule::Replace("--model-medium", &["-D__SDCC_MODEL_MEDIUM"]),
+            Rule::Replace("--model-large", &["-D__SDCC_MODEL_LARGE"]),
+            Rule::Replace("--model-huge", &["-D__SDCC_MODEL_HUGE"]),
+            Rule::Replace("--std-c89", &["-std=c89"]),
+            Rule::Replace("--std-sdcc89", &["-std=gnu89"]),
+            Rule::Replace("--std-c99", &["-std=c99"]),
+            Rule::Replace("--std-sdcc99", &["-std=gnu99"]),
+            Rule::Replace("--std-c11", &["-std=c11"]),
+            Rule::Replace("--std-sdcc11", &["-std=gnu11"]),
+            Rule::Replace("--std-c2x", &["-std=c2x"]),
+            Rule::Replace("--std-sdcc2x", &["-std=gnu2x"]),
+            Rule::Replace("--nostdinc", &["-nostdinc"]),
+            Rule::Replace("--debug", &["-g"]),
+            Rule::Replace("--Werror", &["-Werror"]),
+            Rule::Drop("--opt-code-size"),
+            Rule::Drop("--opt-code-speed"),
+            Rule::Drop("--stack-auto"),
+            Rule::Drop("--int-long-reent"),
+            Rule::Drop("--float-reent"),
+            Rule::Drop("--less-pedantic"),
+            Rule::Drop("--use-stdout"),
+            Rule::Drop("--no-*"),
+            Rule::DropValued("--xram-loc"),
+            Rule::DropValued("--xram-size"),
+            Rule::DropValued("--iram-size"),
+            Rule::DropValued("--code-loc"),
+            Rule::DropValued("--code-size"),
+            Rule::DropValued("--stack-loc"),
+            Rule::DropValued("--data-loc"),
+            Rule::DropValued("--peep-file"),
+        ],
+    },
+    Preset {
+        name: "xtensa",
+        compilers: &["xtensa-*-elf-gcc*", "xtensa-*-elf-g++*"],
+        target: Some("xtensa-esp-elf"),
+        flags: &[],
+        derive: Some(xtensa_cpu),
+        rules: &[
+            Rule::Drop("-mlongcalls"),
+            Rule::Drop("-mtext-section-literals"),
+            Rule::Drop("-mfix-esp32-psram-cache-issue"),
+            Rule::Drop("-mfix-esp32-psram-cache-strategy=*"),
+            Rule::Drop("-mdisable-hardware-atomics"),
+            // The unified compiler of ESP-IDF 5.2 loads the configuration of the chip.
+            Rule::Map("-mdynconfig=*", xtensa_dynconfig_cpu),
+            Rule::Drop("-fstrict-volatile-bitfields"),
+            Rule::Drop("-fno-tree-*"),
+            Rule::Drop("-fstack-usage"),
+        ],
+    },
+    Preset {
+        name: "ndk",
+        compilers: &["*-linux-android*-clang*"],
+        target: None,
+        flags: &[],
+        // The entry keeps the flags of the NDK, e.g. `--sysroot` and `-fno-addrsig`, as is.
+        derive: Some(ndk_target),
+        rules: &[],
+    },
+    Preset {
+        name: "qcc",
+        compilers: &["qcc", "q++"],
+        target: None,
+        flags: &[],
+        derive: None,
+        rules: &[
+            Rule::Map("-V*", qcc_variant),
+            // Arguments passed through to the compiler or preprocessor of the variant.
+            Rule::Map("-Wc,*", pass_through),
+            Rule::Map("-Wp,*", preprocessor_pass_through),
+            Rule::Replace("-lang-c++", &["-x", "c++"]),
+            Rule::Replace("-lang-c", &["-x", "c"]),
+            Rule::Drop("-EL"),
+            Rule::Drop("-EB"),
+        ],
+    },
+    Preset {
+        name: "ghs",
+        compilers: &[
+            "cc*arm", "cx*arm", "cc*ppc", "cx*ppc", "ccrh850", "cxrh850", "ccv850", "cxv850",
+        ],
+        target: None,
+        flags: &["-D__ghs__=1", "-D__ghs=1", "-D__interrupt=", "-D__packed="],
+        derive: Some(ghs_target),
+        rules: &[
+            Rule::Map("-cpu", ghs_cpu),
+            Rule::Replace("-G", &["-g"]),
+            Rule::Replace("-c89", &["-std=c89"]),
+            Rule::Replace("-c99", &["-std=c99"]),
+            Rule::Replace("-gnu99", &["-std=gnu99"]),
+            Rule::Replace("--exceptions", &["-fexceptions"]),
+            Rule::Replace("--no_exceptions", &["-fno-exceptions"]),
+            Rule::Replace("--rtti", &["-frtti"]),
+            Rule::Replace("--no_rtti", &["-fno-rtti"]),
+            Rule::Replace("-Ospeed", &["-O2"]),
+            Rule::Replace("-Osize", &["-Os"]),
+            Rule::Replace("-Omax", &["-O3"]),
+            Rule::DropValued("-bsp"),
+            Rule::DropValued("-os_dir"),
+            Rule::DropValued("--diag_suppress"),
+            Rule::DropValued("--diag_error"),
+            Rule::DropValued("--diag_warning"),
+            Rule::Drop("-object_dir=*"),
+            Rule::Drop("--gnu_asm"),
+            Rule::Drop("--ee"),
+            Rule::Drop("--link_once_templates"),
+            Rule::Drop("-preprocess_assembly_files"),
+            Rule::Drop("-keeptempfiles"),
+            Rule::Drop("-list*"),
+            Rule::Drop("--no_*"),
+        ],
+    },
+    Preset {
+        name: "riscv-esp",
+        compilers: &["riscv32-esp-elf-gcc*", "riscv32-esp-elf-g++*"],
+        target: Some("riscv32-esp-elf"),
+        flags: &[],
+        derive: None,
+        rules: &[
+            Rule::Drop("-fstrict-volatile-bitfields"),
+            Rule::Drop("-fno-tree-*"),
+            Rule::Drop("-fstack-usage"),
+            Rule::Drop("-mtune=esp-*"),
+            Rule::Drop("-fzero-init-padding-bits=*"),
+        ],
+    },
+    Preset {
+        name: "zephyr",
+        compilers: &[
+            "*-zephyr-elf-gcc*",
+            "*-zephyr-elf-g++*",
+            "*-zephyr-eabi-gcc*",
+            "*-zephyr-eabi-g++*",
+        ],
+        target: None,
+        // The headers of Zephyr rely on it, also in code not built as part of a Zephyr library.
+        flags: &["-D__ZEPHYR__=1"],
+        derive: Some(zephyr_target),
+        rules: &[
+            Rule::Drop("-specs=*"),
+            Rule::Drop("--specs=*"),
+            Rule::Drop("-fno-reorder-functions"),
+            Rule::Drop("-fno-defer-pop"),
+            Rule::Drop("-fno-printf-return-value"),
+            Rule::Drop("-fno-freestanding"),
+            Rule::Drop("-mfp16-format=*"),
+            Rule::Drop("--param=*"),
+            Rule::DropValued("--param"),
+            Rule::Drop("-fstack-usage"),
+            Rule::Drop("-mlongcalls"),
+            Rule::Drop("-mtext-section-literals"),
+        ],
+    },
+];
+
+/// Returns the target of a compiler of the Zephyr SDK, e.g. `--target=arm-none-eabi` for
+/// `arm-zephyr-eabi-gcc`, since clang knows no Zephyr OS.
+fn zephyr_target(compiler: &str) -> Vec<String> {
+    let name = program_name(compiler);
+    match name.split_once("-zephyr-") {
+        Some((arch, rest)) if rest.starts_with("eabi") => {
+            vec![format!("--target={}-none-eabi", arch)]
+        }
+        Some((arch, _)) => vec![format!("--target={}-unknown-elf", arch)],
+        None => Vec::new(),
+    }
+}
+
+/// Returns the target the wrapper scripts of the Android NDK pass to clang, e.g.
+/// `--target=aarch64-linux-android21` for `aarch64-linux-android21-clang++`, such that tools not
+/// deriving the target from the name of the compiler agree with it.
+fn ndk_target(compiler: &str) -> Vec<String> {
+    let name = program_name(compiler);
+    match name.rsplit_once("-clang") {
+        Some((target, _)) => vec![format!("--target={}", target)],
+        None => Vec::new(),
+    }
+}
+
+/// Returns the target of the variant of qcc selected by `-V`, e.g. `--target=aarch64-unknown-nto-qnx`
+/// for `gcc_ntoaarch64le` or `12.2.0,gcc_ntoaarch64le_cxx`.
+fn qcc_variant(variant: &str) -> Vec<String> {
+    let variant = variant.rsplit(',').next().unwrap_or_default();
+    let arch = match variant.split_once("nto") {
+        Some((_, arch)) => arch,
+        None => return Vec::new(),
+    };
+    let arch = arch.split("_cxx").next().unwrap_or_default();
+    let arch = arch.split("_gpp").next().unwrap_or_default();
+    let arch = match arch {
+        "x86" => "i586",
+        arch => arch.trim_end_matches("le").trim_end_matches("be"),
+    };
+    vec![format!("--target={}-unknown-nto-qnx", arch)]
+}
+
+/// Returns the comma separated arguments of `-Wc,` and `-Wp,` of qcc.
+fn pass_through(args: &str) -> Vec<String> {
+    args.split(',')
+        .filter(|a| !a.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Returns the comma separated arguments of `-Wp,` of qcc in the spelling of the driver.  The
+/// preprocessor itself takes the file of `-MD` and `-MMD` as next argument, e.g. `-Wp,-MD,a.d`,
+/// which is `-MD -MF a.d` to the driver.
+fn preprocessor_pass_through(args: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut args = pass_through(args).into_iter();
+    while let Some(arg) = args.next() {
+        let dependencies = arg == "-MD" || arg == "-MMD";
+        result.push(arg);
+        if dependencies {
+            if let Some(file) = args.next() {
+                result.extend(["-MF".to_string(), file]);
+            }
+        }
+    }
+    result
+}
+
+/// Returns the target of a driver of Green Hills by the architecture in its name, if clang knows
+/// it, e.g. `--target=arm-none-eabi` for `ccarm` or `cxintarm`.
+fn ghs_target(compiler: &str) -> Vec<String> {
+    let name = program_name(compiler);
+    if name.ends_with("arm") {
+        vec!["--target=arm-none-eabi".to_string()]
+    } else if name.ends_with("ppc") {
+        vec!["--target=powerpc-unknown-eabi".to_string()]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Returns the `-mcpu=` of the CPU of Green Hills, e.g. `cortex-m4` for `cortexm4`.  Other CPUs
+/// are dropped since their names differ from the ones of clang.
+fn ghs_cpu(cpu: &str) -> Vec<String> {
+    let cpu = cpu.to_lowercase();
+    match cpu.strip_prefix("cortex") {
+        Some(rest) => vec![format!("-mcpu=cortex-{}", rest.trim_start_matches('-'))],
+        None => Vec::new(),
+    }
+}
+
+/// Returns the name of `compiler` without directory and `.exe`.
+fn program_name(compiler: &str) -> String {
+    let name = Path::new(compiler)
+        .file_name()
+        .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+    match name.strip_suffix(".exe") {
+        Some(stem) => stem.to_string(),
+        None => name,
+    }
+}
+
+/// Returns the CPU of an Xtensa compiler of Espressif, e.g. `-mcpu=esp32s3` for
+/// `xtensa-esp32s3-elf-gcc`.
+fn xtensa_cpu(compiler: &str) -> Vec<String> {
+    let name = program_name(compiler);
+    match name.split('-').nth(1) {
+        // The unified `xtensa-esp-elf-gcc` is built for no single chip.
+        Some(chip) if chip.starts_with("esp") && chip != "esp" => {
+            vec![format!("-mcpu={}", chip)]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the CPU of the configuration the unified Xtensa compiler of Espressif loads, e.g.
+/// `-mcpu=esp32s3` for `-mdynconfig=xtensa_esp32s3.so`.
+fn xtensa_dynconfig_cpu(config: &str) -> Vec<String> {
+    let stem = Path::new(config)
+        .file_stem()
+        .map_or_else(String::new, |s| s.to_string_lossy().into_owned());
+    match stem.strip_prefix("xtensa_") {
+        Some(chip) if chip.starts_with("esp") => vec![format!("-mcpu={}", chip)],
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the preset named `name`.
+pub fn find(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|p| p.name == name)
+}
+
+/// Returns the preset of the toolchain `compiler` belongs to, if any.
+pub fn detect(compiler: &str) -> Option<&'static Preset> {
+    let name = program_name(compiler);
+    PRESETS
+        .iter()
+        .find(|p| p.compilers.iter().any(|c| pattern::glob_match(c, &name)))
+}
+
+/// Returns the preset selected by `selection`, i.e., a name or `auto` for the one of the
+/// toolchain of `compiler`.
+pub fn select(selection: &str, compiler: &str) -> Result<Option<&'static Preset>, String> {
+    if selection == "auto" {
+        return Ok(detect(compiler));
+    }
+    match find(selection) {
+        Some(preset) => Ok(Some(preset)),
+        None => Err(format!(
+            "unknown preset '{}', expected auto or one of {}",
+            selection,
+            PRESETS
+                .iter()
+                .map(|p| p.name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Returns the preset for `invocation`, i.e., the one of the innermost directory of
+/// `CDBGEN_PRESET_DIRS` containing its directory or else the one of `CDBGEN_PRESET` or of the
+/// configuration of the shim.
+pub fn for_invocation(
+    config: &Config,
+    invocation: &Invocation,
+) -> Result<Option<&'static Preset>, String> {
+    let compiler = match invocation.arguments.first() {
+        Some(compiler) => compiler,
+        None => return Ok(None),
+    };
+    let directory = Path::new(&invocation.directory);
+    let base = config.database.parent().unwrap_or_else(|| Path::new(""));
+    let by_directory = config
+        .preset_dirs
+        .iter()
+        .map(|(dir, preset)| (paths::normalize(&directory.join(base).join(dir)), preset))
+        .filter(|(dir, _)| directory.starts_with(dir))
+        .max_by_key(|(dir, _)| dir.components().count())
+        .map(|(_, preset)| preset);
+    match by_directory.or(config.preset.as_ref()) {
+        Some(selection) => select(selection, compiler),
+        None => Ok(None),
+    }
+}
+
+/// Parses the value of `CDBGEN_PRESET_DIRS`, i.e., comma separated pairs `DIR=PRESET`.
+pub fn parse_dirs(value: &str) -> Result<Vec<(PathBuf, String)>, String> {
+    value
+        .split(',')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.rsplit_once('=') {
+            Some((dir, preset)) if !dir.is_empty() && !preset.is_empty() => {
+                Ok((PathBuf::from(dir), preset.to_string()))
+            }
+            _ => Err(format!("expected 'DIR=PRESET' but found '{}'", pair)),
+        })
+        .collect()
+}
+
+/// Returns the CPU name of a vendor in the spelling of clang, e.g. `cortex-m4` for armcc's
+/// `Cortex-M4.fp.sp`.
+fn cpu(value: &str) -> String {
+    let value = value.to_lowercase();
+    let value = value.split('.').next().unwrap_or_default();
+    value.to_string()
+}
+
+impl Preset {
+    /// Translates the compiler invocation `arguments` (the compiler first).  The compiler itself
+    /// is kept such that the entry still names the one that built the file.
+    pub fn apply(&self, arguments: &[String]) -> Vec<String> {
+        let (compiler, rest) = match arguments.split_first() {
+            Some(split) => split,
+            None => return arguments.to_vec(),
+        };
+        let mut result = vec![compiler.clone()];
+        let has_target = rest
+            .iter()
+            .any(|a| a.starts_with("--target=") || a == "-target");
+        if let (Some(target), false) = (self.target, has_target) {
+            result.push(format!("--target={}", target));
+        }
+        result.extend(self.flags.iter().map(|f| f.to_string()));
+        if let Some(derive) = self.derive {
+            result.extend(
+                derive(compiler)
+                    .into_iter()
+                    .filter(|f| !(has_target && f.starts_with("--target="))),
+            );
+        }
+        let mut iter = rest.iter();
+        while let Some(arg) = iter.next() {
+            let matched = self
+                .rules
+                .iter()
+                .find_map(|r| r.matches(arg).map(|value| (r, value)));
+            let (rule, value) = match matched {
+                Some(matched) => matched,
+                None => {
+                    result.push(arg.clone());
+                    continue;
+                }
+            };
+            // The value of a valued flag follows unless it was joined.
+            let mut value = || value.map(String::from).or_else(|| iter.next().cloned());
+            match rule {
+                Rule::Drop(_) => (),
+                Rule::DropValued(_) => {
+                    value();
+                }
+                Rule::Replace(_, flags) => result.extend(flags.iter().map(|f| f.to_string())),
+                Rule::Value(_, prefix) => {
+                    if let Some(value) = value() {
+                        if prefix.ends_with('=') {
+                            result.push(format!("{}{}", prefix, cpu(&value)));
+                        } else {
+                            result.extend([prefix.to_string(), value]);
+                        }
+                    }
+                }
+                Rule::Map(_, f) => {
+                    if let Some(value) = value() {
+                        result.extend(f(&value));
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn apply() {
+        let iar = detect("/opt/iar/bin/iccarm.exe").unwrap();
+        assert_eq!(iar.name, "iar");
+        assert_eq!(
+            iar.apply(&strings(&[
+                "iccarm",
+                "--cpu",
+                "Cortex-M4",
+                "-e",
+                "--dlib_config",
+                "DLib_Config_Normal.h",
+                "-Ohz",
+                "-Iinc",
+                "main.c"
+            ])),
+            strings(&[
+                "iccarm",
+                "--target=arm-none-eabi",
+                "-D__ICCARM__",
+                "-D__IAR_SYSTEMS_ICC__=9",
+                "-mcpu=cortex-m4",
+                "-Oz",
+                "-Iinc",
+                "main.c"
+            ])
+        );
+        let armcc = find("armcc").unwrap();
+        assert_eq!(
+            armcc.apply(&strings(&[
+                "armcc",
+                "--target=thumbv7em-none-eabi",
+                "--cpu=Cortex-M4.fp.sp",
+                "--preinclude=cfg.h",
+                "--depend=a.d",
+                "-c",
+                "a.c"
+            ]))[1..],
+            strings(&[
+                "-D__CC_ARM",
+                "-D__ARMCC_VERSION=5060750",
+                "--target=thumbv7em-none-eabi",
+                "-mcpu=cortex-m4",
+                "-include",
+                "cfg.h",
+                "-c",
+                "a.c"
+            ])
+        );
+        assert_eq!(
+            detect("xtensa-esp32s3-elf-gcc").unwrap().apply(&strings(&[
+                "xtensa-esp32s3-elf-gcc",
+                "-mlongcalls",
+                "-c",
+                "a.c"
+            ])),
+            strings(&[
+                "xtensa-esp32s3-elf-gcc",
+                "--target=xtensa-esp-elf",
+                "-mcpu=esp32s3",
+                "-c",
+                "a.c"
+            ])
+        );
+        let sdcc = detect("sdcc").unwrap().apply(&strings(&[
+            "sdcc",
+            "-mmcs51",
+            "--model-large",
+            "--xram-loc",
+            "0x8000",
+            "-c",
+            "a.c",
+        ]));
+        assert_eq!(
+            sdcc[sdcc.len() - 4..],
+            strings(&["-D__SDCC_mcs51", "-D__SDCC_MODEL_LARGE", "-c", "a.c"])
+        );
+        assert_eq!(detect("arm-none-eabi-gcc-12.2").unwrap().name, "arm-gcc");
+        assert!(detect("gcc").is_none());
+        assert_eq!(
+            parse_dirs("boot=avr,fw=iar,").unwrap(),
+            [
+                (PathBuf::from("boot"), "avr".to_string()),
+                (PathBuf::from("fw"), "iar".to_string())
+            ]
+        );
+        assert!(parse_dirs("boot:avr").is_err());
+    }
+
+    #[test]
+    fn qcc_and_ghs() {
+        let qcc = detect("/opt/qnx800/host/linux/x86_64/usr/bin/q++").unwrap();
+        assert_eq!(
+            qcc.apply(&strings(&[
+                "q++",
+                "-V",
+                "gcc_ntoaarch64le_cxx",
+                "-Wc,-fno-common,-Wall",
+                "-Wp,-MD,a.d",
+                "-lang-c++",
+                "-EL",
+                "-c",
+                "a.cc"
+            ])),
+            strings(&[
+                "q++",
+                "--target=aarch64-unknown-nto-qnx",
+                "-fno-common",
+                "-Wall",
+                "-MD",
+                "-MF",
+                "a.d",
+                "-x",
+                "c++",
+                "-c",
+                "a.cc"
+            ])
+        );
+        assert_eq!(
+            qcc.apply(&strings(&["qcc", "-V12.2.0,gcc_ntox86_64"]))[1],
+            "--target=x86_64-unknown-nto-qnx"
+        );
+
+        let ghs = detect("C:/ghs/comp_202354/ccintarm.exe").unwrap();
+        assert_eq!(ghs.name, "ghs");
+        assert_eq!(
+            ghs.apply(&strings(&[
+                "ccintarm",
+                "-cpu=cortexm4",
+                "-bsp",
+                "sim800",
+                "--no_commons",
+                "-c99",
+                "-G",
+                "-c",
+                "a.c"
+            ]))[5..],
+            strings(&[
+                "--target=arm-none-eabi",
+                "-mcpu=cortex-m4",
+                "-std=c99",
+                "-g",
+                "-c",
+                "a.c"
+            ])
+        );
+    }
+}
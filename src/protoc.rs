@@ -0,0 +1,246 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::cli::Options;
+use crate::config::Config;
+use crate::db::{self, Entry};
+use crate::paths;
+
+/// A `.proto` file compiled by `protoc` together with the sources generated from it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ProtoEntry {
+    pub directory: String,
+    pub proto: String,
+    /// Import paths given by `-I` or `--proto_path`.
+    pub proto_paths: Vec<String>,
+    /// Generated sources relative to `directory`, e.g. `gen/foo.pb.cc` and `gen/foo.pb.h`.
+    pub generated: Vec<String>,
+    pub arguments: Vec<String>,
+}
+
+/// Returns the database `.proto` files are recorded into, i.e., `proto_sources.json` next to the
+/// database of `config`.
+pub fn database(config: &Config) -> PathBuf {
+    config.database.with_file_name("proto_sources.json")
+}
+
+thread_local! {
+    /// Names of the protocol buffer compiler as matched by `is_protoc`, compiled once since
+    /// imports match it against every execution.
+    static PROTOC: Regex = Regex::new(r"^protoc(-[0-9.]+)?(\.exe)?$").unwrap();
+}
+
+/// Returns true if `program` names the protocol buffer compiler.
+pub fn is_protoc(program: &str) -> bool {
+    let name = Path::new(program)
+        .file_name()
+        .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+    PROTOC.with(|re| re.is_match(&name))
+}
+
+/// Strips the generator options of an output directory, e.g. `dllexport_decl=X:gen`, but keeps
+/// drive letters.
+fn out_dir(value: &str) -> &str {
+    match value.split_once(':') {
+        Some((options, dir)) if options.len() > 1 => dir,
+        _ => value,
+    }
+}
+
+/// Returns the `.proto` files compiled by the `protoc` invocation `arguments` (`protoc` first)
+/// in `directory` together with the C++ sources generated from them.
+pub fn entries(directory: &str, arguments: &[String]) -> Vec<ProtoEntry> {
+    let mut proto_paths = Vec::new();
+    // Output directories with the suffixes of the generated files.
+    let mut outputs = Vec::new();
+    let mut protos = Vec::new();
+    let mut iter = arguments.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if arg == "-I" || arg == "--proto_path" {
+            proto_paths.extend(iter.next().cloned());
+        } else if let Some(p) = arg
+            .strip_prefix("--proto_path=")
+            .or_else(|| arg.strip_prefix("-I"))
+        {
+            proto_paths.push(p.to_string());
+        } else if let Some(o) = arg.strip_prefix("--cpp_out=") {
+            outputs.push((out_dir(o).to_string(), [".pb.cc", ".pb.h"]));
+        } else if let Some(o) = arg.strip_prefix("--grpc_out=") {
+            outputs.push((out_dir(o).to_string(), [".grpc.pb.cc", ".grpc.pb.h"]));
+        } else if arg.ends_with(".proto") && !arg.starts_with('-') {
+            protos.push(arg.clone());
+        }
+    }
+    let import_paths = if proto_paths.is_empty() {
+        vec![".".to_string()]
+    } else {
+        proto_paths.clone()
+    };
+    protos
+        .into_iter()
+        .map(|proto| {
+            let resolved = paths::resolve(directory, &proto);
+            // Generated files are named after the path of the proto within its import path.
+            let relative = import_paths
+                .iter()
+                .find_map(|p| {
+                    resolved
+                        .strip_prefix(paths::resolve(directory, p))
+                        .ok()
+                        .map(Path::to_path_buf)
+                })
+                .unwrap_or_else(|| PathBuf::from(resolved.file_name().unwrap_or_default()));
+            let stem = relative.with_extension("").to_string_lossy().into_owned();
+            let stem = &stem;
+            let generated = outputs
+                .iter()
+                .flat_map(|(dir, suffixes)| {
+                    suffixes.iter().map(move |suffix| {
+                        Path::new(dir)
+                            .join(format!("{}{}", stem, suffix))
+                            .to_string_lossy()
+                            .into_owned()
+                    })
+                })
+                .collect();
+            ProtoEntry {
+                directory: directory.to_string(),
+                proto,
+                proto_paths: proto_paths.clone(),
+                generated,
+                arguments: arguments.to_vec(),
+            }
+        })
+        .collect()
+}
+
+/// Reads the `.proto` files recorded in `json_path`.
+pub fn read(json_path: &Path) -> Result<BTreeSet<ProtoEntry>, Box<dyn Error>> {
+    db::read_json(json_path)
+}
+
+/// Records `protos` into the database at `json_path` where each replaces the entry of the same
+/// `.proto` file.
+pub fn record(json_path: &Path, protos: Vec<ProtoEntry>) -> Result<(), Box<dyn Error>> {
+    db::update_json(json_path, |all: &mut BTreeSet<ProtoEntry>| {
+        for proto in &protos {
+            all.retain(|p| p.directory != proto.directory || p.proto != proto.proto);
+            all.insert(proto.clone());
+        }
+        Ok(())
+    })
+}
+
+/// A `.proto` file joined with the compile entries of the sources generated from it.
+#[derive(Debug, Serialize)]
+struct Joined<'a> {
+    #[serde(flatten)]
+    proto: &'a ProtoEntry,
+    compiled: Vec<&'a Entry>,
+}
+
+fn join<'a>(protos: &'a BTreeSet<ProtoEntry>, entries: &'a BTreeSet<Entry>) -> Vec<Joined<'a>> {
+    protos
+        .iter()
+        .map(|proto| {
+            let generated: BTreeSet<_> = proto
+                .generated
+                .iter()
+                .map(|g| paths::resolve(&proto.directory, g))
+                .collect();
+            Joined {
+                proto,
+                compiled: entries
+                    .iter()
+                    .filter(|e| generated.contains(&paths::resolve(&e.directory, &e.file)))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &["--json"], &["--db"])?;
+    let json_path = opts.database();
+    let protos = read(&json_path.with_file_name("proto_sources.json"))?;
+    let entries = db::read_entries(&json_path)?;
+    let mut joined = join(&protos, &entries);
+    if !opts.positional.is_empty() {
+        // Either the proto or one of the sources generated from it may be given.
+        let cwd = env::current_dir()?.to_string_lossy().into_owned();
+        let files: BTreeSet<_> = opts
+            .positional
+            .iter()
+            .map(|f| paths::resolve(&cwd, f))
+            .collect();
+        joined.retain(|j| {
+            let p = j.proto;
+            std::iter::once(&p.proto)
+                .chain(&p.generated)
+                .any(|f| files.contains(&paths::resolve(&p.directory, f)))
+        });
+    }
+    if opts.has("--json") {
+        println!("{}", serde_json::to_string_pretty(&joined)?);
+        return Ok(());
+    }
+    for j in &joined {
+        let p = j.proto;
+        println!("{}", paths::resolve(&p.directory, &p.proto).display());
+        if !p.proto_paths.is_empty() {
+            println!("  import paths: {}", p.proto_paths.join(" "));
+        }
+        for g in &p.generated {
+            println!("  generated: {}", paths::resolve(&p.directory, g).display());
+        }
+        for e in &j.compiled {
+            println!(
+                "  compiled: {} (directory {})",
+                paths::resolve(&e.directory, &e.file).display(),
+                e.directory
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn entries() {
+        let args = strings(&[
+            "protoc",
+            "-Iproto",
+            "--proto_path",
+            "third_party",
+            "--cpp_out=dllexport_decl=API:gen",
+            "proto/foo/bar.proto",
+            "third_party/x.proto",
+        ]);
+        let entries = super::entries("/build", &args);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].proto_paths, ["proto", "third_party"]);
+        assert_eq!(
+            entries[0].generated,
+            ["gen/foo/bar.pb.cc", "gen/foo/bar.pb.h"]
+        );
+        assert_eq!(entries[1].generated, ["gen/x.pb.cc", "gen/x.pb.h"]);
+
+        let args = strings(&["protoc", "--grpc_out=.", "--cpp_out=.", "a.proto"]);
+        assert_eq!(
+            super::entries("/build", &args)[0].generated,
+            ["./a.grpc.pb.cc", "./a.grpc.pb.h", "./a.pb.cc", "./a.pb.h"]
+        );
+        assert!(super::is_protoc("/usr/bin/protoc-3.21.12"));
+        assert!(!super::is_protoc("protoc-gen-grpc"));
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::path::Path;
+
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::meta::{self, EntryMeta, Meta, Record};
+use crate::time;
+
+/// Minimal time between two automatic prunes of a database in seconds.
+const AUTO_PRUNE_INTERVAL: u64 = 60 * 60;
+
+/// Parses an age like `90s`, `15m`, `12h`, `30d`, or `4w` into seconds.
+pub fn parse_age(age: &str) -> Result<u64, Box<dyn Error>> {
+    let split = age.find(|c: char| !c.is_ascii_digit()).unwrap_or(age.len());
+    let (number, unit) = age.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid age '{}'", age))?;
+    let factor = match unit {
+        "s" | "" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("invalid unit of age '{}'", age).into()),
+    };
+    number
+        .checked_mul(factor)
+        .ok_or_else(|| format!("age '{}' is too large", age).into())
+}
+
+/// Removes the entries whose provenance and most recent record satisfy `is_stale` from `entries`
+/// and from `meta` and returns the entries kept.  Entries without provenance are kept.
+pub fn remove<P>(entries: &BTreeSet<Entry>, meta: &mut Meta, is_stale: P) -> BTreeSet<Entry>
+where
+    P: Fn(&EntryMeta, &Record) -> bool,
+{
+    let stale: BTreeSet<(String, String)> = meta
+        .entries
+        .iter()
+        .filter(|e| e.history.last().map_or(false, |r| is_stale(e, r)))
+        .map(|e| (e.directory.clone(), e.file.clone()))
+        .collect();
+    let is_kept =
+        |directory: &str, file: &str| !stale.contains(&(directory.to_string(), file.to_string()));
+    meta.entries.retain(|e| is_kept(&e.directory, &e.file));
+    entries
+        .iter()
+        .filter(|e| is_kept(&e.directory, &e.file))
+        .cloned()
+        .collect()
+}
+
+/// Removes the entries of the database at `json_path` not seen since `cutoff` and returns how
+/// many were removed.
+fn prune(json_path: &Path, cutoff: u64, dry_run: bool) -> Result<usize, Box<dyn Error>> {
+    let mut removed = 0;
+    db::update_any(json_path, |entries| {
+        let mut meta = meta::read_unlocked(json_path)?;
+        let kept = remove(entries, &mut meta, |_, r| r.last_seen < cutoff);
+        removed = entries.len() - kept.len();
+        if dry_run {
+            return Ok(entries.clone());
+        }
+        meta.last_pruned = time::now();
+        meta::write_unlocked(json_path, &meta)?;
+        Ok(kept)
+    })?;
+    Ok(removed)
+}
+
+/// Prunes the database at `json_path` from entries older than `age` seconds unless this was done
+/// recently.
+pub fn auto(json_path: &Path, age: u64) -> Result<(), Box<dyn Error>> {
+    let now = time::now();
+    if now.saturating_sub(meta::read(json_path)?.last_pruned) < AUTO_PRUNE_INTERVAL {
+        return Ok(());
+    }
+    prune(json_path, now.saturating_sub(age), false)?;
+    Ok(())
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &["--dry-run", "-n"], &["--db", "--older-than"])?;
+    let age = parse_age(
+        opts.value("--older-than")
+            .ok_or("usage: cdbgen prune [--dry-run] --older-than AGE")?,
+    )?;
+    let dry_run = opts.has("--dry-run") || opts.has("-n");
+    let removed = prune(&opts.database(), time::now().saturating_sub(age), dry_run)?;
+    if dry_run {
+        println!("would remove {} entries", removed);
+    } else {
+        println!("removed {} entries", removed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Invocation;
+
+    #[test]
+    fn prune() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let json_path = temp.path().join("compile_commands.json");
+        db::update_any(&json_path, |_| {
+            let mut entries = BTreeSet::new();
+            let mut meta = Meta::default();
+            for (file, now) in [("old.c", 10), ("new.c", 1000)] {
+                let arguments = vec!["cc".to_string(), file.to_string()];
+                let invocation = Invocation {
+                    argv: arguments.clone(),
+                    arguments,
+                    directory: "/src".to_string(),
+                    files: [(file.to_string(), None)].into_iter().collect(),
+                    duration: None,
+                    session: None,
+                };
+                db::replace(&mut entries, &invocation);
+                meta::merge(&mut meta, &invocation, now);
+            }
+            meta::write_unlocked(&json_path, &meta)?;
+            Ok(entries)
+        })
+        .unwrap();
+
+        assert_eq!(super::prune(&json_path, 500, true).unwrap(), 1);
+        assert_eq!(db::read_entries(&json_path).unwrap().len(), 2);
+        assert_eq!(super::prune(&json_path, 500, false).unwrap(), 1);
+        let entries = db::read_entries(&json_path).unwrap();
+        assert_eq!(entries.iter().next().unwrap().file, "new.c");
+    }
+
+    #[test]
+    fn parse_age() {
+        assert_eq!(super::parse_age("30d").unwrap(), 30 * 24 * 60 * 60);
+        assert_eq!(super::parse_age("90").unwrap(), 90);
+        assert_eq!(super::parse_age("2w").unwrap(), 14 * 24 * 60 * 60);
+        assert!(super::parse_age("d").is_err());
+        assert!(super::parse_age("3y").is_err());
+        assert!(super::parse_age("99999999999999999w").is_err());
+    }
+}
@@ -0,0 +1,466 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::args;
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::diff;
+use crate::meta;
+use crate::paths;
+
+fn to_string(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Rewrites `directory`, `file`, `output`, and all path-valued flags of `entry` by means of `f`
+/// which is called with the absolute directory of the entry and a path relative to it.
+fn map_paths<F>(entry: &Entry, directory: &Path, f: F) -> Entry
+where
+    F: Fn(&Path, &str) -> String,
+{
+    let arguments = match entry.arguments.split_first() {
+        Some((compiler, rest)) => std::iter::once(compiler.clone())
+            .chain(
+                args::flags(rest)
+                    .into_iter()
+                    .flat_map(|flag| match flag.path() {
+                        Some(path) => flag.with_path(&f(directory, path)),
+                        None => flag.args,
+                    }),
+            )
+            .collect(),
+        None => Vec::new(),
+    };
+    Entry {
+        directory: entry.directory.clone(),
+        file: f(directory, &entry.file),
+        arguments,
+        output: entry.output.as_ref().map(|o| f(directory, o)),
+    }
+}
+
+/// Turns every path below `root` into a path relative to the entry's directory.  The directory
+/// itself is made relative to `db_dir`, i.e., the directory containing the database.
+fn relativize(entry: &Entry, db_dir: &Path, root: &Path) -> Entry {
+    let directory = paths::resolve(&to_string(db_dir), &entry.directory);
+    let mut result = map_paths(entry, &directory, |dir, path| {
+        let abs = paths::resolve(&to_string(dir), path);
+        if abs.starts_with(root) {
+            to_string(&paths::relative(&abs, dir))
+        } else {
+            path.to_string()
+        }
+    });
+    if directory.starts_with(root) {
+        result.directory = to_string(&paths::relative(&directory, db_dir));
+    }
+    result
+}
+
+/// Turns every relative path into an absolute one.
+pub fn absolutize(entry: &Entry, db_dir: &Path) -> Entry {
+    let directory = paths::resolve(&to_string(db_dir), &entry.directory);
+    let mut result = map_paths(entry, &directory, |dir, path| {
+        to_string(&paths::resolve(&to_string(dir), path))
+    });
+    result.directory = to_string(&directory);
+    result
+}
+
+/// Replaces the prefix `old` of every absolute path of `entry` by `new` for each pair of
+/// `prefixes`, the first one matching winning.
+pub fn remap(entry: &Entry, prefixes: &[(PathBuf, PathBuf)]) -> Entry {
+    let apply = |path: &str| {
+        let path = Path::new(path);
+        prefixes
+            .iter()
+            .find_map(|(old, new)| path.strip_prefix(old).ok().map(|rest| new.join(rest)))
+            .map_or_else(|| to_string(path), |p| to_string(&p))
+    };
+    let mut result = map_paths(entry, Path::new(&entry.directory), |_, path| apply(path));
+    result.directory = apply(&entry.directory);
+    result
+}
+
+/// Turns `entry` into its machine-independent form: the compiler is recorded by name only, the
+/// paths below `root` are relative (see `relativize`), and other absolute paths are remapped by
+/// `prefixes`.
+fn deterministic(
+    entry: &Entry,
+    db_dir: &Path,
+    root: &Path,
+    prefixes: &[(PathBuf, PathBuf)],
+) -> Entry {
+    let mut result = remap(&relativize(entry, db_dir, root), prefixes);
+    if let Some(compiler) = result.arguments.first_mut() {
+        if let Some(name) = Path::new(compiler.as_str()).file_name() {
+            *compiler = name.to_string_lossy().into_owned();
+        }
+    }
+    result
+}
+
+/// Options whose value names a directory searched for headers or a header included implicitly.
+const INCLUDE_FLAGS: &[&str] = &[
+    "-I",
+    "-include",
+    "-imacros",
+    "-isystem",
+    "-iquote",
+    "-idirafter",
+];
+
+/// Resolves the relative values of the include options of `arguments` (the compiler first)
+/// against `directory`, keeping their spelling otherwise.  Values relative to the sysroot, i.e.,
+/// starting with `=`, are kept as well.
+pub fn absolutize_includes(arguments: &[String], directory: &str) -> Vec<String> {
+    let (compiler, rest) = match arguments.split_first() {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+    let mut result = vec![compiler.clone()];
+    for flag in args::flags(rest) {
+        match &flag.value {
+            Some(value)
+                if INCLUDE_FLAGS.contains(&flag.name.as_str())
+                    && !value.starts_with('=')
+                    && Path::new(value).is_relative() =>
+            {
+                result.extend(flag.with_path(&to_string(&paths::resolve(directory, value))));
+            }
+            _ => result.extend(flag.args),
+        }
+    }
+    result
+}
+
+/// Options whose joined value is a path besides the ones of `args::PATH_VALUED`.
+const PATH_VALUED_EQ: &[&str] = &[
+    "-fsanitize-ignorelist=",
+    "-fsanitize-blacklist=",
+    "-fprofile-use=",
+    "-fprofile-instr-use=",
+    "-fprofile-sample-use=",
+    "-fprofile-list=",
+    "-fcoverage-data-file=",
+    "-fmodule-file=",
+    "-fmodule-output=",
+    "-fmodule-mapper=",
+    "-fmodules-cache-path=",
+    "-fprebuilt-module-path=",
+    "-ivfsoverlay=",
+];
+
+/// Options passing options to other tools, whose paths cannot be told apart.
+const PASS_THROUGH: &[&str] = &[
+    "-Wp,",
+    "-Wa,",
+    "-Wl,",
+    "-Xclang",
+    "-Xpreprocessor",
+    "-Xassembler",
+    "-Xlinker",
+    "-mllvm",
+];
+
+/// Rewrites the relative paths of `arguments` (the compiler first), which are relative to
+/// `directory`, to be relative to `root` instead, e.g. for an entry whose directory is `root`.
+/// The path of a response file `@FILE` is rewritten as well.  Returns `None` if an argument may
+/// refer to a relative path which cannot be rewritten, i.e., passes options through to another
+/// tool or is an unknown option with a value naming an existing file, like `-fopt=file.txt`.
+pub fn rebase(arguments: &[String], directory: &str, root: &Path) -> Option<Vec<String>> {
+    let (compiler, rest) = arguments.split_first()?;
+    let rebase = |path: &str| {
+        let resolved = paths::resolve(directory, path);
+        to_string(&paths::relative(&resolved, root))
+    };
+    let relative =
+        |path: &str| path != "-" && !path.starts_with('=') && Path::new(path).is_relative();
+    let mut result = vec![compiler.clone()];
+    for flag in args::flags(rest) {
+        let arg = &flag.args[0];
+        if PASS_THROUGH.iter().any(|p| arg.starts_with(p)) {
+            return None;
+        }
+        if let Some((option, value)) = PATH_VALUED_EQ
+            .iter()
+            .find_map(|o| arg.strip_prefix(o).map(|value| (o, value)))
+        {
+            match relative(value) {
+                true => result.push(format!("{}{}", option, rebase(value))),
+                false => result.push(arg.clone()),
+            }
+            continue;
+        }
+        match flag.path() {
+            Some(path) if path.starts_with('@') => result.push(format!("@{}", rebase(&path[1..]))),
+            Some(path) if relative(path) => result.extend(flag.with_path(&rebase(path))),
+            Some(_) => result.extend(flag.args),
+            None => {
+                let value = arg.split_once('=').map(|(_, value)| value);
+                if value.map_or(false, |v| {
+                    relative(v) && !v.is_empty() && paths::resolve(directory, v).is_file()
+                }) {
+                    return None;
+                }
+                result.extend(flag.args);
+            }
+        }
+    }
+    Some(result)
+}
+
+/// Undoes the rewriting of `ccache` with a base directory, i.e., resolves the relative paths of
+/// `arguments` (the compiler first) against `directory` if they point below `basedir`, since
+/// `ccache` relativizes exactly those.  Paths outside of `basedir` are kept as given.
+pub fn restore_basedir(arguments: &[String], directory: &str, basedir: &Path) -> Vec<String> {
+    let (compiler, rest) = match arguments.split_first() {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+    let mut result = vec![compiler.clone()];
+    for flag in args::flags(rest) {
+        match flag
+            .path()
+            .map(|path| (path, paths::resolve(directory, path)))
+        {
+            Some((path, resolved))
+                if Path::new(path).is_relative() && resolved.starts_with(basedir) =>
+            {
+                result.extend(flag.with_path(&to_string(&resolved)));
+            }
+            _ => result.extend(flag.args),
+        }
+    }
+    result
+}
+
+fn run<F>(args: &[String], name: &str, f: F) -> Result<(), Box<dyn Error>>
+where
+    F: Fn(&Entry, &Path, &Path) -> Entry,
+{
+    let opts = Options::parse(args, &["--dry-run", "-n"], &["--db", "--root"])?;
+    let json_path = opts.database();
+    let db_dir = paths::normalize(&env::current_dir()?.join(&json_path))
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("/"));
+    let root = match opts.value("--root") {
+        Some(root) => paths::normalize(&env::current_dir()?.join(root)),
+        None => db_dir.clone(),
+    };
+    let convert = |entries: &BTreeSet<Entry>| -> BTreeSet<Entry> {
+        entries.iter().map(|e| f(e, &db_dir, &root)).collect()
+    };
+
+    if opts.has("--dry-run") || opts.has("-n") {
+        let entries = db::read_entries(&json_path)?;
+        let mut stdout = io::stdout();
+        let mut count = 0;
+        for old in &entries {
+            let new = f(old, &db_dir, &root);
+            if &new != old {
+                diff::write_entry_diff(&mut stdout, old, &new, diff::color_enabled(1))?;
+                count += 1;
+            }
+        }
+        println!("{} entries would be changed", count);
+    } else {
+        db::update_any(&json_path, |entries| {
+            meta::note_remap(&json_path, &format!("{} --root {}", name, root.display()))?;
+            Ok(convert(entries))
+        })?;
+    }
+    Ok(())
+}
+
+pub fn main_relativize(args: &[String]) -> Result<(), Box<dyn Error>> {
+    run(args, "relativize", relativize)
+}
+
+pub fn main_absolutize(args: &[String]) -> Result<(), Box<dyn Error>> {
+    run(args, "absolutize", |e, db_dir, _| absolutize(e, db_dir))
+}
+
+/// Parses prefix maps of the form `OLD=NEW`.
+pub fn parse_prefix_maps(maps: &[&str]) -> Result<Vec<(PathBuf, PathBuf)>, Box<dyn Error>> {
+    maps.iter()
+        .map(|map| {
+            map.split_once('=')
+                .map(|(old, new)| (PathBuf::from(old), PathBuf::from(new)))
+                .ok_or_else(|| format!("invalid prefix map '{}', expected OLD=NEW", map).into())
+        })
+        .collect()
+}
+
+/// Returns the entries of the database at `json_path` (absolute) in the machine-independent form,
+/// e.g. for writing them into `out_dir`, serialized such that equal databases give equal bytes.
+pub fn artifact(
+    json_path: &Path,
+    out_dir: &Path,
+    root: &Path,
+    prefixes: &[(PathBuf, PathBuf)],
+) -> Result<String, Box<dyn Error>> {
+    let db_dir = json_path.parent().unwrap_or_else(|| Path::new("/"));
+    let entries: BTreeSet<Entry> = db::read_entries(json_path)?
+        .iter()
+        .map(|e| deterministic(&absolutize(e, db_dir), out_dir, root, prefixes))
+        .collect();
+    Ok(serde_json::to_string_pretty(&entries)? + "\n")
+}
+
+pub fn main_export_artifact(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &[], &["--db", "--root", "--prefix-map", "-o"])?;
+    let cwd = env::current_dir()?;
+    let json_path = paths::normalize(&cwd.join(opts.database()));
+    let output = opts.value("-o").map(|o| paths::normalize(&cwd.join(o)));
+    let db_dir = json_path.parent().unwrap_or_else(|| Path::new("/"));
+    // Directories are relative to the file written.
+    let out_dir = match &output {
+        Some(output) => output.parent().unwrap_or_else(|| Path::new("/")),
+        None => db_dir,
+    };
+    let root = match opts.value("--root") {
+        Some(root) => paths::normalize(&cwd.join(root)),
+        None => db_dir.to_path_buf(),
+    };
+    let prefixes = parse_prefix_maps(&opts.values("--prefix-map"))?;
+    let data = artifact(&json_path, out_dir, &root, &prefixes)?;
+    match output {
+        Some(output) => std::fs::write(output, data)?,
+        None => print!("{}", data),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn absolutize_includes() {
+        let arguments = strings(&[
+            "cc",
+            "-Iinc",
+            "-include",
+            "../config.h",
+            "-iquote=sys",
+            "-isystem",
+            "/usr/include",
+            "-c",
+            "a.c",
+        ]);
+        assert_eq!(
+            super::absolutize_includes(&arguments, "/proj/build"),
+            [
+                "cc",
+                "-I/proj/build/inc",
+                "-include",
+                "/proj/config.h",
+                "-iquote=sys",
+                "-isystem",
+                "/usr/include",
+                "-c",
+                "a.c",
+            ]
+        );
+    }
+
+    #[test]
+    fn restore_basedir() {
+        let arguments = strings(&[
+            "cc",
+            "-I../inc",
+            "-I../../opt/inc",
+            "-c",
+            "../src/a.c",
+            "-o",
+            "a.o",
+        ]);
+        assert_eq!(
+            super::restore_basedir(&arguments, "/proj/build", Path::new("/proj")),
+            [
+                "cc",
+                "-I/proj/inc",
+                "-I../../opt/inc",
+                "-c",
+                "/proj/src/a.c",
+                "-o",
+                "/proj/build/a.o",
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let entry = Entry {
+            directory: "/proj/build".to_string(),
+            file: "/proj/src/a.c".to_string(),
+            arguments: strings(&[
+                "cc",
+                "-I/proj/include",
+                "-isystem",
+                "/usr/include/foo",
+                "-o",
+                "/proj/build/a.o",
+                "-c",
+                "/proj/src/a.c",
+            ]),
+            output: Some("/proj/build/a.o".to_string()),
+        };
+        let db_dir = Path::new("/proj");
+
+        let rel = relativize(&entry, db_dir, db_dir);
+        assert_eq!(rel.directory, "build");
+        assert_eq!(rel.file, "../src/a.c");
+        assert_eq!(rel.output.as_deref(), Some("a.o"));
+        assert_eq!(
+            rel.arguments,
+            [
+                "cc",
+                "-I../include",
+                "-isystem",
+                "/usr/include/foo",
+                "-o",
+                "a.o",
+                "-c",
+                "../src/a.c"
+            ]
+        );
+
+        assert_eq!(absolutize(&rel, db_dir), entry);
+    }
+
+    #[test]
+    fn deterministic() {
+        let entry = Entry {
+            directory: "/home/ci/src/build".to_string(),
+            file: "../a.c".to_string(),
+            arguments: strings(&["/usr/bin/gcc", "-I/opt/sdk-1.2/include", "-c", "../a.c"]),
+            output: Some("/home/ci/src/build/a.o".to_string()),
+        };
+        let prefixes = [(PathBuf::from("/opt/sdk-1.2"), PathBuf::from("/sdk"))];
+        let result = super::deterministic(
+            &entry,
+            Path::new("/home/ci/src"),
+            Path::new("/home/ci/src"),
+            &prefixes,
+        );
+        assert_eq!(
+            result,
+            Entry {
+                directory: "build".to_string(),
+                file: "../a.c".to_string(),
+                arguments: strings(&["gcc", "-I/sdk/include", "-c", "../a.c"]),
+                output: Some("a.o".to_string()),
+            }
+        );
+    }
+}
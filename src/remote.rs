@@ -0,0 +1,256 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::git;
+use crate::output;
+use crate::paths;
+use crate::relocate;
+
+const USAGE: &str =
+    "usage: cdbgen cache [--db DB] [--backend URL] [--key KEY] [--prefix-map OLD=NEW]... push | pull";
+
+/// Where databases are shared, given as `--backend` or `CDBGEN_REMOTE_CACHE`.
+enum Backend {
+    /// A directory, e.g. on a network share, given as a path or `file://` URL.
+    Dir(PathBuf),
+    /// An S3-compatible bucket, or any server accepting `PUT`, given as `http(s)://` URL of the
+    /// prefix under which the objects are stored.  Requests are signed if `AWS_ACCESS_KEY_ID`
+    /// is set.
+    Http(String),
+}
+
+impl Backend {
+    fn parse(url: &str) -> Backend {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            Backend::Http(url.trim_end_matches('/').to_string())
+        } else {
+            Backend::Dir(PathBuf::from(url.strip_prefix("file://").unwrap_or(url)))
+        }
+    }
+
+    /// Runs `curl` for the object `name`, uploading `data` if given, and returns what it printed.
+    /// The credentials are passed as config on stdin such that they do not show up in the
+    /// arguments of the process, i.e., `data` is passed by a temporary file.
+    fn curl(url: &str, name: &str, data: Option<&str>) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut command = Command::new("curl");
+        command.args([
+            "--fail",
+            "--silent",
+            "--show-error",
+            "--location",
+            "--config",
+            "-",
+        ]);
+        let mut config = String::new();
+        if let (Ok(id), Ok(secret)) = (
+            env::var("AWS_ACCESS_KEY_ID"),
+            env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            let region = env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+            command
+                .arg("--aws-sigv4")
+                .arg(format!("aws:amz:{}:s3", region));
+            config += &format!("user = {}\n", quote(&format!("{}:{}", id, secret)));
+            if let Ok(token) = env::var("AWS_SESSION_TOKEN") {
+                let header = format!("x-amz-security-token: {}", token);
+                config += &format!("header = {}\n", quote(&header));
+            }
+        }
+        let upload = env::temp_dir().join(format!("cdbgen-upload-{}", std::process::id()));
+        if let Some(data) = data {
+            // Created anew such that a file planted in the shared directory is not written.
+            let mut file = fs::File::options()
+                .write(true)
+                .create_new(true)
+                .open(&upload)
+                .map_err(|e| format!("{}: {}", upload.display(), e))?;
+            file.write_all(data.as_bytes())?;
+            command.arg("--upload-file").arg(&upload);
+        }
+        let output = command
+            .arg(format!("{}/{}", url, name))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("cannot run curl: {}", e).into())
+            .and_then(|mut child| {
+                let mut stdin = child.stdin.take().unwrap();
+                stdin.write_all(config.as_bytes())?;
+                drop(stdin);
+                Ok::<_, Box<dyn Error>>(child.wait_with_output()?)
+            });
+        if data.is_some() {
+            let _ = fs::remove_file(&upload);
+        }
+        let output = output?;
+        if !output.status.success() {
+            return Err(format!(
+                "{}/{}: {}",
+                url,
+                name,
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            )
+            .into());
+        }
+        Ok(output.stdout)
+    }
+
+    fn put(&self, name: &str, data: &str) -> Result<(), Box<dyn Error>> {
+        match self {
+            Backend::Dir(dir) => {
+                fs::create_dir_all(dir)?;
+                // Readers never see a partial database.
+                let tmp = dir.join(format!(".{}.{}", name, std::process::id()));
+                fs::write(&tmp, data)?;
+                fs::rename(&tmp, dir.join(name))?;
+                Ok(())
+            }
+            Backend::Http(url) => Self::curl(url, name, Some(data)).map(|_| ()),
+        }
+    }
+
+    fn get(&self, name: &str) -> Result<String, Box<dyn Error>> {
+        match self {
+            Backend::Dir(dir) => {
+                let path = dir.join(name);
+                fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e).into())
+            }
+            Backend::Http(url) => Ok(String::from_utf8(Self::curl(url, name, None)?)?),
+        }
+    }
+}
+
+/// Quotes `value` for a config file of curl.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Returns the name of the object of the database named `database` for `key`.
+fn object(database: &Path, key: &str) -> String {
+    let stem = database
+        .file_stem()
+        .map_or_else(|| "compile_commands".into(), |s| s.to_string_lossy());
+    format!("{}-{}.json", stem, key)
+}
+
+/// Uploads the database at `json_path` (absolute) in the machine-independent form of
+/// `cdbgen export-artifact`.
+fn push(
+    backend: &Backend,
+    json_path: &Path,
+    key: &str,
+    prefixes: &[(PathBuf, PathBuf)],
+) -> Result<(), Box<dyn Error>> {
+    let db_dir = json_path.parent().unwrap_or_else(|| Path::new("/"));
+    let data = relocate::artifact(json_path, db_dir, db_dir, prefixes)?;
+    backend.put(&object(json_path, key), &data)
+}
+
+/// Downloads the database for `key` and replaces the one at `json_path` (absolute) by it with its
+/// paths made absolute again and the prefix maps `prefixes` undone.  Returns the number of
+/// entries.
+fn pull(
+    backend: &Backend,
+    json_path: &Path,
+    key: &str,
+    prefixes: &[(PathBuf, PathBuf)],
+) -> Result<usize, Box<dyn Error>> {
+    let data = backend.get(&object(json_path, key))?;
+    let db_dir = json_path.parent().unwrap_or_else(|| Path::new("/"));
+    let reversed: Vec<_> = prefixes
+        .iter()
+        .map(|(old, new)| (new.clone(), old.clone()))
+        .collect();
+    let entries: Vec<Entry> = serde_json::from_str(&data)?;
+    let count = entries.len();
+    db::update_any(json_path, |_| {
+        Ok(entries
+            .iter()
+            .map(|e| relocate::remap(&relocate::absolutize(e, db_dir), &reversed))
+            .collect())
+    })?;
+    Ok(count)
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &[], &["--db", "--backend", "--key", "--prefix-map"])?;
+    let backend = opts
+        .value("--backend")
+        .map(String::from)
+        .or_else(|| env::var("CDBGEN_REMOTE_CACHE").ok())
+        .ok_or("no backend given by --backend or CDBGEN_REMOTE_CACHE")?;
+    let backend = Backend::parse(&backend);
+    let json_path = paths::normalize(&env::current_dir()?.join(opts.database()));
+    let key = match opts.value("--key") {
+        Some(key) => key.to_string(),
+        None => git::run(Path::new("."), &["rev-parse", "HEAD"])
+            .map_err(|_| "no --key given and no git commit to default to")?,
+    };
+    // The key names an object next to the others, not a path.
+    if key.is_empty() || key.contains(&['/', '\\'][..]) || key.contains("..") {
+        return Err(format!("invalid key '{}'", key).into());
+    }
+    let prefixes = relocate::parse_prefix_maps(&opts.values("--prefix-map"))?;
+    match opts.positional.as_slice() {
+        [command] if command == "push" => {
+            push(&backend, &json_path, &key, &prefixes)?;
+            output::info(&format!("pushed {} for {}", json_path.display(), key));
+        }
+        [command] if command == "pull" => {
+            let count = pull(&backend, &json_path, &key, &prefixes)?;
+            output::info(&format!(
+                "pulled {} entries for {} into {}",
+                count,
+                key,
+                json_path.display()
+            ));
+        }
+        _ => return Err(USAGE.into()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_and_pull() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let backend = Backend::parse(&format!("file://{}", temp.path().join("remote").display()));
+        let entry = |directory: &Path| {
+            let arguments = ["/usr/bin/cc", "-I/opt/sdk/include", "a.c"];
+            db::entry(directory.to_str().unwrap(), "a.c", &arguments)
+        };
+        let prefixes = [(PathBuf::from("/opt/sdk"), PathBuf::from("/sdk"))];
+
+        let here = temp.path().join("here");
+        fs::create_dir(&here).unwrap();
+        let json_path = here.join("compile_commands.json");
+        fs::write(&json_path, serde_json::to_string(&[entry(&here)]).unwrap()).unwrap();
+        push(&backend, &json_path, "abc", &prefixes).unwrap();
+        assert!(temp
+            .path()
+            .join("remote/compile_commands-abc.json")
+            .exists());
+
+        let there = temp.path().join("there");
+        fs::create_dir(&there).unwrap();
+        let json_path = there.join("compile_commands.json");
+        assert_eq!(pull(&backend, &json_path, "abc", &prefixes).unwrap(), 1);
+        let entries = db::read_entries(&json_path).unwrap();
+        let pulled = entries.iter().next().unwrap();
+        assert_eq!(pulled.directory, there.to_str().unwrap());
+        assert_eq!(pulled.arguments[..2], ["cc", "-I/opt/sdk/include"]);
+        assert_eq!(pulled.file, there.join("a.c").to_str().unwrap());
+        assert!(pull(&backend, &json_path, "def", &prefixes).is_err());
+        assert_eq!(quote(r#"id:se"c\ret"#), r#""id:se\"c\\ret""#);
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use crate::config::Config;
+
+/// Returns the database resource compiles are recorded into, i.e., `resource_commands.json` next
+/// to the database of `config`.
+pub fn database(config: &Config) -> PathBuf {
+    config.database.with_file_name("resource_commands.json")
+}
+
+thread_local! {
+    /// Names of resource compilers as matched by `is_resource_compiler`, compiled once since
+    /// imports match it against every execution.
+    static RESOURCE_COMPILER: Regex =
+        Regex::new(r"^((.*-)?windres|rc|llvm-rc(-[0-9.]+)?)(\.exe)?$").unwrap();
+}
+
+/// Returns true if `program` names a resource compiler for Windows resource scripts, i.e.,
+/// `windres` (possibly with a target prefix), `rc.exe`, or `llvm-rc`.
+pub fn is_resource_compiler(program: &str) -> bool {
+    let name = Path::new(program)
+        .file_name()
+        .map_or_else(String::new, |n| n.to_string_lossy().to_lowercase());
+    RESOURCE_COMPILER.with(|re| re.is_match(&name))
+}
+
+fn is_script(arg: &str) -> bool {
+    arg.to_lowercase().ends_with(".rc")
+}
+
+/// Returns the resource scripts compiled by a resource compiler invocation with arguments `args`
+/// (without the compiler), mapped to the output.  Both the GNU style of `windres`, e.g.
+/// `-i app.rc -o app.o`, and the style of `rc.exe`, e.g. `/fo app.res app.rc`, are understood.
+pub fn scripts(args: &[String]) -> BTreeMap<String, Option<String>> {
+    let mut scripts = Vec::new();
+    let mut output = None;
+    let mut positional = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let lower = arg.to_lowercase();
+        if arg == "-o" || arg == "--output" || lower == "/fo" || lower == "-fo" {
+            output = iter.next().cloned();
+        } else if arg == "-i" || arg == "--input" {
+            scripts.extend(iter.next().cloned());
+        } else if let Some(o) = arg.strip_prefix("--output=") {
+            output = Some(o.to_string());
+        } else if let Some(i) = arg.strip_prefix("--input=") {
+            scripts.push(i.to_string());
+        } else if lower.starts_with("/fo") || lower.starts_with("-fo") {
+            output = Some(arg[3..].to_string());
+        } else if matches!(
+            arg.as_str(),
+            "-I" | "--include-dir"
+                | "-D"
+                | "--define"
+                | "-U"
+                | "--undefine"
+                | "-O"
+                | "--output-format"
+                | "-J"
+                | "--input-format"
+                | "-F"
+                | "--target"
+                | "--preprocessor"
+                | "-c"
+                | "--codepage"
+                | "-l"
+                | "--language"
+        ) || matches!(
+            lower.as_str(),
+            "/i" | "-i" | "/d" | "/u" | "/c" | "/l" | "/fm"
+        ) {
+            iter.next();
+        } else if !arg.starts_with('-') && !arg.starts_with('/') || is_script(arg) {
+            positional.push(arg.clone());
+        }
+    }
+    // Without `-i` windres takes the input and the output as positional arguments.
+    let mut positional = positional.into_iter();
+    if scripts.is_empty() {
+        scripts.extend(positional.next());
+    }
+    if output.is_none() {
+        output = positional.next();
+    }
+    scripts
+        .into_iter()
+        .filter(|s| is_script(s))
+        .map(|s| (s, output.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    fn collect(args: &[&str]) -> Vec<(String, Option<String>)> {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        super::scripts(&args).into_iter().collect()
+    }
+
+    #[test]
+    fn scripts() {
+        let expected = |s: &str, o: &str| vec![(s.to_string(), Some(o.to_string()))];
+        assert_eq!(
+            collect(&["-I", "inc", "-i", "app.rc", "-o", "app.o"]),
+            expected("app.rc", "app.o")
+        );
+        assert_eq!(
+            collect(&["--include-dir", "inc", "app.rc", "app.o"]),
+            expected("app.rc", "app.o")
+        );
+        assert_eq!(
+            collect(&["/nologo", "/I", "inc", "/foapp.res", "App.RC"]),
+            expected("App.RC", "app.res")
+        );
+        assert_eq!(
+            collect(&["/fo", "out\\app.res", "C:\\src\\app.rc"]),
+            expected("C:\\src\\app.rc", "out\\app.res")
+        );
+        assert!(collect(&["--version"]).is_empty());
+        assert!(super::is_resource_compiler("x86_64-w64-mingw32-windres"));
+        assert!(super::is_resource_compiler("RC.EXE"));
+        assert!(!super::is_resource_compiler("rcc"));
+    }
+}
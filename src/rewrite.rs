@@ -0,0 +1,204 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::io;
+
+use crate::args;
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::diff;
+use crate::paths;
+use crate::pattern::Matcher;
+
+enum Edit {
+    Remove(Matcher),
+    Replace(Matcher, Vec<String>),
+    AddBefore(Vec<String>),
+    AddAfter(Vec<String>),
+}
+
+fn split_words(s: &str) -> Vec<String> {
+    s.split_whitespace().map(str::to_string).collect()
+}
+
+/// Splits a replacement `OLD=NEW` into its two halves.  Since flags themselves often contain a
+/// `=` the split happens at the first `=` which is followed by a dash, e.g.,
+/// `-std=c++14=-std=c++17` is split into `-std=c++14` and `-std=c++17`.
+fn split_replacement(spec: &str) -> Result<(&str, &str), Box<dyn Error>> {
+    let pos = spec
+        .find("=-")
+        .or_else(|| spec.rfind('='))
+        .ok_or_else(|| format!("replacement '{}' is not of the form OLD=NEW", spec))?;
+    Ok((&spec[..pos], &spec[pos + 1..]))
+}
+
+fn matches(matcher: &Matcher, flag: &args::Flag) -> bool {
+    matcher.is_match(&flag.joined()) || matcher.is_match(&flag.separate())
+}
+
+/// Applies all edits in the order given to the arguments of an entry.  The compiler, i.e., the
+/// first argument, is never touched.
+fn apply(edits: &[Edit], arguments: &[String]) -> Vec<String> {
+    let (compiler, mut rest) = match arguments.split_first() {
+        Some((compiler, rest)) => (compiler.clone(), rest.to_vec()),
+        None => return Vec::new(),
+    };
+    for edit in edits {
+        rest = match edit {
+            Edit::Remove(m) => args::flags(&rest)
+                .into_iter()
+                .filter(|f| !matches(m, f))
+                .flat_map(|f| f.args)
+                .collect(),
+            Edit::Replace(m, new) => args::flags(&rest)
+                .into_iter()
+                .flat_map(|f| if matches(m, &f) { new.clone() } else { f.args })
+                .collect(),
+            Edit::AddBefore(new) => new.iter().chain(&rest).cloned().collect(),
+            Edit::AddAfter(new) => rest.iter().chain(new).cloned().collect(),
+        };
+    }
+    std::iter::once(compiler).chain(rest).collect()
+}
+
+fn rewrite(entries: &BTreeSet<Entry>, edits: &[Edit], files: &[Matcher]) -> Vec<(Entry, Entry)> {
+    entries
+        .iter()
+        .filter(|e| {
+            let path = paths::resolve(&e.directory, &e.file);
+            files.is_empty() || files.iter().any(|m| m.is_match(&path.to_string_lossy()))
+        })
+        .filter_map(|e| {
+            let arguments = apply(edits, &e.arguments);
+            if arguments == e.arguments {
+                None
+            } else {
+                let new = Entry {
+                    arguments,
+                    ..e.clone()
+                };
+                Some((e.clone(), new))
+            }
+        })
+        .collect()
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(
+        args,
+        &["--dry-run", "-n", "--regex", "-E"],
+        &[
+            "--db",
+            "--file",
+            "--remove",
+            "--replace",
+            "--add-before",
+            "--add-after",
+        ],
+    )?;
+    let regex = opts.has("--regex") || opts.has("-E");
+
+    // Edits are applied in the order in which they occur on the command line.
+    let mut edits = Vec::new();
+    for (name, value) in opts.ordered(&["--remove", "--replace", "--add-before", "--add-after"]) {
+        edits.push(match name {
+            "--remove" => Edit::Remove(Matcher::new(value, regex)?),
+            "--replace" => {
+                let (old, new) = split_replacement(value)?;
+                Edit::Replace(Matcher::new(old, regex)?, split_words(new))
+            }
+            "--add-before" => Edit::AddBefore(split_words(value)),
+            _ => Edit::AddAfter(split_words(value)),
+        });
+    }
+    if edits.is_empty() {
+        return Err("usage: cdbgen rewrite-flags [--dry-run] [--file GLOB] \
+                    [--remove FLAG] [--replace OLD=NEW] [--add-before FLAGS] [--add-after FLAGS]"
+            .into());
+    }
+    let files = opts
+        .values("--file")
+        .into_iter()
+        .map(|g| Matcher::new(g, false))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let json_path = opts.database();
+    if opts.has("--dry-run") || opts.has("-n") {
+        let changes = rewrite(&db::read_entries(&json_path)?, &edits, &files);
+        let mut stdout = io::stdout();
+        for (old, new) in &changes {
+            diff::write_entry_diff(&mut stdout, old, new, diff::color_enabled(1))?;
+        }
+        println!("{} entries would be changed", changes.len());
+    } else {
+        let mut count = 0;
+        db::update_any(&json_path, |entries| {
+            let changes = rewrite(entries, &edits, &files);
+            count = changes.len();
+            let mut entries = entries.clone();
+            for (old, new) in changes {
+                entries.remove(&old);
+                entries.insert(new);
+            }
+            Ok(entries)
+        })?;
+        println!("{} entries changed", count);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn split_replacement() {
+        assert_eq!(
+            super::split_replacement("-std=c++14=-std=c++17").unwrap(),
+            ("-std=c++14", "-std=c++17")
+        );
+        assert_eq!(super::split_replacement("-O2=-O3").unwrap(), ("-O2", "-O3"));
+        assert!(super::split_replacement("-O2").is_err());
+    }
+
+    #[test]
+    fn apply() {
+        let edits = [
+            Edit::Remove(Matcher::new("-Werror", false).unwrap()),
+            Edit::Replace(
+                Matcher::new("-std=c++14", false).unwrap(),
+                strings(&["-std=c++17"]),
+            ),
+            Edit::Remove(Matcher::new("-D OLD*", false).unwrap()),
+            Edit::AddBefore(strings(&["-I", "foo"])),
+            Edit::AddAfter(strings(&["-g"])),
+        ];
+        let arguments = strings(&[
+            "c++",
+            "-Werror",
+            "-std=c++14",
+            "-D",
+            "OLD_API",
+            "-DNEW",
+            "a.cc",
+        ]);
+        assert_eq!(
+            super::apply(&edits, &arguments),
+            ["c++", "-I", "foo", "-std=c++17", "-DNEW", "a.cc", "-g"]
+        );
+    }
+
+    #[test]
+    fn rewrite() {
+        let entry = |file: &str| db::entry("/src", file, &["cc", "-Werror", file]);
+        let entries: BTreeSet<_> = [entry("a/x.c"), entry("b/y.c")].into_iter().collect();
+        let edits = [Edit::Remove(Matcher::new("-Werror", false).unwrap())];
+        let files = [Matcher::new("/src/a/*", false).unwrap()];
+        let changes = super::rewrite(&entries, &edits, &files);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].1.arguments, ["cc", "a/x.c"]);
+    }
+}
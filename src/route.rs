@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::db::Invocation;
+use crate::paths;
+
+/// Returns the root of the project `file` (absolute and normalized) belongs to.  Configured roots
+/// take precedence over discovered ones and the innermost root wins.
+fn root_of(roots: &[PathBuf], markers: &[String], file: &Path) -> Option<PathBuf> {
+    let configured = roots
+        .iter()
+        .filter(|r| file.starts_with(r))
+        .max_by_key(|r| r.components().count());
+    if let Some(root) = configured {
+        return Some(root.clone());
+    }
+    if markers.is_empty() {
+        return None;
+    }
+    file.ancestors()
+        .skip(1)
+        .find(|dir| markers.iter().any(|m| dir.join(m).exists()))
+        .map(Path::to_path_buf)
+}
+
+/// Splits `invocation` by the databases its files are routed to.  Without roots everything goes
+/// into the database of `config`.
+pub fn split(config: &Config, invocation: &Invocation) -> Vec<(PathBuf, Invocation)> {
+    if config.roots.is_empty() && config.markers.is_empty() {
+        return vec![(config.database.clone(), invocation.clone())];
+    }
+    let roots: Vec<PathBuf> = config
+        .roots
+        .iter()
+        .map(|r| paths::resolve(&invocation.directory, &r.to_string_lossy()))
+        .collect();
+    let name = config
+        .database
+        .file_name()
+        .map_or_else(|| "compile_commands.json".into(), |n| n.to_os_string());
+
+    let mut split: BTreeMap<PathBuf, Invocation> = BTreeMap::new();
+    for (file, output) in &invocation.files {
+        let path = paths::resolve(&invocation.directory, file);
+        let database = match root_of(&roots, &config.markers, &path) {
+            Some(root) => root.join(&name),
+            None => config.database.clone(),
+        };
+        split
+            .entry(database)
+            .or_insert_with(|| Invocation {
+                files: BTreeMap::new(),
+                ..invocation.clone()
+            })
+            .files
+            .insert(file.clone(), output.clone());
+    }
+    split.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    #[test]
+    fn root_of() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("a/.git").create_dir_all().unwrap();
+        temp.child("a/sub/.cdbgen-root").touch().unwrap();
+        let markers = [".git".to_string(), ".cdbgen-root".to_string()];
+        let file = |f: &str| temp.path().join(f);
+
+        assert_eq!(
+            super::root_of(&[], &markers, &file("a/src/x.c")),
+            Some(file("a"))
+        );
+        assert_eq!(
+            super::root_of(&[], &markers, &file("a/sub/y.c")),
+            Some(file("a/sub"))
+        );
+        assert_eq!(super::root_of(&[], &markers, &file("b/z.c")), None);
+        assert_eq!(
+            super::root_of(&[file("a/src")], &markers, &file("a/src/x.c")),
+            Some(file("a/src"))
+        );
+        assert_eq!(super::root_of(&[file("a/src")], &[], &file("a/x.c")), None);
+    }
+}
@@ -0,0 +1,234 @@
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::hash;
+use crate::output;
+use crate::paths;
+use crate::pattern;
+use crate::stats;
+
+const INDEX: &str = "\
+GET /entries                  all entries
+GET /entries?file=GLOB        entries whose file matches GLOB
+GET /entries?directory=DIR    entries of files below the absolute directory DIR
+GET /stats                    summary as by 'cdbgen stats --json'
+";
+
+/// A response to a request.
+#[derive(Debug, PartialEq)]
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    etag: Option<String>,
+    body: String,
+}
+
+impl Response {
+    fn new(status: &'static str, body: String) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            etag: None,
+            body,
+        }
+    }
+
+    /// Returns the JSON `body` with an entity tag derived from it, or `304 Not Modified` if the
+    /// client has it already.
+    fn json(body: String, if_none_match: Option<&str>) -> Self {
+        let etag = format!("\"{:016x}\"", hash::fnv1a(body.as_bytes()));
+        if if_none_match.map_or(false, |tags| {
+            tags.split(',').any(|t| t.trim() == etag || t.trim() == "*")
+        }) {
+            return Response {
+                etag: Some(etag),
+                ..Response::new("304 Not Modified", String::new())
+            };
+        }
+        Response {
+            status: "200 OK",
+            content_type: "application/json",
+            etag: Some(etag),
+            body,
+        }
+    }
+}
+
+/// Decodes a component of a query string, i.e., `+` and `%XX` escapes.
+fn decode(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match component
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 2;
+                }
+                None => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Returns the value of the parameter `name` of the query string `query`.
+fn parameter(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| decode(value))
+}
+
+/// Answers the request for `target` with the database at `json_path`.
+fn respond(
+    json_path: &Path,
+    method: &str,
+    target: &str,
+    if_none_match: Option<&str>,
+) -> Result<Response, Box<dyn Error>> {
+    if method != "GET" && method != "HEAD" {
+        return Ok(Response::new(
+            "405 Method Not Allowed",
+            "read-only\n".to_string(),
+        ));
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    match path {
+        "/" => Ok(Response::new("200 OK", INDEX.to_string())),
+        "/entries" => {
+            let file = parameter(query, "file");
+            let directory = parameter(query, "directory").map(PathBuf::from);
+            let entries = db::read_entries(json_path)?;
+            let entries: Vec<&Entry> = entries
+                .iter()
+                .filter(|e| {
+                    let resolved = paths::resolve(&e.directory, &e.file);
+                    directory.as_ref().map_or(true, |d| resolved.starts_with(d))
+                        && file.as_ref().map_or(true, |f| {
+                            pattern::glob_match(f, &e.file)
+                                || pattern::glob_match(f, &resolved.to_string_lossy())
+                        })
+                })
+                .collect();
+            Ok(Response::json(
+                serde_json::to_string_pretty(&entries)? + "\n",
+                if_none_match,
+            ))
+        }
+        "/stats" => Ok(Response::json(
+            stats::to_json(json_path)? + "\n",
+            if_none_match,
+        )),
+        _ => Ok(Response::new("404 Not Found", INDEX.to_string())),
+    }
+}
+
+/// Reads a request from `stream` and writes the response.
+fn handle(json_path: &Path, stream: TcpStream) -> Result<(), Box<dyn Error>> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut words = line.split_whitespace();
+    let (method, target) = match (words.next(), words.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Ok(()),
+    };
+    let mut if_none_match = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("if-none-match") {
+                if_none_match = Some(value.trim().to_string());
+            }
+        }
+    }
+    let response = respond(json_path, &method, &target, if_none_match.as_deref())
+        .unwrap_or_else(|error| Response::new("500 Internal Server Error", format!("{}\n", error)));
+    let mut out = stream;
+    write!(
+        out,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    )?;
+    if let Some(etag) = &response.etag {
+        write!(out, "ETag: {}\r\n", etag)?;
+    }
+    write!(out, "\r\n")?;
+    if method != "HEAD" {
+        out.write_all(response.body.as_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &[], &["--db", "--listen"])?;
+    let json_path = paths::normalize(&std::env::current_dir()?.join(opts.database()));
+    let address = opts.value("--listen").unwrap_or("127.0.0.1:7878");
+    let listener = TcpListener::bind(address)?;
+    output::info(&format!(
+        "serving {} on http://{}/",
+        json_path.display(),
+        listener.local_addr()?
+    ));
+    for stream in listener.incoming() {
+        let json_path = json_path.clone();
+        let stream = stream?;
+        // A slow client must not block the others.
+        std::thread::spawn(move || {
+            if let Err(error) = handle(&json_path, stream) {
+                output::warn(&format!("request failed: {}", error));
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn respond() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let json_path = temp.path().join("compile_commands.json");
+        let entry = |file: &str| db::entry("/src", file, &["cc", file]);
+        std::fs::write(
+            &json_path,
+            serde_json::to_string(&[entry("a.c"), entry("lib/b.c")]).unwrap(),
+        )
+        .unwrap();
+
+        let response = super::respond(&json_path, "GET", "/entries?file=lib%2F*", None).unwrap();
+        assert_eq!(response.status, "200 OK");
+        let entries: Vec<Entry> = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(entries, [entry("lib/b.c")]);
+
+        let etag = response.etag.unwrap();
+        let response =
+            super::respond(&json_path, "GET", "/entries?file=lib%2F*", Some(&etag)).unwrap();
+        assert_eq!(response.status, "304 Not Modified");
+        assert!(response.body.is_empty());
+
+        let response = super::respond(&json_path, "PUT", "/entries", None).unwrap();
+        assert_eq!(response.status, "405 Method Not Allowed");
+        assert_eq!(decode("a+b%20c%"), "a b c%");
+    }
+}
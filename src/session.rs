@@ -0,0 +1,115 @@
+use std::error::Error;
+use std::path::Path;
+
+use crate::cli::Options;
+use crate::config::{Config, Session};
+use crate::db;
+use crate::meta;
+use crate::paths;
+use crate::prune;
+use crate::time;
+
+/// Returns a new identifier of a build session.
+pub fn new_id() -> String {
+    format!("{}-{}", time::now(), std::process::id())
+}
+
+/// Removes the entries which were recorded last by a previous session of the build of `session`
+/// and whose sources are gone, i.e., entries the build cannot produce anymore.  Entries of
+/// sources merely not recompiled by an incremental build are kept, as are entries of other
+/// builds.  Returns the number of entries removed.
+pub fn prune(json_path: &Path, session: &Session) -> Result<usize, Box<dyn Error>> {
+    let mut removed = 0;
+    db::update_any(json_path, |entries| {
+        let mut meta = meta::read_unlocked(json_path)?;
+        let kept = prune::remove(entries, &mut meta, |e, r| {
+            r.build_id.as_deref() == Some(&session.build_id)
+                && r.session.as_deref() != Some(&session.id)
+                && !paths::resolve(&e.directory, &e.file).exists()
+        });
+        removed = entries.len() - kept.len();
+        if removed > 0 {
+            meta::write_unlocked(json_path, &meta)?;
+        }
+        Ok(kept)
+    })?;
+    Ok(removed)
+}
+
+pub fn main_end_build(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &[], &["--db", "--build-id", "--session"])?;
+    let env = Config::from_env().session;
+    let build_id = opts
+        .value("--build-id")
+        .map(str::to_string)
+        .or_else(|| env.as_ref().map(|s| s.build_id.clone()))
+        .ok_or("missing build id (--build-id or CDBGEN_BUILD_ID)")?;
+    let id = opts
+        .value("--session")
+        .map(str::to_string)
+        .or_else(|| env.map(|s| s.id))
+        .ok_or("missing session (--session or CDBGEN_SESSION)")?;
+    let removed = prune(&opts.database(), &Session { build_id, id })?;
+    println!("removed {} stale entries", removed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Invocation;
+
+    fn record(json_path: &Path, build_id: &str, id: &str, file: &str) {
+        let arguments = vec!["cc".to_string(), "-c".to_string(), file.to_string()];
+        let invocation = Invocation {
+            argv: arguments.clone(),
+            arguments,
+            directory: "/src".to_string(),
+            files: [(file.to_string(), None)].into_iter().collect(),
+            duration: None,
+            session: Some(Session {
+                build_id: build_id.to_string(),
+                id: id.to_string(),
+            }),
+        };
+        db::update(json_path, |entries| {
+            let mut entries = entries.clone();
+            db::replace(&mut entries, &invocation);
+            meta::record(
+                json_path,
+                &invocation,
+                meta::Header::new("shim", "file"),
+                &Default::default(),
+                false,
+            )?;
+            Ok(entries)
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn prune() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let json_path = temp.path().join("compile_commands.json");
+        record(&json_path, "x", "1", "a.c");
+        record(&json_path, "x", "1", "b.c");
+        record(&json_path, "y", "1", "c.c");
+        record(&json_path, "x", "2", "a.c");
+
+        let session = Session {
+            build_id: "x".to_string(),
+            id: "2".to_string(),
+        };
+        assert_eq!(super::prune(&json_path, &session).unwrap(), 1);
+        let files: Vec<_> = db::read_entries(&json_path)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.file)
+            .collect();
+        assert_eq!(files, ["a.c", "c.c"]);
+        assert!(meta::read(&json_path)
+            .unwrap()
+            .find("/src", "b.c")
+            .is_none());
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use which::which;
+
+use crate::cli::Options;
+use crate::output;
+use crate::shims::{self, Link, KNOWN_ARCHIVERS, KNOWN_COMPILERS};
+
+/// Scans the directories of `path` (like `PATH`) for compilers and, if `archivers` is set, for
+/// archivers.  If a compiler is found in several directories, the first one wins as it does for
+/// the shell.
+fn scan(path: &OsStr, archivers: bool) -> BTreeMap<String, PathBuf> {
+    let mut found = BTreeMap::new();
+    for dir in env::split_paths(path) {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let wanted = shims::is_compiler(&name) || archivers && shims::is_archiver(&name);
+            if wanted && shims::is_executable(&entry.path()) {
+                found.entry(name).or_insert_with(|| entry.path());
+            }
+        }
+    }
+    found
+}
+
+/// Parses the answer to the selection prompt, i.e., one-based numbers separated by spaces or
+/// commas, `all` (the default), or `none`.  Returns the selected indices.
+fn parse_selection(answer: &str, n: usize) -> Result<Vec<usize>, Box<dyn Error>> {
+    match answer.trim() {
+        "" | "a" | "all" | "y" | "yes" => return Ok((0..n).collect()),
+        "n" | "no" | "none" => return Ok(Vec::new()),
+        _ => (),
+    }
+    let mut selected = Vec::new();
+    for word in answer.split(|c: char| c == ',' || c.is_whitespace()) {
+        if word.is_empty() {
+            continue;
+        }
+        let i: usize = word
+            .parse()
+            .map_err(|_| format!("invalid selection '{}'", word))?;
+        if i == 0 || i > n {
+            return Err(format!("selection {} out of range 1-{}", i, n).into());
+        }
+        if !selected.contains(&(i - 1)) {
+            selected.push(i - 1);
+        }
+    }
+    Ok(selected)
+}
+
+/// Asks which of the `found` compilers shims should be created for.
+fn select(found: &BTreeMap<String, PathBuf>) -> Result<Vec<String>, Box<dyn Error>> {
+    for (i, (name, path)) in found.iter().enumerate() {
+        println!("{:>4}  {:<24} {}", i + 1, name, path.display());
+    }
+    print!("Create shims for which compilers? [all, none, or numbers] ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        return Err("no selection given (pass --yes to select all)".into());
+    }
+    let names: Vec<&String> = found.keys().collect();
+    Ok(parse_selection(&answer, names.len())?
+        .into_iter()
+        .map(|i| names[i].clone())
+        .collect())
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(
+        args,
+        &[
+            "--auto",
+            "--yes",
+            "-y",
+            "--masquerade",
+            "--archivers",
+            "--qt",
+        ],
+        &["--dir"],
+    )?;
+    let archivers = opts.has("--archivers");
+    let qt: &[&str] = if opts.has("--qt") {
+        &["moc", "uic", "rcc"]
+    } else {
+        &[]
+    };
+    let masquerade = opts.has("--masquerade");
+    if masquerade && opts.value("--dir").is_none() {
+        return Err("--masquerade requires --dir since the shims shadow the compilers".into());
+    }
+    let dir = match opts.value("--dir") {
+        Some(dir) => PathBuf::from(dir),
+        None => env::current_exe()?
+            .parent()
+            .ok_or("cannot determine the directory of cdbgen")?
+            .to_path_buf(),
+    };
+
+    let compilers: Vec<String> = if opts.has("--auto") {
+        let found = scan(&env::var_os("PATH").unwrap_or_default(), archivers);
+        if found.is_empty() {
+            return Err("no compilers found on PATH".into());
+        }
+        if opts.has("--yes") || opts.has("-y") {
+            for (name, path) in &found {
+                println!("{:<24} {}", name, path.display());
+            }
+            found.into_keys().collect()
+        } else {
+            select(&found)?
+        }
+    } else if opts.positional.is_empty() {
+        KNOWN_COMPILERS
+            .iter()
+            .chain(if archivers { KNOWN_ARCHIVERS } else { &[] })
+            .chain(qt)
+            .filter(|c| which(c).is_ok())
+            .map(|c| c.to_string())
+            .collect()
+    } else {
+        opts.positional.clone()
+    };
+
+    fs::create_dir_all(&dir)?;
+    for compiler in &compilers {
+        let created = if masquerade {
+            shims::create_named(&dir, compiler)?
+        } else {
+            shims::create_shim(&dir, compiler)?
+        };
+        match created {
+            (shim, Link::Symlink) => output::info(&format!("created {}", shim.display())),
+            (shim, link) => output::info(&format!("created {} as {}", shim.display(), link)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn scan() {
+        use std::os::unix::fs::PermissionsExt;
+        let first = assert_fs::TempDir::new().unwrap();
+        let second = assert_fs::TempDir::new().unwrap();
+        for (dir, name) in [
+            (&first, "arm-none-eabi-gcc"),
+            (&first, "gcc-ar"),
+            (&first, "cdbgen-gcc"),
+            (&second, "arm-none-eabi-gcc"),
+            (&second, "clang++-15"),
+        ] {
+            let file = dir.child(name);
+            file.touch().unwrap();
+            std::fs::set_permissions(file.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        second.child("cc").touch().unwrap();
+
+        let path = std::env::join_paths([first.path(), second.path()]).unwrap();
+        let found = super::scan(&path, false);
+        assert_eq!(
+            found.keys().collect::<Vec<_>>(),
+            ["arm-none-eabi-gcc", "clang++-15"]
+        );
+        assert!(super::scan(&path, true).contains_key("gcc-ar"));
+        assert_eq!(
+            found["arm-none-eabi-gcc"],
+            first.path().join("arm-none-eabi-gcc")
+        );
+    }
+
+    #[test]
+    fn parse_selection() {
+        assert_eq!(super::parse_selection("\n", 3).unwrap(), [0, 1, 2]);
+        assert!(super::parse_selection("none", 3).unwrap().is_empty());
+        assert_eq!(super::parse_selection("3, 1 3", 3).unwrap(), [2, 0]);
+        assert!(super::parse_selection("4", 3).is_err());
+        assert!(super::parse_selection("x", 3).is_err());
+    }
+}
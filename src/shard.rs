@@ -0,0 +1,303 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cli::Options;
+use crate::config::Conflict;
+use crate::db::{self, Entry, Invocation};
+use crate::hash;
+use crate::paths;
+use crate::pattern::Matcher;
+use crate::summary::Changes;
+
+/// Index of a sharded database which maps source directories to the shards holding their
+/// entries.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    shards: BTreeMap<String, String>,
+}
+
+/// Directory holding the shards of the database at `json_path`, e.g. `compile_commands.d` for
+/// `compile_commands.json`.
+pub fn shard_dir(json_path: &Path) -> PathBuf {
+    json_path.with_extension("d")
+}
+
+fn manifest_path(json_path: &Path) -> PathBuf {
+    shard_dir(json_path).join("manifest.json")
+}
+
+/// Returns true if the database at `json_path` is stored in shards.
+pub fn is_sharded(json_path: &Path) -> bool {
+    manifest_path(json_path).exists()
+}
+
+/// Returns the directory of the source file of `entry` which selects its shard.
+fn shard_key(entry: &Entry) -> String {
+    let path = paths::resolve(&entry.directory, &entry.file);
+    path.parent()
+        .map_or_else(String::new, |p| p.to_string_lossy().into_owned())
+}
+
+fn shard_name(key: &str) -> String {
+    format!("{:016x}.json", hash::fnv1a(key.as_bytes()))
+}
+
+fn read_shard(path: &Path) -> Result<BTreeSet<Entry>, Box<dyn Error>> {
+    match fs::read_to_string(path) {
+        Ok(data) => db::parse_entries(&data),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(BTreeSet::new()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Runs `f` on the manifest while holding its lock and writes it back if it changed.  Since `f`
+/// writes the shards, a manifest replaced by another tool meanwhile is not merged.
+fn with_manifest<T, F>(json_path: &Path, f: F) -> Result<T, Box<dyn Error>>
+where
+    F: FnOnce(&mut Manifest) -> Result<T, Box<dyn Error>>,
+{
+    fs::create_dir_all(shard_dir(json_path))?;
+    let mut f = Some(f);
+    db::update_json(&manifest_path(json_path), |manifest: &mut Manifest| {
+        if manifest.version == 0 {
+            manifest.version = 1;
+        }
+        match f.take() {
+            Some(f) => f(manifest),
+            None => Err(format!(
+                "the manifest of '{}' was replaced meanwhile",
+                json_path.display()
+            )
+            .into()),
+        }
+    })
+}
+
+/// Records `invocation` into the sharded database at `json_path`.  Only the shards of the
+/// directories of the files compiled are read and rewritten.  Conflicts are resolved according to
+/// `policy`.  The caller-supplied `also` is run while the lock is held before any shard is written.
+pub fn record<F>(
+    json_path: &Path,
+    invocation: &Invocation,
+    policy: Conflict,
+    also: F,
+) -> Result<Changes, Box<dyn Error>>
+where
+    F: FnOnce() -> Result<(), Box<dyn Error>>,
+{
+    let dir = shard_dir(json_path);
+    with_manifest(json_path, |manifest| {
+        let mut changes = Changes::default();
+        let mut by_shard: BTreeMap<String, BTreeSet<Entry>> = BTreeMap::new();
+        for entry in invocation.entries() {
+            by_shard.entry(shard_key(&entry)).or_default().insert(entry);
+        }
+        // Merge into all shards before writing any such that a conflict leaves them untouched.
+        let mut changed = Vec::new();
+        for (key, new) in by_shard {
+            let name = manifest
+                .shards
+                .entry(key.clone())
+                .or_insert_with(|| shard_name(&key))
+                .clone();
+            let path = dir.join(name);
+            let old = read_shard(&path)?;
+            let mut entries = old.clone();
+            changes += db::merge(&mut entries, new, policy)?;
+            if entries != old {
+                changed.push((path, entries));
+            }
+        }
+        also()?;
+        for (path, entries) in changed {
+            fs::write(&path, serde_json::to_string_pretty(&entries)? + "\n")?;
+        }
+        Ok(changes)
+    })
+}
+
+/// Replaces the entries of all shards by the ones computed by `f` from the current entries.  Only
+/// shards whose entries changed are rewritten.
+pub fn update_all<F>(json_path: &Path, f: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnOnce(&BTreeSet<Entry>) -> Result<BTreeSet<Entry>, Box<dyn Error>>,
+{
+    let dir = shard_dir(json_path);
+    with_manifest(json_path, |manifest| {
+        let mut old: BTreeMap<String, BTreeSet<Entry>> = BTreeMap::new();
+        for (key, name) in &manifest.shards {
+            old.insert(key.clone(), read_shard(&dir.join(name))?);
+        }
+        let all: BTreeSet<Entry> = old.values().flatten().cloned().collect();
+        let mut new: BTreeMap<String, BTreeSet<Entry>> =
+            old.keys().map(|k| (k.clone(), BTreeSet::new())).collect();
+        for entry in f(&all)? {
+            new.entry(shard_key(&entry)).or_default().insert(entry);
+        }
+        for (key, entries) in new {
+            if old.get(&key) == Some(&entries) {
+                continue;
+            }
+            let name = manifest
+                .shards
+                .entry(key.clone())
+                .or_insert_with(|| shard_name(&key))
+                .clone();
+            fs::write(
+                dir.join(name),
+                serde_json::to_string_pretty(&entries)? + "\n",
+            )?;
+        }
+        Ok(())
+    })
+}
+
+/// Reads the entries of all shards whose key is accepted by `filter`.
+fn read_filtered<F>(json_path: &Path, filter: F) -> Result<BTreeSet<Entry>, Box<dyn Error>>
+where
+    F: Fn(&str) -> bool,
+{
+    let dir = shard_dir(json_path);
+    with_manifest(json_path, |manifest| {
+        let mut entries = BTreeSet::new();
+        for (key, name) in &manifest.shards {
+            if filter(key) {
+                entries.extend(read_shard(&dir.join(name))?);
+            }
+        }
+        Ok(entries)
+    })
+}
+
+/// Reads the entries of all shards of the database at `json_path`.
+pub fn read_all(json_path: &Path) -> Result<BTreeSet<Entry>, Box<dyn Error>> {
+    read_filtered(json_path, |_| true)
+}
+
+pub fn main_export(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &[], &["--db", "-o"])?;
+    let json_path = opts.database();
+    if !is_sharded(&json_path) {
+        return Err(format!("'{}' is not sharded", json_path.display()).into());
+    }
+    let entries = read_all(&json_path)?;
+    let output = opts
+        .value("-o")
+        .map_or_else(|| json_path.clone(), PathBuf::from);
+    if output == Path::new("-") {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+    db::update(&output, |_| Ok(entries))
+}
+
+pub fn main_query(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &["--regex"], &["--db", "--file", "--directory"])?;
+    let json_path = opts.database();
+    let cwd = env::current_dir()?;
+    let files = opts
+        .value("--file")
+        .map(|f| Matcher::new(f, opts.has("--regex")))
+        .transpose()?;
+    let directory = opts.value("--directory").map(|d| {
+        paths::normalize(&cwd.join(d))
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    let entries = if is_sharded(&json_path) {
+        // A source directory restricts the shards to read.
+        read_filtered(&json_path, |key| {
+            directory
+                .as_ref()
+                .map_or(true, |d| Path::new(key).starts_with(d))
+        })?
+    } else {
+        db::read_entries(&json_path)?
+    };
+    let entries: Vec<&Entry> = entries
+        .iter()
+        .filter(|e| {
+            let path = paths::resolve(&e.directory, &e.file);
+            directory.as_ref().map_or(true, |d| path.starts_with(d))
+                && files.as_ref().map_or(true, |m| {
+                    m.is_match(&e.file) || m.is_match(&path.to_string_lossy())
+                })
+        })
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&entries)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invocation(directory: &str, files: &[&str]) -> Invocation {
+        let arguments: Vec<String> = std::iter::once("cc")
+            .chain(files.iter().copied())
+            .map(String::from)
+            .collect();
+        Invocation {
+            argv: arguments.clone(),
+            arguments,
+            directory: directory.to_string(),
+            files: files.iter().map(|f| (f.to_string(), None)).collect(),
+            duration: None,
+            session: None,
+        }
+    }
+
+    #[test]
+    fn record() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let json_path = temp.path().join("compile_commands.json");
+        let ok = || Ok(());
+        super::record(
+            &json_path,
+            &invocation("/src", &["a/x.c", "b/y.c"]),
+            Conflict::Replace,
+            ok,
+        )
+        .unwrap();
+        super::record(
+            &json_path,
+            &invocation("/src", &["a/z.c"]),
+            Conflict::Replace,
+            ok,
+        )
+        .unwrap();
+        super::record(
+            &json_path,
+            &invocation("/src", &["a/x.c"]),
+            Conflict::Replace,
+            ok,
+        )
+        .unwrap();
+
+        let manifest: Manifest =
+            serde_json::from_str(&fs::read_to_string(manifest_path(&json_path)).unwrap()).unwrap();
+        assert_eq!(
+            manifest.shards.keys().collect::<Vec<_>>(),
+            ["/src/a", "/src/b"]
+        );
+        let a = read_shard(&shard_dir(&json_path).join(&manifest.shards["/src/a"])).unwrap();
+        assert_eq!(a.len(), 2);
+        assert_eq!(read_all(&json_path).unwrap().len(), 3);
+        assert_eq!(
+            read_filtered(&json_path, |key| key == "/src/b")
+                .unwrap()
+                .into_iter()
+                .map(|e| e.file)
+                .collect::<Vec<_>>(),
+            ["b/y.c"]
+        );
+    }
+}
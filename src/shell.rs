@@ -0,0 +1,191 @@
+use std::env;
+use std::error::Error;
+use std::path::Path;
+
+use crate::cli::Options;
+use crate::shims::KNOWN_COMPILERS;
+
+/// Quotes `arg` for a POSIX shell unless it consists of safe characters only.  Control
+/// characters are kept as they are within single quotes, which POSIX shells as well as the
+/// tokenizer of clang read back unchanged, i.e., a newline puts the command on several lines.
+pub fn quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_=+./:,@%^".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Joins `args` into a command line a POSIX shell splits into the very same arguments.
+pub fn join<S: AsRef<str>>(args: &[S]) -> String {
+    args.iter()
+        .map(|a| quote(a.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Splits the command line `command` into arguments the way a POSIX shell does, i.e., with
+/// single and double quotes and backslash escapes, but without expansions.
+pub fn split(command: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => args.extend(arg.take()),
+            '\'' => {
+                let word = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(format!("unterminated quote in '{}'", command)),
+                    }
+                }
+            }
+            '"' => {
+                let word = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if "$`\"\\\n".contains(c) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(format!("unterminated quote in '{}'", command)),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(format!("unterminated quote in '{}'", command)),
+                    }
+                }
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    arg.get_or_insert_with(String::new).push(c);
+                }
+            }
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    Ok(args)
+}
+
+/// Emits code for `shell` which wraps `compilers` by cdbgen at `cdbgen` for the current session,
+/// i.e., defines a function per compiler, points `CC` and `CXX` to cdbgen, and defines a function
+/// `cdbgen_deactivate` undoing it.
+fn init(shell: &str, cdbgen: &str, compilers: &[&str]) -> Result<String, Box<dyn Error>> {
+    let mut out = String::new();
+    match shell {
+        "bash" | "zsh" | "sh" => {
+            let q = quote(cdbgen);
+            for c in compilers {
+                out += &format!("{}() {{ command {} {} \"$@\"; }}\n", c, q, c);
+            }
+            out += "_cdbgen_old_cc=${CC-}\n_cdbgen_old_cxx=${CXX-}\n";
+            out += &format!("export CC={}\n", quote(&format!("{} cc", q)));
+            out += &format!("export CXX={}\n", quote(&format!("{} c++", q)));
+            out += &format!(
+                "cdbgen_deactivate() {{\n  unset -f {}\n  \
+                 if [ -n \"$_cdbgen_old_cc\" ]; then CC=$_cdbgen_old_cc; else unset CC; fi\n  \
+                 if [ -n \"$_cdbgen_old_cxx\" ]; then CXX=$_cdbgen_old_cxx; else unset CXX; fi\n  \
+                 unset _cdbgen_old_cc _cdbgen_old_cxx\n  unset -f cdbgen_deactivate\n}}\n",
+                compilers.join(" ")
+            );
+        }
+        "fish" => {
+            let q = quote(cdbgen);
+            for c in compilers {
+                out += &format!("function {}; command {} {} $argv; end\n", c, q, c);
+            }
+            out += "set -g _cdbgen_old_cc $CC\nset -g _cdbgen_old_cxx $CXX\n";
+            out += &format!("set -gx CC {}\n", quote(&format!("{} cc", q)));
+            out += &format!("set -gx CXX {}\n", quote(&format!("{} c++", q)));
+            out += &format!(
+                "function cdbgen_deactivate\n  functions -e {}\n  \
+                 set -gx CC $_cdbgen_old_cc\n  set -gx CXX $_cdbgen_old_cxx\n  \
+                 set -e _cdbgen_old_cc _cdbgen_old_cxx\n  functions -e cdbgen_deactivate\nend\n",
+                compilers.join(" ")
+            );
+        }
+        "powershell" | "pwsh" => {
+            let q = format!("'{}'", cdbgen.replace('\'', "''"));
+            for c in compilers {
+                out += &format!(
+                    "Set-Item -Path 'function:global:{}' -Value {{ & {} {} @args }}\n",
+                    c, q, c
+                );
+            }
+            out += "$global:CdbgenOldCC = $env:CC\n$global:CdbgenOldCXX = $env:CXX\n";
+            out += &format!(
+                "$env:CC = \"{} cc\"\n$env:CXX = \"{} c++\"\n",
+                cdbgen, cdbgen
+            );
+            out += "function global:cdbgen_deactivate {\n";
+            for c in compilers {
+                out += &format!("  Remove-Item -Path 'function:global:{}'\n", c);
+            }
+            out += "  $env:CC = $global:CdbgenOldCC\n  $env:CXX = $global:CdbgenOldCXX\n  \
+                    Remove-Item -Path 'function:global:cdbgen_deactivate'\n}\n";
+        }
+        _ => return Err(format!("unsupported shell '{}'", shell).into()),
+    }
+    Ok(out)
+}
+
+pub fn main_init(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &[], &[])?;
+    let shell = match opts.positional.first() {
+        Some(shell) => shell.clone(),
+        None => env::var_os("SHELL")
+            .and_then(|s| Some(Path::new(&s).file_name()?.to_string_lossy().into_owned()))
+            .ok_or("usage: cdbgen shell-init [bash|zsh|fish|powershell]")?,
+    };
+    let cdbgen = env::current_exe()?;
+    print!(
+        "{}",
+        init(&shell, &cdbgen.to_string_lossy(), KNOWN_COMPILERS)?
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn quote() {
+        assert_eq!(super::quote("-DFOO=1"), "-DFOO=1");
+        assert_eq!(super::quote(""), "''");
+        assert_eq!(super::quote("a b"), "'a b'");
+        assert_eq!(super::quote("it's"), "'it'\\''s'");
+        assert_eq!(super::join(&["cc", "-DX=\"y\""]), "cc '-DX=\"y\"'");
+        assert_eq!(super::quote("-DX=a\nb\t"), "'-DX=a\nb\t'");
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(
+            super::split(r#"cc  -DX=\"a b\" '-DY=it'\''s' "-DZ=\$\x" -c a.c"#).unwrap(),
+            ["cc", "-DX=\"a", "b\"", "-DY=it's", "-DZ=$\\x", "-c", "a.c"]
+        );
+        assert_eq!(super::split("cc ''").unwrap(), ["cc", ""]);
+        assert!(super::split("cc 'a").is_err());
+        let args = ["cc", "-DX=\"y\"", "a b.c"];
+        assert_eq!(super::split(&super::join(&args)).unwrap(), args);
+        // Control characters, e.g. of generated defines.
+        let args = ["cc", "-DX=a\nb", "-DY=\t", "-DZ=\x01'\x01", "\n"];
+        assert_eq!(super::split(&super::join(&args)).unwrap(), args);
+    }
+
+    #[test]
+    fn init() {
+        let bash = super::init("bash", "/opt/my bin/cdbgen", &["gcc", "c++"]).unwrap();
+        assert!(bash.contains("gcc() { command '/opt/my bin/cdbgen' gcc \"$@\"; }\n"));
+        assert!(bash.contains("export CC=''\\''/opt/my bin/cdbgen'\\'' cc'\n"));
+        assert!(bash.contains("unset -f gcc c++\n"));
+        let fish = super::init("fish", "/bin/cdbgen", &["gcc"]).unwrap();
+        assert!(fish.contains("function gcc; command /bin/cdbgen gcc $argv; end\n"));
+        assert!(super::init("csh", "/bin/cdbgen", &["gcc"]).is_err());
+    }
+}
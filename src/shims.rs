@@ -0,0 +1,360 @@
+use std::env;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Compilers shims are created for by default.
+pub const KNOWN_COMPILERS: &[&str] = &["cc", "c++", "gcc", "g++", "clang", "clang++"];
+
+/// Archivers shims are created for by `cdbgen setup --archivers`.
+pub const KNOWN_ARCHIVERS: &[&str] = &["ar", "llvm-ar"];
+
+thread_local! {
+    /// Names of compilers as matched by `is_compiler`, compiled once since imports match it
+    /// against every execution.
+    static COMPILER: Regex = Regex::new(
+        r"^(.*-)?(cc|c\+\+|gcc|g\+\+|clang|clang\+\+|icc|icpc|icx|icpx|cl|iccarm|armcc|sdcc|qcc|q\+\+|c[cx](int)?(arm|ppc|rh850|v850))(-[0-9.]+)?(\.exe)?$",
+    )
+    .unwrap();
+    /// Names of archivers as matched by `is_archiver`.
+    static ARCHIVER: Regex =
+        Regex::new(r"^((.*-)?(ar|llvm-lib)(-[0-9.]+)?(\.exe)?|lib\.exe)$").unwrap();
+}
+
+/// Returns true if `program` names a C or C++ compiler, possibly with a target prefix or version
+/// suffix, e.g. `arm-none-eabi-gcc` or `clang++-15`.
+pub fn is_compiler(program: &str) -> bool {
+    let name = Path::new(program)
+        .file_name()
+        .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+    !name.starts_with("cdbgen-") && COMPILER.with(|re| re.is_match(&name))
+}
+
+/// Returns true if `program` names an archiver creating static libraries, e.g. `ar`, `gcc-ar`,
+/// `llvm-ar-15`, or `lib.exe`.
+pub fn is_archiver(program: &str) -> bool {
+    let name = Path::new(program)
+        .file_name()
+        .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+    !name.starts_with("cdbgen-") && ARCHIVER.with(|re| re.is_match(&name))
+}
+
+/// Returns true if `path` is an executable file.
+pub fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(path).map_or(false, |m| {
+            m.is_file() && m.permissions().mode() & 0o111 != 0
+        })
+    }
+    #[cfg(not(unix))]
+    path.is_file()
+}
+
+/// Finds the next executable named `name` on `path` which is not cdbgen itself.  This is the
+/// compiler a shim named exactly like it masquerades as, e.g. a shim `gcc` in a directory put in
+/// front of `PATH`.
+pub fn find_masqueraded(name: &str, path: &OsStr) -> Option<PathBuf> {
+    let cdbgen = env::current_exe().ok()?.canonicalize().ok()?;
+    env::split_paths(path)
+        .map(|dir| dir.join(name))
+        .filter(|p| is_executable(p))
+        .find(|p| p.canonicalize().map_or(false, |p| p != cdbgen))
+}
+
+/// Splits the version suffix off `compiler`, e.g. `g++-13` into `g++` and `13`.
+fn split_version(compiler: &str) -> Option<(&str, &str)> {
+    let (name, version) = compiler.rsplit_once('-')?;
+    let is_version = !version.is_empty()
+        && version.starts_with(|c: char| c.is_ascii_digit())
+        && version.chars().all(|c| c.is_ascii_digit() || c == '.');
+    if is_version && !name.is_empty() {
+        Some((name, version))
+    } else {
+        None
+    }
+}
+
+fn version_key(version: &str) -> Vec<u32> {
+    version.split('.').map(|v| v.parse().unwrap_or(0)).collect()
+}
+
+/// Returns the compilers to try if `compiler` cannot be found: the name without version suffix
+/// followed by the versioned siblings found in the directories of `path`, highest version first.
+pub fn fallbacks(compiler: &str, path: &OsStr) -> Vec<String> {
+    let name = split_version(compiler).map_or(compiler, |(name, _)| name);
+    let mut siblings = Vec::new();
+    for dir in env::split_paths(path) {
+        for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if let Some((n, version)) = split_version(&file_name) {
+                if n == name && file_name != compiler {
+                    siblings.push((version_key(version), file_name.clone()));
+                }
+            }
+        }
+    }
+    siblings.sort();
+    siblings.dedup();
+    let mut fallbacks = Vec::new();
+    if name != compiler {
+        fallbacks.push(name.to_string());
+    }
+    fallbacks.extend(siblings.into_iter().rev().map(|(_, s)| s));
+    fallbacks
+}
+
+/// Returns the edit distance of `a` and `b`, i.e., the least number of characters to insert,
+/// delete, or substitute to turn one into the other.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Returns the executables in the directories of `path` whose names are close to `compiler`,
+/// i.e., its versioned siblings and names within a small edit distance, closest first.  Shims of
+/// cdbgen are left out.
+pub fn suggestions(compiler: &str, path: &OsStr) -> Vec<String> {
+    let name = split_version(compiler).map_or(compiler, |(name, _)| name);
+    let max = (compiler.chars().count() / 2).clamp(1, 3);
+    let mut found = Vec::new();
+    for dir in env::split_paths(path) {
+        for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if file_name == compiler || file_name.starts_with("cdbgen") {
+                continue;
+            }
+            let sibling = split_version(&file_name).map_or(false, |(n, _)| n == name);
+            let d = if sibling || file_name == name {
+                0
+            } else {
+                distance(compiler, &file_name)
+            };
+            if d <= max && is_executable(&entry.path()) {
+                found.push((d, file_name));
+            }
+        }
+    }
+    found.sort();
+    found.dedup();
+    found.into_iter().map(|(_, s)| s).take(5).collect()
+}
+
+/// Returns the file name of the shim for `compiler`.
+pub fn shim_name(compiler: &str) -> String {
+    if cfg!(windows) {
+        format!("cdbgen-{}.exe", compiler)
+    } else {
+        format!("cdbgen-{}", compiler)
+    }
+}
+
+/// How a shim refers to the cdbgen binary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Link {
+    Symlink,
+    Hardlink,
+    Copy,
+}
+
+impl fmt::Display for Link {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Link::Symlink => "symlink",
+            Link::Hardlink => "hardlink",
+            Link::Copy => "copy",
+        })
+    }
+}
+
+/// Links `target` to `link` by the first of `kinds` which succeeds.  Filesystems like FAT or
+/// some network shares do not support symbolic links, and hard links do not cross filesystems.
+fn create_link(target: &Path, link: &Path, kinds: &[Link]) -> Result<Link, Box<dyn Error>> {
+    let mut last_error = None;
+    for &kind in kinds {
+        let result = match kind {
+            #[cfg(unix)]
+            Link::Symlink => std::os::unix::fs::symlink(target, link),
+            #[cfg(not(unix))]
+            Link::Symlink => continue,
+            Link::Hardlink => fs::hard_link(target, link),
+            Link::Copy => fs::copy(target, link).map(|_| ()),
+        };
+        match result {
+            Ok(()) => return Ok(kind),
+            Err(error) => last_error = Some(error),
+        }
+    }
+    Err(last_error.map_or_else(|| "no way to create a link".into(), Into::into))
+}
+
+/// Creates the shim for `compiler` in `dir`, i.e., a link to the cdbgen binary.  Unless symbolic
+/// links are supported, the shim is a hard link or a copy of the binary.
+pub fn create_shim(dir: &Path, compiler: &str) -> Result<(PathBuf, Link), Box<dyn Error>> {
+    create_named(dir, &shim_name(compiler))
+}
+
+/// Creates a shim named `name` in `dir`, e.g. one masquerading as the compiler of that name.
+pub fn create_named(dir: &Path, name: &str) -> Result<(PathBuf, Link), Box<dyn Error>> {
+    let cdbgen = env::current_exe()?;
+    let shim = dir.join(name);
+    match fs::remove_file(&shim) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+        _ => (),
+    }
+    let link = create_link(&cdbgen, &shim, &[Link::Symlink, Link::Hardlink, Link::Copy])?;
+    Ok((shim, link))
+}
+
+/// A temporary directory with shims which is removed once dropped.
+pub struct TempShimDir {
+    path: PathBuf,
+}
+
+impl TempShimDir {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        let path = env::temp_dir().join(format!("cdbgen-{}-{}", std::process::id(), nanos));
+        fs::create_dir(&path)?;
+        Ok(TempShimDir { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempShimDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+
+    #[test]
+    fn fallbacks() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        for name in ["g++-9", "g++-12", "g++-12.1", "gcc-12", "g++-ar"] {
+            temp.child(name).touch().unwrap();
+        }
+        let path = temp.path().as_os_str();
+        assert_eq!(
+            super::fallbacks("g++-13", path),
+            ["g++", "g++-12.1", "g++-12", "g++-9"]
+        );
+        assert_eq!(
+            super::fallbacks("g++", path),
+            ["g++-12.1", "g++-12", "g++-9"]
+        );
+        assert_eq!(super::fallbacks("clang", path), Vec::<String>::new());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn suggestions() {
+        use std::os::unix::fs::PermissionsExt;
+        let temp = assert_fs::TempDir::new().unwrap();
+        for name in ["gcc-12", "gcc", "g++", "clang", "cdbgen-gcc", "readme"] {
+            temp.child(name).touch().unwrap();
+            let mode = if name == "readme" { 0o644 } else { 0o755 };
+            fs::set_permissions(temp.path().join(name), fs::Permissions::from_mode(mode)).unwrap();
+        }
+        let path = temp.path().as_os_str();
+        assert_eq!(super::suggestions("gcc-13", path), ["gcc", "gcc-12"]);
+        assert_eq!(super::suggestions("gcx", path), ["gcc"]);
+        assert_eq!(super::suggestions("clnag", path), ["clang"]);
+        assert_eq!(super::suggestions("raedme", path), Vec::<String>::new());
+        assert_eq!(super::distance("clnag", "clang"), 2);
+    }
+
+    #[test]
+    fn create_link() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let target = temp.child("cdbgen");
+        target.write_str("binary").unwrap();
+        let link = |name: &str, kinds: &[Link]| {
+            let link = temp.path().join(name);
+            let kind = super::create_link(target.path(), &link, kinds).unwrap();
+            assert_eq!(fs::read_to_string(&link).unwrap(), "binary");
+            kind
+        };
+        assert_eq!(
+            link("cdbgen-a", &[Link::Hardlink, Link::Copy]),
+            Link::Hardlink
+        );
+        assert_eq!(link("cdbgen-b", &[Link::Copy]), Link::Copy);
+        assert!(super::create_link(
+            target.path(),
+            &temp.path().join("cdbgen-a"),
+            &[Link::Copy, Link::Hardlink]
+        )
+        .is_ok());
+        assert!(
+            super::create_link(target.path(), &temp.path().join("x/y"), &[Link::Hardlink]).is_err()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn find_masqueraded() {
+        use std::os::unix::fs::PermissionsExt;
+        let shims = assert_fs::TempDir::new().unwrap();
+        let bin = assert_fs::TempDir::new().unwrap();
+        create_named(shims.path(), "gcc").unwrap();
+        let gcc = bin.child("gcc");
+        gcc.touch().unwrap();
+        fs::set_permissions(gcc.path(), fs::Permissions::from_mode(0o755)).unwrap();
+        let path = env::join_paths([shims.path(), bin.path()]).unwrap();
+        assert_eq!(
+            super::find_masqueraded("gcc", &path),
+            Some(gcc.path().to_path_buf())
+        );
+        assert_eq!(super::find_masqueraded("cc", &path), None);
+    }
+
+    #[test]
+    fn is_compiler() {
+        assert!(super::is_compiler("/usr/bin/arm-none-eabi-g++"));
+        assert!(super::is_compiler("gcc-12"));
+        assert!(!super::is_compiler("cdbgen-gcc"));
+        assert!(!super::is_compiler("/usr/libexec/gcc/cc1"));
+        assert!(!super::is_compiler("gcc-ar"));
+        assert!(!super::is_compiler("ld"));
+    }
+
+    #[test]
+    fn is_archiver() {
+        assert!(super::is_archiver("/usr/bin/ar"));
+        assert!(super::is_archiver("arm-none-eabi-gcc-ar"));
+        assert!(super::is_archiver("llvm-ar-15"));
+        assert!(super::is_archiver("lib.exe"));
+        assert!(!super::is_archiver("cdbgen-ar"));
+        assert!(!super::is_archiver("gcc"));
+        assert!(!super::is_archiver("lib"));
+    }
+}
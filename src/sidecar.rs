@@ -0,0 +1,198 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use which::which;
+
+/// Configuration of a single shim which is read from a file next to it, e.g. `cdbgen-cc.toml`
+/// for `cdbgen-cc`.  Only a subset of TOML is understood: comments and keys with a string or an
+/// array of strings as value.
+#[derive(Debug, Default, PartialEq)]
+pub struct ShimConfig {
+    /// Compiler to run instead of the one named by the shim.
+    pub compiler: Option<PathBuf>,
+    /// Flags recorded in addition to the ones the compiler is run with.
+    pub extra_flags: Vec<String>,
+    /// Database invocations through the shim are recorded into.
+    pub database: Option<PathBuf>,
+    /// Preset translating the flags of the compiler for clang, or `auto`.
+    pub preset: Option<String>,
+}
+
+/// Parses a TOML basic string, i.e., a double quoted string with backslash escapes, at the start
+/// of `s`.  Returns the string and the remainder of `s`.
+fn parse_string(s: &str) -> Result<(String, &str), String> {
+    let rest = s
+        .strip_prefix('"')
+        .ok_or_else(|| format!("expected a string but found '{}'", s))?;
+    let mut value = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &rest[i + 1..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some(c @ ('"' | '\\')) => value.push(c),
+                _ => return Err("invalid escape sequence".to_string()),
+            },
+            c => value.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+fn parse_strings(s: &str) -> Result<Vec<String>, String> {
+    let mut rest = s
+        .strip_prefix('[')
+        .ok_or_else(|| format!("expected an array but found '{}'", s))?
+        .trim_start();
+    let mut values = Vec::new();
+    loop {
+        if let Some(r) = rest.strip_prefix(']') {
+            rest = r;
+            break;
+        }
+        let (value, r) = parse_string(rest)?;
+        values.push(value);
+        rest = r.trim_start();
+        if let Some(r) = rest.strip_prefix(',') {
+            rest = r.trim_start();
+        } else if !rest.starts_with(']') {
+            return Err("expected ',' or ']'".to_string());
+        }
+    }
+    if !rest.trim_start().is_empty() && !rest.trim_start().starts_with('#') {
+        return Err(format!("trailing characters '{}'", rest.trim()));
+    }
+    Ok(values)
+}
+
+/// Parses the configuration of a shim where relative paths are taken relative to `dir`.
+fn parse(data: &str, dir: &Path) -> Result<ShimConfig, String> {
+    let mut config = ShimConfig::default();
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| format!("line {}: {}", i + 1, message);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected 'key = value'".to_string()))?;
+        let value = value.trim();
+        let path = || -> Result<PathBuf, String> {
+            let (s, rest) = parse_string(value).map_err(error)?;
+            if !rest.trim().is_empty() && !rest.trim().starts_with('#') {
+                return Err(error(format!("trailing characters '{}'", rest.trim())));
+            }
+            Ok(dir.join(s))
+        };
+        match key.trim() {
+            "compiler" => config.compiler = Some(path()?),
+            "database" => config.database = Some(path()?),
+            "extra_flags" => config.extra_flags = parse_strings(value).map_err(error)?,
+            "preset" => {
+                let (preset, rest) = parse_string(value).map_err(error)?;
+                if !rest.trim().is_empty() && !rest.trim().starts_with('#') {
+                    return Err(error(format!("trailing characters '{}'", rest.trim())));
+                }
+                config.preset = Some(preset);
+            }
+            key => return Err(error(format!("unknown key '{}'", key))),
+        }
+    }
+    Ok(config)
+}
+
+/// Returns the path of the shim cdbgen was invoked as, i.e., `argv0` itself or where it is found
+/// on `PATH`.
+fn shim_path(argv0: &str) -> Option<PathBuf> {
+    if argv0.contains(std::path::MAIN_SEPARATOR) {
+        Some(PathBuf::from(argv0))
+    } else {
+        // Without following symbolic links since the configuration is next to the shim.
+        env::split_paths(&env::var_os("PATH")?)
+            .map(|dir| dir.join(argv0))
+            .find(|p| p.exists())
+    }
+}
+
+/// Reads the configuration of the shim cdbgen was invoked as, if any.
+pub fn read(argv0: &str) -> Result<Option<ShimConfig>, Box<dyn Error>> {
+    match shim_path(argv0) {
+        Some(shim) => read_for(&shim),
+        None => Ok(None),
+    }
+}
+
+/// Reads the configuration of the shim at `shim`, if any.
+pub fn read_for(shim: &Path) -> Result<Option<ShimConfig>, Box<dyn Error>> {
+    let mut name = shim.file_name().unwrap_or_default().to_os_string();
+    name.push(".toml");
+    let path = shim.with_file_name(name);
+    let data = match fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+    let dir = shim.parent().unwrap_or_else(|| Path::new("."));
+    let mut config = parse(&data, dir).map_err(|e| format!("{}: {}", path.display(), e))?;
+    // A compiler given by name is looked up on PATH.
+    if let Some(compiler) = &config.compiler {
+        let name = compiler.strip_prefix(dir).unwrap_or(compiler);
+        if name.components().count() == 1 {
+            config.compiler = Some(which(name)?);
+        }
+    }
+    Ok(Some(config))
+}
+
+/// Writes the configuration of the shim at `shim` such that it runs `compiler`.
+pub fn write_for(shim: &Path, compiler: &Path) -> Result<(), Box<dyn Error>> {
+    let mut name = shim.file_name().unwrap_or_default().to_os_string();
+    name.push(".toml");
+    let quoted = compiler
+        .to_string_lossy()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"");
+    fs::write(
+        shim.with_file_name(name),
+        format!("compiler = \"{}\"\n", quoted),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let data = r#"
+# Cross compiler of the firmware
+compiler = "/opt/gcc/bin/arm-none-eabi-gcc"
+extra_flags = ["-DFIRMWARE", "-I include", ] # recorded only
+database = "../firmware/compile_commands.json"
+preset = "arm-gcc"
+"#;
+        let config = super::parse(data, Path::new("/shims")).unwrap();
+        assert_eq!(
+            config,
+            ShimConfig {
+                compiler: Some(PathBuf::from("/opt/gcc/bin/arm-none-eabi-gcc")),
+                extra_flags: vec!["-DFIRMWARE".to_string(), "-I include".to_string()],
+                database: Some(PathBuf::from("/shims/../firmware/compile_commands.json")),
+                preset: Some("arm-gcc".to_string()),
+            }
+        );
+        assert!(super::parse("compiler = gcc", Path::new("/")).is_err());
+        assert!(super::parse("flags = []", Path::new("/")).is_err());
+        assert_eq!(
+            parse_string(r#""a \"b\" \\ c" rest"#).unwrap(),
+            (r#"a "b" \ c"#.to_string(), " rest")
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::args;
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::hash;
+use crate::paths;
+use crate::relocate;
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Returns the directories below `root` Sourcetrail should index headers in, i.e., the include
+/// directories and the directories of the sources where nested directories are omitted.
+fn indexed_header_paths(entries: &BTreeSet<Entry>, root: &Path) -> Vec<PathBuf> {
+    let mut dirs = BTreeSet::new();
+    for e in entries {
+        if let Some(parent) = paths::resolve(&e.directory, &e.file).parent() {
+            dirs.insert(parent.to_path_buf());
+        }
+        for flag in args::flags(e.arguments.get(1..).unwrap_or_default()) {
+            if let ("-I" | "-iquote", Some(dir)) = (flag.name.as_str(), &flag.value) {
+                dirs.insert(paths::resolve(&e.directory, dir));
+            }
+        }
+    }
+    let dirs: Vec<_> = dirs.into_iter().filter(|d| d.starts_with(root)).collect();
+    dirs.iter()
+        .filter(|d| !dirs.iter().any(|o| o != *d && d.starts_with(o)))
+        .cloned()
+        .collect()
+}
+
+fn project(name: &str, db: &Path, headers: &[PathBuf], project_dir: &Path) -> String {
+    // Sourcetrail identifies source groups by UUID.  Derive it from the name so that re-exporting
+    // a project does not change it.
+    let id = format!(
+        "{:016x}{:016x}",
+        hash::fnv1a(name.as_bytes()),
+        hash::fnv1a(format!("{}.srctrlprj", name).as_bytes())
+    );
+    let uuid = format!(
+        "{}-{}-{}-{}-{}",
+        &id[..8],
+        &id[8..12],
+        &id[12..16],
+        &id[16..20],
+        &id[20..]
+    );
+    let relative = |p: &Path| xml_escape(&paths::relative(p, project_dir).to_string_lossy());
+    let mut s = String::new();
+    s.push_str("<?xml version=\"1.0\" encoding=\"utf-8\" ?>\n");
+    s.push_str("<config>\n");
+    s.push_str("    <source_groups>\n");
+    s.push_str(&format!("        <source_group_{}>\n", uuid));
+    s.push_str("            <build_file_path>\n");
+    s.push_str(&format!(
+        "                <compilation_db_path>{}</compilation_db_path>\n",
+        relative(db)
+    ));
+    s.push_str("            </build_file_path>\n");
+    s.push_str("            <indexed_header_paths>\n");
+    for h in headers {
+        s.push_str(&format!(
+            "                <indexed_header_path>{}</indexed_header_path>\n",
+            relative(h)
+        ));
+    }
+    s.push_str("            </indexed_header_paths>\n");
+    s.push_str("            <name>C/C++ from Compilation Database</name>\n");
+    s.push_str("            <status>enabled</status>\n");
+    s.push_str("            <type>C/C++ from Compilation Database</type>\n");
+    s.push_str(&format!("        </source_group_{}>\n", uuid));
+    s.push_str("    </source_groups>\n");
+    s.push_str("    <version>8</version>\n");
+    s.push_str("</config>\n");
+    s
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &[], &["--db", "-o", "--root"])?;
+    let cwd = env::current_dir()?;
+    let json_path = paths::normalize(&cwd.join(opts.database()));
+    let root = paths::normalize(&cwd.join(opts.value("--root").unwrap_or(".")));
+    let output = match opts.value("-o") {
+        Some(output) => paths::normalize(&cwd.join(output)),
+        None => {
+            let name = root.file_name().map_or_else(
+                || "project".to_string(),
+                |n| n.to_string_lossy().into_owned(),
+            );
+            root.join(format!("{}.srctrlprj", name))
+        }
+    };
+    let project_dir = output.parent().unwrap_or(&root).to_path_buf();
+    let name = output
+        .file_stem()
+        .map_or_else(String::new, |s| s.to_string_lossy().into_owned());
+
+    let mut entries = db::read_entries(&json_path)?;
+
+    // Sourcetrail resolves a relative directory against its own working directory instead of the
+    // one of the database.  Hand it an absolutized copy in that case.
+    let mut db_for_project = json_path.clone();
+    if entries
+        .iter()
+        .any(|e| Path::new(&e.directory).is_relative())
+    {
+        let db_dir = json_path.parent().unwrap_or(&root);
+        entries = entries
+            .iter()
+            .map(|e| relocate::absolutize(e, db_dir))
+            .collect();
+        db_for_project = project_dir.join(format!("{}.compile_commands.json", name));
+        fs::write(
+            &db_for_project,
+            serde_json::to_string_pretty(&entries)? + "\n",
+        )?;
+    }
+
+    let headers = indexed_header_paths(&entries, &root);
+    fs::write(
+        &output,
+        project(&name, &db_for_project, &headers, &project_dir),
+    )?;
+    println!("wrote {}", output.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn project() {
+        let entries: BTreeSet<_> = [
+            db::entry(
+                "/proj/build",
+                "../src/a/x.c",
+                &["cc", "-I../include", "-I/usr/include/foo", "x.c"],
+            ),
+            db::entry(
+                "/proj/build",
+                "../src/y.c",
+                &["cc", "-iquote", "../src/a", "y.c"],
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let headers = indexed_header_paths(&entries, Path::new("/proj"));
+        assert_eq!(
+            headers,
+            [Path::new("/proj/include"), Path::new("/proj/src")]
+        );
+
+        let xml = super::project(
+            "p",
+            Path::new("/proj/build/compile_commands.json"),
+            &headers,
+            Path::new("/proj"),
+        );
+        assert!(
+            xml.contains("<compilation_db_path>build/compile_commands.json</compilation_db_path>")
+        );
+        assert!(xml.contains("<indexed_header_path>src</indexed_header_path>"));
+        assert!(xml.contains("<type>C/C++ from Compilation Database</type>"));
+    }
+}
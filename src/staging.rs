@@ -0,0 +1,308 @@
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::db::Entry;
+use crate::hash;
+use crate::output;
+use crate::paths;
+
+/// Identifies a staging buffer.
+const MAGIC: u64 = 0x6364_6267_656e_0001;
+/// Size of a staging buffer.  Pages are only allocated once written.
+const CAPACITY: u64 = 64 << 20;
+/// Size of the header, i.e., the magic followed by the end of the reserved records.
+const HEADER: u64 = 64;
+/// Bit of the end of the reserved records set once the buffer is drained.
+const CLOSED: u64 = 1 << 63;
+/// Size of a record which did not fit anymore.
+const ABANDONED: u32 = u32::MAX;
+/// How long draining waits for records which are reserved but not written yet.
+const COMMIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Returns the staging buffer of the database at `json_path` in `CDBGEN_SHM_DIR` or else in
+/// `/dev/shm`, i.e., in memory.  The name includes the user such that users sharing the
+/// directory do not stage into each other's buffers.
+pub fn path(json_path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let json_path = paths::normalize(&env::current_dir()?.join(json_path));
+    let dir = match env::var_os("CDBGEN_SHM_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None if Path::new("/dev/shm").is_dir() => PathBuf::from("/dev/shm"),
+        None => env::temp_dir(),
+    };
+    let hash = hash::fnv1a(json_path.to_string_lossy().as_bytes());
+    Ok(dir.join(format!("cdbgen-{}-{:016x}", user(), hash)))
+}
+
+#[cfg(unix)]
+fn user() -> u32 {
+    unsafe { libc::geteuid() }
+}
+
+#[cfg(not(unix))]
+fn user() -> u32 {
+    0
+}
+
+fn flushing_path(path: &Path) -> PathBuf {
+    path.with_extension("flushing")
+}
+
+/// A staging buffer mapped into memory.
+struct Mapping {
+    ptr: *mut u8,
+}
+
+impl Mapping {
+    /// Maps the buffer at `path`, creating it if needed.  Symbolic links and files of other
+    /// users are refused since the directory is usually shared, e.g. `/dev/shm`.
+    fn new(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut options = File::options();
+        options.read(true).write(true).create(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600).custom_flags(libc::O_NOFOLLOW);
+        }
+        let file = options
+            .open(path)
+            .map_err(|error| format!("cannot open '{}': {}", path.display(), error))?;
+        let metadata = file.metadata()?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if !metadata.is_file() || metadata.uid() != user() {
+                return Err(
+                    format!("'{}' is not owned by the current user", path.display()).into(),
+                );
+            }
+        }
+        if metadata.len() < CAPACITY {
+            file.set_len(CAPACITY)?;
+        }
+        let ptr = Self::map(&file)?;
+        let mapping = Mapping { ptr };
+        match mapping
+            .magic()
+            .compare_exchange(0, MAGIC, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => Ok(mapping),
+            Err(MAGIC) => Ok(mapping),
+            Err(_) => Err(format!("'{}' is not a staging buffer", path.display()).into()),
+        }
+    }
+
+    #[cfg(unix)]
+    fn map(file: &File) -> Result<*mut u8, Box<dyn Error>> {
+        use std::os::unix::io::AsRawFd;
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                CAPACITY as usize,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(ptr as *mut u8)
+    }
+
+    #[cfg(not(unix))]
+    fn map(_: &File) -> Result<*mut u8, Box<dyn Error>> {
+        Err("the staging buffer is only supported on Unix".into())
+    }
+
+    fn magic(&self) -> &AtomicU64 {
+        unsafe { &*(self.ptr as *const AtomicU64) }
+    }
+
+    /// End of the reserved records relative to the header.
+    fn tail(&self) -> &AtomicU64 {
+        unsafe { &*(self.ptr.add(8) as *const AtomicU64) }
+    }
+
+    /// Size reserved for the record at `offset`, stored right after the reservation.
+    fn size_at(&self, offset: u64) -> &AtomicU32 {
+        unsafe { &*(self.ptr.add(offset as usize) as *const AtomicU32) }
+    }
+
+    /// Length of the payload of the record at `offset`, stored once the payload is written.
+    fn len_at(&self, offset: u64) -> &AtomicU32 {
+        unsafe { &*(self.ptr.add(offset as usize + 4) as *const AtomicU32) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, CAPACITY as usize);
+        }
+    }
+}
+
+/// Appends `entries` to the staging buffer of the database at `json_path` without taking any
+/// lock, i.e., space is reserved by an atomic addition, its size is stored right away, and the
+/// record is published by storing its length.  Returns false if the buffer is full or being
+/// drained, or if there is no staging buffer on this platform.
+pub fn append(json_path: &Path, entries: &[Entry]) -> Result<bool, Box<dyn Error>> {
+    if cfg!(not(unix)) {
+        return Ok(false);
+    }
+    append_to(&path(json_path)?, entries)
+}
+
+fn append_to(buffer: &Path, entries: &[Entry]) -> Result<bool, Box<dyn Error>> {
+    let payload = serde_json::to_vec(entries)?;
+    let size = (8 + payload.len() as u64 + 7) & !7;
+    let mapping = Mapping::new(buffer)?;
+    let reserved = mapping.tail().fetch_add(size, Ordering::AcqRel);
+    if reserved & CLOSED != 0 {
+        return Ok(false);
+    }
+    let offset = HEADER + reserved;
+    if offset + size > CAPACITY {
+        if offset + 4 <= CAPACITY {
+            mapping.size_at(offset).store(ABANDONED, Ordering::Release);
+        }
+        return Ok(false);
+    }
+    mapping
+        .size_at(offset)
+        .store(size as u32, Ordering::Release);
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            payload.as_ptr(),
+            mapping.ptr.add(offset as usize + 8),
+            payload.len(),
+        );
+    }
+    mapping
+        .len_at(offset)
+        .store(payload.len() as u32, Ordering::Release);
+    Ok(true)
+}
+
+/// Reads the records of the drained buffer at `path` up to `end`.  Records reserved but not
+/// written within the timeout, e.g. by a crashed compile, are skipped by their size.  Only if a compile did not
+/// even get to store the size are the records after it lost.
+fn read(path: &Path, mapping: &Mapping, end: u64) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let mut entries = Vec::new();
+    let mut offset = HEADER;
+    let start = Instant::now();
+    while offset + 8 <= end {
+        let (size, len) = loop {
+            let size = mapping.size_at(offset).load(Ordering::Acquire);
+            let len = mapping.len_at(offset).load(Ordering::Acquire);
+            if len != 0 || size == ABANDONED || start.elapsed() > COMMIT_TIMEOUT {
+                break (u64::from(size), len);
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        if size == u64::from(ABANDONED) {
+            break;
+        }
+        if size < 8 || offset + size > end {
+            output::warn(&format!(
+                "dropping the entries staged in '{}' after an unfinished compile",
+                path.display()
+            ));
+            break;
+        }
+        if len != 0 {
+            let payload = unsafe {
+                std::slice::from_raw_parts(mapping.ptr.add(offset as usize + 8), len as usize)
+            };
+            let records: Vec<Entry> = serde_json::from_slice(payload)?;
+            entries.extend(records);
+        }
+        offset += size;
+    }
+    Ok(entries)
+}
+
+/// Drains the staging buffer of the database at `json_path` and returns the entries in the order
+/// they were appended.  Compiles started meanwhile stage into a fresh buffer.  The drained buffer
+/// is kept until `finish` is called such that a failure while merging loses nothing.  Must be
+/// called by a single writer, i.e., while holding the lock of the database.
+pub fn drain(json_path: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+    drain_from(&path(json_path)?)
+}
+
+fn drain_from(buffer: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let flushing = flushing_path(buffer);
+    let mut entries = Vec::new();
+    if !flushing.exists() {
+        match fs::rename(buffer, &flushing) {
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(entries),
+            result => result?,
+        }
+    }
+    let mapping = Mapping::new(&flushing)?;
+    let end = mapping.tail().fetch_or(CLOSED, Ordering::AcqRel) & !CLOSED;
+    entries.extend(read(&flushing, &mapping, (HEADER + end).min(CAPACITY))?);
+    Ok(entries)
+}
+
+/// Removes the buffer drained by `drain` once its entries are merged.
+pub fn finish(json_path: &Path) -> Result<(), Box<dyn Error>> {
+    finish_at(&path(json_path)?)
+}
+
+fn finish_at(buffer: &Path) -> Result<(), Box<dyn Error>> {
+    match fs::remove_file(flushing_path(buffer)) {
+        Err(error) if error.kind() != ErrorKind::NotFound => Err(error.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Returns true if there is anything to drain for the database at `json_path`.
+pub fn is_pending(json_path: &Path) -> Result<bool, Box<dyn Error>> {
+    is_pending_at(&path(json_path)?)
+}
+
+fn is_pending_at(buffer: &Path) -> Result<bool, Box<dyn Error>> {
+    Ok(buffer.exists() || flushing_path(buffer).exists())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn append_and_drain() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let buffer = temp.path().join("cdbgen-buffer");
+        let entry = |file: &str| crate::db::entry("/src", file, &["cc", file]);
+        assert!(!is_pending_at(&buffer).unwrap());
+        assert!(append_to(&buffer, &[entry("a.c"), entry("b.c")]).unwrap());
+        // A compile crashing after reserving its record is skipped.
+        let crashed = Mapping::new(&buffer).unwrap();
+        let reserved = crashed.tail().fetch_add(64, Ordering::AcqRel);
+        crashed
+            .size_at(HEADER + reserved)
+            .store(64, Ordering::Release);
+        assert!(append_to(&buffer, &[entry("c.c")]).unwrap());
+        let entries = drain_from(&buffer).unwrap();
+        assert_eq!(entries, [entry("a.c"), entry("b.c"), entry("c.c")]);
+        // Compiles after the drain stage into a fresh buffer.
+        assert!(append_to(&buffer, &[entry("d.c")]).unwrap());
+        finish_at(&buffer).unwrap();
+        assert_eq!(drain_from(&buffer).unwrap(), [entry("d.c")]);
+        finish_at(&buffer).unwrap();
+        assert!(!is_pending_at(&buffer).unwrap());
+
+        // Buffers planted by others, e.g. as a link, are refused.
+        std::os::unix::fs::symlink(temp.path().join("other"), &buffer).unwrap();
+        assert!(append_to(&buffer, &[entry("e.c")]).is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::args;
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::meta::{self, Header, Meta};
+use crate::paths;
+use crate::time;
+use crate::usage::{self, Usage};
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Stats {
+    entries: usize,
+    files: usize,
+    directories: usize,
+    compilers: BTreeMap<String, usize>,
+    languages: BTreeMap<String, usize>,
+    /// Files recorded under several paths which are linked by their canonical path.
+    aliased: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    header: Option<Header>,
+}
+
+/// Returns the language of `entry` as given by `-x` or else derived from the file extension.
+fn language(entry: &Entry) -> String {
+    let arguments = entry.arguments.get(1..).unwrap_or_default();
+    match args::language(arguments, &entry.file).as_deref() {
+        Some("c") => "C".to_string(),
+        Some("c++") | None => "C++".to_string(),
+        Some(other) => other.to_string(),
+    }
+}
+
+fn stats(entries: &BTreeSet<Entry>, meta: &Meta) -> Stats {
+    let mut compilers = BTreeMap::new();
+    let mut languages = BTreeMap::new();
+    let mut files = BTreeSet::new();
+    let mut directories = BTreeSet::new();
+    for e in entries {
+        let compiler = e.arguments.first().map_or_else(String::new, |c| {
+            Path::new(c)
+                .file_name()
+                .map_or_else(|| c.clone(), |n| n.to_string_lossy().into_owned())
+        });
+        *compilers.entry(compiler).or_insert(0) += 1;
+        *languages.entry(language(e)).or_insert(0) += 1;
+        files.insert(paths::resolve(&e.directory, &e.file));
+        directories.insert(e.directory.as_str());
+    }
+    let mut spellings: BTreeMap<&str, usize> = BTreeMap::new();
+    for m in &meta.entries {
+        if let Some(canonical) = &m.canonical {
+            *spellings.entry(canonical).or_insert(0) += 1;
+        }
+    }
+    Stats {
+        entries: entries.len(),
+        files: files.len(),
+        directories: directories.len(),
+        compilers,
+        languages,
+        aliased: spellings.values().filter(|&&n| n > 1).count(),
+        header: meta.header.clone(),
+    }
+}
+
+/// Prints the header of a database in a single line, e.g. for `cdbgen explain`.
+pub fn print_header(header: &Header) {
+    println!(
+        "database: created {} by cdbgen {} (capture {}, storage {})",
+        time::format(header.created_at),
+        header.cdbgen_version,
+        header.capture,
+        header.storage
+    );
+    for remap in &header.path_remaps {
+        println!("  paths rewritten by {}", remap);
+    }
+}
+
+/// Returns the summary of the database at `json_path` as printed by `--json`.
+pub fn to_json(json_path: &Path) -> Result<String, Box<dyn Error>> {
+    let stats = stats(&db::read_entries(json_path)?, &meta::read(json_path)?);
+    Ok(serde_json::to_string_pretty(&stats)?)
+}
+
+fn print(stats: &Stats) {
+    if let Some(header) = &stats.header {
+        print_header(header);
+    }
+    println!(
+        "{} entries for {} files in {} directories",
+        stats.entries, stats.files, stats.directories
+    );
+    if stats.aliased > 0 {
+        println!("{} files recorded under several paths", stats.aliased);
+    }
+    println!("Compilers:");
+    for (compiler, count) in &stats.compilers {
+        println!("  {:>6}  {}", count, compiler);
+    }
+    println!("Languages:");
+    for (language, count) in &stats.languages {
+        println!("  {:>6}  {}", count, language);
+    }
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &["--json", "--self"], &["--db"])?;
+    if opts.has("--self") {
+        let path = usage::path().ok_or("CDBGEN_SELF_STATS names no statistics file")?;
+        let usage =
+            Usage::read(&path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        if opts.has("--json") {
+            println!("{}", serde_json::to_string_pretty(&usage)?);
+        } else {
+            print!("{}", usage.render());
+        }
+        return Ok(());
+    }
+    let json_path = opts.database();
+    if opts.has("--json") {
+        println!("{}", to_json(&json_path)?);
+    } else {
+        let stats = stats(&db::read_entries(&json_path)?, &meta::read(&json_path)?);
+        print(&stats);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats() {
+        let entries: BTreeSet<_> = [
+            db::entry("/src", "a.c", &["/usr/bin/gcc", "-c", "a.c"]),
+            db::entry("/src", "b.cc", &["g++", "-c", "b.cc"]),
+            db::entry("/src/sub", "../a.c", &["gcc", "-x", "c++", "../a.c"]),
+        ]
+        .into_iter()
+        .collect();
+        let stats = super::stats(&entries, &Meta::default());
+        assert_eq!(stats.entries, 3);
+        assert_eq!(stats.files, 2);
+        assert_eq!(stats.directories, 2);
+        assert_eq!(
+            stats.compilers.into_iter().collect::<Vec<_>>(),
+            [("g++".to_string(), 1), ("gcc".to_string(), 2)]
+        );
+        assert_eq!(stats.languages["C++"], 2);
+        assert_eq!(stats.languages["C"], 1);
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Bytes, Seek, SeekFrom, Write};
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::config::Conflict;
+use crate::db::{self, Entry};
+use crate::fragment;
+
+/// Reads the entries of a database one after the other without reading the whole file.
+struct Elements<R: BufRead> {
+    bytes: Peekable<Bytes<R>>,
+    started: bool,
+    done: bool,
+}
+
+impl<R: BufRead> Elements<R> {
+    fn new(reader: R) -> Self {
+        Elements {
+            bytes: reader.bytes().peekable(),
+            started: false,
+            done: false,
+        }
+    }
+
+    /// Skips whitespace and returns the next byte without consuming it.
+    fn peek(&mut self) -> Result<Option<u8>, Box<dyn Error>> {
+        loop {
+            match self.bytes.peek() {
+                Some(Ok(b)) if b.is_ascii_whitespace() => {
+                    self.bytes.next();
+                }
+                Some(Ok(b)) => return Ok(Some(*b)),
+                Some(Err(_)) => return Err(self.bytes.next().unwrap().unwrap_err().into()),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Returns the bytes of the next element of the array, i.e., up to the matching brace.
+    fn element(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut element = Vec::new();
+        let (mut depth, mut in_string, mut escaped) = (0usize, false, false);
+        for b in &mut self.bytes {
+            let b = b?;
+            element.push(b);
+            if in_string {
+                match b {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => (),
+                }
+                continue;
+            }
+            match b {
+                b'"' => in_string = true,
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+            if depth == 0 {
+                return Ok(element);
+            }
+        }
+        Err("unexpected end of database".into())
+    }
+
+    fn next_entry(&mut self) -> Result<Option<Entry>, Box<dyn Error>> {
+        if self.done {
+            return Ok(None);
+        }
+        match (self.started, self.peek()?) {
+            // Like an empty array, e.g. a database just created by taking its lock.
+            (false, None) => {
+                self.done = true;
+                return Ok(None);
+            }
+            (false, Some(b'[')) => {
+                self.bytes.next();
+                self.started = true;
+                if self.peek()? == Some(b']') {
+                    self.done = true;
+                    return Ok(None);
+                }
+            }
+            (true, Some(b',')) => {
+                self.bytes.next();
+            }
+            (true, Some(b']')) => {
+                self.done = true;
+                return Ok(None);
+            }
+            _ => return Err("database is not a JSON array".into()),
+        }
+        self.peek()?;
+        Ok(Some(serde_json::from_slice(&self.element()?)?))
+    }
+}
+
+impl<R: BufRead> Iterator for Elements<R> {
+    type Item = Result<Entry, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+/// Rough estimate of the memory taken by `entry`.
+fn size(entry: &Entry) -> u64 {
+    let strings = entry
+        .arguments
+        .iter()
+        .chain([&entry.directory, &entry.file]);
+    strings.map(|s| s.len() as u64 + 32).sum::<u64>() + 64
+}
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Sorted runs spilled to temporary files next to the database of `json_path`, each holding at
+/// most about `limit` bytes of entries.  They are removed once dropped.
+struct Runs<'a> {
+    json_path: &'a Path,
+    limit: u64,
+    chunk: BTreeSet<Entry>,
+    bytes: u64,
+    paths: Vec<PathBuf>,
+}
+
+impl<'a> Runs<'a> {
+    fn new(json_path: &'a Path, limit: u64) -> Self {
+        Runs {
+            json_path,
+            limit,
+            chunk: BTreeSet::new(),
+            bytes: 0,
+            paths: Vec::new(),
+        }
+    }
+
+    /// Adds `entries` to the current run the way `db::merge` does, or if `policy` is `None`,
+    /// keeps all of them.
+    fn add(&mut self, entries: Vec<Entry>, policy: Option<Conflict>) -> Result<(), Box<dyn Error>> {
+        self.bytes += entries.iter().map(size).sum::<u64>();
+        match policy {
+            Some(policy) => {
+                db::merge(&mut self.chunk, entries, policy)?;
+            }
+            None => self.chunk.extend(entries),
+        }
+        if self.bytes >= self.limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the current run, one entry per line.
+    fn spill(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        let dir = fragment::dir(self.json_path);
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!(
+            ".sort.{}.{}.jsonl",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for entry in &self.chunk {
+            serde_json::to_writer(&mut writer, entry)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        self.paths.push(path);
+        self.chunk.clear();
+        self.bytes = 0;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<&[PathBuf], Box<dyn Error>> {
+        self.spill()?;
+        Ok(&self.paths)
+    }
+}
+
+impl Drop for Runs<'_> {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+type Source<'a> = Peekable<Box<dyn Iterator<Item = Result<Entry, Box<dyn Error>>> + 'a>>;
+
+fn open_run<'a>(path: &Path) -> Result<Source<'a>, Box<dyn Error>> {
+    let lines = BufReader::new(File::open(path)?).lines();
+    let entries = lines.map(|line| Ok::<Entry, _>(serde_json::from_str(&line?)?));
+    Ok((Box::new(entries) as Box<dyn Iterator<Item = _> + 'a>).peekable())
+}
+
+fn key(entry: &Entry) -> (&str, &str) {
+    (&entry.directory, &entry.file)
+}
+
+/// Returns the entries at the front of `source` for the file with `key`.
+fn take(source: &mut Source<'_>, key: (&str, &str)) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let mut entries = Vec::new();
+    while let Some(Ok(e)) = source.peek() {
+        if self::key(e) != key {
+            break;
+        }
+        entries.push(source.next().unwrap()?);
+    }
+    if let Some(Err(_)) = source.peek() {
+        source.next().unwrap()?;
+    }
+    Ok(entries)
+}
+
+/// Writes `entries` the way `serde_json::to_string_pretty` formats a sequence.
+struct Output<W: Write> {
+    writer: W,
+    empty: bool,
+}
+
+impl<W: Write> Output<W> {
+    fn write(&mut self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        self.writer
+            .write_all(if self.empty { b"[\n" } else { b",\n" })?;
+        self.empty = false;
+        let pretty = serde_json::to_string_pretty(entry)?;
+        for (i, line) in pretty.lines().enumerate() {
+            if i > 0 {
+                self.writer.write_all(b"\n")?;
+            }
+            write!(self.writer, "  {}", line)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<W, Box<dyn Error>> {
+        self.writer
+            .write_all(if self.empty { b"[]\n" } else { b"\n]\n" })?;
+        Ok(self.writer)
+    }
+}
+
+/// Merges the `runs` into the entries of `old`, which is the database itself or runs of it, and
+/// writes the result to `output`.  Returns `None` if `old` turns out not to be sorted, and
+/// otherwise whether any entry changed.
+fn merge_runs<W: Write>(
+    mut old: Vec<Source<'_>>,
+    runs: &[PathBuf],
+    policy: Conflict,
+    output: &mut Output<W>,
+) -> Result<Option<bool>, Box<dyn Error>> {
+    let mut new = runs
+        .iter()
+        .map(|path| open_run(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut changed = false;
+    let mut last: Option<Entry> = None;
+    loop {
+        let next =
+            old.iter_mut()
+                .chain(new.iter_mut())
+                .fold(None, |min, s| match (min, s.peek()) {
+                    (Some(min), Some(Ok(e))) if key(e) >= key(&min) => Some(min),
+                    (_, Some(Ok(e))) => Some(e.clone()),
+                    (min, _) => min,
+                });
+        let next = match next {
+            Some(next) => next,
+            None => break,
+        };
+        let mut group = BTreeSet::new();
+        for source in &mut old {
+            group.extend(take(source, key(&next))?);
+        }
+        if let Some(first) = group.iter().next() {
+            if last.as_ref().map_or(false, |last| key(first) < key(last)) {
+                return Ok(None);
+            }
+        }
+        let unchanged = group.clone();
+        for source in &mut new {
+            db::merge(&mut group, take(source, key(&next))?, policy)?;
+        }
+        changed |= group != unchanged;
+        for entry in &group {
+            output.write(entry)?;
+        }
+        last = group.into_iter().next_back().or(last);
+    }
+    // An error is only peeked at, i.e., left at the front of its source.
+    for source in old.iter_mut().chain(new.iter_mut()) {
+        if let Some(Err(_)) = source.peek() {
+            source.next().unwrap()?;
+        }
+    }
+    Ok(Some(changed))
+}
+
+/// Merges the `runs` into the entries of the locked database `json_file` at `json_path`, and
+/// writes the result to `temp`.  Returns whether any entry changed.
+fn merge_file(
+    json_path: &Path,
+    json_file: &mut File,
+    runs: &[PathBuf],
+    policy: Conflict,
+    limit: u64,
+    temp: &Path,
+) -> Result<bool, Box<dyn Error>> {
+    let mut output = Output {
+        writer: BufWriter::new(File::create(temp)?),
+        empty: true,
+    };
+    // Read through the locked file itself since closing a duplicate of it would release the
+    // lock.
+    let reader = BufReader::new(&*json_file);
+    let old = (Box::new(Elements::new(reader)) as Box<dyn Iterator<Item = _>>).peekable();
+    let changed = match merge_runs(vec![old], runs, policy, &mut output)? {
+        Some(changed) => changed,
+        None => {
+            let mut old_runs = Runs::new(json_path, limit);
+            json_file.seek(SeekFrom::Start(0))?;
+            for entry in Elements::new(BufReader::new(&*json_file)) {
+                old_runs.add(vec![entry?], None)?;
+            }
+            let old = old_runs
+                .finish()?
+                .iter()
+                .map(|path| open_run(path))
+                .collect::<Result<_, _>>()?;
+            output = Output {
+                writer: BufWriter::new(File::create(temp)?),
+                empty: true,
+            };
+            merge_runs(old, runs, policy, &mut output)?.unwrap_or(true)
+        }
+    };
+    output.finish()?.flush()?;
+    Ok(changed)
+}
+
+/// Merges the `batches` of entries in order into the database at `json_path` like `db::merge`
+/// with `policy` does, but with about `limit` bytes of entries in memory at most.  The new
+/// entries are sorted in runs spilled to temporary files and merged with the database while both
+/// are streamed.  Unless the database is sorted as cdbgen writes it, it is sorted into runs first.
+pub fn merge<I>(
+    json_path: &Path,
+    limit: u64,
+    batches: I,
+    policy: Conflict,
+) -> Result<(), Box<dyn Error>>
+where
+    I: IntoIterator<Item = Result<Vec<Entry>, Box<dyn Error>>>,
+{
+    let temp = fragment::dir(json_path).join(format!(".sort.{}.json", process::id()));
+    let result = db::with_lock(json_path, |json_file| {
+        let stamp = db::Stamp::of(&json_file.metadata()?);
+        let mut runs = Runs::new(json_path, limit);
+        for batch in batches {
+            runs.add(batch?, Some(policy))?;
+        }
+        let runs = runs.finish()?;
+
+        let mut changed = merge_file(json_path, json_file, runs, policy, limit, &temp)?;
+        // Merged again with the database another tool replaced meanwhile.
+        let mut replaced = None;
+        if changed {
+            if let Some(mut file) = db::replaced(json_path, &stamp)? {
+                changed = merge_file(json_path, &mut file, runs, policy, limit, &temp)?;
+                replaced = Some(file);
+            }
+        }
+        if changed {
+            let json_file = replaced.as_mut().unwrap_or(json_file);
+            json_file.set_len(0)?;
+            json_file.seek(SeekFrom::Start(0))?;
+            io::copy(&mut File::open(&temp)?, json_file)?;
+        }
+        Ok(())
+    });
+    let _ = fs::remove_file(&temp);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let json_path = temp.path().join("compile_commands.json");
+        let old: BTreeSet<_> = (0..50)
+            .map(|i| db::entry("/src", &format!("{:02}.c", i), &["cc", "-O2"]))
+            .collect();
+        // Unsorted as well as sorted databases.
+        let reversed: Vec<_> = old.iter().rev().collect();
+        let batch = |i: usize| {
+            vec![
+                db::entry("/src", &format!("{:02}.c", i), &["cc", "-O3"]),
+                db::entry("/src", &format!("new{:02}.c", i), &["cc", r#""{[\"]}"#]),
+            ]
+        };
+        for data in [
+            serde_json::to_string(&reversed).unwrap(),
+            serde_json::to_string_pretty(&old).unwrap(),
+        ] {
+            fs::write(&json_path, data).unwrap();
+            let batches = (0..50).step_by(5).map(|i| Ok(batch(i)));
+            super::merge(&json_path, 500, batches, Conflict::Replace).unwrap();
+
+            let mut expected = old.clone();
+            for i in (0..50).step_by(5) {
+                db::merge(&mut expected, batch(i), Conflict::Replace).unwrap();
+            }
+            let data = fs::read_to_string(&json_path).unwrap();
+            assert_eq!(
+                data,
+                serde_json::to_string_pretty(&expected).unwrap() + "\n"
+            );
+        }
+        assert_eq!(fs::read_dir(fragment::dir(&json_path)).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,194 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::ops::AddAssign;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// How recording an invocation changed a database.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Changes {
+    pub added: u64,
+    pub updated: u64,
+    pub unchanged: u64,
+    /// Entries written to fragments or a staging buffer, or merged while streaming, whose effect
+    /// is not known.
+    pub deferred: u64,
+}
+
+impl AddAssign for Changes {
+    fn add_assign(&mut self, other: Self) {
+        self.added += other.added;
+        self.updated += other.updated;
+        self.unchanged += other.unchanged;
+        self.deferred += other.deferred;
+    }
+}
+
+thread_local! {
+    static RECORDED: RefCell<BTreeMap<PathBuf, Changes>> = RefCell::new(BTreeMap::new());
+}
+
+/// Notes that recording the invocation made `changes` to the database at `json_path`.
+pub fn note(json_path: &Path, changes: Changes) {
+    RECORDED
+        .with(|recorded| *recorded.borrow_mut().entry(json_path.into()).or_default() += changes);
+}
+
+/// Returns the changes noted so far per database.
+pub fn recorded() -> BTreeMap<PathBuf, Changes> {
+    RECORDED.with(|recorded| recorded.borrow().clone())
+}
+
+/// The part of an event of the event log a summary is made of.
+#[derive(Debug, Serialize, Deserialize)]
+struct Line {
+    compiler: Option<PathBuf>,
+    databases: BTreeMap<PathBuf, Changes>,
+    overhead_us: u64,
+    outcome: String,
+}
+
+/// What a wrapped build recorded altogether.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Summary {
+    pub invocations: u64,
+    #[serde(flatten)]
+    pub changes: Changes,
+    /// Entries removed after the build by `--build-id` or `--sweep`.
+    pub removed: u64,
+    /// What was recorded per database.
+    pub databases: BTreeMap<PathBuf, Changes>,
+    /// Compilers invoked through a shim which recorded nothing, e.g. for linking, and how often.
+    pub misses: BTreeMap<String, u64>,
+    /// Invocations which failed to record.
+    pub failed: u64,
+    pub overhead_us: u64,
+}
+
+impl Summary {
+    /// Sums up the events logged at `path` by `events::log` from byte `offset` on, i.e., the ones
+    /// of a single build.  A missing log means no invocation.
+    pub fn read(path: &Path, offset: u64) -> Result<Self, Box<dyn Error>> {
+        let mut summary = Summary::default();
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(summary),
+            Err(error) => return Err(error.into()),
+        };
+        file.seek(SeekFrom::Start(offset))?;
+        for line in BufReader::new(file).lines() {
+            let line: Line = serde_json::from_str(&line?)?;
+            summary.invocations += 1;
+            summary.overhead_us += line.overhead_us;
+            match line.outcome.as_str() {
+                "failed" => summary.failed += 1,
+                "skipped" => {
+                    let name = line
+                        .compiler
+                        .as_ref()
+                        .and_then(|c| c.file_name())
+                        .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+                    *summary.misses.entry(name).or_default() += 1;
+                }
+                _ => (),
+            }
+            for (json_path, changes) in line.databases {
+                summary.changes += changes;
+                *summary.databases.entry(json_path).or_default() += changes;
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Renders the summary for humans.
+    pub fn render(&self) -> String {
+        let c = &self.changes;
+        let mut out = format!(
+            "cdbgen: {} entries added, {} updated, {} unchanged, {} removed in {} database(s)\n",
+            c.added,
+            c.updated,
+            c.unchanged,
+            self.removed,
+            self.databases.len()
+        );
+        if c.deferred > 0 {
+            out += &format!("cdbgen: {} entries deferred until merged\n", c.deferred);
+        }
+        if !self.misses.is_empty() {
+            let misses: Vec<_> = self
+                .misses
+                .iter()
+                .map(|(compiler, n)| format!("{} ({})", compiler, n))
+                .collect();
+            out += &format!(
+                "cdbgen: {} invocation(s) recorded nothing: {}\n",
+                self.misses.values().sum::<u64>(),
+                misses.join(", ")
+            );
+        }
+        if self.failed > 0 {
+            out += &format!("cdbgen: {} invocation(s) failed to record\n", self.failed);
+        }
+        out += &format!(
+            "cdbgen: recorder overhead {:.3}s over {} invocation(s)\n",
+            self.overhead_us as f64 / 1e6,
+            self.invocations
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let log = temp.path().join("events.jsonl");
+        let changes = |added, updated| Changes {
+            added,
+            updated,
+            ..Changes::default()
+        };
+        let line = |compiler: &str, databases: BTreeMap<PathBuf, Changes>, outcome: &str| {
+            serde_json::to_string(&Line {
+                compiler: Some(PathBuf::from(compiler)),
+                databases,
+                overhead_us: 10,
+                outcome: outcome.to_string(),
+            })
+            .unwrap()
+                + "\n"
+        };
+        let one = |path: &str, changes| [(PathBuf::from(path), changes)].into_iter().collect();
+        let data = line("/bin/gcc", one("/old.json", changes(5, 0)), "recorded");
+        let offset = data.len() as u64;
+        let data = data
+            + &line("/bin/gcc", one("/b.json", changes(1, 0)), "recorded")
+            + &line("/bin/gcc", one("/a.json", changes(2, 1)), "recorded")
+            + &line("/bin/ld", BTreeMap::new(), "skipped")
+            + &line("/bin/gcc", BTreeMap::new(), "failed");
+        std::fs::write(&log, data).unwrap();
+        let summary = Summary::read(&log, offset).unwrap();
+        assert_eq!(summary.invocations, 4);
+        assert_eq!(summary.changes, changes(3, 1));
+        assert_eq!(
+            summary.databases.keys().collect::<Vec<_>>(),
+            [Path::new("/a.json"), Path::new("/b.json")]
+        );
+        assert_eq!(
+            summary.misses,
+            [("ld".to_string(), 1)].into_iter().collect()
+        );
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.overhead_us, 40);
+        assert!(summary
+            .render()
+            .starts_with("cdbgen: 3 entries added, 1 updated, 0 unchanged, 0 removed in 2"));
+    }
+}
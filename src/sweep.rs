@@ -0,0 +1,122 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::db::{self, Entry, Invocation};
+use crate::meta;
+use crate::paths;
+
+/// Keys, i.e., directory and file, of marked entries.
+type Marks = BTreeSet<(String, String)>;
+
+/// Marks the entries recorded by `invocation` into the database at `json_path` in the mark file
+/// at `mark_file`.  Each line is a JSON array of the absolute database path, the directory, and
+/// the file of an entry.
+pub fn mark(
+    mark_file: &Path,
+    json_path: &Path,
+    invocation: &Invocation,
+) -> Result<(), Box<dyn Error>> {
+    let json_path = paths::normalize(&env::current_dir()?.join(json_path));
+    let mut lines = String::new();
+    for file in invocation.files.keys() {
+        let mark = (&json_path, &invocation.directory, file);
+        lines.push_str(&serde_json::to_string(&mark)?);
+        lines.push('\n');
+    }
+    // A single write to a file opened for appending does not interleave with the ones of
+    // concurrent compiles.
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(mark_file)?
+        .write_all(lines.as_bytes())?;
+    Ok(())
+}
+
+/// Reads the mark file at `mark_file` and returns the marked entries per database.
+fn read_marks(mark_file: &Path) -> Result<BTreeMap<PathBuf, Marks>, Box<dyn Error>> {
+    let data = match fs::read_to_string(mark_file) {
+        Ok(data) => data,
+        Err(error) if error.kind() == ErrorKind::NotFound => String::new(),
+        Err(error) => return Err(error.into()),
+    };
+    let mut marks: BTreeMap<PathBuf, Marks> = BTreeMap::new();
+    for line in data.lines().filter(|l| !l.is_empty()) {
+        let (json_path, directory, file): (PathBuf, String, String) = serde_json::from_str(line)?;
+        marks
+            .entry(json_path)
+            .or_default()
+            .insert((directory, file));
+    }
+    Ok(marks)
+}
+
+/// Returns whether `entry` survives the sweep, i.e., it was marked, it lies outside of the
+/// directories covered by the build, or its source still exists and was merely not recompiled by
+/// an incremental build.
+fn is_kept(entry: &Entry, marked: &Marks, covered: &BTreeSet<&str>) -> bool {
+    marked.contains(&(entry.directory.clone(), entry.file.clone()))
+        || !covered
+            .iter()
+            .any(|dir| Path::new(&entry.directory).starts_with(dir))
+        || paths::resolve(&entry.directory, &entry.file).exists()
+}
+
+/// Removes all entries of deleted sources which were not marked but lie within the directories
+/// in which the build compiled something.  Returns the number of entries removed.
+pub fn sweep(mark_file: &Path) -> Result<usize, Box<dyn Error>> {
+    let mut removed = 0;
+    for (json_path, marked) in read_marks(mark_file)? {
+        let covered: BTreeSet<&str> = marked.iter().map(|(d, _)| d.as_str()).collect();
+        db::update_any(&json_path, |entries| {
+            let kept: BTreeSet<Entry> = entries
+                .iter()
+                .filter(|e| is_kept(e, &marked, &covered))
+                .cloned()
+                .collect();
+            if kept.len() < entries.len() {
+                meta::retain_unlocked(&json_path, &kept)?;
+            }
+            removed += entries.len() - kept.len();
+            Ok(kept)
+        })?;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(directory: &str, file: &str) -> Entry {
+        db::entry(directory, file, &["cc", file])
+    }
+
+    #[test]
+    fn is_kept() {
+        let marked: BTreeSet<_> = [("/src/a".to_string(), "x.c".to_string())]
+            .into_iter()
+            .collect();
+        let covered: BTreeSet<_> = ["/src/a"].into_iter().collect();
+        assert!(super::is_kept(&entry("/src/a", "x.c"), &marked, &covered));
+        assert!(!super::is_kept(&entry("/src/a", "y.c"), &marked, &covered));
+        assert!(!super::is_kept(
+            &entry("/src/a/sub", "z.c"),
+            &marked,
+            &covered
+        ));
+        assert!(super::is_kept(&entry("/src/b", "y.c"), &marked, &covered));
+
+        // Sources not recompiled by an incremental build are kept.
+        let temp = assert_fs::TempDir::new().unwrap();
+        fs::write(temp.path().join("old.c"), "").unwrap();
+        let dir = temp.path().to_str().unwrap();
+        let covered: BTreeSet<_> = [dir].into_iter().collect();
+        assert!(super::is_kept(&entry(dir, "old.c"), &marked, &covered));
+        assert!(!super::is_kept(&entry(dir, "gone.c"), &marked, &covered));
+    }
+}
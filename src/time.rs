@@ -0,0 +1,57 @@
+use std::error::Error;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::prune;
+
+/// Returns the current time in seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Parses a duration like `250ms` or an age like `2s` or `5m`.
+pub fn parse_duration(duration: &str) -> Result<Duration, Box<dyn Error>> {
+    match duration.strip_suffix("ms") {
+        Some(ms) => Ok(Duration::from_millis(
+            ms.parse()
+                .map_err(|_| format!("invalid duration '{}'", duration))?,
+        )),
+        None => Ok(Duration::from_secs(prune::parse_age(duration)?)),
+    }
+}
+
+/// Formats seconds since the Unix epoch as RFC 3339 timestamp in UTC.
+pub fn format(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn format() {
+        assert_eq!(super::format(0), "1970-01-01T00:00:00Z");
+        assert_eq!(super::format(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(super::format(1792022399), "2026-10-14T23:59:59Z");
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, Conflict};
+use crate::db::{self, Invocation};
+use crate::pattern;
+
+/// Returns the database tool commands are recorded into, i.e., `tool_commands.json` next to the
+/// database of `config`.
+pub fn database(config: &Config) -> PathBuf {
+    config.database.with_file_name("tool_commands.json")
+}
+
+/// Code generators of Qt which `qt` in `CDBGEN_TOOLS` stands for, including the suffixed names
+/// some distributions install them as, e.g. `moc-qt5`.
+pub const QT_TOOLS: &[&str] = &["moc", "uic", "rcc", "moc-qt*", "uic-qt*", "rcc-qt*"];
+
+/// Options of the Qt code generators which take a separate value that is not an input.
+const QT_VALUED: &[&str] = &[
+    "--include",
+    "-name",
+    "--name",
+    "-root",
+    "--root",
+    "-compress",
+    "--compress",
+    "-threshold",
+    "--threshold",
+    "-tr",
+    "--tr",
+    "-M",
+];
+
+/// Returns true if `program` matches any of the patterns configured in `CDBGEN_TOOLS`.
+pub fn is_tool(config: &Config, program: &str) -> bool {
+    let name = Path::new(program)
+        .file_name()
+        .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+    config
+        .tools
+        .iter()
+        .flat_map(|p| match p.as_str() {
+            "qt" => QT_TOOLS.to_vec(),
+            p => vec![p],
+        })
+        .any(|p| pattern::glob_match(p, &name))
+}
+
+/// Returns the output named by `-o FILE`, `-oFILE`, or `--output=FILE`.
+fn output(args: &[String]) -> Option<String> {
+    let mut output = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "-o" || arg == "--output" {
+            output = iter.next().cloned();
+        } else if let Some(o) = arg.strip_prefix("--output=") {
+            output = Some(o.to_string());
+        } else if let Some(o) = arg.strip_prefix("-o") {
+            output = Some(o.to_string());
+        }
+    }
+    output
+}
+
+/// Returns the input files of a tool invocation with arguments `args` (without the tool), i.e.,
+/// the existing files in `directory` given as positional arguments, mapped to the output.
+pub fn inputs(args: &[String], directory: &Path) -> BTreeMap<String, Option<String>> {
+    let output = output(args);
+    let mut inputs = BTreeMap::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "-o" || arg == "--output" || QT_VALUED.contains(&arg.as_str()) {
+            iter.next();
+        } else if !arg.starts_with('-') && directory.join(arg).is_file() {
+            inputs.insert(arg.clone(), output.clone());
+        }
+    }
+    inputs
+}
+
+/// Records the tool `invocation` into the database at `json_path`, e.g. `tool_commands.json`,
+/// the same way compiles are recorded.
+pub fn record(json_path: &Path, invocation: &Invocation) -> Result<(), Box<dyn Error>> {
+    db::update_any(json_path, |old_entries| {
+        let mut entries = old_entries.clone();
+        db::merge(&mut entries, invocation.entries(), Conflict::Replace)?;
+        Ok(entries)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn inputs() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("parser.y").touch().unwrap();
+        temp.child("parser.c").touch().unwrap();
+        let args = strings(&["-d", "-o", "parser.c", "parser.y", "missing.y"]);
+        assert_eq!(
+            super::inputs(&args, temp.path())
+                .into_iter()
+                .collect::<Vec<_>>(),
+            [("parser.y".to_string(), Some("parser.c".to_string()))]
+        );
+        let args = strings(&["--cpp_out=gen", "parser.y"]);
+        assert_eq!(
+            super::inputs(&args, temp.path())
+                .into_iter()
+                .collect::<Vec<_>>(),
+            [("parser.y".to_string(), None)]
+        );
+        temp.child("res.qrc").touch().unwrap();
+        let args = strings(&[
+            "-name", "res.qrc", "--root", "/", "res.qrc", "-o", "qrc.cpp",
+        ]);
+        assert_eq!(
+            super::inputs(&args, temp.path())
+                .into_iter()
+                .collect::<Vec<_>>(),
+            [("res.qrc".to_string(), Some("qrc.cpp".to_string()))]
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::args;
+use crate::cli::Options;
+use crate::meta::{self, Meta};
+use crate::paths;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Unit {
+    file: String,
+    directory: String,
+    duration_ms: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct DirectoryTime {
+    directory: String,
+    units: usize,
+    total_ms: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct FlagCorrelation {
+    flag: String,
+    units: usize,
+    mean_ms: f64,
+    mean_without_ms: f64,
+    /// Mean compile time with the flag divided by the mean compile time without it.
+    ratio: f64,
+    /// Fraction of the total compile time spent in units compiled with the flag.
+    share: f64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Report {
+    total_ms: u64,
+    slowest: Vec<Unit>,
+    directories: Vec<DirectoryTime>,
+    flags: Vec<FlagCorrelation>,
+}
+
+/// Flags which are unique per unit or do not influence compile time and are therefore useless
+/// for correlations.
+fn is_interesting(flag: &args::Flag) -> bool {
+    flag.path().is_none() && !matches!(flag.name.as_str(), "-c" | "-MT" | "-MQ" | "-MD" | "-MMD")
+}
+
+fn report(meta: &Meta, n: usize) -> Report {
+    let mut units = Vec::new();
+    let mut flags_of_unit = Vec::new();
+    for entry in &meta.entries {
+        let last = match entry.history.last() {
+            Some(last) => last,
+            None => continue,
+        };
+        if let Some(duration_ms) = last.duration_ms {
+            let file = paths::resolve(&entry.directory, &entry.file)
+                .to_string_lossy()
+                .into_owned();
+            units.push(Unit {
+                file,
+                directory: entry.directory.clone(),
+                duration_ms,
+            });
+            let mut flags: Vec<String> = args::flags(last.arguments.get(1..).unwrap_or_default())
+                .iter()
+                .filter(|f| is_interesting(f))
+                .map(|f| f.joined())
+                .collect();
+            flags.sort();
+            flags.dedup();
+            flags_of_unit.push(flags);
+        }
+    }
+    let total_ms: u64 = units.iter().map(|u| u.duration_ms).sum();
+
+    let mut directories: BTreeMap<String, (usize, u64)> = BTreeMap::new();
+    for u in &units {
+        let dir = Path::new(&u.file)
+            .parent()
+            .map_or_else(String::new, |p| p.to_string_lossy().into_owned());
+        let d = directories.entry(dir).or_default();
+        d.0 += 1;
+        d.1 += u.duration_ms;
+    }
+    let mut directories: Vec<_> = directories
+        .into_iter()
+        .map(|(directory, (units, total_ms))| DirectoryTime {
+            directory,
+            units,
+            total_ms,
+        })
+        .collect();
+    directories.sort_by_key(|d| std::cmp::Reverse(d.total_ms));
+    directories.truncate(n);
+
+    let mut with: BTreeMap<&str, (usize, u64)> = BTreeMap::new();
+    for (u, flags) in units.iter().zip(&flags_of_unit) {
+        for f in flags {
+            let w = with.entry(f).or_default();
+            w.0 += 1;
+            w.1 += u.duration_ms;
+        }
+    }
+    let mut flags: Vec<_> = with
+        .into_iter()
+        .filter(|&(_, (count, _))| count < units.len())
+        .map(|(flag, (count, ms))| {
+            let mean_ms = ms as f64 / count as f64;
+            let mean_without_ms = (total_ms - ms) as f64 / (units.len() - count) as f64;
+            FlagCorrelation {
+                flag: flag.to_string(),
+                units: count,
+                mean_ms,
+                mean_without_ms,
+                ratio: if mean_without_ms > 0.0 {
+                    mean_ms / mean_without_ms
+                } else {
+                    f64::INFINITY
+                },
+                share: if total_ms > 0 {
+                    ms as f64 / total_ms as f64
+                } else {
+                    0.0
+                },
+            }
+        })
+        .collect();
+    flags.sort_by(|a, b| b.ratio.partial_cmp(&a.ratio).unwrap());
+    flags.truncate(n);
+
+    units.sort_by_key(|u| std::cmp::Reverse(u.duration_ms));
+    units.truncate(n);
+
+    Report {
+        total_ms,
+        slowest: units,
+        directories,
+        flags,
+    }
+}
+
+fn secs(ms: f64) -> String {
+    format!("{:.2}s", ms / 1000.0)
+}
+
+fn print(report: &Report) {
+    println!("Total compile time: {}", secs(report.total_ms as f64));
+    println!();
+    println!("Slowest translation units:");
+    for u in &report.slowest {
+        println!("  {:>10}  {}", secs(u.duration_ms as f64), u.file);
+    }
+    println!();
+    println!("Time per directory:");
+    for d in &report.directories {
+        println!(
+            "  {:>10}  {:>5} units  {}",
+            secs(d.total_ms as f64),
+            d.units,
+            d.directory
+        );
+    }
+    println!();
+    println!("Flags correlated with compile time:");
+    for f in &report.flags {
+        println!(
+            "  {:>6.1}x  {:>5.1}% of total  {:>5} units  mean {} vs {}  {}",
+            f.ratio,
+            100.0 * f.share,
+            f.units,
+            secs(f.mean_ms),
+            secs(f.mean_without_ms),
+            f.flag
+        );
+    }
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &["--json"], &["--db", "-n"])?;
+    let n = match opts.value("-n") {
+        Some(n) => n.parse()?,
+        None => 10,
+    };
+    let report = report(&meta::read(&opts.database())?, n);
+    if report.slowest.is_empty() {
+        return Err("no timing data recorded (build with CDBGEN_TIMING=1)".into());
+    }
+    if opts.has("--json") {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print(&report);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Invocation;
+    use std::time::Duration;
+
+    #[test]
+    fn report() {
+        let mut meta = Meta::default();
+        for (file, flags, ms) in [
+            ("a/x.c", "-O3 -flto", 9000),
+            ("a/y.c", "-O3 -flto", 7000),
+            ("b/z.c", "-O2", 1000),
+            ("b/w.c", "-O2", 1000),
+        ] {
+            let arguments: Vec<String> = std::iter::once("cc")
+                .chain(flags.split(' '))
+                .chain(["-c", file])
+                .map(str::to_string)
+                .collect();
+            let invocation = Invocation {
+                argv: arguments.clone(),
+                arguments,
+                directory: "/src".to_string(),
+                files: [(file.to_string(), None)].into_iter().collect(),
+                duration: Some(Duration::from_millis(ms)),
+                session: None,
+            };
+            meta::merge(&mut meta, &invocation, 0);
+        }
+
+        let report = super::report(&meta, 3);
+        assert_eq!(report.total_ms, 18000);
+        assert_eq!(report.slowest.len(), 3);
+        assert_eq!(report.slowest[0].file, "/src/a/x.c");
+        assert_eq!(report.directories[0].directory, "/src/a");
+        assert_eq!(report.directories[0].total_ms, 16000);
+        assert_eq!(report.flags[0].ratio, 8.0);
+        assert!(["-O3", "-flto"].contains(&report.flags[0].flag.as_str()));
+        assert_eq!(report.flags[2].flag, "-O2");
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::db;
+use crate::output;
+use crate::time;
+
+/// How often a compiler or directory was seen and what came of it.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Counts {
+    pub invocations: u64,
+    pub recorded: u64,
+    /// Invocations with nothing to record, e.g. links.
+    pub skipped: u64,
+    pub failed: u64,
+}
+
+impl Counts {
+    fn add(&mut self, outcome: &str) {
+        self.invocations += 1;
+        match outcome {
+            "recorded" => self.recorded += 1,
+            "skipped" => self.skipped += 1,
+            _ => self.failed += 1,
+        }
+    }
+}
+
+/// Accumulated statistics of the shims of a machine, kept in the file named by
+/// `CDBGEN_SELF_STATS` and never sent anywhere.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    /// Seconds since the epoch of the first and the last invocation.
+    pub since: u64,
+    pub until: u64,
+    #[serde(flatten)]
+    pub total: Counts,
+    pub compilers: BTreeMap<String, Counts>,
+    pub directories: BTreeMap<PathBuf, Counts>,
+    pub overhead_us: u64,
+    pub lock_wait_us: u64,
+}
+
+/// A single invocation as counted.
+pub struct Invocation<'a> {
+    pub timestamp: u64,
+    /// The file name of the compiler, if found.
+    pub compiler: &'a str,
+    pub directory: &'a Path,
+    pub outcome: &'a str,
+    pub overhead_us: u64,
+    pub lock_wait_us: u64,
+}
+
+impl Usage {
+    fn add(&mut self, invocation: &Invocation) {
+        if self.total.invocations == 0 {
+            self.since = invocation.timestamp;
+        }
+        self.until = invocation.timestamp;
+        self.total.add(invocation.outcome);
+        self.compilers
+            .entry(invocation.compiler.to_string())
+            .or_default()
+            .add(invocation.outcome);
+        self.directories
+            .entry(invocation.directory.into())
+            .or_default()
+            .add(invocation.outcome);
+        self.overhead_us += invocation.overhead_us;
+        self.lock_wait_us += invocation.lock_wait_us;
+    }
+
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let data = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    /// Renders the statistics for humans.
+    pub fn render(&self) -> String {
+        let percent = |n: u64| 100.0 * n as f64 / self.total.invocations.max(1) as f64;
+        let mut out = format!(
+            "{} invocations from {} to {}: {} recorded ({:.1}%), {} skipped, {} failed\n",
+            self.total.invocations,
+            time::format(self.since),
+            time::format(self.until),
+            self.total.recorded,
+            percent(self.total.recorded),
+            self.total.skipped,
+            self.total.failed
+        );
+        out += &format!(
+            "overhead {:.3}s in total, {:.3}ms per invocation, {:.3}s waiting for locks\n",
+            self.overhead_us as f64 / 1e6,
+            self.overhead_us as f64 / 1e3 / self.total.invocations.max(1) as f64,
+            self.lock_wait_us as f64 / 1e6
+        );
+        out += "Compilers:\n";
+        for (compiler, c) in &self.compilers {
+            out += &format!(
+                "  {:>8} {:>8} recorded  {}\n",
+                c.invocations, c.recorded, compiler
+            );
+        }
+        out += "Directories:\n";
+        for (directory, c) in &self.directories {
+            out += &format!(
+                "  {:>8} {:>8} recorded  {}\n",
+                c.invocations,
+                c.recorded,
+                directory.display()
+            );
+        }
+        out
+    }
+}
+
+/// Returns the file named by `CDBGEN_SELF_STATS`, if any.
+pub fn path() -> Option<PathBuf> {
+    env::var_os("CDBGEN_SELF_STATS")
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Adds `invocation` to the statistics at `path`.  Statistics which do not parse are warned
+/// about and started over.
+pub fn count(path: &Path, invocation: &Invocation) -> Result<(), Box<dyn Error>> {
+    let corrupt = |error| {
+        output::warn(&format!(
+            "ignoring the corrupt statistics in {}: {}",
+            path.display(),
+            error
+        ));
+        Ok(Usage::default())
+    };
+    db::update_json_or(path, corrupt, |usage: &mut Usage| {
+        usage.add(invocation);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let path = temp.path().join("stats.json");
+        let invocation = |timestamp, compiler, outcome| Invocation {
+            timestamp,
+            compiler,
+            directory: Path::new("/src"),
+            outcome,
+            overhead_us: 100,
+            lock_wait_us: 10,
+        };
+        super::count(&path, &invocation(1, "gcc", "recorded")).unwrap();
+        super::count(&path, &invocation(2, "gcc", "skipped")).unwrap();
+        super::count(&path, &invocation(3, "clang", "failed")).unwrap();
+        let corrupt = temp.path().join("corrupt.json");
+        std::fs::write(&corrupt, "{bad").unwrap();
+        super::count(&corrupt, &invocation(1, "gcc", "recorded")).unwrap();
+        assert_eq!(Usage::read(&corrupt).unwrap().total.invocations, 1);
+        let usage = Usage::read(&path).unwrap();
+        assert_eq!((usage.since, usage.until), (1, 3));
+        assert_eq!(
+            usage.total,
+            Counts {
+                invocations: 3,
+                recorded: 1,
+                skipped: 1,
+                failed: 1
+            }
+        );
+        assert_eq!(usage.compilers["gcc"].invocations, 2);
+        assert_eq!(usage.directories[Path::new("/src")].invocations, 3);
+        assert_eq!((usage.overhead_us, usage.lock_wait_us), (300, 30));
+    }
+}
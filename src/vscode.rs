@@ -0,0 +1,297 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::args;
+use crate::cli::Options;
+use crate::db::{self, Entry};
+use crate::paths;
+
+/// A configuration as understood by the Microsoft C/C++ extension of VS Code.
+#[derive(Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Configuration {
+    name: String,
+    include_path: Vec<String>,
+    defines: Vec<String>,
+    compiler_path: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    compiler_args: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    c_standard: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cpp_standard: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    intelli_sense_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compile_commands: Option<String>,
+}
+
+/// Maps the value of `-std=` to the spelling the extension expects.
+pub fn standard(std: &str) -> String {
+    let (prefix, version) = match std.find(|c: char| c.is_ascii_digit()) {
+        Some(pos) => std.split_at(pos),
+        None => return std.to_string(),
+    };
+    let version = match (prefix.ends_with("++"), version) {
+        (true, "0x") => "11",
+        (true, "1y") => "14",
+        (true, "1z") => "17",
+        (true, "2a") => "20",
+        (true, "2b") => "23",
+        (false, "9x") => "99",
+        (false, "1x") => "11",
+        (false, "18") => "17",
+        (false, "2x") => "23",
+        (_, v) => v,
+    };
+    format!("{}{}", prefix, version)
+}
+
+fn intelli_sense_mode(compiler: &str) -> Option<String> {
+    let name = Path::new(compiler)
+        .file_name()?
+        .to_string_lossy()
+        .to_lowercase();
+    let platform = if cfg!(target_os = "macos") {
+        "macos"
+    } else if cfg!(windows) {
+        "windows"
+    } else {
+        "linux"
+    };
+    let kind = if name.contains("clang") {
+        "clang"
+    } else if name == "cl" || name == "cl.exe" {
+        return Some("windows-msvc-x64".to_string());
+    } else if name.contains("gcc") || name.contains("g++") || name == "cc" || name == "c++" {
+        "gcc"
+    } else {
+        return None;
+    };
+    let arch = if name.starts_with("arm") || name.starts_with("aarch64") {
+        "arm64"
+    } else {
+        "x64"
+    };
+    Some(format!("{}-{}-{}", platform, kind, arch))
+}
+
+/// Returns the most frequent element of `values`, preferring the smallest one on ties.
+fn most_common(values: Vec<String>) -> Option<String> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for v in values {
+        *counts.entry(v).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .fold(None, |best: Option<(String, usize)>, (v, n)| match best {
+            Some((_, m)) if m >= n => best,
+            _ => Some((v, n)),
+        })
+        .map(|(v, _)| v)
+}
+
+fn push_unique(values: &mut Vec<String>, value: String) {
+    if !values.contains(&value) {
+        values.push(value);
+    }
+}
+
+/// Derives one configuration per compiler from the entries of the database.
+fn configurations(entries: &BTreeSet<Entry>, compile_commands: Option<&str>) -> Vec<Configuration> {
+    let mut by_compiler: BTreeMap<&str, Vec<&Entry>> = BTreeMap::new();
+    for e in entries {
+        if let Some(compiler) = e.arguments.first() {
+            by_compiler.entry(compiler).or_default().push(e);
+        }
+    }
+
+    let mut names = BTreeSet::new();
+    by_compiler
+        .into_iter()
+        .map(|(compiler, entries)| {
+            let base = Path::new(compiler).file_name().map_or_else(
+                || compiler.to_string(),
+                |n| n.to_string_lossy().into_owned(),
+            );
+            let mut name = base.clone();
+            let mut i = 1;
+            while !names.insert(name.clone()) {
+                i += 1;
+                name = format!("{}-{}", base, i);
+            }
+
+            let mut conf = Configuration {
+                name,
+                compiler_path: compiler.to_string(),
+                intelli_sense_mode: intelli_sense_mode(compiler),
+                compile_commands: compile_commands.map(str::to_string),
+                ..Configuration::default()
+            };
+            let (mut c_std, mut cpp_std) = (Vec::new(), Vec::new());
+            for e in entries {
+                let arguments = e.arguments.get(1..).unwrap_or_default();
+                let is_cpp = args::language(arguments, &e.file).map_or(false, |l| l.contains("++"));
+                for flag in args::flags(arguments) {
+                    match (flag.name.as_str(), flag.value) {
+                        ("-I" | "-isystem" | "-iquote" | "-idirafter", Some(path)) => {
+                            let path = paths::resolve(&e.directory, &path);
+                            let path = path.to_string_lossy().into_owned();
+                            push_unique(&mut conf.include_path, path);
+                        }
+                        ("-D", Some(define)) => push_unique(&mut conf.defines, define),
+                        ("-target", Some(value)) => {
+                            push_unique(&mut conf.compiler_args, format!("--target={}", value))
+                        }
+                        ("--sysroot" | "--sysroot=", Some(value)) => {
+                            push_unique(&mut conf.compiler_args, format!("--sysroot={}", value))
+                        }
+                        (name, None) if name.starts_with("-std=") => {
+                            let std = standard(&name["-std=".len()..]);
+                            if is_cpp {
+                                cpp_std.push(std);
+                            } else {
+                                c_std.push(std);
+                            }
+                        }
+                        (name, None) if name.starts_with("-m") || name.starts_with("--target=") => {
+                            push_unique(&mut conf.compiler_args, name.to_string())
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            conf.c_standard = most_common(c_std);
+            conf.cpp_standard = most_common(cpp_std);
+            conf
+        })
+        .collect()
+}
+
+/// Merges `configurations` into the existing properties `props` by replacing configurations of
+/// the same name and keeping all others.
+fn merge(props: &mut Value, configurations: Vec<Configuration>) -> Result<(), Box<dyn Error>> {
+    let obj = props
+        .as_object_mut()
+        .ok_or("c_cpp_properties.json is not a JSON object")?;
+    obj.entry("version").or_insert(Value::from(4));
+    let list = obj
+        .entry("configurations")
+        .or_insert_with(|| Value::Array(Vec::new()))
+        .as_array_mut()
+        .ok_or("configurations of c_cpp_properties.json are not an array")?;
+    for conf in configurations {
+        let value = serde_json::to_value(&conf)?;
+        match list
+            .iter_mut()
+            .find(|c| c.get("name").and_then(Value::as_str) == Some(&conf.name))
+        {
+            Some(existing) => *existing = value,
+            None => list.push(value),
+        }
+    }
+    Ok(())
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(args, &["--no-compile-commands"], &["--db", "-o"])?;
+    let json_path = opts.database();
+    let entries = db::read_entries(&json_path)?;
+    let compile_commands = if opts.has("--no-compile-commands") {
+        None
+    } else {
+        Some(paths::normalize(&env::current_dir()?.join(&json_path)))
+    };
+    let configurations = configurations(
+        &entries,
+        compile_commands
+            .as_ref()
+            .map(|p| p.to_string_lossy())
+            .as_deref(),
+    );
+
+    let output = opts.value("-o").map_or_else(
+        || PathBuf::from(".vscode/c_cpp_properties.json"),
+        PathBuf::from,
+    );
+    if output == Path::new("-") {
+        let mut props = serde_json::json!({});
+        merge(&mut props, configurations)?;
+        println!("{}", serde_json::to_string_pretty(&props)?);
+        return Ok(());
+    }
+    let mut props = match fs::read_to_string(&output) {
+        Ok(data) => serde_json::from_str(&data)?,
+        Err(error) if error.kind() == ErrorKind::NotFound => serde_json::json!({}),
+        Err(error) => return Err(error.into()),
+    };
+    merge(&mut props, configurations)?;
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&output, serde_json::to_string_pretty(&props)? + "\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard() {
+        assert_eq!(super::standard("c++1z"), "c++17");
+        assert_eq!(super::standard("gnu++2a"), "gnu++20");
+        assert_eq!(super::standard("gnu11"), "gnu11");
+        assert_eq!(super::standard("c18"), "c17");
+    }
+
+    #[test]
+    fn configurations() {
+        let entries: BTreeSet<_> = [
+            db::entry(
+                "/proj",
+                "a.c",
+                &["/usr/bin/gcc", "-Iinc", "-DFOO=1", "-std=gnu11", "a.c"],
+            ),
+            db::entry(
+                "/proj",
+                "b.cc",
+                &["/usr/bin/gcc", "-I", "inc", "-std=c++1z", "-m32", "b.cc"],
+            ),
+            db::entry(
+                "/proj",
+                "c.c",
+                &["/opt/bin/clang", "-isystem", "/sys", "c.c"],
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let confs = super::configurations(&entries, None);
+        assert_eq!(confs.len(), 2);
+        assert_eq!(confs[0].name, "clang");
+        assert_eq!(confs[0].include_path, ["/sys"]);
+        assert_eq!(confs[1].name, "gcc");
+        assert_eq!(confs[1].include_path, ["/proj/inc"]);
+        assert_eq!(confs[1].defines, ["FOO=1"]);
+        assert_eq!(confs[1].compiler_args, ["-m32"]);
+        assert_eq!(confs[1].c_standard.as_deref(), Some("gnu11"));
+        assert_eq!(confs[1].cpp_standard.as_deref(), Some("c++17"));
+
+        let mut props = serde_json::json!({
+            "configurations": [{"name": "gcc"}, {"name": "custom"}],
+            "version": 4
+        });
+        merge(&mut props, confs).unwrap();
+        let list = props["configurations"].as_array().unwrap();
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[0]["compilerPath"], "/usr/bin/gcc");
+        assert_eq!(list[1]["name"], "custom");
+    }
+}
@@ -0,0 +1,180 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::cli::Options;
+use crate::db;
+use crate::fragment;
+use crate::fsmonitor::{self, Monitor};
+use crate::output;
+use crate::time;
+
+/// Notifications about fragments completed in a directory.  These are only implemented by
+/// inotify, i.e., on Linux; elsewhere the directory is polled each `wait`, e.g. on macOS, where
+/// kqueue or FSEvents are not used.
+struct Watcher {
+    #[cfg(target_os = "linux")]
+    fd: i32,
+}
+
+impl Watcher {
+    /// Watches `dir` for fragments renamed into place by inotify.
+    #[cfg(target_os = "linux")]
+    fn new(dir: &Path) -> Result<Self, Box<dyn Error>> {
+        use std::os::unix::ffi::OsStrExt;
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let watcher = Watcher { fd };
+        let path = std::ffi::CString::new(dir.as_os_str().as_bytes())?;
+        let mask = libc::IN_MOVED_TO | libc::IN_CLOSE_WRITE;
+        if unsafe { libc::inotify_add_watch(fd, path.as_ptr(), mask) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(watcher)
+    }
+
+    /// Elsewhere the directory is polled.
+    #[cfg(not(target_os = "linux"))]
+    fn new(_: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(Watcher {})
+    }
+
+    /// Waits until something changed in the directory or `timeout` passed.  Returns true in the
+    /// former case.
+    #[cfg(target_os = "linux")]
+    fn wait(&self, timeout: Duration) -> Result<bool, Box<dyn Error>> {
+        let mut poll = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+        let ret = unsafe { libc::poll(&mut poll, 1, timeout) };
+        if ret < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() != std::io::ErrorKind::Interrupted {
+                return Err(error.into());
+            }
+        }
+        if ret > 0 {
+            // Only the fact that something changed matters.
+            let mut buffer = [0u8; 4096];
+            unsafe {
+                libc::read(
+                    self.fd,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                )
+            };
+        }
+        Ok(ret > 0)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn wait(&self, timeout: Duration) -> Result<bool, Box<dyn Error>> {
+        std::thread::sleep(timeout);
+        Ok(true)
+    }
+
+    /// Waits after a change until no further change happened for `debounce`, but at most
+    /// `max_staleness` in total, such that a burst of fragments is merged by a single rewrite.
+    fn settle(&self, debounce: Duration, max_staleness: Duration) -> Result<(), Box<dyn Error>> {
+        let start = Instant::now();
+        loop {
+            let left = max_staleness.saturating_sub(start.elapsed());
+            if left.is_zero() || !self.wait(debounce.min(left))? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse(
+        args,
+        &[],
+        &[
+            "--db",
+            "--interval",
+            "--debounce",
+            "--max-staleness",
+            "--prune-deleted",
+            "--root",
+        ],
+    )?;
+    let json_path = opts.database();
+    // Entries staged in shared memory raise no events and are picked up each interval.
+    let interval = time::parse_duration(opts.value("--interval").unwrap_or("5s"))?;
+    let debounce = time::parse_duration(opts.value("--debounce").unwrap_or("0ms"))?;
+    let max_staleness = time::parse_duration(opts.value("--max-staleness").unwrap_or("10s"))?;
+    let dir = fragment::dir(&json_path);
+    fs::create_dir_all(&dir)?;
+    let watcher = Watcher::new(&dir)?;
+    let mut monitor = match opts.value("--prune-deleted") {
+        Some(kind) => Some(Monitor::new(
+            kind,
+            Path::new(opts.value("--root").unwrap_or(".")),
+        )?),
+        None => None,
+    };
+    output::info(&format!(
+        "watching {} for {}",
+        dir.display(),
+        json_path.display()
+    ));
+    loop {
+        db::merge_fragments(&json_path)?;
+        if let Some(monitor) = &mut monitor {
+            // The monitor may fail for a while, e.g. while Watchman restarts.
+            match monitor
+                .deleted()
+                .and_then(|deleted| fsmonitor::prune_deleted(&json_path, &deleted))
+            {
+                Ok(0) => (),
+                Ok(removed) => {
+                    output::info(&format!("pruned {} entries of deleted sources", removed))
+                }
+                Err(error) => output::warn(&format!("cannot prune deleted sources: {}", error)),
+            }
+        }
+        if watcher.wait(interval)? && !debounce.is_zero() {
+            watcher.settle(debounce, max_staleness)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn wait() {
+        use std::time::{Duration, Instant};
+        let temp = assert_fs::TempDir::new().unwrap();
+        let json_path = temp.path().join("compile_commands.json");
+        let dir = crate::fragment::dir(&json_path);
+        std::fs::create_dir_all(&dir).unwrap();
+        let watcher = super::Watcher::new(&dir).unwrap();
+        crate::fragment::write(&json_path, Vec::new()).unwrap();
+        let start = Instant::now();
+        assert!(watcher.wait(Duration::from_secs(30)).unwrap());
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(!watcher.wait(Duration::from_millis(10)).unwrap());
+
+        // A burst is waited for up to the staleness bound.
+        let start = Instant::now();
+        watcher
+            .settle(Duration::from_millis(50), Duration::from_millis(200))
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_millis(200));
+    }
+}
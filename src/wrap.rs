@@ -0,0 +1,319 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::time::Duration;
+
+use which::which;
+
+use crate::background;
+use crate::cli::Options;
+use crate::config::Session;
+use crate::output;
+use crate::paths;
+use crate::session;
+use crate::shims::{self, TempShimDir, KNOWN_COMPILERS};
+use crate::sidecar;
+use crate::summary::{Changes, Summary};
+use crate::sweep;
+
+/// Environment variables naming compilers which build systems commonly respect.
+const COMPILER_VARS: &[&str] = &["CC", "CXX"];
+
+/// Rewrites the compiler variable `value` such that its compiler is invoked through a shim, e.g.,
+/// `gcc -m32` becomes `cdbgen-gcc -m32`.  The name of the compiler is added to `compilers`
+/// together with its path if given by one, e.g. `/opt/bin/gcc`, which the shim is to run rather
+/// than the compiler of that name on `PATH`.
+fn wrap_compiler_var(value: &str, compilers: &mut BTreeMap<String, Option<PathBuf>>) -> String {
+    let mut words = value.splitn(2, ' ');
+    let program = words.next().unwrap_or_default();
+    let name = Path::new(program)
+        .file_name()
+        .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+    if name.is_empty() || name.starts_with("cdbgen-") {
+        return value.to_string();
+    }
+    let path = Some(PathBuf::from(program)).filter(|_| program != name);
+    compilers.insert(name.clone(), path);
+    match words.next() {
+        Some(rest) => format!("cdbgen-{} {}", name, rest),
+        None => format!("cdbgen-{}", name),
+    }
+}
+
+/// The environment of a build which is wrapped by cdbgen, i.e., a temporary directory with shims
+/// for all compilers which is put in front of `PATH`, and compiler variables pointing to them.
+pub struct Wrapper {
+    shims: TempShimDir,
+    vars: Vec<(String, OsString)>,
+    database: PathBuf,
+}
+
+impl Wrapper {
+    /// Prepares the environment of a build recording into the database at `database`.
+    pub fn new(database: &Path) -> Result<Self, Box<dyn Error>> {
+        let shims = TempShimDir::new()?;
+        let mut compilers: BTreeMap<String, Option<PathBuf>> = KNOWN_COMPILERS
+            .iter()
+            .filter(|c| which(c).is_ok())
+            .map(|c| (c.to_string(), None))
+            .collect();
+        let mut vars = Vec::new();
+
+        for &var in COMPILER_VARS {
+            let default = if var == "CC" { "cc" } else { "c++" };
+            let value = match env::var(var) {
+                Ok(value) => value,
+                Err(_) if which(default).is_ok() => default.to_string(),
+                Err(_) => continue,
+            };
+            vars.push((
+                var.to_string(),
+                wrap_compiler_var(&value, &mut compilers).into(),
+            ));
+        }
+        let cwd = env::current_dir()?;
+        for (compiler, path) in &compilers {
+            let (shim, _) = shims::create_shim(shims.path(), compiler)?;
+            if let Some(path) = path {
+                sidecar::write_for(&shim, &paths::normalize(&cwd.join(path)))?;
+            }
+        }
+
+        let mut path = vec![shims.path().to_path_buf()];
+        if let Some(old) = env::var_os("PATH") {
+            path.extend(env::split_paths(&old));
+        }
+        vars.push(("PATH".to_string(), env::join_paths(path)?));
+        let database = paths::normalize(&env::current_dir()?.join(database));
+        vars.push(("CDBGEN".to_string(), database.clone().into_os_string()));
+        vars.push(("CDBGEN_CAPTURE".to_string(), "wrap".into()));
+
+        Ok(Wrapper {
+            shims,
+            vars,
+            database,
+        })
+    }
+
+    /// Sets the environment variable `name` for the build.
+    pub fn set<S: Into<OsString>>(&mut self, name: &str, value: S) {
+        self.vars.push((name.to_string(), value.into()));
+    }
+
+    /// The absolute path of the database the build records into.
+    pub fn database(&self) -> &Path {
+        &self.database
+    }
+
+    pub fn shim_dir(&self) -> &Path {
+        self.shims.path()
+    }
+
+    /// Returns a command running `program` in the wrapped environment.
+    pub fn command<S: AsRef<std::ffi::OsStr>>(&self, program: S) -> Command {
+        let mut cmd = Command::new(program);
+        cmd.envs(self.vars.iter().map(|(k, v)| (k, v)));
+        cmd
+    }
+}
+
+/// Exits with the same status as a child process did.
+pub fn exit_like(status: ExitStatus) -> ! {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            std::process::exit(128 + signal);
+        }
+    }
+    std::process::exit(status.code().unwrap_or(1))
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse_command(
+        args,
+        &["--sweep", "--quiet"],
+        &["--db", "--build-id", "--summary-json"],
+    )?;
+    let (program, program_args) = opts.rest.split_first().ok_or(
+        "usage: cdbgen wrap [--db DB] [--build-id ID] [--sweep] [--quiet] [--summary-json FILE] \
+         [--] COMMAND [ARGS...]",
+    )?;
+    let mut wrapper = Wrapper::new(&opts.database())?;
+    let session = opts.value("--build-id").map(|build_id| Session {
+        build_id: build_id.to_string(),
+        id: session::new_id(),
+    });
+    if let Some(session) = &session {
+        wrapper.set("CDBGEN_BUILD_ID", &session.build_id);
+        wrapper.set("CDBGEN_SESSION", &session.id);
+    }
+    let mark_file = wrapper.shim_dir().join("marks");
+    if opts.has("--sweep") {
+        wrapper.set("CDBGEN_MARK", &mark_file);
+    }
+    // A log of the user is kept and summed up from where this build starts.
+    let events = env::var_os("CDBGEN_EVENTS")
+        .map_or_else(|| wrapper.shim_dir().join("events.jsonl"), PathBuf::from);
+    let offset = fs::metadata(&events).map_or(0, |m| m.len());
+    wrapper.set("CDBGEN_EVENTS", &events);
+    // Helpers recording in the background log their events once done.
+    let pending = wrapper.shim_dir().join("pending");
+    fs::create_dir_all(&pending)?;
+    wrapper.set("CDBGEN_PENDING", &pending);
+    let status = wrapper.command(program).args(program_args).status()?;
+    if !background::wait(&pending, Duration::from_secs(10))? {
+        output::warn("recording in the background is still running; the summary is incomplete");
+    }
+    let mut summary = Summary::read(&events, offset)?;
+    if status.success() {
+        summary.removed = clean_up(&opts, &wrapper, &mark_file, session.as_ref())?;
+    }
+    if !opts.has("--quiet") && !output::is_quiet() {
+        eprint!("{}", summary.render());
+    }
+    if let Some(warning) = nothing_recorded(&summary, wrapper.database()) {
+        if !output::is_quiet() {
+            eprint!("{}", warning);
+        }
+    }
+    if let Some(path) = opts.value("--summary-json") {
+        fs::write(path, serde_json::to_string_pretty(&summary)? + "\n")?;
+    }
+    if !status.success() {
+        // Exiting skips the destructors, i.e., the shims would be left behind.
+        drop(wrapper);
+        exit_like(status);
+    }
+    Ok(())
+}
+
+/// Returns a warning with the likely causes if the build recorded no entry into `database`.
+fn nothing_recorded(summary: &Summary, database: &Path) -> Option<String> {
+    let total = |c: &Changes| c.added + c.updated + c.unchanged + c.deferred;
+    if summary.databases.get(database).map_or(0, total) > 0 {
+        return None;
+    }
+    let mut warning = format!(
+        "cdbgen: warning: the build recorded no entries into {}\n",
+        database.display()
+    );
+    let elsewhere: Vec<_> = summary
+        .databases
+        .iter()
+        .filter(|(_, c)| total(c) > 0)
+        .map(|(path, _)| path.display().to_string())
+        .collect();
+    if !elsewhere.is_empty() {
+        warning += &format!(
+            "cdbgen:   entries were recorded into {} instead; check the database of the shim \
+             configurations and CDBGEN_ALSO\n",
+            elsewhere.join(", ")
+        );
+    }
+    if summary.invocations == 0 {
+        warning += "cdbgen:   no shim was invoked, e.g. since the build invokes compilers by \
+                    absolute path or ignores CC and CXX; set them explicitly or use the shims of \
+                    'cdbgen install'\n";
+    }
+    if !summary.misses.is_empty() {
+        warning += "cdbgen:   shims were invoked without source files, e.g. since the build only \
+                    links or uses extensions cdbgen does not know; pass the language by -x\n";
+    }
+    if summary.failed > 0 {
+        warning += "cdbgen:   recording failed, see the errors above\n";
+    }
+    Some(warning)
+}
+
+/// Removes the entries not compiled by the successful build as requested by `--sweep` and
+/// `--build-id`, and returns how many were removed.
+fn clean_up(
+    opts: &Options,
+    wrapper: &Wrapper,
+    mark_file: &Path,
+    session: Option<&Session>,
+) -> Result<u64, Box<dyn Error>> {
+    let mut total = 0;
+    if opts.has("--sweep") {
+        let removed = sweep::sweep(mark_file)?;
+        if removed > 0 {
+            output::info(&format!(
+                "cdbgen: swept {} entries not compiled by the build",
+                removed
+            ));
+        }
+        total += removed as u64;
+    }
+    // Only a successful build is known to have compiled everything it consists of.
+    if let Some(session) = session {
+        let removed = session::prune(wrapper.database(), session)?;
+        if removed > 0 {
+            output::info(&format!(
+                "cdbgen: removed {} stale entries of build '{}'",
+                removed, session.build_id
+            ));
+        }
+        total += removed as u64;
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_recorded() {
+        let database = Path::new("/src/compile_commands.json");
+        let mut summary = Summary::default();
+        let warning = super::nothing_recorded(&summary, database).unwrap();
+        assert!(warning.contains("no shim was invoked"));
+        summary.invocations = 2;
+        summary.misses.insert("gcc".to_string(), 1);
+        let unchanged = Changes {
+            unchanged: 1,
+            ..Changes::default()
+        };
+        summary
+            .databases
+            .insert(PathBuf::from("/other.json"), unchanged);
+        let warning = super::nothing_recorded(&summary, database).unwrap();
+        assert!(!warning.contains("no shim was invoked"));
+        assert!(warning.contains("without source files"));
+        assert!(warning.contains("recorded into /other.json instead"));
+        summary.databases.insert(database.to_path_buf(), unchanged);
+        assert_eq!(super::nothing_recorded(&summary, database), None);
+    }
+
+    #[test]
+    fn wrap_compiler_var() {
+        let mut compilers = BTreeMap::new();
+        assert_eq!(
+            super::wrap_compiler_var("gcc -m32", &mut compilers),
+            "cdbgen-gcc -m32"
+        );
+        assert_eq!(
+            super::wrap_compiler_var("/opt/bin/arm-none-eabi-gcc", &mut compilers),
+            "cdbgen-arm-none-eabi-gcc"
+        );
+        assert_eq!(
+            super::wrap_compiler_var("cdbgen-clang", &mut compilers),
+            "cdbgen-clang"
+        );
+        assert_eq!(
+            compilers.into_iter().collect::<Vec<_>>(),
+            [
+                (
+                    "arm-none-eabi-gcc".to_string(),
+                    Some(PathBuf::from("/opt/bin/arm-none-eabi-gcc"))
+                ),
+                ("gcc".to_string(), None)
+            ]
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+use crate::cli::Options;
+use crate::config::Conflict;
+use crate::db::{self, Entry};
+use crate::fragment;
+use crate::output;
+use crate::paths;
+use crate::relocate;
+use crate::wrap;
+
+/// Returns the configuration BitBake reads after `local.conf` by `-R`: cdbgen becomes the
+/// launcher of the compilers by `CCACHE` which BitBake puts in front of them and leaves out of
+/// the task signatures, each recipe is recorded into a database of its own in `recipes`, and
+/// pseudo does not track the files of cdbgen in `state`.
+fn conf(cdbgen: &Path, recipes: &Path, state: &Path) -> String {
+    format!(
+        "# Written by 'cdbgen yocto'.\n\
+         CCACHE:forcevariable = \"{} \"\n\
+         export CDBGEN = \"{}/${{PN}}/compile_commands.json\"\n\
+         export CDBGEN_CAPTURE = \"yocto\"\n\
+         BB_BASEHASH_IGNORE_VARS:append = \" CDBGEN CDBGEN_CAPTURE\"\n\
+         PSEUDO_IGNORE_PATHS:append = \",{}\"\n",
+        cdbgen.display(),
+        recipes.display(),
+        state.display()
+    )
+}
+
+/// Returns the work directory of the recipe `pn` containing `path`, i.e., the
+/// `TMPDIR/work/ARCH/PN/VERSION` above it.
+fn workdir(path: &str, pn: &str) -> Option<PathBuf> {
+    let components: Vec<Component> = Path::new(path).components().collect();
+    let i = (0..components.len().saturating_sub(3))
+        .rev()
+        .find(|&i| components[i].as_os_str() == "work" && components[i + 2].as_os_str() == pn)?;
+    Some(components[..i + 4].iter().collect())
+}
+
+#[cfg(unix)]
+fn link(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn link(_target: &Path, _link: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "symbolic links are not supported",
+    ))
+}
+
+/// Returns the `entries` of the recipe `pn` with the paths below its work directory remapped to
+/// the stable link in `links` which is pointed to it.  The work directory changes with the version
+/// of the recipe, so only entries of the most recent one which still exists are kept.  Entries
+/// outside of any work directory, e.g. of `devtool modify` or `externalsrc`, are kept as they are.
+fn stable(entries: &BTreeSet<Entry>, pn: &str, links: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let outside = entries
+        .iter()
+        .filter(|e| workdir(&e.directory, pn).is_none())
+        .cloned();
+    let modified = |dir: &Path| fs::metadata(dir).and_then(|m| m.modified()).ok();
+    let current = entries
+        .iter()
+        .filter_map(|e| workdir(&e.directory, pn))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter_map(|dir| Some((modified(&dir)?, dir)))
+        .max_by_key(|(time, _): &(SystemTime, PathBuf)| *time)
+        .map(|(_, dir)| dir);
+    let current = match current {
+        Some(current) => current,
+        None => return Ok(outside.collect()),
+    };
+    fs::create_dir_all(links)?;
+    let stable = links.join(pn);
+    if fs::symlink_metadata(&stable).is_ok() {
+        fs::remove_file(&stable)?;
+    }
+    link(&current, &stable)?;
+    let prefixes = [(current.clone(), stable)];
+    Ok(entries
+        .iter()
+        .filter(|e| workdir(&e.directory, pn).as_ref() == Some(&current))
+        .map(|e| relocate::remap(e, &prefixes))
+        .chain(outside)
+        .collect())
+}
+
+/// Merges the databases of the recipes in `recipes` into the one at `json_path`, linking the work
+/// directories from `links`.  Returns the number of entries merged.
+fn merge(json_path: &Path, recipes: &Path, links: &Path) -> Result<usize, Box<dyn Error>> {
+    let mut merged = Vec::new();
+    for dir in fs::read_dir(recipes)?.flatten() {
+        let recipe_db = dir.path().join("compile_commands.json");
+        if !recipe_db.is_file() {
+            continue;
+        }
+        let pn = dir.file_name().to_string_lossy().into_owned();
+        merged.extend(stable(&db::read_entries(&recipe_db)?, &pn, links)?);
+    }
+    let count = merged.len();
+    db::update_any(json_path, |entries| {
+        let mut entries = entries.clone();
+        db::merge(&mut entries, merged, Conflict::Replace)?;
+        Ok(entries)
+    })?;
+    Ok(count)
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse_command(args, &["--merge-only"], &["--db", "--bitbake"])?;
+    let cwd = env::current_dir()?;
+    let build_dir = env::var_os("BUILDDIR").map_or_else(|| cwd.clone(), PathBuf::from);
+    if !build_dir.join("conf/local.conf").is_file() {
+        return Err(format!(
+            "{} is no build directory of BitBake; source oe-init-build-env first",
+            build_dir.display()
+        )
+        .into());
+    }
+    let json_path = match opts.value("--db") {
+        Some(db) => paths::normalize(&cwd.join(db)),
+        None => build_dir.join("compile_commands.json"),
+    };
+    let state = fragment::dir(&json_path);
+    let recipes = state.join("yocto");
+    let links = state.join("yocto-work");
+    fs::create_dir_all(&recipes)?;
+    let mut status = None;
+    if !opts.has("--merge-only") {
+        let conf_path = state.join("yocto.conf");
+        fs::write(&conf_path, conf(&env::current_exe()?, &recipes, &state))?;
+        let bitbake = opts.value("--bitbake").unwrap_or("bitbake");
+        let build = match opts.rest.split_first() {
+            Some((sep, build)) if sep == "--" => build,
+            _ => &opts.rest[..],
+        };
+        // Partial builds are merged as well.
+        status = Some(
+            Command::new(bitbake)
+                .arg("-R")
+                .arg(&conf_path)
+                .args(build)
+                .current_dir(&build_dir)
+                .status()
+                .map_err(|error| format!("cannot run '{}': {}", bitbake, error))?,
+        );
+    }
+    let entries = merge(&json_path, &recipes, &links)?;
+    output::info(&format!(
+        "merged {} entries into {}",
+        entries,
+        json_path.display()
+    ));
+    match status {
+        Some(status) if !status.success() => wrap::exit_like(status),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let work = temp.path().join("tmp/work/core2-64-poky-linux/foo");
+        let entry = |version: &str| {
+            let sysroot = format!("--sysroot={}/{}/recipe-sysroot", work.display(), version);
+            db::entry(
+                &work.join(version).join("build").to_string_lossy(),
+                "../git/a.c",
+                &["x86_64-poky-linux-gcc", &sysroot, "-c", "../git/a.c"],
+            )
+        };
+        fs::create_dir_all(work.join("1.1-r0")).unwrap();
+        let recipes = temp.path().join("recipes");
+        fs::create_dir_all(recipes.join("foo")).unwrap();
+        // Built from a source tree of devtool outside of the work directory.
+        let external = Entry {
+            directory: temp
+                .path()
+                .join("workspace/sources/foo")
+                .to_string_lossy()
+                .into_owned(),
+            file: "b.c".to_string(),
+            arguments: vec!["x86_64-poky-linux-gcc".to_string(), "b.c".to_string()],
+            output: None,
+        };
+        fs::write(
+            recipes.join("foo/compile_commands.json"),
+            serde_json::to_string(&[entry("1.0-r0"), entry("1.1-r0"), external.clone()]).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            workdir(&entry("1.1-r0").directory, "foo").unwrap(),
+            work.join("1.1-r0")
+        );
+
+        let json_path = temp.path().join("compile_commands.json");
+        let links = temp.path().join("links");
+        assert_eq!(super::merge(&json_path, &recipes, &links).unwrap(), 2);
+        let stable = links.join("foo");
+        assert_eq!(fs::read_link(&stable).unwrap(), work.join("1.1-r0"));
+        let entries: Vec<_> = db::read_entries(&json_path).unwrap().into_iter().collect();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.contains(&external));
+        let entries: Vec<_> = entries.into_iter().filter(|e| *e != external).collect();
+        assert_eq!(entries[0].directory, stable.join("build").to_string_lossy());
+        assert_eq!(
+            entries[0].arguments[1],
+            format!("--sysroot={}/recipe-sysroot", stable.display())
+        );
+    }
+}
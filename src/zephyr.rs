@@ -0,0 +1,103 @@
+use std::env;
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::cli::Options;
+use crate::output;
+use crate::paths;
+use crate::wrap;
+
+/// Returns the arguments of `west` building with `args` where cdbgen at `cdbgen` is the compiler
+/// launcher of CMake.  The definitions go after the `--` separating the arguments of CMake, which
+/// is added unless given.
+fn west_args(args: &[String], cdbgen: &str) -> Vec<String> {
+    let mut result = vec!["build".to_string()];
+    result.extend(args.iter().cloned());
+    if !args.iter().any(|a| a == "--") {
+        result.push("--".to_string());
+    }
+    for language in ["C", "CXX"] {
+        result.push(format!("-DCMAKE_{}_COMPILER_LAUNCHER={}", language, cdbgen));
+    }
+    result
+}
+
+/// Returns the root of the west workspace containing the current directory.
+fn topdir(west: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let output = Command::new(west)
+        .arg("topdir")
+        .output()
+        .map_err(|error| format!("cannot run '{}': {}", west, error))?;
+    if !output.status.success() {
+        return Err(format!(
+            "not in a west workspace: {}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )
+        .into());
+    }
+    Ok(PathBuf::from(String::from_utf8(output.stdout)?.trim_end()))
+}
+
+pub fn main(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse_command(args, &[], &["--db", "--west"])?;
+    let west = opts.value("--west").unwrap_or("west");
+    // A leading `--` only separates the options of cdbgen, later ones are the ones of west.
+    let build = match opts.rest.split_first() {
+        Some((sep, build)) if sep == "--" => build,
+        _ => &opts.rest[..],
+    };
+    let database = match opts.value("--db") {
+        Some(db) => paths::normalize(&env::current_dir()?.join(db)),
+        None => topdir(west)?.join("compile_commands.json"),
+    };
+    let cdbgen = env::current_exe()?;
+    let mut command = Command::new(west);
+    command
+        .args(west_args(build, &cdbgen.to_string_lossy()))
+        .env("CDBGEN", &database)
+        .env("CDBGEN_CAPTURE", "zephyr")
+        // Generated headers live in the build directory but the database in the workspace.
+        .env("CDBGEN_INCLUDE_PATHS", "absolute");
+    if env::var_os("CDBGEN_PRESET").is_none() {
+        command.env("CDBGEN_PRESET", "auto");
+    }
+    let status = command
+        .status()
+        .map_err(|error| format!("cannot run '{}': {}", west, error))?;
+    if !status.success() {
+        wrap::exit_like(status);
+    }
+    output::info(&format!("recorded into {}", database.display()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::presets;
+
+    #[test]
+    fn west_args() {
+        let args = ["-b", "nrf52840dk/nrf52840", "app", "--", "-DEXTRA=1"].map(String::from);
+        assert_eq!(
+            super::west_args(&args, "/bin/cdbgen").join(" "),
+            "build -b nrf52840dk/nrf52840 app -- -DEXTRA=1 \
+             -DCMAKE_C_COMPILER_LAUNCHER=/bin/cdbgen -DCMAKE_CXX_COMPILER_LAUNCHER=/bin/cdbgen"
+        );
+        assert_eq!(
+            super::west_args(&[], "cdbgen").join(" "),
+            "build -- -DCMAKE_C_COMPILER_LAUNCHER=cdbgen -DCMAKE_CXX_COMPILER_LAUNCHER=cdbgen"
+        );
+
+        let compiler = "/opt/zephyr-sdk/arm-zephyr-eabi/bin/arm-zephyr-eabi-gcc";
+        let arguments = ["-fno-reorder-functions", "-c", "main.c"].map(String::from);
+        let arguments: Vec<_> = [compiler.to_string()]
+            .into_iter()
+            .chain(arguments)
+            .collect();
+        assert_eq!(
+            presets::detect(compiler).unwrap().apply(&arguments)[1..],
+            ["-D__ZEPHYR__=1", "--target=arm-none-eabi", "-c", "main.c"]
+        );
+    }
+}